@@ -1,37 +1,59 @@
 use std::{
-    collections::BTreeMap,
-    fmt::Display,
-    path::{Path, PathBuf},
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, btree_map},
+    ffi::OsStr,
+    fmt::{Display, Write},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
 };
 
 use compact_str::CompactString;
+#[cfg(feature = "metadata-tree")]
+use pb_filesystem::{FileStat, tree::MetadataTree};
 use pb_ore::{assert_none, id_gen::Gen};
-use pb_trie::TrieMap;
-use pb_types::{BuildTarget, BuildTargetPath, FileMetadataXx64, InternedPath, SourceDependency};
+use pb_trie::{TrieGlob, TrieMap, TrieNode};
+use pb_types::{
+    BuildTarget, BuildTargetPath, FileMetadataXx64, InternedPath, Label, SourceDependency,
+    TargetPattern, Visibility,
+};
+#[cfg(feature = "metadata-tree")]
+use pb_types::{FileMetadata, Xxh64Hash};
 use smallvec::SmallVec;
 
-#[derive(Debug)]
+mod shared;
+
+pub use shared::SharedBuildTree;
+
+/// The build graph for a workspace, the files in it and the build targets defined over them.
+///
+/// Every map is reference counted, so cloning a [`BuildTree`] is cheap and a clone only copies
+/// the maps that are later modified through it, see [`SharedBuildTree`].
+#[derive(Debug, Clone)]
 pub struct BuildTree {
     /// Locations of all the files in our workspace.
     file_locations: TrieMap<InternedPath, (), FileId>,
     /// Map of [`FileId`] to [`FileNode`].
-    files: BTreeMap<FileId, FileNode>,
+    files: Arc<BTreeMap<FileId, FileNode>>,
 
-    /// Relative path were a glob is defined.
+    /// Globs, keyed by the literal directory their pattern starts with, see [`glob_key`].
     glob_locations: TrieMap<InternedPath, (), GlobId>,
     /// Map of [`GlobId`] to [`GlobNode`].
-    globs: BTreeMap<GlobId, GlobNode>,
+    globs: Arc<BTreeMap<GlobId, GlobNode>>,
 
     /// Map of [`DynamicSourcesId`] to [`DynamicSourcesNode`].
-    dynamic_sources: BTreeMap<DynamicSourcesId, DynamicSourcesNode>,
+    dynamic_sources: Arc<BTreeMap<DynamicSourcesId, DynamicSourcesNode>>,
 
     /// Locations of all our build targets.
     build_target_locations: TrieMap<InternedPath, (), BuildTargetId>,
     /// Map of [`BuildTargetId`] to [`BuildTarget`].
-    build_targets: BTreeMap<BuildTargetId, BuildTargetNode>,
+    build_targets: Arc<BTreeMap<BuildTargetId, BuildTargetNode>>,
+    /// Build targets that need to be re-evaluated.
+    dirty: Arc<BTreeSet<BuildTargetId>>,
+    /// Aliases that point at another build target path, which may itself be an alias.
+    alias_locations: TrieMap<InternedPath, (), InternedPath>,
 
     /// String interner.
-    strings: lasso::Rodeo,
+    strings: Arc<lasso::Rodeo>,
     /// ID generator for all the nodes in our build tree.
     id_gen: Gen<u64>,
 }
@@ -41,17 +63,64 @@ impl BuildTree {
     pub fn new() -> Self {
         BuildTree {
             file_locations: TrieMap::new(),
-            files: BTreeMap::default(),
+            files: Arc::default(),
             glob_locations: TrieMap::new(),
-            globs: BTreeMap::default(),
-            dynamic_sources: BTreeMap::default(),
+            globs: Arc::default(),
+            dynamic_sources: Arc::default(),
             build_target_locations: TrieMap::new(),
-            build_targets: BTreeMap::default(),
-            strings: lasso::Rodeo::new(),
+            build_targets: Arc::default(),
+            dirty: Arc::default(),
+            alias_locations: TrieMap::new(),
+            strings: Arc::new(lasso::Rodeo::new()),
             id_gen: Gen::default(),
         }
     }
 
+    /// Create a new [`BuildTree`] from an existing trie of files in a single pass, instead of
+    /// inserting each file individually.
+    ///
+    /// `strings` must be the interner that was used for the components of `files`, it's re-used
+    /// by the [`BuildTree`].
+    pub fn from_files<T, F>(
+        files: TrieMap<InternedPath, (), T>,
+        strings: lasso::Rodeo,
+        mut metadata: F,
+    ) -> Self
+    where
+        F: FnMut(T) -> FileMetadataXx64,
+    {
+        let mut tree = BuildTree::new();
+        tree.strings = Arc::new(strings);
+
+        let (id_gen, nodes) = (&mut tree.id_gen, Arc::make_mut(&mut tree.files));
+        tree.file_locations = files.map_leaves(|path, data| {
+            let id = FileId(id_gen.next());
+            let node = FileNode {
+                metadata: metadata(data),
+                path: InternedPath(path.iter().copied().collect()),
+                build_dependents: SmallVec::new(),
+            };
+            let prev = nodes.insert(id, node);
+            assert_none!(prev);
+            id
+        });
+
+        tree
+    }
+
+    /// Create a new [`BuildTree`] with all of the files from a [`MetadataTree`].
+    #[cfg(feature = "metadata-tree")]
+    pub fn from_metadata_tree(tree: MetadataTree<(FileStat, Xxh64Hash)>) -> Self {
+        let (files, strings) = tree.into_parts();
+        BuildTree::from_files(files, strings, |(stat, fingerprint)| FileMetadata {
+            size: stat.size,
+            mtime: stat.mtime,
+            inode: stat.inode,
+            mode: stat.mode,
+            fingerprint,
+        })
+    }
+
     /// Insert the provided [`FileMetadataXx64`] into the tree.
     ///
     /// # Errors
@@ -62,19 +131,21 @@ impl BuildTree {
         path: P,
         metadata: FileMetadataXx64,
     ) -> Result<(), anyhow::Error> {
-        // Insert this target.
+        let path = self.intern_file_path(path)?;
+
+        // Add the path mapping first, it fails if the path is underneath a file.
         let id = self.gen_file_id();
+        self.file_locations.insert_leaf(path.clone(), id)?;
+
+        // Insert this file.
         let node = FileNode {
             metadata,
+            path,
             build_dependents: SmallVec::new(),
         };
-        let prev = self.files.insert(id, node);
+        let prev = Arc::make_mut(&mut self.files).insert(id, node);
         assert_none!(prev);
 
-        // Add the path mapping.
-        let path = self.intern_file_path(path);
-        self.file_locations.insert_leaf(path, id)?;
-
         Ok(())
     }
 
@@ -87,7 +158,7 @@ impl BuildTree {
         let node = self
             .lookup_file_path(path)
             .and_then(|path| self.file_locations.get_leaf(path))
-            .and_then(|id| self.files.get_mut(id))
+            .and_then(|id| Arc::make_mut(&mut self.files).get_mut(id))
             .ok_or_else(|| anyhow::anyhow!("file does not exist"))?;
 
         // Update the metadata.
@@ -96,6 +167,118 @@ impl BuildTree {
         Ok(node.build_dependents.iter().copied())
     }
 
+    /// Update the metadata for many files at once, e.g. for a batch of filesystem events.
+    ///
+    /// Returns all of the build targets that depend on any of the updated files. Files within the
+    /// same directory share a single walk of the tree.
+    ///
+    /// # Errors
+    ///
+    /// * If any of the files do not exist, in which case no files are updated.
+    pub fn update_files<I, P>(&mut self, files: I) -> Result<BTreeSet<BuildTargetId>, anyhow::Error>
+    where
+        I: IntoIterator<Item = (P, FileMetadataXx64)>,
+        P: AsRef<Path>,
+    {
+        // Group the files by their parent directory.
+        let mut directories: BTreeMap<InternedPath, Vec<_>> = BTreeMap::new();
+        for (path, metadata) in files {
+            let path = path.as_ref();
+            let interned = self
+                .lookup_file_path(path)
+                .and_then(|interned| Some((interned.parent()?, interned.last()?)));
+            let Some((parent, name)) = interned else {
+                anyhow::bail!("file does not exist: {path:?}");
+            };
+            directories
+                .entry(parent)
+                .or_default()
+                .push((path.to_path_buf(), name, metadata));
+        }
+
+        // Make sure every file exists before we update any of them.
+        let mut updates = Vec::new();
+        for (parent, files) in directories {
+            let children = match self.file_locations.get(parent) {
+                Some(TrieNode::Edge { children, .. }) => Some(children),
+                Some(TrieNode::Leaf { .. }) | None => None,
+            };
+            for (path, name, metadata) in files {
+                let id = match children.and_then(|children| children.get(&name)) {
+                    Some(TrieNode::Leaf { data }) => *data,
+                    Some(TrieNode::Edge { .. }) | None => {
+                        anyhow::bail!("file does not exist: {path:?}")
+                    }
+                };
+                updates.push((id, metadata));
+            }
+        }
+
+        let mut dependents = BTreeSet::new();
+        for (id, metadata) in updates {
+            let node = Arc::make_mut(&mut self.files)
+                .get_mut(&id)
+                .expect("file should exist");
+            node.metadata = metadata;
+            dependents.extend(node.build_dependents.iter().copied());
+        }
+
+        Ok(dependents)
+    }
+
+    /// Returns all of the build targets that need to be re-evaluated because a file was added
+    /// at `path` that matches one of the globs they depend on.
+    ///
+    /// Note: [`BuildTree::insert_file`] does not do this itself so a batch of new files can be
+    /// inserted before any targets are re-evaluated.
+    pub fn notify_file_added<P: AsRef<Path>>(&self, path: P) -> BTreeSet<BuildTargetId> {
+        self.glob_dependents(path.as_ref())
+    }
+
+    /// Returns all of the build targets that need to be re-evaluated because a file was removed
+    /// from `path` that matches one of the globs they depend on.
+    ///
+    /// Note: [`BuildTree::remove_file`] already includes these targets, this is for files that
+    /// were never tracked in the tree, e.g. those reported by a file watcher.
+    pub fn notify_file_removed<P: AsRef<Path>>(&self, path: P) -> BTreeSet<BuildTargetId> {
+        self.glob_dependents(path.as_ref())
+    }
+
+    /// Returns the dependents of all the globs that match `path`.
+    fn glob_dependents(&self, path: &Path) -> BTreeSet<BuildTargetId> {
+        self.matching_globs(path)
+            .into_iter()
+            .flat_map(|id| {
+                let glob = self.globs.get(&id).expect("glob should exist");
+                glob.build_dependents.iter().copied()
+            })
+            .collect()
+    }
+
+    /// Remove the file at the provided path from the tree.
+    ///
+    /// Returns all of the build targets that need to be re-evaluated because they depended on
+    /// this file, either directly, through a glob that matched it, or through the dynamic
+    /// sources of another target.
+    ///
+    /// # Errors
+    ///
+    /// * If the file does not exist.
+    pub fn remove_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<BTreeSet<BuildTargetId>, anyhow::Error> {
+        let path = path.as_ref();
+        let interned = self
+            .lookup_file_path(path)
+            .filter(|interned| self.file_locations.get_leaf(interned.clone()).is_some())
+            .ok_or_else(|| anyhow::anyhow!("file does not exist: {path:?}"))?;
+        let Some(TrieNode::Leaf { data: id }) = self.file_locations.remove(interned)? else {
+            unreachable!("checked above that the file exists");
+        };
+        Ok(self.remove_file_node(id))
+    }
+
     /// Get the [`FileMetadataXx64`] associated with the provided path, if it exists.
     pub fn get_file(&self, path: &PathBuf) -> Option<&FileMetadataXx64> {
         let path = self.lookup_file_path(path)?;
@@ -105,313 +288,2980 @@ impl BuildTree {
             .map(|node| &node.metadata)
     }
 
+    /// Get the [`FileId`] of the file at the provided path, if it exists.
+    pub fn file_id<P: AsRef<Path>>(&self, path: P) -> Option<FileId> {
+        let path = self.lookup_file_path(path)?;
+        self.file_locations.get_leaf(path).copied()
+    }
+
+    /// Move every file at or underneath `from` so it's underneath `to` instead.
+    ///
+    /// Build targets track files by ID so they continue to depend on the moved files.
+    ///
+    /// # Errors
+    ///
+    /// * If nothing exists at `from`.
+    /// * If something already exists at `to`, or `to` is underneath a file.
+    /// * If `to` is underneath `from`.
+    pub fn rename_path<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> Result<(), anyhow::Error> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let from_interned = self
+            .lookup_file_path(from)
+            .filter(|path| !path.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("path does not exist: {from:?}"))?;
+        let to_components =
+            normalize_path(to).ok_or_else(|| anyhow::anyhow!("invalid path: {to:?}"))?;
+
+        // Validate against the components we've already interned, so a failed rename doesn't
+        // leave new strings in the interner. Paths past the first unknown component can't
+        // exist in the tree.
+        let known: InternedPath = to_components
+            .iter()
+            .map_while(|component| self.strings.get(encode_component(component)))
+            .collect();
+        if to_components.is_empty() || known.starts_with(&from_interned) {
+            anyhow::bail!("cannot move {from:?} to {to:?}");
+        }
+
+        // Make sure the graft below can't fail, so we never lose the subtree.
+        let mut ancestor = known.clone();
+        if known.len() == to_components.len() {
+            if self.file_locations.get(known).is_some() {
+                anyhow::bail!("path already exists: {to:?}");
+            }
+            ancestor.pop();
+        }
+        while !ancestor.is_empty() {
+            if let Some(TrieNode::Leaf { .. }) = self.file_locations.get(ancestor.clone()) {
+                anyhow::bail!("non-directory in path: {to:?}");
+            }
+            ancestor.pop();
+        }
+        let to_interned = self.intern_file_path(to)?;
+
+        let subtree = self
+            .file_locations
+            .take_subtree(from_interned)?
+            .ok_or_else(|| anyhow::anyhow!("path does not exist: {from:?}"))?;
+        let prev = self.file_locations.graft(to_interned.clone(), subtree)?;
+        assert_none!(prev);
+
+        // Update the paths of all the files we moved.
+        for (components, id) in self.file_locations.iter_prefix(to_interned) {
+            let node = Arc::make_mut(&mut self.files)
+                .get_mut(id)
+                .expect("file should exist");
+            node.path = InternedPath(components.into_iter().collect());
+        }
+
+        Ok(())
+    }
+
+    /// Returns an iterator over every file in the tree, in sorted order.
+    pub fn iter_files(&self) -> impl Iterator<Item = (PathBuf, &FileMetadataXx64)> + '_ {
+        self.resolve_files(self.file_locations.iter())
+    }
+
+    /// Returns an iterator over every file at or underneath `prefix`, in sorted order.
+    pub fn iter_files_under<P: AsRef<Path>>(
+        &self,
+        prefix: P,
+    ) -> impl Iterator<Item = (PathBuf, &FileMetadataXx64)> + '_ {
+        let files = self
+            .lookup_file_path(prefix)
+            .map(|prefix| self.file_locations.iter_prefix(prefix))
+            .into_iter()
+            .flatten();
+        self.resolve_files(files)
+    }
+
+    /// Returns if there is at least one file at or underneath `prefix`.
+    pub fn contains_files_under<P: AsRef<Path>>(&self, prefix: P) -> bool {
+        self.lookup_file_path(prefix)
+            .is_some_and(|prefix| self.file_locations.contains_prefix(prefix))
+    }
+
+    /// Returns an iterator over every file that matches `glob`, in sorted order.
+    ///
+    /// Note: Path components that are not valid UTF-8 will only match wildcards.
+    pub fn match_files<'a>(
+        &'a self,
+        glob: &'a TrieGlob,
+    ) -> impl Iterator<Item = (PathBuf, &'a FileMetadataXx64)> + 'a {
+        let files = self
+            .file_locations
+            .match_glob(glob, |component| self.strings.resolve(component));
+        self.resolve_files(files)
+    }
+
     /// Insert a new [`BuildTarget`] into our [`BuildTree`].
+    ///
+    /// # Errors
+    ///
+    /// * If a build target or an alias already exists at `path`, see
+    ///   [`BuildTree::replace_build_target`].
+    /// * If any of the dependencies do not exist, or are not visible to `path`.
     pub fn insert_build_target(
         &mut self,
         path: &BuildTargetPath,
         target: BuildTarget,
     ) -> Result<(), anyhow::Error> {
-        // Lookup our dependencies.
-        let source_deps: Vec<_> = target
-            .source_deps
-            .into_iter()
-            .map(|dep| {
-                let dep = match dep {
-                    SourceDependency::File(path) => self
-                        .lookup_file_path(&path)
-                        .and_then(|path| self.file_locations.get_leaf(path).copied())
-                        .map(|file_id| SourceDependencyId::File(file_id))
-                        .ok_or_else(|| anyhow::anyhow!("depends on non-existent file {path:?}"))?,
-                    SourceDependency::Glob(glob) => todo!(),
-                    SourceDependency::Rule(rule) => self
-                        .lookup_build_path(&rule)
-                        .and_then(|path| self.build_target_locations.get_leaf(path).copied())
-                        .map(|rule_id| SourceDependencyId::Rule(rule_id))
-                        .ok_or_else(|| {
-                            anyhow::anyhow!("depends on non-existent target {rule:?}")
-                        })?,
-                };
-                Ok::<_, anyhow::Error>(dep)
-            })
-            .collect::<Result<_, _>>()?;
-        let build_deps = target
-            .build_deps
-            .into_iter()
-            .map(|path| {
-                let dep = self
-                    .lookup_build_path(&path)
-                    .and_then(|path| self.build_target_locations.get_leaf(path).copied())
-                    .ok_or_else(|| anyhow::anyhow!("depends on non-existent target {path:?}"))?;
-                Ok::<_, anyhow::Error>(dep)
-            })
-            .collect::<Result<_, _>>()?;
+        if self.lookup_alias(path).is_some() {
+            anyhow::bail!("an alias already exists at {path}");
+        }
+        if let Some(existing) = self.lookup_build_path(path)
+            && self.build_target_locations.get(existing).is_some()
+        {
+            anyhow::bail!("a build target already exists at {path}");
+        }
+        let (source_deps, build_deps) = self.lookup_dependencies(path, &target)?;
+        let tree_path = self.intern_build_path(path)?;
 
+        // Add the path mapping first, it fails if the path is underneath another build target.
         let id = self.gen_build_target_id();
-        // Update our source dependencies so we know what build rules depend on them.
-        for source_dep in &source_deps {
-            match source_dep {
-                SourceDependencyId::File(file_dep) => {
-                    let file = self.files.get_mut(&file_dep).expect("file should exist");
-                    file.build_dependents.push(id);
-                }
-                SourceDependencyId::Glob(glob_dep) => todo!(),
-                SourceDependencyId::Rule(rule_dep) => todo!(),
-            }
-        }
+        let prev = self
+            .build_target_locations
+            .insert_leaf(tree_path.clone(), id)?;
+        assert_none!(prev);
+
+        // Attach ourselves to all of our dependencies.
+        let source_deps = self.attach_dependencies(id, source_deps, &build_deps);
 
         // Create the node from the provided build target.
-        let rule = self.strings.get_or_intern(&target.rule);
-        let tree_path = self.intern_build_path(path);
+        let rule = self.intern(&target.rule);
+        let tags = self.intern_tags(&target.tags);
         let node = BuildTargetNode {
             name: path.name.clone(),
             rule,
             source_deps,
             build_deps,
-            path: tree_path.clone(),
+            build_dependents: SmallVec::new(),
+            dynamic_sources: None,
+            visibility: target.visibility,
+            tags,
+            path: tree_path,
         };
 
         // Insert this node.
-        let prev = self.build_targets.insert(id, node);
+        let prev = Arc::make_mut(&mut self.build_targets).insert(id, node);
         assert_none!(prev);
 
-        // Add the path mapping.
-        self.build_target_locations.insert_leaf(tree_path, id)?;
-
         Ok(())
     }
 
-    /// Return a pretty version of the file tree that can be displayed.
-    pub fn pretty_file_tree(&self) -> impl Display {
-        self.file_locations
-            .pretty(|f, name| f.write_all(self.strings.resolve(name).as_bytes()))
-    }
+    /// Replace the definition of an existing build target, e.g. because the manifest that
+    /// defines it changed.
+    ///
+    /// The target keeps its [`BuildTargetId`] so anything that depends on it continues to.
+    /// Returns all of the build targets that need to be re-evaluated because they depend on it.
+    ///
+    /// # Errors
+    ///
+    /// * If the build target does not exist.
+    /// * If any of the new dependencies do not exist.
+    pub fn replace_build_target(
+        &mut self,
+        path: &BuildTargetPath,
+        target: BuildTarget,
+    ) -> Result<BTreeSet<BuildTargetId>, anyhow::Error> {
+        let id = self
+            .lookup_build_target(path)
+            .ok_or_else(|| anyhow::anyhow!("build target does not exist: {path}"))?;
+        let (source_deps, build_deps) = self.lookup_dependencies(path, &target)?;
 
-    /// Intern a [`PathBuf`].
-    fn intern_file_path<P: AsRef<Path>>(&mut self, path: P) -> InternedPath {
-        let path = path.as_ref();
-        let mut components = SmallVec::default();
+        // Make sure everything that already depends on us still can.
+        let label = Label::try_from(path)?;
+        for dependent in self.dependents_of(id) {
+            let dependent = self.build_target_label(dependent)?;
+            if !target.visibility.allows(&label, &dependent) {
+                anyhow::bail!("{label} would no longer be visible to {dependent}");
+            }
+        }
 
-        // Add the relative path.
-        for component in path.components() {
-            let s = component.as_os_str().to_str().expect("non UTF-8 path");
-            let component = self.strings.get_or_intern(s);
-            components.push(component);
+        // Unlike inserting, replacing a target can introduce a cycle.
+        let rule_deps = source_deps.iter().filter_map(|dep| match dep {
+            PendingSourceDependency::Rule(rule_id) => Some(rule_id),
+            PendingSourceDependency::File(_) | PendingSourceDependency::Glob { .. } => None,
+        });
+        for dep in build_deps.iter().chain(rule_deps) {
+            if let Some(mut cycle) = self.dependency_path(*dep, id) {
+                cycle.insert(0, id);
+                let cycle: Vec<_> = cycle
+                    .into_iter()
+                    .map(|id| self.display_build_target(id))
+                    .collect();
+                anyhow::bail!("dependency cycle: {}", cycle.join(" -> "));
+            }
         }
 
-        InternedPath(components)
+        // Swap out our old dependencies for the new ones.
+        self.detach_dependencies(id);
+        let source_deps = self.attach_dependencies(id, source_deps, &build_deps);
+        let rule = self.intern(&target.rule);
+        let tags = self.intern_tags(&target.tags);
+
+        let node = Arc::make_mut(&mut self.build_targets)
+            .get_mut(&id)
+            .expect("build target should exist");
+        node.rule = rule;
+        node.source_deps = source_deps;
+        node.build_deps = build_deps;
+        node.visibility = target.visibility;
+        node.tags = tags;
+
+        Ok(self.dependents_of(id))
     }
 
-    /// Get the [`InternedPath`] for this [`PathBuf`], if one exists.
-    fn lookup_file_path<P: AsRef<Path>>(&self, path: P) -> Option<InternedPath> {
-        let path = path.as_ref();
-        let mut components = SmallVec::default();
+    /// Remove a build target from the tree.
+    ///
+    /// Any build targets that depended on the removed target, either directly or through its
+    /// outputs, have that dependency dropped and are returned so they can be re-evaluated.
+    ///
+    /// # Errors
+    ///
+    /// * If the build target does not exist.
+    pub fn remove_build_target(
+        &mut self,
+        path: &BuildTargetPath,
+    ) -> Result<BTreeSet<BuildTargetId>, anyhow::Error> {
+        let id = self
+            .lookup_build_target(path)
+            .ok_or_else(|| anyhow::anyhow!("build target does not exist: {path}"))?;
+        let (path, dependents) = self.remove_build_target_node(id);
 
-        // Add the relative path.
-        for component in path.components() {
-            let s = component.as_os_str().to_str().expect("non UTF-8 path");
-            let component = self.strings.get(s)?;
-            components.push(component);
-        }
+        // Remove the path mapping.
+        self.build_target_locations.remove(path)?;
 
-        Some(InternedPath(components))
+        Ok(dependents)
     }
 
-    /// Construct a [`PathBuf`] from the provided [`InternedPath`];
-    fn resolve_file_path(&self, path: &InternedPath) -> PathBuf {
-        let mut pathbuf = PathBuf::new();
-        for component in &path.0 {
-            pathbuf.push(self.strings.resolve(component));
+    /// Insert an alias so anything that depends on `alias` depends on `actual` instead, e.g.
+    /// because a build target was renamed or moved to another package.
+    ///
+    /// Aliases are resolved when dependencies are looked up, so `actual` doesn't need to exist
+    /// yet. Inserting an alias that already exists replaces where it points.
+    ///
+    /// # Errors
+    ///
+    /// * If a build target already exists at `alias`.
+    /// * If the alias would create a cycle of aliases.
+    pub fn insert_alias(
+        &mut self,
+        alias: &BuildTargetPath,
+        actual: &BuildTargetPath,
+    ) -> Result<(), anyhow::Error> {
+        if self.lookup_build_target(alias).is_some() {
+            anyhow::bail!("a build target already exists at {alias}");
+        }
+        let alias_path = self.intern_build_path(alias)?;
+        let actual_path = self.intern_build_path(actual)?;
+
+        // Follow the chain of aliases to make sure it doesn't lead back to us.
+        let mut current = actual_path.clone();
+        loop {
+            if current == alias_path {
+                anyhow::bail!("alias cycle: {alias} -> {actual}");
+            }
+            match self.alias_locations.get_leaf(current) {
+                Some(next) => current = next.clone(),
+                None => break,
+            }
         }
-        pathbuf
+
+        self.alias_locations.insert_leaf(alias_path, actual_path)?;
+        Ok(())
     }
 
-    /// Intern a [`BuildTargetPath`].
-    fn intern_build_path(&mut self, path: &BuildTargetPath) -> InternedPath {
-        let mut components = SmallVec::default();
+    /// Remove an alias from the tree.
+    ///
+    /// Build targets that depended on the alias keep depending on what it pointed to.
+    ///
+    /// # Errors
+    ///
+    /// * If the alias does not exist.
+    pub fn remove_alias(&mut self, alias: &BuildTargetPath) -> Result<(), anyhow::Error> {
+        let path = self
+            .lookup_alias(alias)
+            .ok_or_else(|| anyhow::anyhow!("alias does not exist: {alias}"))?;
+        self.alias_locations.remove(path)?;
+        Ok(())
+    }
 
-        // Add the repository.
-        let repository = self.strings.get_or_intern(&path.repository);
-        components.push(repository);
+    /// Returns the path that `alias` ultimately points to, following any chain of aliases, or
+    /// `None` if `alias` is not an alias.
+    pub fn resolve_alias(&self, alias: &BuildTargetPath) -> Option<BuildTargetPath> {
+        let path = self.lookup_alias(alias)?;
+        Some(self.resolve_build_path(&self.follow_aliases(path)))
+    }
 
-        // Add the relative path.
-        let parent = self.intern_file_path(&path.parents);
-        components.extend_from_slice(&parent.0[..]);
+    /// Remove the [`BuildTargetNode`] for `id` and any edges pointing at it, returning its path
+    /// and the build targets that need to be re-evaluated.
+    fn remove_build_target_node(
+        &mut self,
+        id: BuildTargetId,
+    ) -> (InternedPath, BTreeSet<BuildTargetId>) {
+        let dependents = self.dependents_of(id);
 
-        // Add the target name.
-        let name = self.strings.get_or_intern(&path.name);
-        components.push(name);
+        // Drop the edges pointing at this target.
+        self.detach_dependencies(id);
+        let node = Arc::make_mut(&mut self.build_targets)
+            .remove(&id)
+            .expect("build target should exist");
+        Arc::make_mut(&mut self.dirty).remove(&id);
+        for dependent in &node.build_dependents {
+            let dependent = Arc::make_mut(&mut self.build_targets)
+                .get_mut(dependent)
+                .expect("build target should exist");
+            dependent.build_deps.retain(|dep| *dep != id);
+        }
+        if let Some(dynamic_id) = node.dynamic_sources {
+            let dynamic = Arc::make_mut(&mut self.dynamic_sources)
+                .remove(&dynamic_id)
+                .expect("dynamic sources should exist");
+            for dependent in &dynamic.build_dependents {
+                let dependent = Arc::make_mut(&mut self.build_targets)
+                    .get_mut(dependent)
+                    .expect("build target should exist");
+                dependent
+                    .source_deps
+                    .retain(|dep| *dep != SourceDependencyId::Dynamic(dynamic_id));
+            }
+        }
 
-        InternedPath(components)
+        (node.path, dependents)
     }
 
-    /// Construct a [`BuildTargetPath`] from the provided [`InternedPath`];
-    fn resolve_build_path(&self, path: &InternedPath) -> BuildTargetPath {
-        let repository = self.strings.resolve(&path.0[0]);
-        let mut parents = PathBuf::new();
-        for component in &path.0[1..path.0.len() - 1] {
-            parents.push(self.strings.resolve(component));
+    /// Reclaim any files, globs, and build targets that are no longer reachable from their
+    /// location in the tree, e.g. because another node was inserted at the same path, and
+    /// compact the string interner.
+    ///
+    /// Returns all of the build targets that need to be re-evaluated because one of their
+    /// dependencies was reclaimed.
+    pub fn gc(&mut self) -> BTreeSet<BuildTargetId> {
+        let mut dependents = BTreeSet::new();
+
+        let live: BTreeSet<_> = self
+            .build_target_locations
+            .iter()
+            .map(|(_, id)| *id)
+            .collect();
+        let orphans: Vec<_> = self
+            .build_targets
+            .keys()
+            .filter(|id| !live.contains(id))
+            .copied()
+            .collect();
+        for id in orphans {
+            let (_path, target_dependents) = self.remove_build_target_node(id);
+            dependents.extend(target_dependents);
         }
-        let name = self.strings.resolve(&path.0[path.0.len() - 1]);
 
-        BuildTargetPath {
-            repository: CompactString::new(repository),
-            parents,
-            name: CompactString::new(name),
+        let live: BTreeSet<_> = self.file_locations.iter().map(|(_, id)| *id).collect();
+        let orphans: Vec<_> = self
+            .files
+            .keys()
+            .filter(|id| !live.contains(id))
+            .copied()
+            .collect();
+        for id in orphans {
+            dependents.extend(self.remove_file_node(id));
+        }
+
+        let live: BTreeSet<_> = self.glob_locations.iter().map(|(_, id)| *id).collect();
+        let orphans: Vec<_> = self
+            .globs
+            .keys()
+            .filter(|id| !live.contains(id))
+            .copied()
+            .collect();
+        for id in orphans {
+            let glob = Arc::make_mut(&mut self.globs)
+                .remove(&id)
+                .expect("glob should exist");
+            for dependent in &glob.build_dependents {
+                let target = Arc::make_mut(&mut self.build_targets)
+                    .get_mut(dependent)
+                    .expect("build target should exist");
+                target
+                    .source_deps
+                    .retain(|dep| *dep != SourceDependencyId::Glob(id));
+            }
+            dependents.extend(glob.build_dependents);
         }
+
+        self.compact_strings();
+
+        // Don't report any targets that were reclaimed themselves.
+        dependents.retain(|id| self.build_targets.contains_key(id));
+        dependents
     }
 
-    /// Get the [`InternedPath`] for this [`BuildTargetPath`], if one exists.
-    fn lookup_build_path(&self, path: &BuildTargetPath) -> Option<InternedPath> {
-        let mut components = SmallVec::default();
+    /// Returns the provided build targets and all of their transitive dependencies, ordered so
+    /// every target comes after the targets it depends on.
+    ///
+    /// Targets that could run in either order are ordered by their [`BuildTargetId`], so the
+    /// order is stable for a given tree.
+    ///
+    /// # Errors
+    ///
+    /// * If any of the roots do not exist.
+    /// * If the targets contain a dependency cycle, the error names the targets in the cycle.
+    pub fn topo_order<I>(&self, roots: I) -> Result<Vec<BuildTargetId>, anyhow::Error>
+    where
+        I: IntoIterator<Item = BuildTargetId>,
+    {
+        // Find every target reachable from the roots, along with its dependencies.
+        let mut dependencies: BTreeMap<BuildTargetId, BTreeSet<BuildTargetId>> = BTreeMap::new();
+        let mut stack = Vec::new();
+        for root in roots {
+            if !self.build_targets.contains_key(&root) {
+                anyhow::bail!("build target does not exist: {root:?}");
+            }
+            stack.push(root);
+        }
+        while let Some(id) = stack.pop() {
+            if let btree_map::Entry::Vacant(entry) = dependencies.entry(id) {
+                let deps = entry.insert(self.dependencies_of(id).collect());
+                stack.extend(deps.iter().copied());
+            }
+        }
 
-        // Add the repository.
-        let repository = self.strings.get(&path.repository)?;
-        components.push(repository);
+        let mut dependents: BTreeMap<BuildTargetId, Vec<BuildTargetId>> = BTreeMap::new();
+        for (id, deps) in &dependencies {
+            for dep in deps {
+                dependents.entry(*dep).or_default().push(*id);
+            }
+        }
+        let mut remaining: BTreeMap<_, _> = dependencies
+            .iter()
+            .map(|(id, deps)| (*id, deps.len()))
+            .collect();
+        let mut ready: BTreeSet<_> = remaining
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(id, _)| *id)
+            .collect();
 
-        // Add the relative path.
-        let parent = self.lookup_file_path(&path.parents)?;
-        components.extend_from_slice(&parent.0[..]);
+        let mut order = Vec::with_capacity(dependencies.len());
+        while let Some(id) = ready.pop_first() {
+            order.push(id);
+            for dependent in dependents.get(&id).into_iter().flatten() {
+                let count = remaining.get_mut(dependent).expect("known target");
+                *count -= 1;
+                if *count == 0 {
+                    ready.insert(*dependent);
+                }
+            }
+        }
 
-        // Add the target name.
-        let name = self.strings.get(&path.name)?;
-        components.push(name);
+        if order.len() != dependencies.len() {
+            // Every target that's left depends on another target that's left, so following
+            // those dependencies from any of them eventually loops back around.
+            let left: BTreeSet<_> = remaining
+                .iter()
+                .filter(|(_, count)| **count > 0)
+                .map(|(id, _)| *id)
+                .collect();
+            let mut path = vec![*left.first().expect("cycle is not empty")];
+            loop {
+                let last = path.last().expect("path is not empty");
+                let next = dependencies[last]
+                    .iter()
+                    .find(|dep| left.contains(*dep))
+                    .copied()
+                    .expect("targets left in a cycle have a dependency left");
+                if let Some(start) = path.iter().position(|id| *id == next) {
+                    let cycle: Vec<_> = path[start..]
+                        .iter()
+                        .chain([&next])
+                        .map(|id| self.display_build_target(*id))
+                        .collect();
+                    anyhow::bail!("dependency cycle: {}", cycle.join(" -> "));
+                }
+                path.push(next);
+            }
+        }
 
-        Some(InternedPath(components))
+        Ok(order)
     }
 
-    fn gen_file_id(&mut self) -> FileId {
-        FileId(self.id_gen.next())
-    }
+    /// Returns the provided build targets along with every build target that transitively
+    /// depends on them.
+    ///
+    /// If `depth` is provided, only dependents at most that many edges away are included, e.g.
+    /// a depth of `1` includes the direct dependents.
+    ///
+    /// # Errors
+    ///
+    /// * If any of the provided build targets do not exist.
+    pub fn rdeps<I>(
+        &self,
+        targets: I,
+        depth: Option<usize>,
+    ) -> Result<BTreeSet<BuildTargetId>, anyhow::Error>
+    where
+        I: IntoIterator<Item = BuildTargetId>,
+    {
+        let mut rdeps = BTreeSet::new();
+        let mut frontier = Vec::new();
+        for target in targets {
+            if !self.build_targets.contains_key(&target) {
+                anyhow::bail!("build target does not exist: {target:?}");
+            }
+            if rdeps.insert(target) {
+                frontier.push(target);
+            }
+        }
 
-    fn gen_build_target_id(&mut self) -> BuildTargetId {
-        BuildTargetId(self.id_gen.next())
+        let mut current_depth = 0;
+        while !frontier.is_empty() && depth.is_none_or(|depth| current_depth < depth) {
+            let mut next = Vec::new();
+            for id in frontier {
+                for dependent in self.dependents_of(id) {
+                    if rdeps.insert(dependent) {
+                        next.push(dependent);
+                    }
+                }
+            }
+            frontier = next;
+            current_depth += 1;
+        }
+
+        Ok(rdeps)
     }
-}
 
-/// A single build target within the tree.
-///
-/// Externally we interface with [`BuildTarget`]s, but in a [`BuildTree`] we store this type.
-#[derive(Clone, Debug)]
-struct BuildTargetNode {
-    /// Name of the build target.
-    name: CompactString,
-    /// Name of the rule associated with this target.
-    rule: lasso::Spur,
+    /// Returns every build target that matches `pattern`, optionally only including targets
+    /// that use the provided `rule`.
+    ///
+    /// # Errors
+    ///
+    /// * If `pattern` names a single target that does not exist.
+    pub fn query_targets(
+        &self,
+        pattern: &TargetPattern,
+        rule: Option<&str>,
+    ) -> Result<Vec<BuildTargetId>, anyhow::Error> {
+        let package =
+            self.lookup_package_path(pattern.repository().unwrap_or_default(), pattern.package());
+        let mut ids: Vec<_> = match pattern {
+            TargetPattern::Target(label) => {
+                let id = self
+                    .lookup_build_target(&label.into())
+                    .ok_or_else(|| anyhow::anyhow!("build target does not exist: {label}"))?;
+                vec![id]
+            }
+            TargetPattern::Package { .. } => {
+                match package.and_then(|package| self.build_target_locations.get(package)) {
+                    Some(TrieNode::Edge { children, .. }) => children
+                        .values()
+                        .filter_map(|child| match child {
+                            TrieNode::Leaf { data } => Some(*data),
+                            TrieNode::Edge { .. } => None,
+                        })
+                        .collect(),
+                    Some(TrieNode::Leaf { .. }) | None => Vec::new(),
+                }
+            }
+            TargetPattern::Recursive { .. } => package
+                .map(|package| self.build_target_locations.iter_prefix(package))
+                .into_iter()
+                .flatten()
+                .map(|(_, id)| *id)
+                .collect(),
+        };
 
-    /// Other dependencies in our build graph that this target depends on.
-    build_deps: Vec<BuildTargetId>,
-    /// Source files that this build target directly depends on.
-    source_deps: Vec<SourceDependencyId>,
+        if let Some(rule) = rule {
+            let rule = self.strings.get(rule);
+            ids.retain(|id| {
+                let node = self
+                    .build_targets
+                    .get(id)
+                    .expect("build target should exist");
+                Some(node.rule) == rule
+            });
+        }
 
-    /// The path this node is located at.
-    path: InternedPath,
-}
+        Ok(ids)
+    }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-enum SourceDependencyId {
-    File(FileId),
+    /// Returns every build target that has `tag`, in order of their paths.
+    pub fn query_targets_with_tag(&self, tag: &str) -> Vec<BuildTargetId> {
+        let Some(tag) = self.strings.get(tag) else {
+            return Vec::new();
+        };
+        self.build_target_locations
+            .iter()
+            .map(|(_, id)| *id)
+            .filter(|id| {
+                let node = self
+                    .build_targets
+                    .get(id)
+                    .expect("build target should exist");
+                node.tags.contains(&tag)
+            })
+            .collect()
+    }
+
+    /// Mark a file or build target as dirty, along with every build target that transitively
+    /// depends on it.
+    ///
+    /// # Errors
+    ///
+    /// * If the file or build target does not exist.
+    pub fn mark_dirty(&mut self, id: impl Into<NodeId>) -> Result<(), anyhow::Error> {
+        let targets = match id.into() {
+            NodeId::File(file_id) => {
+                let file = self
+                    .files
+                    .get(&file_id)
+                    .ok_or_else(|| anyhow::anyhow!("file does not exist: {file_id:?}"))?;
+                let dynamic = self
+                    .dynamic_sources
+                    .values()
+                    .filter(|dynamic| dynamic.files.as_ref().is_some_and(|f| f.contains(&file_id)))
+                    .flat_map(|dynamic| dynamic.build_dependents.iter());
+                let dependents: Vec<_> = file
+                    .build_dependents
+                    .iter()
+                    .chain(dynamic)
+                    .copied()
+                    .collect();
+                self.rdeps(dependents, None)?
+            }
+            NodeId::BuildTarget(target_id) => self.rdeps([target_id], None)?,
+        };
+        Arc::make_mut(&mut self.dirty).extend(targets);
+        Ok(())
+    }
+
+    /// Returns if the provided build target is dirty.
+    pub fn is_dirty(&self, id: BuildTargetId) -> bool {
+        self.dirty.contains(&id)
+    }
+
+    /// Returns all of the dirty build targets, marking them clean.
+    pub fn take_dirty_targets(&mut self) -> BTreeSet<BuildTargetId> {
+        Arc::unwrap_or_clone(std::mem::take(&mut self.dirty))
+    }
+
+    /// Get the [`BuildTarget`] at the provided path, if it exists.
+    pub fn get_build_target(&self, path: &BuildTargetPath) -> Option<BuildTarget> {
+        let id = self.lookup_build_target(path)?;
+        let node = self.build_targets.get(&id)?;
+        Some(self.resolve_build_target(node))
+    }
+
+    /// Get the path and [`BuildTarget`] for the provided [`BuildTargetId`], if it exists.
+    pub fn target_info(&self, id: BuildTargetId) -> Option<(BuildTargetPath, BuildTarget)> {
+        let node = self.build_targets.get(&id)?;
+        let path = self.resolve_build_path(&node.path);
+        Some((path, self.resolve_build_target(node)))
+    }
+
+    /// Returns the [`DynamicSourcesId`] for the outputs of the provided target, if any other
+    /// target depends on them.
+    pub fn dynamic_sources_id(&self, target: &BuildTargetPath) -> Option<DynamicSourcesId> {
+        let id = self.lookup_build_target(target)?;
+        self.build_targets.get(&id)?.dynamic_sources
+    }
+
+    /// Record the files that were resolved for the provided dynamic sources, after the build
+    /// target that produces them has run.
+    ///
+    /// Returns all of the build targets that depend on these sources.
+    ///
+    /// # Errors
+    ///
+    /// * If the dynamic sources do not exist.
+    /// * If any of the files do not exist in the tree.
+    pub fn record_dynamic_sources<I, P>(
+        &mut self,
+        id: DynamicSourcesId,
+        files: I,
+    ) -> Result<impl Iterator<Item = BuildTargetId> + '_, anyhow::Error>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let files = files
+            .into_iter()
+            .map(|path| {
+                let path = path.as_ref();
+                self.lookup_file_path(path)
+                    .and_then(|path| self.file_locations.get_leaf(path).copied())
+                    .ok_or_else(|| anyhow::anyhow!("non-existent file {path:?}"))
+            })
+            .collect::<Result<Box<[_]>, _>>()?;
+        let node = Arc::make_mut(&mut self.dynamic_sources)
+            .get_mut(&id)
+            .ok_or_else(|| anyhow::anyhow!("dynamic sources do not exist"))?;
+
+        node.files = Some(files);
+        Ok(node.build_dependents.iter().copied())
+    }
+
+    /// Returns the files recorded for the provided dynamic sources, or `None` if the sources
+    /// don't exist or haven't been recorded yet.
+    pub fn get_dynamic_sources(&self, id: DynamicSourcesId) -> Option<&[FileId]> {
+        self.dynamic_sources.get(&id)?.files.as_deref()
+    }
+
+    /// Render the build graph in the Graphviz DOT format, for debugging dependency problems.
+    ///
+    /// If `filter` is provided, only the matching build targets, and the files, globs, and other
+    /// targets they directly depend on, are included.
+    ///
+    /// # Errors
+    ///
+    /// * If `filter` names a single target that does not exist.
+    pub fn to_dot(&self, filter: Option<&TargetPattern>) -> Result<String, anyhow::Error> {
+        let targets: BTreeSet<_> = match filter {
+            Some(filter) => self.query_targets(filter, None)?.into_iter().collect(),
+            None => self.build_targets.keys().copied().collect(),
+        };
+        let mut nodes = BTreeMap::new();
+        let mut edges = Vec::new();
+        for id in &targets {
+            let node = self
+                .build_targets
+                .get(id)
+                .expect("build target should exist");
+            let rule = self.strings.resolve(&node.rule);
+            let label = format!(
+                "{}\\n({})",
+                dot_escape(&self.display_build_target(*id)),
+                dot_escape(rule)
+            );
+            nodes.insert(format!("target_{}", id.0), (label, "box"));
+
+            for dep in &node.build_deps {
+                let label = dot_escape(&self.display_build_target(*dep));
+                nodes
+                    .entry(format!("target_{}", dep.0))
+                    .or_insert((label, "box"));
+                edges.push((
+                    format!("target_{}", id.0),
+                    format!("target_{}", dep.0),
+                    "solid",
+                ));
+            }
+            for dep in &node.source_deps {
+                let (name, label, shape, style) = match dep {
+                    SourceDependencyId::File(file_id) => {
+                        let file = self.files.get(file_id).expect("file should exist");
+                        let path = self.resolve_file_path(&file.path);
+                        let label = dot_escape(&path.display().to_string());
+                        (format!("file_{}", file_id.0), label, "note", "solid")
+                    }
+                    SourceDependencyId::Glob(glob_id) => {
+                        let glob = self.globs.get(glob_id).expect("glob should exist");
+                        let label = dot_escape(&glob.pattern);
+                        (format!("glob_{}", glob_id.0), label, "folder", "solid")
+                    }
+                    SourceDependencyId::Dynamic(dynamic_id) => {
+                        let dynamic = self
+                            .dynamic_sources
+                            .get(dynamic_id)
+                            .expect("dynamic sources should exist");
+                        let producer = dynamic.build_target;
+                        let label = dot_escape(&self.display_build_target(producer));
+                        (format!("target_{}", producer.0), label, "box", "dashed")
+                    }
+                };
+                nodes.entry(name.clone()).or_insert((label, shape));
+                edges.push((format!("target_{}", id.0), name, style));
+            }
+        }
+
+        let mut dot = String::from("digraph build_tree {\n");
+        for (name, (label, shape)) in nodes {
+            writeln!(dot, "    {name} [label=\"{label}\", shape={shape}];").expect("infallible");
+        }
+        for (from, to, style) in edges {
+            writeln!(dot, "    {from} -> {to} [style={style}];").expect("infallible");
+        }
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+
+    /// Returns the files and build targets that were added, removed, or changed in `other`
+    /// relative to `self`.
+    ///
+    /// Files and build targets are compared by path, so the two trees don't need to share IDs.
+    pub fn diff(&self, other: &BuildTree) -> BuildTreeDiff {
+        let mut diff = BuildTreeDiff::default();
+
+        let files: BTreeMap<_, _> = self.iter_files().collect();
+        let other_files: BTreeMap<_, _> = other.iter_files().collect();
+        diff_maps(
+            &files,
+            &other_files,
+            &mut diff.added_files,
+            &mut diff.removed_files,
+            &mut diff.changed_files,
+        );
+
+        let targets = self.resolve_build_targets();
+        let other_targets = other.resolve_build_targets();
+        diff_maps(
+            &targets,
+            &other_targets,
+            &mut diff.added_targets,
+            &mut diff.removed_targets,
+            &mut diff.changed_targets,
+        );
+
+        diff
+    }
+
+    /// Return a pretty version of the file tree that can be displayed.
+    pub fn pretty_file_tree(&self) -> impl Display {
+        self.file_locations.pretty(|f, name| {
+            let name = self.resolve_component(name);
+            f.write_all(name.to_string_lossy().as_bytes())
+        })
+    }
+
+    /// Returns every build target in the tree, converted back into a [`BuildTarget`].
+    fn resolve_build_targets(&self) -> BTreeMap<BuildTargetPath, BuildTarget> {
+        self.build_targets
+            .values()
+            .map(|node| {
+                let path = self.resolve_build_path(&node.path);
+                let target = self.resolve_build_target(node);
+                (path, target)
+            })
+            .collect()
+    }
+
+    /// Convert a [`BuildTargetNode`] back into a [`BuildTarget`].
+    fn resolve_build_target(&self, node: &BuildTargetNode) -> BuildTarget {
+        let build_path = |id: &BuildTargetId| {
+            let node = self
+                .build_targets
+                .get(id)
+                .expect("build target should exist");
+            self.resolve_build_path(&node.path)
+        };
+        let build_deps = node.build_deps.iter().map(build_path).collect();
+        let source_deps = node
+            .source_deps
+            .iter()
+            .map(|dep| match dep {
+                SourceDependencyId::File(file_id) => {
+                    let file = self.files.get(file_id).expect("file should exist");
+                    SourceDependency::File(self.resolve_file_path(&file.path))
+                }
+                SourceDependencyId::Glob(glob_id) => {
+                    let glob = self.globs.get(glob_id).expect("glob should exist");
+                    SourceDependency::Glob(glob.pattern.clone())
+                }
+                SourceDependencyId::Dynamic(dynamic_id) => {
+                    let dynamic = self
+                        .dynamic_sources
+                        .get(dynamic_id)
+                        .expect("dynamic sources should exist");
+                    SourceDependency::Rule(build_path(&dynamic.build_target))
+                }
+            })
+            .collect();
+
+        BuildTarget {
+            rule: CompactString::new(self.strings.resolve(&node.rule)),
+            build_deps,
+            source_deps,
+            visibility: node.visibility.clone(),
+            tags: node
+                .tags
+                .iter()
+                .map(|tag| CompactString::new(self.strings.resolve(tag)))
+                .collect(),
+        }
+    }
+
+    /// Resolve the paths and metadata for the files yielded by a [`TrieMap`] iterator.
+    fn resolve_files<'a>(
+        &'a self,
+        files: impl Iterator<Item = (Vec<lasso::Spur>, &'a FileId)> + 'a,
+    ) -> impl Iterator<Item = (PathBuf, &'a FileMetadataXx64)> + 'a {
+        files.map(|(components, id)| {
+            let path = self.resolve_components(&components);
+            let node = self.files.get(id).expect("file should exist");
+            (path, &node.metadata)
+        })
+    }
+
+    /// Intern a [`PathBuf`].
+    ///
+    /// # Errors
+    ///
+    /// * If the path is absolute or escapes the root of the workspace, see [`normalize_path`].
+    fn intern_file_path<P: AsRef<Path>>(&mut self, path: P) -> Result<InternedPath, anyhow::Error> {
+        let path = path.as_ref();
+        let components =
+            normalize_path(path).ok_or_else(|| anyhow::anyhow!("invalid path: {path:?}"))?;
+        let mut interned = InternedPath::new();
+
+        // Add the relative path.
+        for component in components {
+            let component = encode_component(component);
+            interned.push(self.intern(component));
+        }
+
+        Ok(interned)
+    }
+
+    /// Get the [`InternedPath`] for this [`PathBuf`], if one exists.
+    fn lookup_file_path<P: AsRef<Path>>(&self, path: P) -> Option<InternedPath> {
+        let path = path.as_ref();
+        let mut interned = InternedPath::new();
+
+        // Add the relative path.
+        for component in normalize_path(path)? {
+            let component = encode_component(component);
+            interned.push(self.strings.get(component)?);
+        }
+
+        Some(interned)
+    }
+
+    /// Construct a [`PathBuf`] from the provided [`InternedPath`];
+    fn resolve_file_path(&self, path: &InternedPath) -> PathBuf {
+        self.resolve_components(path.components())
+    }
+
+    /// Construct a [`PathBuf`] from the provided components.
+    fn resolve_components(&self, components: &[lasso::Spur]) -> PathBuf {
+        components
+            .iter()
+            .map(|component| self.resolve_component(component))
+            .collect()
+    }
+
+    /// Resolve a single component of a file path, see [`encode_component`].
+    fn resolve_component(&self, component: &lasso::Spur) -> Cow<'_, OsStr> {
+        decode_component(self.strings.resolve(component))
+    }
+
+    /// Intern a [`BuildTargetPath`].
+    fn intern_build_path(&mut self, path: &BuildTargetPath) -> Result<InternedPath, anyhow::Error> {
+        // Add the repository.
+        let repository = self.intern(&path.repository);
+        let mut interned = InternedPath::new().join(repository);
+
+        // Add the relative path.
+        let parent = self.intern_file_path(&path.parents)?;
+        interned = interned.join_path(&parent);
+
+        // Add the target name.
+        let name = self.intern(&path.name);
+        interned.push(name);
+
+        Ok(interned)
+    }
+
+    /// Construct a [`BuildTargetPath`] from the provided [`InternedPath`];
+    fn resolve_build_path(&self, path: &InternedPath) -> BuildTargetPath {
+        let (repository, rest) = path
+            .components()
+            .split_first()
+            .expect("build paths always have a repository");
+        let (name, parents) = rest.split_last().expect("build paths always have a name");
+
+        let repository = self.strings.resolve(repository);
+        let parents = self.resolve_components(parents);
+        let name = self.strings.resolve(name);
+
+        BuildTargetPath {
+            repository: CompactString::new(repository),
+            parents,
+            name: CompactString::new(name),
+        }
+    }
+
+    /// Get the [`InternedPath`] for this [`BuildTargetPath`], if one exists.
+    fn lookup_build_path(&self, path: &BuildTargetPath) -> Option<InternedPath> {
+        // Add the repository.
+        let repository = self.strings.get(&path.repository)?;
+        let mut interned = InternedPath::new().join(repository);
+
+        // Add the relative path.
+        let parent = self.lookup_file_path(&path.parents)?;
+        interned = interned.join_path(&parent);
+
+        // Add the target name.
+        let name = self.strings.get(&path.name)?;
+        interned.push(name);
+
+        Some(interned)
+    }
+
+    /// Get the [`InternedPath`] for a package in the provided repository, if one exists.
+    fn lookup_package_path(&self, repository: &str, package: &str) -> Option<InternedPath> {
+        let repository = self.strings.get(repository)?;
+        let mut interned = InternedPath::new().join(repository);
+        for component in package.split('/').filter(|c| !c.is_empty()) {
+            interned.push(self.strings.get(component)?);
+        }
+        Some(interned)
+    }
+
+    /// Get the [`BuildTargetId`] for this [`BuildTargetPath`], if one exists.
+    fn lookup_build_target(&self, path: &BuildTargetPath) -> Option<BuildTargetId> {
+        self.lookup_build_path(path)
+            .and_then(|path| self.build_target_locations.get_leaf(path).copied())
+    }
+
+    /// Get the [`BuildTargetId`] for this [`BuildTargetPath`], following any aliases.
+    fn lookup_aliased_build_target(&self, path: &BuildTargetPath) -> Option<BuildTargetId> {
+        let path = self.follow_aliases(self.lookup_build_path(path)?);
+        self.build_target_locations.get_leaf(path).copied()
+    }
+
+    /// Get the [`InternedPath`] of the alias at `path`, if one exists.
+    fn lookup_alias(&self, path: &BuildTargetPath) -> Option<InternedPath> {
+        let path = self.lookup_build_path(path)?;
+        self.alias_locations
+            .get_leaf(path.clone())
+            .is_some()
+            .then_some(path)
+    }
+
+    /// Follow the chain of aliases starting at `path`, returning the first path that is not an
+    /// alias.
+    fn follow_aliases(&self, mut path: InternedPath) -> InternedPath {
+        // Cycles are rejected when inserting an alias, so this always terminates.
+        while let Some(next) = self.alias_locations.get_leaf(path.clone()) {
+            path = next.clone();
+        }
+        path
+    }
+
+    /// Remove the [`FileNode`] for `id` and any edges pointing at it, returning the build targets
+    /// that need to be re-evaluated.
+    fn remove_file_node(&mut self, id: FileId) -> BTreeSet<BuildTargetId> {
+        let node = Arc::make_mut(&mut self.files)
+            .remove(&id)
+            .expect("file should exist");
+        let path = self.resolve_file_path(&node.path);
+
+        // Targets that directly depend on this file no longer can.
+        let mut dependents: BTreeSet<_> = node.build_dependents.into_iter().collect();
+        for dependent in &dependents {
+            let target = Arc::make_mut(&mut self.build_targets)
+                .get_mut(dependent)
+                .expect("build target should exist");
+            target
+                .source_deps
+                .retain(|dep| *dep != SourceDependencyId::File(id));
+        }
+
+        // The set of files matched by these globs changed.
+        for glob_id in self.matching_globs(&path) {
+            let glob = self.globs.get(&glob_id).expect("glob should exist");
+            dependents.extend(glob.build_dependents.iter().copied());
+        }
+
+        // The outputs of a target no longer exist.
+        for dynamic in Arc::make_mut(&mut self.dynamic_sources).values_mut() {
+            let Some(files) = dynamic.files.as_mut() else {
+                continue;
+            };
+            if files.contains(&id) {
+                *files = files.iter().copied().filter(|file| *file != id).collect();
+                dependents.extend(dynamic.build_dependents.iter().copied());
+            }
+        }
+
+        dependents
+    }
+
+    /// Lookup the dependencies of `target`, returning an error if any of them don't exist.
+    ///
+    /// # Errors
+    ///
+    /// * If any of the dependencies do not exist.
+    /// * If any of the build targets we depend on are not visible to `path`.
+    fn lookup_dependencies(
+        &self,
+        path: &BuildTargetPath,
+        target: &BuildTarget,
+    ) -> Result<(Vec<PendingSourceDependency>, Vec<BuildTargetId>), anyhow::Error> {
+        let source_deps = target
+            .source_deps
+            .iter()
+            .map(|dep| {
+                let dep = match dep {
+                    SourceDependency::File(path) => self
+                        .lookup_file_path(path)
+                        .and_then(|path| self.file_locations.get_leaf(path).copied())
+                        .map(PendingSourceDependency::File)
+                        .ok_or_else(|| anyhow::anyhow!("depends on non-existent file {path:?}"))?,
+                    SourceDependency::Glob(pattern) => {
+                        let globset = compile_glob(pattern)?;
+                        let (dir, _) = glob_key(pattern);
+                        if normalize_path(&dir).is_none() {
+                            anyhow::bail!("invalid glob: {pattern:?}");
+                        }
+                        PendingSourceDependency::Glob {
+                            pattern: pattern.clone(),
+                            globset,
+                        }
+                    }
+                    SourceDependency::Rule(rule) => self
+                        .lookup_aliased_build_target(rule)
+                        .map(PendingSourceDependency::Rule)
+                        .ok_or_else(|| anyhow::anyhow!("depends on non-existent target {rule}"))?,
+                };
+                Ok::<_, anyhow::Error>(dep)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let build_deps = target
+            .build_deps
+            .iter()
+            .map(|path| {
+                let dep = self
+                    .lookup_aliased_build_target(path)
+                    .ok_or_else(|| anyhow::anyhow!("depends on non-existent target {path}"))?;
+                Ok::<_, anyhow::Error>(dep)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Make sure we're allowed to depend on these targets.
+        let label = Label::try_from(path)?;
+        let rule_deps = source_deps.iter().filter_map(|dep| match dep {
+            PendingSourceDependency::Rule(rule_id) => Some(rule_id),
+            PendingSourceDependency::File(_) | PendingSourceDependency::Glob { .. } => None,
+        });
+        for dep in build_deps.iter().chain(rule_deps) {
+            let node = self
+                .build_targets
+                .get(dep)
+                .expect("build target should exist");
+            let dep = self.build_target_label(*dep)?;
+            if !node.visibility.allows(&dep, &label) {
+                anyhow::bail!("{dep} is not visible to {label}");
+            }
+        }
+
+        Ok((source_deps, build_deps))
+    }
+
+    /// Returns the [`Label`] for the build target `id`.
+    fn build_target_label(&self, id: BuildTargetId) -> Result<Label, anyhow::Error> {
+        let node = self
+            .build_targets
+            .get(&id)
+            .expect("build target should exist");
+        let path = self.resolve_build_path(&node.path);
+        Ok(Label::try_from(&path)?)
+    }
+
+    /// Record that the build target `id` depends on the provided dependencies, returning the
+    /// resolved source dependencies.
+    fn attach_dependencies(
+        &mut self,
+        id: BuildTargetId,
+        source_deps: Vec<PendingSourceDependency>,
+        build_deps: &[BuildTargetId],
+    ) -> Vec<SourceDependencyId> {
+        let source_deps: Vec<_> = source_deps
+            .into_iter()
+            .map(|dep| match dep {
+                PendingSourceDependency::File(file_id) => SourceDependencyId::File(file_id),
+                PendingSourceDependency::Glob { pattern, globset } => {
+                    SourceDependencyId::Glob(self.glob_for(pattern, globset))
+                }
+                PendingSourceDependency::Rule(rule_id) => {
+                    SourceDependencyId::Dynamic(self.dynamic_sources_for(rule_id))
+                }
+            })
+            .collect();
+
+        // Update our source dependencies so we know what build rules depend on them.
+        for source_dep in &source_deps {
+            let build_dependents = self.source_dependents_mut(*source_dep);
+            build_dependents.push(id);
+        }
+        for build_dep in build_deps {
+            let node = Arc::make_mut(&mut self.build_targets)
+                .get_mut(build_dep)
+                .expect("build target should exist");
+            node.build_dependents.push(id);
+        }
+
+        source_deps
+    }
+
+    /// Remove the build target `id` from the dependents of everything it depends on.
+    fn detach_dependencies(&mut self, id: BuildTargetId) {
+        let node = self
+            .build_targets
+            .get(&id)
+            .expect("build target should exist");
+        let source_deps = node.source_deps.clone();
+        let build_deps = node.build_deps.clone();
+
+        for source_dep in source_deps {
+            let build_dependents = self.source_dependents_mut(source_dep);
+            build_dependents.retain(|dependent| *dependent != id);
+            let unused = build_dependents.is_empty();
+
+            // Nothing depends on this glob anymore.
+            if let SourceDependencyId::Glob(glob_id) = source_dep
+                && unused
+            {
+                let glob = Arc::make_mut(&mut self.globs)
+                    .remove(&glob_id)
+                    .expect("glob should exist");
+                self.glob_locations
+                    .remove(glob.path)
+                    .expect("glob location should exist");
+            }
+
+            // Nothing depends on these outputs anymore.
+            if let SourceDependencyId::Dynamic(dynamic_id) = source_dep
+                && unused
+            {
+                let dynamic = Arc::make_mut(&mut self.dynamic_sources)
+                    .remove(&dynamic_id)
+                    .expect("dynamic sources should exist");
+                let producer = Arc::make_mut(&mut self.build_targets)
+                    .get_mut(&dynamic.build_target)
+                    .expect("build target should exist");
+                producer.dynamic_sources = None;
+            }
+        }
+        for build_dep in build_deps {
+            let node = Arc::make_mut(&mut self.build_targets)
+                .get_mut(&build_dep)
+                .expect("build target should exist");
+            node.build_dependents.retain(|dependent| *dependent != id);
+        }
+    }
+
+    /// Returns the dependents of the provided source dependency.
+    fn source_dependents_mut(
+        &mut self,
+        source_dep: SourceDependencyId,
+    ) -> &mut SmallVec<[BuildTargetId; 2]> {
+        match source_dep {
+            SourceDependencyId::File(file_id) => {
+                let file = Arc::make_mut(&mut self.files)
+                    .get_mut(&file_id)
+                    .expect("file should exist");
+                &mut file.build_dependents
+            }
+            SourceDependencyId::Glob(glob_id) => {
+                let glob = Arc::make_mut(&mut self.globs)
+                    .get_mut(&glob_id)
+                    .expect("glob should exist");
+                &mut glob.build_dependents
+            }
+            SourceDependencyId::Dynamic(dynamic_id) => {
+                let dynamic = Arc::make_mut(&mut self.dynamic_sources)
+                    .get_mut(&dynamic_id)
+                    .expect("dynamic sources should exist");
+                &mut dynamic.build_dependents
+            }
+        }
+    }
+
+    /// Returns all of the build targets that directly depend on the build target `id`, either
+    /// as a build dependency or through its outputs.
+    fn dependents_of(&self, id: BuildTargetId) -> BTreeSet<BuildTargetId> {
+        let node = self
+            .build_targets
+            .get(&id)
+            .expect("build target should exist");
+        let dynamic = node
+            .dynamic_sources
+            .and_then(|dynamic_id| self.dynamic_sources.get(&dynamic_id))
+            .map(|dynamic| &dynamic.build_dependents[..])
+            .unwrap_or_default();
+        node.build_dependents
+            .iter()
+            .chain(dynamic)
+            .copied()
+            .collect()
+    }
+
+    /// Returns a chain of dependencies from the build target `from` to `to`, including both
+    /// ends, if `from` depends on `to`.
+    fn dependency_path(
+        &self,
+        from: BuildTargetId,
+        to: BuildTargetId,
+    ) -> Option<Vec<BuildTargetId>> {
+        // Map of target to the target we first reached it from.
+        let mut visited = BTreeMap::from([(from, from)]);
+        let mut stack = vec![from];
+
+        while let Some(id) = stack.pop() {
+            if id == to {
+                let mut path = vec![id];
+                let mut current = id;
+                while current != from {
+                    current = visited[&current];
+                    path.push(current);
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            for dep in self.dependencies_of(id) {
+                if let btree_map::Entry::Vacant(entry) = visited.entry(dep) {
+                    entry.insert(id);
+                    stack.push(dep);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns all of the build targets that the build target `id` directly depends on, either
+    /// as a build dependency or through their outputs. May contain duplicates.
+    fn dependencies_of(&self, id: BuildTargetId) -> impl Iterator<Item = BuildTargetId> + '_ {
+        let node = self
+            .build_targets
+            .get(&id)
+            .expect("build target should exist");
+        let producers = node.source_deps.iter().filter_map(|dep| match dep {
+            SourceDependencyId::Dynamic(dynamic_id) => {
+                let dynamic = self
+                    .dynamic_sources
+                    .get(dynamic_id)
+                    .expect("dynamic sources should exist");
+                Some(dynamic.build_target)
+            }
+            SourceDependencyId::File(_) | SourceDependencyId::Glob(_) => None,
+        });
+        node.build_deps.iter().copied().chain(producers)
+    }
+
+    /// Returns a human readable name for the build target `id`, for use in error messages.
+    fn display_build_target(&self, id: BuildTargetId) -> String {
+        let node = self
+            .build_targets
+            .get(&id)
+            .expect("build target should exist");
+        self.resolve_build_path(&node.path).to_string()
+    }
+
+    /// Re-intern all of the strings that are still in use into a new interner, dropping the rest.
+    fn compact_strings(&mut self) {
+        let old = std::mem::take(&mut self.strings);
+        let strings = Arc::make_mut(&mut self.strings);
+        let mut remap = |path: &InternedPath| {
+            let components = path
+                .components()
+                .iter()
+                .map(|component| strings.get_or_intern(old.resolve(component)));
+            InternedPath(components.collect())
+        };
+
+        self.file_locations = remap_trie(&self.file_locations, &mut remap);
+        self.glob_locations = remap_trie(&self.glob_locations, &mut remap);
+        self.build_target_locations = remap_trie(&self.build_target_locations, &mut remap);
+        let mut alias_locations = TrieMap::new();
+        for (components, actual) in self.alias_locations.iter() {
+            let alias = remap(&InternedPath(components.into_iter().collect()));
+            alias_locations
+                .insert_leaf(alias, remap(actual))
+                .expect("paths from a valid trie");
+        }
+        self.alias_locations = alias_locations;
+        for file in Arc::make_mut(&mut self.files).values_mut() {
+            file.path = remap(&file.path);
+        }
+        for glob in Arc::make_mut(&mut self.globs).values_mut() {
+            glob.path = remap(&glob.path);
+        }
+        for target in Arc::make_mut(&mut self.build_targets).values_mut() {
+            target.path = remap(&target.path);
+        }
+        for target in Arc::make_mut(&mut self.build_targets).values_mut() {
+            target.rule = strings.get_or_intern(old.resolve(&target.rule));
+            for tag in &mut target.tags {
+                *tag = strings.get_or_intern(old.resolve(tag));
+            }
+        }
+    }
+
+    /// Intern a single string, only copying a shared interner if the string is new.
+    fn intern(&mut self, s: impl AsRef<str>) -> lasso::Spur {
+        match self.strings.get(&s) {
+            Some(key) => key,
+            None => Arc::make_mut(&mut self.strings).get_or_intern(s),
+        }
+    }
+
+    /// Intern the tags for a build target, dropping any duplicates.
+    fn intern_tags(&mut self, tags: &[CompactString]) -> SmallVec<[lasso::Spur; 2]> {
+        let mut interned = SmallVec::new();
+        for tag in tags {
+            let tag = self.intern(tag);
+            if !interned.contains(&tag) {
+                interned.push(tag);
+            }
+        }
+        interned
+    }
+
+    /// Get the [`GlobId`] for `pattern`, creating it if necessary.
+    ///
+    /// The pattern must have already been validated, see [`BuildTree::lookup_dependencies`].
+    fn glob_for(&mut self, pattern: CompactString, globset: globset::GlobSet) -> GlobId {
+        let (dir, name) = glob_key(&pattern);
+        let mut path = self.intern_file_path(dir).expect("validated glob");
+        path.push(self.intern(name));
+        if let Some(id) = self.glob_locations.get_leaf(path.clone()) {
+            return *id;
+        }
+
+        let id = GlobId(self.id_gen.next());
+        let glob = GlobNode {
+            pattern,
+            globset,
+            path: path.clone(),
+            build_dependents: SmallVec::new(),
+        };
+        let prev = Arc::make_mut(&mut self.globs).insert(id, glob);
+        assert_none!(prev);
+        self.glob_locations
+            .insert_leaf(path, id)
+            .expect("glob keys never conflict with directories");
+
+        id
+    }
+
+    /// Returns all of the globs that match the file at `path`.
+    ///
+    /// A glob can only match files under the literal directory its pattern starts with, so we
+    /// only need to check the globs registered in one of the parent directories of `path`.
+    fn matching_globs(&self, path: &Path) -> BTreeSet<GlobId> {
+        let mut matches = BTreeSet::new();
+        let Some(components) = normalize_path(path) else {
+            return matches;
+        };
+        let Some((_, dirs)) = components.split_last() else {
+            return matches;
+        };
+        let path: PathBuf = components.iter().collect();
+
+        let mut dirs = dirs.iter();
+        let mut prefix = InternedPath::new();
+        while let Some(TrieNode::Edge { children, .. }) = self.glob_locations.get(prefix.clone()) {
+            let globs = children.values().filter_map(|child| match child {
+                TrieNode::Leaf { data } => Some(data),
+                TrieNode::Edge { .. } => None,
+            });
+            for id in globs {
+                let glob = self.globs.get(id).expect("glob should exist");
+                if glob.globset.is_match(&path) {
+                    matches.insert(*id);
+                }
+            }
+
+            // If a directory was never interned, no globs can be registered under it.
+            let next = dirs
+                .next()
+                .and_then(|dir| self.strings.get(encode_component(dir)));
+            match next {
+                Some(component) => prefix.push(component),
+                None => break,
+            }
+        }
+
+        matches
+    }
+
+    /// Get the [`DynamicSourcesId`] for the outputs of `build_target`, creating it if necessary.
+    fn dynamic_sources_for(&mut self, build_target: BuildTargetId) -> DynamicSourcesId {
+        let node = self
+            .build_targets
+            .get(&build_target)
+            .expect("build target should exist");
+        if let Some(id) = node.dynamic_sources {
+            return id;
+        }
+
+        let id = DynamicSourcesId(self.id_gen.next());
+        let dynamic = DynamicSourcesNode {
+            files: None,
+            build_target,
+            build_dependents: SmallVec::new(),
+        };
+        let prev = Arc::make_mut(&mut self.dynamic_sources).insert(id, dynamic);
+        assert_none!(prev);
+
+        let node = Arc::make_mut(&mut self.build_targets)
+            .get_mut(&build_target)
+            .expect("build target should exist");
+        node.dynamic_sources = Some(id);
+
+        id
+    }
+
+    fn gen_file_id(&mut self) -> FileId {
+        FileId(self.id_gen.next())
+    }
+
+    fn gen_build_target_id(&mut self) -> BuildTargetId {
+        BuildTargetId(self.id_gen.next())
+    }
+}
+
+/// Differences between two [`BuildTree`]s, returned from [`BuildTree::diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildTreeDiff {
+    /// Files that only exist in the new tree.
+    pub added_files: Vec<PathBuf>,
+    /// Files that only exist in the old tree.
+    pub removed_files: Vec<PathBuf>,
+    /// Files whose metadata changed.
+    pub changed_files: Vec<PathBuf>,
+    /// Build targets that only exist in the new tree.
+    pub added_targets: Vec<BuildTargetPath>,
+    /// Build targets that only exist in the old tree.
+    pub removed_targets: Vec<BuildTargetPath>,
+    /// Build targets whose rule or dependencies changed.
+    pub changed_targets: Vec<BuildTargetPath>,
+}
+
+impl BuildTreeDiff {
+    /// Returns if the two trees were the same.
+    pub fn is_empty(&self) -> bool {
+        self.added_files.is_empty()
+            && self.removed_files.is_empty()
+            && self.changed_files.is_empty()
+            && self.added_targets.is_empty()
+            && self.removed_targets.is_empty()
+            && self.changed_targets.is_empty()
+    }
+}
+
+/// Compare two maps, recording the keys that were added, removed, or whose values changed.
+fn diff_maps<K: Ord + Clone, V: PartialEq>(
+    old: &BTreeMap<K, V>,
+    new: &BTreeMap<K, V>,
+    added: &mut Vec<K>,
+    removed: &mut Vec<K>,
+    changed: &mut Vec<K>,
+) {
+    for (key, value) in old {
+        match new.get(key) {
+            None => removed.push(key.clone()),
+            Some(new_value) if new_value != value => changed.push(key.clone()),
+            Some(_) => (),
+        }
+    }
+    added.extend(new.keys().filter(|key| !old.contains_key(key)).cloned());
+}
+
+/// Rebuild a [`TrieMap`] with every path mapped through `remap`.
+fn remap_trie<L: Copy>(
+    trie: &TrieMap<InternedPath, (), L>,
+    mut remap: impl FnMut(&InternedPath) -> InternedPath,
+) -> TrieMap<InternedPath, (), L> {
+    let mut remapped = TrieMap::new();
+    for (components, data) in trie.iter() {
+        let path = remap(&InternedPath(components.into_iter().collect()));
+        remapped
+            .insert_leaf(path, *data)
+            .expect("paths from a valid trie");
+    }
+    remapped
+}
+
+/// Normalize a relative path by resolving `.` and `..` components, so equivalent paths are
+/// interned the same way.
+///
+/// Returns `None` if the path is absolute or a `..` would escape the root of the workspace.
+fn normalize_path(path: &Path) -> Option<SmallVec<[&OsStr; 8]>> {
+    let mut components = SmallVec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(component) => components.push(component),
+            Component::CurDir => (),
+            Component::ParentDir => {
+                components.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(components)
+}
+
+/// Prefix for interned path components that are not valid UTF-8.
+///
+/// File names can never contain a NUL byte, so this can't collide with a real component.
+const NON_UTF8_PREFIX: char = '\0';
+
+/// Encode a path component as a string so it can be interned.
+///
+/// Components that are valid UTF-8 are used as-is, anything else is hex encoded with a
+/// [`NON_UTF8_PREFIX`].
+fn encode_component(component: &OsStr) -> Cow<'_, str> {
+    match component.to_str() {
+        Some(s) => Cow::Borrowed(s),
+        None => {
+            let mut encoded = String::from(NON_UTF8_PREFIX);
+            for byte in component.as_encoded_bytes() {
+                write!(encoded, "{byte:02x}").expect("infallible");
+            }
+            Cow::Owned(encoded)
+        }
+    }
+}
+
+/// Decode a path component that was encoded with [`encode_component`].
+fn decode_component(s: &str) -> Cow<'_, OsStr> {
+    let Some(hex) = s.strip_prefix(NON_UTF8_PREFIX) else {
+        return Cow::Borrowed(OsStr::new(s));
+    };
+    let bytes: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).expect("encoded component"))
+        .collect();
+    // SAFETY: The bytes were produced by `OsStr::as_encoded_bytes` in `encode_component`, within
+    // this same process.
+    let component = unsafe { OsStr::from_encoded_bytes_unchecked(&bytes) };
+    Cow::Owned(component.to_os_string())
+}
+
+/// Compile a glob pattern, where wildcards never match a `/`.
+fn compile_glob(pattern: &str) -> Result<globset::GlobSet, anyhow::Error> {
+    let glob = globset::GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()?;
+    Ok(globset::GlobSet::builder().add(glob).build()?)
+}
+
+/// Split a glob pattern into the literal directory it starts with, and the name it's stored
+/// under within that directory.
+///
+/// The name is the entire pattern prefixed with a `/`, which can't appear in a file name, so it
+/// never conflicts with a directory registered by another glob.
+fn glob_key(pattern: &str) -> (PathBuf, String) {
+    let mut components: Vec<_> = pattern.split('/').collect();
+    // The last component always matches files, not directories.
+    components.pop();
+
+    let dir = components
+        .into_iter()
+        .take_while(|component| !component.contains(['*', '?', '[', ']', '{', '}', '\\']))
+        .collect();
+    (dir, format!("/{pattern}"))
+}
+
+/// Escape a string so it can be used within a quoted DOT label.
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// A single build target within the tree.
+///
+/// Externally we interface with [`BuildTarget`]s, but in a [`BuildTree`] we store this type.
+#[derive(Clone, Debug)]
+struct BuildTargetNode {
+    /// Name of the build target.
+    name: CompactString,
+    /// Name of the rule associated with this target.
+    rule: lasso::Spur,
+
+    /// Other dependencies in our build graph that this target depends on.
+    build_deps: Vec<BuildTargetId>,
+    /// Source files that this build target directly depends on.
+    source_deps: Vec<SourceDependencyId>,
+    /// Other targets in our build graph that depend on this target.
+    build_dependents: SmallVec<[BuildTargetId; 2]>,
+    /// Outputs of this target that other targets depend on as sources.
+    dynamic_sources: Option<DynamicSourcesId>,
+    /// Which other targets are allowed to depend on this one.
+    visibility: Visibility,
+    /// Tags attached to this target.
+    tags: SmallVec<[lasso::Spur; 2]>,
+
+    /// The path this node is located at.
+    path: InternedPath,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum SourceDependencyId {
+    File(FileId),
     Glob(GlobId),
+    Dynamic(DynamicSourcesId),
+}
+
+/// A [`SourceDependencyId`] that has been looked up but not yet added to the tree.
+enum PendingSourceDependency {
+    File(FileId),
+    Glob {
+        pattern: CompactString,
+        globset: globset::GlobSet,
+    },
     Rule(BuildTargetId),
 }
 
-/// A single file within the tree.
-#[derive(Clone, Debug)]
-struct FileNode {
-    /// Metadata for this file.
-    metadata: FileMetadataXx64,
-    /// The [`BuildTarget`]s that depend on this file.
-    build_dependents: SmallVec<[BuildTargetId; 2]>,
-}
+/// A single file within the tree.
+#[derive(Clone, Debug)]
+struct FileNode {
+    /// Metadata for this file.
+    metadata: FileMetadataXx64,
+    /// The path this file is located at.
+    path: InternedPath,
+    /// The [`BuildTarget`]s that depend on this file.
+    build_dependents: SmallVec<[BuildTargetId; 2]>,
+}
+
+/// ID for a file in our graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileId(u64);
+
+impl From<u64> for FileId {
+    fn from(value: u64) -> Self {
+        FileId(value)
+    }
+}
+
+/// A glob in the tree that needs to be tracked.
+#[derive(Clone, Debug)]
+struct GlobNode {
+    /// The pattern for this glob.
+    pattern: CompactString,
+    /// Compiled glob, stored as a performance optimization.
+    globset: globset::GlobSet,
+    /// The path this glob is located at, see [`glob_key`].
+    path: InternedPath,
+    /// The [`BuildTarget`]s that depend on this glob.
+    build_dependents: SmallVec<[BuildTargetId; 2]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GlobId(u64);
+
+impl From<u64> for GlobId {
+    fn from(value: u64) -> Self {
+        GlobId(value)
+    }
+}
+
+/// Outputs from a build rule which power the _sources_ of another.
+#[derive(Clone, Debug)]
+struct DynamicSourcesNode {
+    /// The files we resolved the last time the build target was run.
+    files: Option<Box<[FileId]>>,
+    /// Build target responsible for determining these sources.
+    build_target: BuildTargetId,
+    /// The [`BuildTarget`]s that depend on these sources.
+    build_dependents: SmallVec<[BuildTargetId; 2]>,
+}
+
+/// ID for a [`DynamicSourcesNode`] in our graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DynamicSourcesId(u64);
+
+impl From<u64> for DynamicSourcesId {
+    fn from(value: u64) -> Self {
+        DynamicSourcesId(value)
+    }
+}
+
+/// ID for any node in our graph that can be marked dirty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NodeId {
+    File(FileId),
+    BuildTarget(BuildTargetId),
+}
+
+impl From<FileId> for NodeId {
+    fn from(value: FileId) -> Self {
+        NodeId::File(value)
+    }
+}
+
+impl From<BuildTargetId> for NodeId {
+    fn from(value: BuildTargetId) -> Self {
+        NodeId::BuildTarget(value)
+    }
+}
+
+/// ID for a [`BuildTarget`] in our graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BuildTargetId(u64);
+
+impl From<u64> for BuildTargetId {
+    fn from(value: u64) -> Self {
+        BuildTargetId(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smoketest_add_files() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        build_tree
+            .insert_file(
+                "library_a/srcs/lib.rs",
+                FileMetadataXx64::test_rand(&mut rng),
+            )
+            .unwrap();
+        build_tree
+            .insert_file(
+                "library_b/srcs/lib.rs",
+                FileMetadataXx64::test_rand(&mut rng),
+            )
+            .unwrap();
+
+        // let lib_a_srcs_glob = BuildTarget {
+        //     rule: "std.glob".into(),
+        //     build_deps: Vec::default(),
+        //     // file_deps:
+        //     visibility: Visibility::Public,
+        // };
+
+        // build_tree.insert_build_target(path, target)
+
+        let files: Vec<_> = build_tree.iter_files().map(|(path, _)| path).collect();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("library_a/srcs/lib.rs"),
+                PathBuf::from("library_b/srcs/lib.rs"),
+            ]
+        );
+
+        let files: Vec<_> = build_tree
+            .iter_files_under("library_b/srcs")
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(files, vec![PathBuf::from("library_b/srcs/lib.rs")]);
+        assert_eq!(build_tree.iter_files_under("library_c").count(), 0);
+        assert!(build_tree.contains_files_under("library_b"));
+        assert!(!build_tree.contains_files_under("library_c"));
+
+        let glob = TrieGlob::new(["library_a/**/*.rs"]).unwrap();
+        let files: Vec<_> = build_tree
+            .match_files(&glob)
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(files, vec![PathBuf::from("library_a/srcs/lib.rs")]);
+
+        println!("{}", build_tree.pretty_file_tree());
+    }
+
+    #[test]
+    fn smoketest_dynamic_sources() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        let codegen: BuildTargetPath = "//library_a:codegen".parse::<Label>().unwrap().into();
+        let lib: BuildTargetPath = "//library_a:lib".parse::<Label>().unwrap().into();
+
+        build_tree
+            .insert_build_target(
+                &codegen,
+                BuildTarget {
+                    rule: "std.genrule".into(),
+                    build_deps: Vec::new(),
+                    source_deps: Vec::new(),
+                    visibility: Visibility::Public,
+                    tags: Vec::new(),
+                },
+            )
+            .unwrap();
+        assert!(build_tree.dynamic_sources_id(&codegen).is_none());
+
+        build_tree
+            .insert_build_target(
+                &lib,
+                BuildTarget {
+                    rule: "std.rust_library".into(),
+                    build_deps: Vec::new(),
+                    source_deps: vec![SourceDependency::Rule(codegen.clone())],
+                    visibility: Visibility::Public,
+                    tags: Vec::new(),
+                },
+            )
+            .unwrap();
+        let id = build_tree.dynamic_sources_id(&codegen).unwrap();
+        assert!(build_tree.dynamic_sources_id(&lib).is_none());
+        assert!(build_tree.get_dynamic_sources(id).is_none());
+
+        // Generated files need to exist in the tree before they can be recorded.
+        assert!(
+            build_tree
+                .record_dynamic_sources(id, ["library_a/gen/out.rs"])
+                .is_err()
+        );
+        build_tree
+            .insert_file(
+                "library_a/gen/out.rs",
+                FileMetadataXx64::test_rand(&mut rng),
+            )
+            .unwrap();
+        let dependents: Vec<_> = build_tree
+            .record_dynamic_sources(id, ["library_a/gen/out.rs"])
+            .unwrap()
+            .collect();
+        assert_eq!(dependents.len(), 1);
+        assert_eq!(build_tree.get_dynamic_sources(id).map(|f| f.len()), Some(1));
+    }
+
+    #[test]
+    fn smoketest_remove_file() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        build_tree
+            .insert_file("library_a/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        build_tree
+            .insert_file("library_a/util.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+
+        let lib: BuildTargetPath = "//library_a:lib".parse::<Label>().unwrap().into();
+        build_tree
+            .insert_build_target(
+                &lib,
+                BuildTarget {
+                    rule: "std.rust_library".into(),
+                    build_deps: Vec::new(),
+                    source_deps: vec![
+                        SourceDependency::File("library_a/lib.rs".into()),
+                        SourceDependency::File("library_a/util.rs".into()),
+                    ],
+                    visibility: Visibility::Public,
+                    tags: Vec::new(),
+                },
+            )
+            .unwrap();
+
+        let dependents = build_tree.remove_file("library_a/util.rs").unwrap();
+        assert_eq!(dependents.len(), 1);
+        assert!(build_tree.get_file(&"library_a/util.rs".into()).is_none());
+        assert!(build_tree.get_file(&"library_a/lib.rs".into()).is_some());
+        assert!(build_tree.remove_file("library_a/util.rs").is_err());
+
+        // Files that nothing depends on don't invalidate anything.
+        build_tree
+            .insert_file("library_a/util.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        let dependents = build_tree.remove_file("library_a/util.rs").unwrap();
+        assert!(dependents.is_empty());
+    }
+
+    #[test]
+    fn smoketest_glob_dependents() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        let target = |globs: &[&str]| BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps: Vec::new(),
+            source_deps: globs
+                .iter()
+                .map(|glob| SourceDependency::Glob((*glob).into()))
+                .collect(),
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        let lib_a: BuildTargetPath = "//library_a:lib".parse().unwrap();
+        let lib_b: BuildTargetPath = "//library_b:lib".parse().unwrap();
+        build_tree
+            .insert_build_target(&lib_a, target(&["library_a/src/**/*.rs"]))
+            .unwrap();
+        build_tree
+            .insert_build_target(&lib_b, target(&["library_b/*.rs", "library_a/src/**/*.rs"]))
+            .unwrap();
+        let id_a = build_tree.lookup_build_target(&lib_a).unwrap();
+        let id_b = build_tree.lookup_build_target(&lib_b).unwrap();
+
+        // Targets that depend on the same pattern share a single glob.
+        assert_eq!(build_tree.globs.len(), 2);
+        let node = build_tree.get_build_target(&lib_b).unwrap();
+        assert_eq!(
+            node.source_deps,
+            target(&["library_b/*.rs", "library_a/src/**/*.rs"]).source_deps
+        );
+
+        let added = build_tree.notify_file_added("library_a/src/nested/mod.rs");
+        assert_eq!(added, BTreeSet::from([id_a, id_b]));
+        let added = build_tree.notify_file_added("library_b/lib.rs");
+        assert_eq!(added, BTreeSet::from([id_b]));
+        // Wildcards don't match across directories.
+        assert!(
+            build_tree
+                .notify_file_added("library_b/nested/lib.rs")
+                .is_empty()
+        );
+        assert!(build_tree.notify_file_added("library_a/lib.rs").is_empty());
+        assert!(
+            build_tree
+                .notify_file_removed("library_c/lib.rs")
+                .is_empty()
+        );
+
+        // Removing a matching file invalidates the glob's dependents.
+        build_tree
+            .insert_file("library_b/util.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        let removed = build_tree.remove_file("library_b/util.rs").unwrap();
+        assert_eq!(removed, BTreeSet::from([id_b]));
+
+        // Globs are dropped once nothing depends on them.
+        build_tree.remove_build_target(&lib_b).unwrap();
+        assert_eq!(build_tree.globs.len(), 1);
+        assert!(
+            build_tree
+                .notify_file_removed("library_b/lib.rs")
+                .is_empty()
+        );
+        let removed = build_tree.notify_file_removed("library_a/src/lib.rs");
+        assert_eq!(removed, BTreeSet::from([id_a]));
+
+        assert!(
+            build_tree
+                .insert_build_target(&lib_b, target(&["library_b/[.rs"]))
+                .is_err()
+        );
+        assert!(
+            build_tree
+                .insert_build_target(&lib_b, target(&["../*.rs"]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn smoketest_remove_build_target() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        build_tree
+            .insert_file("library_a/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        build_tree
+            .insert_file("library_a/util.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+
+        let lib_a: BuildTargetPath = "//library_a:lib".parse::<Label>().unwrap().into();
+        let lib_b: BuildTargetPath = "//library_b:lib".parse::<Label>().unwrap().into();
+        let lib_a_target = |source: &str| BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps: Vec::new(),
+            source_deps: vec![SourceDependency::File(source.into())],
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        build_tree
+            .insert_build_target(&lib_a, lib_a_target("library_a/lib.rs"))
+            .unwrap();
+        build_tree
+            .insert_build_target(
+                &lib_b,
+                BuildTarget {
+                    rule: "std.rust_library".into(),
+                    build_deps: vec![lib_a.clone()],
+                    source_deps: vec![SourceDependency::Rule(lib_a.clone())],
+                    visibility: Visibility::Public,
+                    tags: Vec::new(),
+                },
+            )
+            .unwrap();
+
+        // Replacing a target updates which files it depends on.
+        let dependents = build_tree
+            .replace_build_target(&lib_a, lib_a_target("library_a/util.rs"))
+            .unwrap();
+        assert_eq!(dependents.len(), 1);
+        assert!(
+            build_tree
+                .remove_file("library_a/lib.rs")
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            build_tree.remove_file("library_a/util.rs").unwrap().len(),
+            1
+        );
+
+        // Removing a target drops the edges that pointed at it.
+        let dependents = build_tree.remove_build_target(&lib_a).unwrap();
+        assert_eq!(dependents.len(), 1);
+        assert!(build_tree.dynamic_sources_id(&lib_a).is_none());
+        assert!(build_tree.remove_build_target(&lib_a).is_err());
+        let lib_b_id = build_tree.lookup_build_target(&lib_b).unwrap();
+        let lib_b_node = &build_tree.build_targets[&lib_b_id];
+        assert!(lib_b_node.build_deps.is_empty());
+        assert!(lib_b_node.source_deps.is_empty());
+
+        // Removing the last dependent of a target's outputs drops them.
+        build_tree
+            .insert_file("library_a/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        build_tree
+            .insert_build_target(&lib_a, lib_a_target("library_a/lib.rs"))
+            .unwrap();
+        build_tree
+            .replace_build_target(
+                &lib_b,
+                BuildTarget {
+                    rule: "std.rust_library".into(),
+                    build_deps: Vec::new(),
+                    source_deps: vec![SourceDependency::Rule(lib_a.clone())],
+                    visibility: Visibility::Public,
+                    tags: Vec::new(),
+                },
+            )
+            .unwrap();
+        assert!(build_tree.dynamic_sources_id(&lib_a).is_some());
+        assert!(build_tree.remove_build_target(&lib_b).unwrap().is_empty());
+        assert!(build_tree.dynamic_sources_id(&lib_a).is_none());
+    }
+
+    #[test]
+    fn test_insert_existing() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        build_tree
+            .insert_file("library_a/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+
+        // Files can't be nested underneath another file.
+        let result = build_tree.insert_file(
+            "library_a/lib.rs/nested",
+            FileMetadataXx64::test_rand(&mut rng),
+        );
+        assert!(result.is_err());
+        assert_eq!(build_tree.files.len(), 1);
+        assert_eq!(build_tree.iter_files().count(), 1);
+
+        let lib_a: BuildTargetPath = "//library_a:lib".parse::<Label>().unwrap().into();
+        let nested: BuildTargetPath = "//library_a/lib:nested".parse::<Label>().unwrap().into();
+        let target = BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps: Vec::new(),
+            source_deps: vec![SourceDependency::File("library_a/lib.rs".into())],
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        build_tree
+            .insert_build_target(&lib_a, target.clone())
+            .unwrap();
+        let lib_a_id = build_tree.lookup_build_target(&lib_a).unwrap();
+
+        // Neither failed insert leaves behind a node or any dependency edges.
+        assert!(
+            build_tree
+                .insert_build_target(&lib_a, target.clone())
+                .is_err()
+        );
+        assert!(build_tree.insert_build_target(&nested, target).is_err());
+        assert_eq!(build_tree.build_targets.len(), 1);
+        assert_eq!(build_tree.lookup_build_target(&lib_a), Some(lib_a_id));
+        assert_eq!(build_tree.remove_file("library_a/lib.rs").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_dependency_cycle() {
+        let mut build_tree = BuildTree::new();
+
+        let target = |build_deps: &[&BuildTargetPath]| BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps: build_deps.iter().map(|dep| (*dep).clone()).collect(),
+            source_deps: Vec::new(),
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        let lib_a: BuildTargetPath = "//library_a:lib".parse::<Label>().unwrap().into();
+        let lib_b: BuildTargetPath = "//library_b:lib".parse::<Label>().unwrap().into();
+        let lib_c: BuildTargetPath = "//library_c:lib".parse::<Label>().unwrap().into();
+
+        build_tree.insert_build_target(&lib_a, target(&[])).unwrap();
+        build_tree
+            .insert_build_target(&lib_b, target(&[&lib_a]))
+            .unwrap();
+        build_tree
+            .insert_build_target(&lib_c, target(&[&lib_b]))
+            .unwrap();
+
+        let err = build_tree
+            .replace_build_target(&lib_a, target(&[&lib_c]))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "dependency cycle: //library_a:lib -> //library_c:lib -> //library_b:lib -> //library_a:lib"
+        );
+        let err = build_tree
+            .replace_build_target(&lib_a, target(&[&lib_a]))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "dependency cycle: //library_a:lib -> //library_a:lib"
+        );
+
+        // Cycles through the outputs of a target are also detected.
+        let err = build_tree
+            .replace_build_target(
+                &lib_a,
+                BuildTarget {
+                    rule: "std.rust_library".into(),
+                    build_deps: Vec::new(),
+                    source_deps: vec![SourceDependency::Rule(lib_c.clone())],
+                    visibility: Visibility::Public,
+                    tags: Vec::new(),
+                },
+            )
+            .unwrap_err();
+        assert!(err.to_string().starts_with("dependency cycle"));
+
+        // Failing to replace a target leaves the tree unchanged.
+        build_tree
+            .replace_build_target(&lib_c, target(&[&lib_a]))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_visibility() {
+        let mut build_tree = BuildTree::new();
+
+        let target = |build_deps: &[&BuildTargetPath], visibility: Visibility| BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps: build_deps.iter().map(|dep| (*dep).clone()).collect(),
+            source_deps: Vec::new(),
+            visibility,
+            tags: Vec::new(),
+        };
+        let private: BuildTargetPath = "//library_a:private".parse::<Label>().unwrap().into();
+        let sibling: BuildTargetPath = "//library_a:lib".parse::<Label>().unwrap().into();
+        let other: BuildTargetPath = "//library_b:lib".parse::<Label>().unwrap().into();
+
+        build_tree
+            .insert_build_target(&private, target(&[], Visibility::Private))
+            .unwrap();
+
+        // Targets in the same package can always depend on a private target.
+        build_tree
+            .insert_build_target(&sibling, target(&[&private], Visibility::Public))
+            .unwrap();
+        let err = build_tree
+            .insert_build_target(&other, target(&[&private], Visibility::Public))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "//library_a:private is not visible to //library_b:lib"
+        );
+        assert!(build_tree.lookup_build_target(&other).is_none());
+
+        // Explicitly allowing the other package makes it visible.
+        let allow = Visibility::Allow(vec!["//library_b/...".parse().unwrap()]);
+        build_tree
+            .replace_build_target(&private, target(&[], allow))
+            .unwrap();
+        build_tree
+            .insert_build_target(&other, target(&[&private], Visibility::Public))
+            .unwrap();
+
+        // Narrowing the visibility can't break existing dependents.
+        let err = build_tree
+            .replace_build_target(&private, target(&[], Visibility::Private))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "//library_a:private would no longer be visible to //library_b:lib"
+        );
+        let node = build_tree.get_build_target(&private).unwrap();
+        assert!(matches!(node.visibility, Visibility::Allow(_)));
+    }
 
-/// ID for a file in our graph.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct FileId(u64);
+    #[test]
+    fn test_aliases() {
+        let mut build_tree = BuildTree::new();
 
-impl From<u64> for FileId {
-    fn from(value: u64) -> Self {
-        FileId(value)
+        let target = |build_deps: &[&BuildTargetPath]| BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps: build_deps.iter().map(|dep| (*dep).clone()).collect(),
+            source_deps: Vec::new(),
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        let old: BuildTargetPath = "//library_a:lib".parse::<Label>().unwrap().into();
+        let moved: BuildTargetPath = "//library_b:lib".parse::<Label>().unwrap().into();
+        let renamed: BuildTargetPath = "//library_b:core".parse::<Label>().unwrap().into();
+        let binary: BuildTargetPath = "//app:bin".parse::<Label>().unwrap().into();
+
+        // Aliases can be chained, and the target doesn't need to exist yet.
+        build_tree.insert_alias(&old, &moved).unwrap();
+        build_tree.insert_alias(&moved, &renamed).unwrap();
+        assert_eq!(build_tree.resolve_alias(&old), Some(renamed.clone()));
+        assert_eq!(build_tree.resolve_alias(&renamed), None);
+        assert!(
+            build_tree
+                .insert_build_target(&binary, target(&[&old]))
+                .is_err()
+        );
+
+        build_tree
+            .insert_build_target(&renamed, target(&[]))
+            .unwrap();
+        build_tree
+            .insert_build_target(&binary, target(&[&old]))
+            .unwrap();
+        let node = build_tree.get_build_target(&binary).unwrap();
+        assert_eq!(node.build_deps, vec![renamed.clone()]);
+
+        // Aliases and build targets can't share a path, or form a cycle.
+        assert!(build_tree.insert_alias(&renamed, &old).is_err());
+        assert!(build_tree.insert_alias(&binary, &old).is_err());
+        assert!(build_tree.insert_build_target(&moved, target(&[])).is_err());
+
+        // Aliases survive compacting the interner.
+        build_tree.gc();
+        assert_eq!(build_tree.resolve_alias(&old), Some(renamed.clone()));
+
+        build_tree.remove_alias(&moved).unwrap();
+        assert_eq!(build_tree.resolve_alias(&old), Some(moved.clone()));
+        assert!(build_tree.remove_alias(&moved).is_err());
     }
-}
 
-/// A glob in the tree that needs to be tracked.
-#[derive(Clone, Debug)]
-struct GlobNode {
-    /// The pattern for this glob.
-    pattern: CompactString,
-    /// Compiled glob, stored as a performance optimization.
-    globset: globset::GlobSet,
-    /// The [`BuildTarget`]s that depend on this glob.
-    build_dependents: SmallVec<[BuildTargetId; 2]>,
-}
+    #[test]
+    fn test_topo_order() {
+        let mut build_tree = BuildTree::new();
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct GlobId(u64);
+        let target = |build_deps: &[&BuildTargetPath]| BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps: build_deps.iter().map(|dep| (*dep).clone()).collect(),
+            source_deps: Vec::new(),
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        let paths: Vec<BuildTargetPath> = ["//a:lib", "//b:lib", "//c:lib", "//d:lib", "//e:lib"]
+            .into_iter()
+            .map(|label| label.parse::<Label>().unwrap().into())
+            .collect();
+        let [a, b, c, d, e] = &paths[..] else {
+            unreachable!()
+        };
 
-impl From<u64> for GlobId {
-    fn from(value: u64) -> Self {
-        GlobId(value)
+        // d -> {c, b}, c -> a, b -> a, e is unrelated.
+        build_tree.insert_build_target(a, target(&[])).unwrap();
+        build_tree.insert_build_target(b, target(&[a])).unwrap();
+        build_tree.insert_build_target(c, target(&[a])).unwrap();
+        build_tree.insert_build_target(d, target(&[c, b])).unwrap();
+        build_tree.insert_build_target(e, target(&[])).unwrap();
+
+        let ids: Vec<_> = paths
+            .iter()
+            .map(|path| build_tree.lookup_build_target(path).unwrap())
+            .collect();
+        let order = build_tree.topo_order([ids[3]]).unwrap();
+        assert_eq!(order, vec![ids[0], ids[1], ids[2], ids[3]]);
+
+        let order = build_tree.topo_order([ids[4], ids[2]]).unwrap();
+        assert_eq!(order, vec![ids[0], ids[2], ids[4]]);
+
+        assert!(build_tree.topo_order([BuildTargetId(1000)]).is_err());
+
+        // Sneak in a cycle a -> c -> a, which d depends on.
+        let build_targets = Arc::make_mut(&mut build_tree.build_targets);
+        let a_node = build_targets.get_mut(&ids[0]).unwrap();
+        a_node.build_deps.push(ids[2]);
+        let err = build_tree.topo_order([ids[3]]).unwrap_err().to_string();
+        assert_eq!(err, "dependency cycle: //a:lib -> //c:lib -> //a:lib");
     }
-}
 
-/// Outputs from a build rule which power the _sources_ of another.
-#[derive(Clone, Debug)]
-struct DynamicSourcesNode {
-    /// The files we resolved the last time the build target was run.
-    files: Option<Box<[FileId]>>,
-    /// Build target responsible for determining these sources.
-    build_target: BuildTargetId,
-}
+    #[test]
+    fn test_rdeps() {
+        let mut build_tree = BuildTree::new();
 
-/// ID for a [`DynamicSourcesNode`] in our graph.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct DynamicSourcesId(u64);
+        let target = |build_deps: &[&BuildTargetPath]| BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps: build_deps.iter().map(|dep| (*dep).clone()).collect(),
+            source_deps: Vec::new(),
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        let paths: Vec<BuildTargetPath> = ["//a:lib", "//b:lib", "//c:lib", "//d:lib"]
+            .into_iter()
+            .map(|label| label.parse::<Label>().unwrap().into())
+            .collect();
+        let [a, b, c, d] = &paths[..] else {
+            unreachable!()
+        };
 
-impl From<u64> for DynamicSourcesId {
-    fn from(value: u64) -> Self {
-        DynamicSourcesId(value)
+        // c -> b -> a, d is unrelated.
+        build_tree.insert_build_target(a, target(&[])).unwrap();
+        build_tree.insert_build_target(b, target(&[a])).unwrap();
+        build_tree.insert_build_target(c, target(&[b])).unwrap();
+        build_tree.insert_build_target(d, target(&[])).unwrap();
+
+        let ids: Vec<_> = paths
+            .iter()
+            .map(|path| build_tree.lookup_build_target(path).unwrap())
+            .collect();
+        let rdeps = build_tree.rdeps([ids[0]], None).unwrap();
+        assert_eq!(rdeps, BTreeSet::from([ids[0], ids[1], ids[2]]));
+        let rdeps = build_tree.rdeps([ids[0]], Some(1)).unwrap();
+        assert_eq!(rdeps, BTreeSet::from([ids[0], ids[1]]));
+        let rdeps = build_tree.rdeps([ids[2], ids[3]], None).unwrap();
+        assert_eq!(rdeps, BTreeSet::from([ids[2], ids[3]]));
+
+        assert!(build_tree.rdeps([BuildTargetId(1000)], None).is_err());
     }
-}
 
-/// ID for a [`BuildTarget`] in our graph.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct BuildTargetId(u64);
+    #[test]
+    fn test_query_targets() {
+        let mut build_tree = BuildTree::new();
 
-impl From<u64> for BuildTargetId {
-    fn from(value: u64) -> Self {
-        BuildTargetId(value)
+        let targets = [
+            ("//a:lib", "std.rust_library"),
+            ("//a:test", "std.rust_test"),
+            ("//a/b:lib", "std.rust_library"),
+            ("//c:lib", "std.rust_library"),
+        ];
+        for (label, rule) in targets {
+            let path: BuildTargetPath = label.parse::<Label>().unwrap().into();
+            let target = BuildTarget {
+                rule: rule.into(),
+                build_deps: Vec::new(),
+                source_deps: Vec::new(),
+                visibility: Visibility::Public,
+                tags: Vec::new(),
+            };
+            build_tree.insert_build_target(&path, target).unwrap();
+        }
+
+        let query = |pattern: &str, rule: Option<&str>| {
+            let pattern: TargetPattern = pattern.parse().unwrap();
+            let mut labels: Vec<_> = build_tree
+                .query_targets(&pattern, rule)
+                .unwrap()
+                .into_iter()
+                .map(|id| build_tree.display_build_target(id))
+                .collect();
+            labels.sort();
+            labels
+        };
+        assert_eq!(query("//a:lib", None), ["//a:lib"]);
+        assert_eq!(query("//a:all", None), ["//a:lib", "//a:test"]);
+        assert_eq!(query("//a/...", None), ["//a/b:lib", "//a:lib", "//a:test"]);
+        assert_eq!(query("//...", None).len(), 4);
+        assert_eq!(
+            query("//a/...", Some("std.rust_library")),
+            ["//a/b:lib", "//a:lib"]
+        );
+        assert!(query("//d/...", None).is_empty());
+        assert!(query("//...", Some("std.genrule")).is_empty());
+
+        let pattern: TargetPattern = "//a:missing".parse().unwrap();
+        assert!(build_tree.query_targets(&pattern, None).is_err());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_query_targets_with_tag() {
+        let mut build_tree = BuildTree::new();
+
+        let target = |tags: &[&str]| BuildTarget {
+            rule: "std.rust_test".into(),
+            build_deps: Vec::new(),
+            source_deps: Vec::new(),
+            visibility: Visibility::Public,
+            tags: tags.iter().map(|tag| (*tag).into()).collect(),
+        };
+        let targets = [
+            ("//a:test", target(&["manual", "flaky", "manual"])),
+            ("//b:test", target(&["flaky"])),
+            ("//c:test", target(&[])),
+        ];
+        for (label, target) in targets {
+            let path: BuildTargetPath = label.parse().unwrap();
+            build_tree.insert_build_target(&path, target).unwrap();
+        }
+
+        let query = |build_tree: &BuildTree, tag: &str| -> Vec<_> {
+            build_tree
+                .query_targets_with_tag(tag)
+                .into_iter()
+                .map(|id| build_tree.display_build_target(id))
+                .collect()
+        };
+        assert_eq!(query(&build_tree, "flaky"), ["//a:test", "//b:test"]);
+        assert_eq!(query(&build_tree, "manual"), ["//a:test"]);
+        assert!(query(&build_tree, "no-cache").is_empty());
+
+        // Duplicate tags are dropped.
+        let path: BuildTargetPath = "//a:test".parse().unwrap();
+        let node = build_tree.get_build_target(&path).unwrap();
+        assert_eq!(node.tags, ["manual", "flaky"]);
+
+        // Tags are updated when a target is replaced, and survive compacting the interner.
+        build_tree
+            .replace_build_target(&path, target(&["no-cache"]))
+            .unwrap();
+        build_tree.gc();
+        assert_eq!(query(&build_tree, "no-cache"), ["//a:test"]);
+        assert_eq!(query(&build_tree, "flaky"), ["//b:test"]);
+        assert!(query(&build_tree, "manual").is_empty());
+    }
 
     #[test]
-    fn smoketest_add_files() {
+    fn smoketest_update_files() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        let paths = ["library_a/lib.rs", "library_a/util.rs", "library_b/lib.rs"];
+        for path in paths {
+            build_tree
+                .insert_file(path, FileMetadataXx64::test_rand(&mut rng))
+                .unwrap();
+        }
+        for (label, source) in [("//library_a:lib", paths[0]), ("//library_b:lib", paths[2])] {
+            let path: BuildTargetPath = label.parse::<Label>().unwrap().into();
+            let target = BuildTarget {
+                rule: "std.rust_library".into(),
+                build_deps: Vec::new(),
+                source_deps: vec![
+                    SourceDependency::File(source.into()),
+                    SourceDependency::File(paths[1].into()),
+                ],
+                visibility: Visibility::Public,
+                tags: Vec::new(),
+            };
+            build_tree.insert_build_target(&path, target).unwrap();
+        }
+
+        let metadata = FileMetadataXx64::test_rand(&mut rng);
+        let dependents = build_tree
+            .update_files(paths.map(|path| (path, metadata.clone())))
+            .unwrap();
+        assert_eq!(dependents.len(), 2);
+        for path in paths {
+            assert_eq!(build_tree.get_file(&path.into()), Some(&metadata));
+        }
+
+        // Nothing is updated if any of the files don't exist.
+        let missing = FileMetadataXx64::test_rand(&mut rng);
+        let result = build_tree.update_files([
+            (paths[0], missing.clone()),
+            ("library_a/missing.rs", missing.clone()),
+        ]);
+        assert!(result.is_err());
+        assert_eq!(build_tree.get_file(&paths[0].into()), Some(&metadata));
+    }
+
+    #[test]
+    fn smoketest_dirty_tracking() {
         let mut build_tree = BuildTree::new();
         let mut rng = rand::rng();
 
         build_tree
-            .insert_file(
-                "library_a/srcs/lib.rs",
-                FileMetadataXx64::test_rand(&mut rng),
+            .insert_file("a/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        let paths: Vec<BuildTargetPath> = ["//a:lib", "//b:lib", "//c:lib"]
+            .into_iter()
+            .map(|label| label.parse::<Label>().unwrap().into())
+            .collect();
+        let [a, b, c] = &paths[..] else {
+            unreachable!()
+        };
+
+        // b -> a -> a/lib.rs, c is unrelated.
+        let target = |build_deps: Vec<BuildTargetPath>, source_deps| BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps,
+            source_deps,
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        let source = vec![SourceDependency::File("a/lib.rs".into())];
+        build_tree
+            .insert_build_target(a, target(Vec::new(), source))
+            .unwrap();
+        build_tree
+            .insert_build_target(b, target(vec![a.clone()], Vec::new()))
+            .unwrap();
+        build_tree
+            .insert_build_target(c, target(Vec::new(), Vec::new()))
+            .unwrap();
+        let ids: Vec<_> = paths
+            .iter()
+            .map(|path| build_tree.lookup_build_target(path).unwrap())
+            .collect();
+
+        let file_id = build_tree.file_id("a/lib.rs").unwrap();
+        build_tree.mark_dirty(file_id).unwrap();
+        assert!(build_tree.is_dirty(ids[0]));
+        assert!(build_tree.is_dirty(ids[1]));
+        assert!(!build_tree.is_dirty(ids[2]));
+        assert_eq!(
+            build_tree.take_dirty_targets(),
+            BTreeSet::from([ids[0], ids[1]])
+        );
+        assert!(build_tree.take_dirty_targets().is_empty());
+
+        build_tree.mark_dirty(ids[1]).unwrap();
+        build_tree.mark_dirty(ids[2]).unwrap();
+        build_tree.remove_build_target(c).unwrap();
+        assert_eq!(build_tree.take_dirty_targets(), BTreeSet::from([ids[1]]));
+
+        assert!(build_tree.mark_dirty(ids[2]).is_err());
+    }
+
+    #[test]
+    fn smoketest_to_dot() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        build_tree
+            .insert_file("a/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        let a: BuildTargetPath = "//a:lib".parse::<Label>().unwrap().into();
+        let b: BuildTargetPath = "//b:lib".parse::<Label>().unwrap().into();
+        build_tree
+            .insert_build_target(
+                &a,
+                BuildTarget {
+                    rule: "std.rust_library".into(),
+                    build_deps: Vec::new(),
+                    source_deps: vec![SourceDependency::File("a/lib.rs".into())],
+                    visibility: Visibility::Public,
+                    tags: Vec::new(),
+                },
             )
             .unwrap();
         build_tree
-            .insert_file(
-                "library_b/srcs/lib.rs",
-                FileMetadataXx64::test_rand(&mut rng),
+            .insert_build_target(
+                &b,
+                BuildTarget {
+                    rule: "std.rust_library".into(),
+                    build_deps: vec![a.clone()],
+                    source_deps: Vec::new(),
+                    visibility: Visibility::Public,
+                    tags: Vec::new(),
+                },
             )
             .unwrap();
 
-        // let lib_a_srcs_glob = BuildTarget {
-        //     rule: "std.glob".into(),
-        //     build_deps: Vec::default(),
-        //     // file_deps:
-        // };
+        let dot = build_tree.to_dot(None).unwrap();
+        assert!(dot.starts_with("digraph build_tree {"));
+        assert!(dot.contains("label=\"//a:lib\\n(std.rust_library)\", shape=box"));
+        assert!(dot.contains("label=\"a/lib.rs\", shape=note"));
+        assert_eq!(dot.matches(" -> ").count(), 2);
 
-        // build_tree.insert_build_target(path, target)
+        // Filtering only includes the direct dependencies of the matching targets.
+        let filter: TargetPattern = "//b:all".parse().unwrap();
+        let dot = build_tree.to_dot(Some(&filter)).unwrap();
+        assert!(dot.contains("label=\"//a:lib\", shape=box"));
+        assert!(!dot.contains("a/lib.rs"));
+        assert_eq!(dot.matches(" -> ").count(), 1);
 
-        println!("{}", build_tree.pretty_file_tree());
+        // A filter naming a missing target is an error, not an empty graph.
+        let filter: TargetPattern = "//b:missing".parse().unwrap();
+        assert!(build_tree.to_dot(Some(&filter)).is_err());
+    }
+
+    #[test]
+    fn smoketest_diff() {
+        let mut rng = rand::rng();
+        let lib_rs = FileMetadataXx64::test_rand(&mut rng);
+        let target = |source: &str| BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps: Vec::new(),
+            source_deps: vec![SourceDependency::File(source.into())],
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        let a: BuildTargetPath = "//a:lib".parse::<Label>().unwrap().into();
+        let b: BuildTargetPath = "//b:lib".parse::<Label>().unwrap().into();
+
+        let mut old = BuildTree::new();
+        old.insert_file("a/lib.rs", lib_rs.clone()).unwrap();
+        old.insert_file("a/util.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        old.insert_file("b/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        old.insert_build_target(&a, target("a/lib.rs")).unwrap();
+        old.insert_build_target(&b, target("b/lib.rs")).unwrap();
+        assert!(old.diff(&old).is_empty());
+
+        // Insert in a different order so IDs don't line up.
+        let mut new = BuildTree::new();
+        new.insert_file("c/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        new.insert_file("a/util.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        new.insert_file("a/lib.rs", lib_rs).unwrap();
+        new.insert_build_target(&a, target("a/util.rs")).unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added_files, [PathBuf::from("c/lib.rs")]);
+        assert_eq!(diff.removed_files, [PathBuf::from("b/lib.rs")]);
+        assert_eq!(diff.changed_files, [PathBuf::from("a/util.rs")]);
+        assert!(diff.added_targets.is_empty());
+        assert_eq!(diff.removed_targets, [b]);
+        assert_eq!(diff.changed_targets, [a]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn smoketest_non_utf8_paths() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        let path = Path::new(OsStr::from_bytes(b"library_a/fo\x80o.rs"));
+        let metadata = FileMetadataXx64::test_rand(&mut rng);
+        build_tree.insert_file(path, metadata.clone()).unwrap();
+        build_tree
+            .insert_file("library_a/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+
+        assert_eq!(build_tree.get_file(&path.to_path_buf()), Some(&metadata));
+        let paths: Vec<_> = build_tree.iter_files().map(|(path, _)| path).collect();
+        assert!(paths.iter().any(|p| p == path));
+        assert_eq!(paths.len(), 2);
+        build_tree.remove_file(path).unwrap();
+        assert!(build_tree.get_file(&path.to_path_buf()).is_none());
+    }
+
+    #[test]
+    fn test_path_normalization() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        let metadata = FileMetadataXx64::test_rand(&mut rng);
+        build_tree
+            .insert_file("./a/../a//lib.rs", metadata.clone())
+            .unwrap();
+        assert_eq!(build_tree.get_file(&"a/lib.rs".into()), Some(&metadata));
+        assert_eq!(
+            build_tree.get_file(&"a/./b/../lib.rs".into()),
+            Some(&metadata)
+        );
+        build_tree
+            .insert_file("a/lib.rs", metadata.clone())
+            .unwrap();
+        assert_eq!(build_tree.iter_files().count(), 1);
+
+        assert!(
+            build_tree
+                .insert_file("../lib.rs", metadata.clone())
+                .is_err()
+        );
+        assert!(
+            build_tree
+                .insert_file("/a/lib.rs", metadata.clone())
+                .is_err()
+        );
+        assert!(build_tree.get_file(&"../a/lib.rs".into()).is_none());
+    }
+
+    #[test]
+    fn smoketest_get_build_target() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        build_tree
+            .insert_file("a/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        let a: BuildTargetPath = "//a:lib".parse::<Label>().unwrap().into();
+        let b: BuildTargetPath = "//b:lib".parse::<Label>().unwrap().into();
+        let target_a = BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps: Vec::new(),
+            source_deps: vec![SourceDependency::File("a/lib.rs".into())],
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        let target_b = BuildTarget {
+            rule: "std.rust_binary".into(),
+            build_deps: vec![a.clone()],
+            source_deps: vec![SourceDependency::Rule(a.clone())],
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        build_tree
+            .insert_build_target(&a, target_a.clone())
+            .unwrap();
+        build_tree
+            .insert_build_target(&b, target_b.clone())
+            .unwrap();
+
+        assert_eq!(build_tree.get_build_target(&a), Some(target_a));
+        assert_eq!(build_tree.get_build_target(&b), Some(target_b.clone()));
+        let missing: BuildTargetPath = "//c:lib".parse::<Label>().unwrap().into();
+        assert_eq!(build_tree.get_build_target(&missing), None);
+
+        let id = build_tree.lookup_build_target(&b).unwrap();
+        assert_eq!(build_tree.target_info(id), Some((b, target_b)));
+
+        // Source files are reported at their new location after being moved.
+        build_tree.rename_path("a", "c").unwrap();
+        let target = build_tree.get_build_target(&a).unwrap();
+        assert_eq!(
+            target.source_deps,
+            [SourceDependency::File("c/lib.rs".into())]
+        );
+    }
+
+    #[test]
+    fn smoketest_gc() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        build_tree
+            .insert_file("a/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        build_tree
+            .insert_file("a/old.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        let a: BuildTargetPath = "//a:lib".parse::<Label>().unwrap().into();
+        let b: BuildTargetPath = "//b:lib".parse::<Label>().unwrap().into();
+        let target = |build_deps: Vec<BuildTargetPath>, source: &str| BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps,
+            source_deps: vec![SourceDependency::File(source.into())],
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        build_tree
+            .insert_build_target(&a, target(Vec::new(), "a/lib.rs"))
+            .unwrap();
+        build_tree
+            .insert_build_target(&b, target(vec![a.clone()], "a/lib.rs"))
+            .unwrap();
+        build_tree.remove_file("a/old.rs").unwrap();
+        assert!(build_tree.gc().is_empty());
+
+        // Overwrite a file, orphaning the original.
+        let metadata = FileMetadataXx64::test_rand(&mut rng);
+        build_tree
+            .insert_file("a/lib.rs", metadata.clone())
+            .unwrap();
+        build_tree
+            .insert_file("a/new.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        build_tree
+            .replace_build_target(&a, target(Vec::new(), "a/new.rs"))
+            .unwrap();
+        assert_eq!(build_tree.files.len(), 3);
+        assert_eq!(build_tree.build_targets.len(), 2);
+
+        let b_id = build_tree.lookup_build_target(&b).unwrap();
+        assert_eq!(build_tree.gc(), BTreeSet::from([b_id]));
+        assert_eq!(build_tree.files.len(), 2);
+        assert_eq!(build_tree.build_targets.len(), 2);
+
+        // Everything is still reachable after compacting the interner.
+        assert!(build_tree.strings.get("old.rs").is_none());
+        assert_eq!(build_tree.get_file(&"a/lib.rs".into()), Some(&metadata));
+        assert_eq!(
+            build_tree.get_build_target(&a),
+            Some(target(Vec::new(), "a/new.rs"))
+        );
+        let b_target = build_tree.get_build_target(&b).unwrap();
+        assert_eq!(b_target.build_deps, [a]);
+        assert!(b_target.source_deps.is_empty());
+    }
+
+    #[test]
+    fn smoketest_from_files() {
+        let mut rng = rand::rng();
+        let mut strings = lasso::Rodeo::new();
+        let mut files: TrieMap<InternedPath, (), u64> = TrieMap::new();
+        for (idx, path) in ["a/lib.rs", "a/util.rs", "b/lib.rs"]
+            .into_iter()
+            .enumerate()
+        {
+            let components = path.split('/').map(|c| strings.get_or_intern(c));
+            files
+                .insert_leaf(InternedPath(components.collect()), idx as u64)
+                .unwrap();
+        }
+
+        let metadata: Vec<_> = (0..3)
+            .map(|_| FileMetadataXx64::test_rand(&mut rng))
+            .collect();
+        let mut build_tree =
+            BuildTree::from_files(files, strings, |idx| metadata[idx as usize].clone());
+        assert_eq!(build_tree.get_file(&"a/util.rs".into()), Some(&metadata[1]));
+        assert_eq!(build_tree.iter_files().count(), 3);
+
+        // The tree behaves as if the files were inserted individually.
+        build_tree.rename_path("a", "c").unwrap();
+        build_tree
+            .insert_file("a/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+        let lib: BuildTargetPath = "//c:lib".parse::<Label>().unwrap().into();
+        let target = BuildTarget {
+            rule: "std.rust_library".into(),
+            build_deps: Vec::new(),
+            source_deps: vec![SourceDependency::File("c/lib.rs".into())],
+            visibility: Visibility::Public,
+            tags: Vec::new(),
+        };
+        build_tree
+            .insert_build_target(&lib, target.clone())
+            .unwrap();
+        assert_eq!(build_tree.get_build_target(&lib), Some(target));
+    }
+
+    #[test]
+    fn smoketest_rename_path() {
+        let mut build_tree = BuildTree::new();
+        let mut rng = rand::rng();
+
+        let metadata = FileMetadataXx64::test_rand(&mut rng);
+        build_tree
+            .insert_file("library_a/srcs/lib.rs", metadata.clone())
+            .unwrap();
+        build_tree
+            .insert_file("library_b/pb.toml", FileMetadataXx64::test_rand(&mut rng))
+            .unwrap();
+
+        build_tree.rename_path("library_a", "libs/a").unwrap();
+        assert!(
+            build_tree
+                .get_file(&PathBuf::from("library_a/srcs/lib.rs"))
+                .is_none()
+        );
+        assert_eq!(
+            build_tree.get_file(&PathBuf::from("libs/a/srcs/lib.rs")),
+            Some(&metadata)
+        );
+
+        // Can't move onto existing paths, underneath files, or into ourselves.
+        assert!(build_tree.rename_path("libs/a", "library_b").is_err());
+        assert!(
+            build_tree
+                .rename_path("libs/a", "library_b/pb.toml/a")
+                .is_err()
+        );
+        assert!(build_tree.rename_path("libs", "libs/nested").is_err());
+        assert!(build_tree.rename_path("missing", "other").is_err());
+        assert_eq!(build_tree.iter_files().count(), 2);
+
+        // Failed renames don't intern the destination.
+        let interned = build_tree.strings.len();
+        assert!(
+            build_tree
+                .rename_path("libs/a", "library_b/pb.toml/fresh")
+                .is_err()
+        );
+        assert!(build_tree.rename_path("libs", "libs/fresh/nested").is_err());
+        assert_eq!(build_tree.strings.len(), interned);
     }
 }
@@ -0,0 +1,163 @@
+//! A [`BuildTree`] that can be read from many threads while a single writer updates it.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::BuildTree;
+
+/// A [`BuildTree`] that is optimized for concurrent reads.
+///
+/// Readers, e.g. rule evaluation or queries, call [`SharedBuildTree::snapshot`] to get an
+/// immutable, [`Arc`]-shared, view of the tree which they can hold onto for as long as they like
+/// without blocking anyone else. The writer, e.g. the file watcher, calls
+/// [`SharedBuildTree::update`] which applies changes to a copy of the tree and then atomically
+/// publishes it, so readers never observe a partially applied update.
+///
+/// Versions of the tree share everything that an update doesn't modify, e.g. updating files
+/// leaves the build targets shared. Each map that is modified is copied once per update, so
+/// writers should still batch their changes together, e.g. with [`BuildTree::update_files`].
+#[derive(Debug)]
+pub struct SharedBuildTree {
+    /// The most recently published version of the tree.
+    current: RwLock<Arc<BuildTree>>,
+    /// Serializes writers so concurrent updates aren't lost.
+    writer: Mutex<()>,
+}
+
+impl SharedBuildTree {
+    /// Create a new [`SharedBuildTree`] with `tree` as the initial version.
+    pub fn new(tree: BuildTree) -> Self {
+        SharedBuildTree {
+            current: RwLock::new(Arc::new(tree)),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// Returns an immutable snapshot of the current version of the tree.
+    ///
+    /// The snapshot is unaffected by any later calls to [`SharedBuildTree::update`].
+    pub fn snapshot(&self) -> Arc<BuildTree> {
+        let current = self.current.read().expect("poisoned");
+        Arc::clone(&current)
+    }
+
+    /// Apply `f` to a copy of the tree and then publish the result as the current version.
+    ///
+    /// If `f` returns an error the copy is discarded, so a failed update never leaves a
+    /// partially modified tree visible to readers.
+    pub fn update<R>(
+        &self,
+        f: impl FnOnce(&mut BuildTree) -> Result<R, anyhow::Error>,
+    ) -> Result<R, anyhow::Error> {
+        let _writer = self.writer.lock().expect("poisoned");
+
+        let mut next = BuildTree::clone(&self.snapshot());
+        let result = f(&mut next)?;
+
+        let mut current = self.current.write().expect("poisoned");
+        *current = Arc::new(next);
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pb_types::{BuildTarget, BuildTargetPath, FileMetadataXx64, SourceDependency, Visibility};
+
+    use super::*;
+
+    #[test]
+    fn smoketest_snapshot_isolation() {
+        let mut rng = rand::rng();
+        let shared = SharedBuildTree::new(BuildTree::new());
+        shared
+            .update(|tree| tree.insert_file("a/lib.rs", FileMetadataXx64::test_rand(&mut rng)))
+            .unwrap();
+
+        let before = shared.snapshot();
+        let target: BuildTargetPath = "//a:lib".parse().unwrap();
+        shared
+            .update(|tree| {
+                tree.insert_file("a/mod.rs", FileMetadataXx64::test_rand(&mut rng))?;
+                tree.insert_build_target(
+                    &target,
+                    BuildTarget {
+                        rule: "std.rust_library".into(),
+                        build_deps: Vec::new(),
+                        source_deps: vec![SourceDependency::File("a/lib.rs".into())],
+                        visibility: Visibility::Public,
+                        tags: Vec::new(),
+                    },
+                )
+            })
+            .unwrap();
+        let after = shared.snapshot();
+
+        assert_eq!(before.iter_files().count(), 1);
+        assert!(before.get_build_target(&target).is_none());
+        assert_eq!(after.iter_files().count(), 2);
+        assert!(after.get_build_target(&target).is_some());
+
+        // A failed update isn't published.
+        let missing: BuildTargetPath = "//b:lib".parse().unwrap();
+        let result = shared.update(|tree| {
+            tree.insert_file("b/lib.rs", FileMetadataXx64::test_rand(&mut rng))?;
+            tree.remove_build_target(&missing)
+        });
+        assert!(result.is_err());
+        assert_eq!(shared.snapshot().iter_files().count(), 2);
+    }
+
+    #[test]
+    fn smoketest_structural_sharing() {
+        let mut rng = rand::rng();
+        let shared = SharedBuildTree::new(BuildTree::new());
+        shared
+            .update(|tree| tree.insert_file("a/lib.rs", FileMetadataXx64::test_rand(&mut rng)))
+            .unwrap();
+
+        let before = shared.snapshot();
+        shared
+            .update(|tree| {
+                tree.update_file("a/lib.rs", FileMetadataXx64::test_rand(&mut rng))
+                    .map(|_| ())
+            })
+            .unwrap();
+        let after = shared.snapshot();
+
+        // Only the files were copied.
+        assert!(!Arc::ptr_eq(&before.files, &after.files));
+        assert!(Arc::ptr_eq(&before.build_targets, &after.build_targets));
+        assert!(Arc::ptr_eq(&before.strings, &after.strings));
+    }
+
+    #[test]
+    fn smoketest_concurrent_readers() {
+        let shared = SharedBuildTree::new(BuildTree::new());
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut rng = rand::rng();
+                for idx in 0..50 {
+                    let metadata = FileMetadataXx64::test_rand(&mut rng);
+                    shared
+                        .update(|tree| tree.insert_file(format!("src/{idx}.rs"), metadata))
+                        .unwrap();
+                }
+            });
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut last = 0;
+                    for _ in 0..50 {
+                        let count = shared.snapshot().iter_files().count();
+                        // Updates are published in order.
+                        assert!(count >= last);
+                        last = count;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(shared.snapshot().iter_files().count(), 50);
+    }
+}
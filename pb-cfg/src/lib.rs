@@ -2,16 +2,24 @@
 //!
 //! The types in this crate should _not_ be used for configuration of build rules.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
 use std::fmt;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
 use std::sync::{
-    Arc, RwLock,
+    Arc, Mutex, RwLock,
     atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
 };
+use std::time::Duration;
 
-use compact_str::CompactString;
+use compact_str::{CompactString, format_compact};
 use pb_ore::assert_none;
 
+mod watch;
+
+pub use watch::ConfigWatcher;
+
 /// A single configuration setting.
 pub struct Config<V: ConfigDefault> {
     name: &'static str,
@@ -29,19 +37,79 @@ impl<V: ConfigDefault> Config<V> {
         }
     }
 
+    /// Name of this [`Config`] without a namespace, see [`ConfigSet::name`] for the name it's
+    /// registered under in a specific set.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn id(&self) -> ConfigId {
+        ConfigId(std::ptr::from_ref(self).addr())
+    }
+
     /// Read the value of this [`Config`] from the provided [`ConfigSet`].
+    ///
+    /// # Panics
+    /// * If [`Config`] was not previously registered with the original [`ConfigSetBuilder`].
+    /// * If a different [`Config`] with the same name but a different type was registered.
     pub fn read(&self, set: &ConfigSet) -> V::StoredValue {
-        let Some(entry) = set.configs.get(self.name) else {
-            panic!("tried to read unregistered config {}", self.name);
+        match self.try_read(set) {
+            Ok(value) => value,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Read the value of this [`Config`] from the provided [`ConfigSet`].
+    ///
+    /// # Errors
+    ///
+    /// * If [`Config`] was not previously registered with the original [`ConfigSetBuilder`].
+    /// * If a different [`Config`] with the same name but a different type was registered.
+    pub fn try_read(&self, set: &ConfigSet) -> Result<V::StoredValue, anyhow::Error> {
+        let name = set.name(self);
+        let entry = set.entry(name)?;
+        V::from_dyn(&entry.value).ok_or_else(|| type_mismatch(name, &entry.value))
+    }
+
+    /// Call `callback` with the new value of this [`Config`] every time it's updated in the
+    /// provided [`ConfigSet`], or any of its clones.
+    ///
+    /// Callbacks are called synchronously on the thread that made the update, after the new
+    /// value has been stored, so they should be cheap, e.g. sending on a channel.
+    ///
+    /// # Panics
+    /// * If [`Config`] was not previously registered with the original [`ConfigSetBuilder`].
+    pub fn subscribe<F>(&self, set: &ConfigSet, callback: F)
+    where
+        F: Fn(V::StoredValue) + Send + Sync + 'static,
+    {
+        let name = set.name(self);
+        let Some(entry) = set.configs.get(name) else {
+            panic!("tried to subscribe to unregistered config {name}");
         };
-        V::from_dyn(&entry.value)
+        entry.subscribers.push(Arc::new(move |value| {
+            if let Some(value) = V::from_dyn(value) {
+                callback(value);
+            }
+        }));
     }
 }
 
+/// Identifies a [`Config`] by its address, which is stable since they're registered as
+/// `&'static` references.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ConfigId(usize);
+
 /// A thread-safe shareable set of [`Config`]s.
 #[derive(Clone, Debug)]
 pub struct ConfigSet {
     configs: Arc<BTreeMap<CompactString, ConfigSetEntry>>,
+    /// Layers of overrides, in the order they were pushed, see [`ConfigSet::push_layer`].
+    layers: Arc<Mutex<Vec<LayerValues>>>,
+    /// Deprecated names that forward to a config, see [`ConfigSetBuilder::register_alias`].
+    aliases: Arc<BTreeMap<CompactString, CompactString>>,
+    /// Names of the configs registered in a namespace, see [`ConfigSetBuilder::namespace`].
+    qualified_names: Arc<BTreeMap<ConfigId, CompactString>>,
 }
 
 impl ConfigSet {
@@ -50,16 +118,89 @@ impl ConfigSet {
         ConfigSetBuilder::default()
     }
 
+    /// Name `config` is registered under in this set, including its namespace if it has one,
+    /// e.g. `filesystem.max_handles`.
+    pub fn name<'a, V: ConfigDefault>(&'a self, config: &Config<V>) -> &'a str {
+        match self.qualified_names.get(&config.id()) {
+            Some(name) => name.as_str(),
+            None => config.name,
+        }
+    }
+
     /// Update [`Config`] in this [`ConfigSet`] with the specified value.
     ///
     /// # Panics
     /// * If [`Config`] was not previously registered with the original [`ConfigSetBuilder`].
+    /// * If the value is invalid, e.g. not one of the choices for a [`Choice`].
     pub fn update<V: ConfigDefault>(&self, config: &'static Config<V>, value: V) {
+        if let Err(err) = self.try_update_typed(config, value) {
+            panic!("{err}");
+        }
+    }
+
+    /// Update [`Config`] in this [`ConfigSet`] with the specified value.
+    ///
+    /// # Errors
+    ///
+    /// * If [`Config`] was not previously registered with the original [`ConfigSetBuilder`].
+    /// * If a different [`Config`] with the same name but a different type was registered.
+    /// * If the value is invalid, e.g. not one of the choices for a [`Choice`].
+    pub fn try_update_typed<V: ConfigDefault>(
+        &self,
+        config: &Config<V>,
+        value: V,
+    ) -> Result<(), anyhow::Error> {
+        let entry = self.entry(self.name(config))?;
+        entry.update(value.to_dyn(), ConfigSource::Runtime)
+    }
+
+    /// Returns an iterator over every config in this set, in order of their names.
+    pub fn iter(&self) -> impl Iterator<Item = ConfigInfo<'_>> + '_ {
+        self.configs.iter().map(|(name, entry)| ConfigInfo {
+            name,
+            desc: entry.desc,
+            value: entry.value.snapshot(),
+            default: &entry.default,
+            source: entry.source(),
+        })
+    }
+
+    /// Returns every config in this set as a JSON array of objects with a `name`,
+    /// `description`, `value`, `default`, and `source`.
+    pub fn to_json(&self) -> serde_json::Value {
+        self.iter()
+            .map(|info| {
+                serde_json::json!({
+                    "name": info.name,
+                    "description": info.desc,
+                    "value": info.value.to_json(),
+                    "default": info.default.to_json(),
+                    "source": info.source.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the entry for the config named `name`.
+    fn entry(&self, name: &str) -> Result<&ConfigSetEntry, anyhow::Error> {
+        self.lookup(name).map(|(_, entry)| entry)
+    }
+
+    /// Returns the registered name and entry for the config named `name`, following any
+    /// deprecated alias.
+    fn lookup(&self, name: &str) -> Result<(&CompactString, &ConfigSetEntry), anyhow::Error> {
+        if let Some(entry) = self.configs.get_key_value(name) {
+            return Ok(entry);
+        }
+        let Some(target) = self.aliases.get(name) else {
+            anyhow::bail!("config '{name}' is not registered");
+        };
+        tracing::warn!("config '{name}' is deprecated, use '{target}' instead");
         let entry = self
             .configs
-            .get(config.name)
-            .expect("tried to update unregisted config");
-        entry.value.update(value.into_stored().into_dyn());
+            .get_key_value(target)
+            .expect("aliases are checked when building");
+        Ok(entry)
     }
 
     /// Update the [`Config`] in this [`ConfigSet`] with `name` to `value`.
@@ -69,222 +210,1303 @@ impl ConfigSet {
     /// * If no config named `name` exists in this set.
     /// * If the config specified by `name` cannot parse `value`.
     ///
+    /// Optional configs are unset with a `value` of [`UNSET`] or an empty string.
     pub fn try_update(&self, name: &str, value: &str) -> Result<(), anyhow::Error> {
-        let entry = self
-            .configs
-            .get(name)
-            .ok_or_else(|| anyhow::anyhow!("not Config named '{name}' found"))?;
-        entry.value.update_parse(value)?;
-        Ok(())
+        self.try_update_from(name, value, ConfigSource::Runtime)
     }
-}
 
-impl fmt::Display for ConfigSet {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (name, entry) in &*self.configs {
-            writeln!(f, "{} => {}\n\t└─ '{}'", name, entry.value, entry.desc)?;
-        }
-        Ok(())
+    /// Update the [`Config`] in this [`ConfigSet`] with `name` to `value`, recording that the
+    /// value came from `source`.
+    ///
+    /// # Errors
+    ///
+    /// * If no config named `name` exists in this set.
+    /// * If the config specified by `name` cannot parse `value`.
+    pub fn try_update_from(
+        &self,
+        name: &str,
+        value: &str,
+        source: ConfigSource,
+    ) -> Result<(), anyhow::Error> {
+        let entry = self.entry(name)?;
+        entry.update_parse(value, source)
     }
-}
 
-/// Single entry within a [`ConfigSet`].
-#[derive(Clone, Debug)]
-pub struct ConfigSetEntry {
-    value: DynConfigValueShared,
-    desc: &'static str,
-}
-
-/// A builder for a [`ConfigSet`].
-#[derive(Default, Debug)]
-pub struct ConfigSetBuilder {
-    configs: BTreeMap<CompactString, (DynConfigValue, &'static str)>,
+    /// Returns where the current value of the config named `name` came from.
+    ///
+    /// # Errors
+    ///
+    /// * If no config named `name` exists in this set.
+    pub fn source(&self, name: &str) -> Result<ConfigSource, anyhow::Error> {
+        Ok(self.entry(name)?.source())
+    }
 }
 
-impl ConfigSetBuilder {
-    /// Register a [`Config`] into this [`ConfigSetBuilder`] with the default value.
-    pub fn register<V: ConfigDefault>(&mut self, config: &'static Config<V>) -> &mut Self {
-        let value = config.value.into_stored().into_dyn();
-        let prev = self
-            .configs
-            .insert(CompactString::const_new(config.name), (value, config.desc));
-        assert_none!(prev, "config '{}' registered more than once", config.name);
-        self
+impl ConfigSet {
+    /// Apply the entries from the `[pb.config]` table of a TOML document, e.g. the workspace
+    /// spec or a user's `~/.pb/config.toml`.
+    ///
+    /// Each entry is applied with [`ConfigSet::try_update`]. Entries that fail, e.g. because the
+    /// config doesn't exist or the value can't be parsed, are returned and don't prevent the
+    /// remaining entries from being applied. Configs in a namespace can be set with a dotted
+    /// key or a nested table, e.g. `[pb.config.filesystem]`.
+    ///
+    /// # Errors
+    ///
+    /// * If `contents` is not valid TOML.
+    /// * If `pb` or `pb.config` is not a table.
+    pub fn load_toml(&self, contents: &str) -> Result<Vec<ConfigEntryError>, anyhow::Error> {
+        self.load_toml_from(contents, None)
     }
 
-    /// Consumes this [`ConfigSetBuilder`] construting a [`ConfigSet`].
-    pub fn build(self) -> ConfigSet {
-        let configs = self
-            .configs
-            .into_iter()
-            .map(|(name, (value, desc))| {
-                let entry = ConfigSetEntry {
-                    value: value.into_shared(),
-                    desc,
-                };
-                (name, entry)
-            })
-            .collect();
-        ConfigSet {
-            configs: Arc::new(configs),
+    fn load_toml_from(
+        &self,
+        contents: &str,
+        path: Option<&Path>,
+    ) -> Result<Vec<ConfigEntryError>, anyhow::Error> {
+        let mut errors = Vec::new();
+        for (name, value) in toml_config_entries(contents)? {
+            let source = ConfigSource::File(path.map(Path::to_path_buf));
+            let result = value.and_then(|value| self.try_update_from(&name, &value, source));
+            if let Err(error) = result {
+                errors.push(ConfigEntryError { name, error });
+            }
         }
+        Ok(errors)
     }
-}
 
-/// Types that can be provided as a default to a [`Config`].
-pub trait ConfigDefault {
-    /// The type that actually gets stored in a [`ConfigSet`].
-    type StoredValue: ConfigValue;
-
-    fn into_stored(&self) -> Self::StoredValue;
-    fn from_dyn(val: &DynConfigValueShared) -> Self::StoredValue;
-}
+    /// Apply the entries from the `[pb.config]` table of the TOML file at `path`, see
+    /// [`ConfigSet::load_toml`].
+    ///
+    /// # Errors
+    ///
+    /// * If the file can't be read.
+    /// * If the file is not valid TOML, or `pb.config` is not a table.
+    pub fn load_toml_file(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<Vec<ConfigEntryError>, anyhow::Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("failed to read {}: {err}", path.display()))?;
+        self.load_toml_from(&contents, Some(path))
+    }
 
-impl ConfigDefault for bool {
-    type StoredValue = bool;
+    /// Apply any environment variables that start with `prefix` to the matching configs, e.g.
+    /// with a prefix of `PB_` the variable `PB_WORKSPACE_FILENAME` updates `workspace_filename`.
+    ///
+    /// Configs in a namespace are matched with an `_` in place of the `.`, e.g.
+    /// `PB_FILESYSTEM_MAX_HANDLES` updates `filesystem.max_handles`.
+    ///
+    /// Values are parsed with [`ConfigSet::try_update`]. Variables that fail, e.g. because no
+    /// config matches or the value can't be parsed, are returned and don't prevent the remaining
+    /// variables from being applied.
+    pub fn apply_env(&self, prefix: &str) -> Vec<ConfigEntryError> {
+        self.apply_vars(prefix, std::env::vars_os())
+    }
 
-    fn into_stored(&self) -> Self::StoredValue {
-        *self
+    /// Apply `--cfg name=value` style overrides from the command line, e.g. the values collected
+    /// by a repeated clap argument using [`parse_cfg_override`] as its value parser.
+    ///
+    /// Overrides are applied in order, so a later override for the same config wins. Overrides
+    /// that fail, e.g. because they're malformed, no config matches, or the value can't be
+    /// parsed, are returned and don't prevent the remaining overrides from being applied.
+    pub fn apply_cli<I, S>(&self, overrides: I) -> Vec<ConfigEntryError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut errors = Vec::new();
+        for arg in overrides {
+            let arg = arg.as_ref();
+            let result = match parse_cfg_override(arg) {
+                Ok((name, value)) => self
+                    .try_update_from(&name, &value, ConfigSource::Cli)
+                    .map_err(|error| ConfigEntryError { name, error }),
+                Err(error) => Err(ConfigEntryError {
+                    name: CompactString::new(arg),
+                    error,
+                }),
+            };
+            if let Err(error) = result {
+                errors.push(error);
+            }
+        }
+        errors
     }
 
-    fn from_dyn<'a>(val: &'a DynConfigValueShared) -> Self::StoredValue {
-        let DynConfigValueShared::Bool(val) = val else {
-            panic!("programming error, found {val:?} for string")
-        };
-        val.load(Ordering::SeqCst)
+    /// Returns help text describing the `--cfg` override for every config in this set, one
+    /// per line, so the flags stay in sync with the registered configs.
+    pub fn cli_help(&self) -> String {
+        let mut help = String::new();
+        for info in self.iter() {
+            help.push_str(&format!(
+                "--cfg {}=<value>\n\t{} (default: {})\n",
+                info.name, info.desc, info.default
+            ));
+        }
+        help
     }
-}
 
-impl ConfigDefault for i64 {
-    type StoredValue = i64;
+    fn apply_vars<I>(&self, prefix: &str, vars: I) -> Vec<ConfigEntryError>
+    where
+        I: IntoIterator<Item = (OsString, OsString)>,
+    {
+        let mut errors = Vec::new();
+        for (key, value) in vars {
+            let Some(name) = key.to_str().and_then(|key| key.strip_prefix(prefix)) else {
+                continue;
+            };
+            let source = ConfigSource::Env(CompactString::new(key.to_str().unwrap_or_default()));
+            let name = self.env_config_name(&name.to_ascii_lowercase());
+            let result = value
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("value is not valid UTF-8"))
+                .and_then(|value| self.try_update_from(&name, value, source));
+            if let Err(error) = result {
+                errors.push(ConfigEntryError { name, error });
+            }
+        }
+        errors
+    }
 
-    fn into_stored(&self) -> Self::StoredValue {
-        *self
+    /// Returns the name of the config or alias an environment variable named `name`, with its
+    /// prefix stripped and lowercased, refers to, e.g. `filesystem_max_handles` refers to
+    /// `filesystem.max_handles`.
+    fn env_config_name(&self, name: &str) -> CompactString {
+        if self.configs.contains_key(name) || self.aliases.contains_key(name) {
+            return CompactString::new(name);
+        }
+        self.configs
+            .keys()
+            .chain(self.aliases.keys())
+            .filter(|registered| registered.contains('.'))
+            .find(|registered| registered.replacen('.', "_", 1) == name)
+            .cloned()
+            .unwrap_or_else(|| CompactString::new(name))
     }
+}
 
-    fn from_dyn(val: &DynConfigValueShared) -> Self::StoredValue {
-        let DynConfigValueShared::I64(val) = val else {
-            panic!("programming error, found {val:?} for string")
-        };
-        val.load(Ordering::SeqCst)
+/// Parse a `name=value` override for a config, e.g. the argument to `--cfg`.
+///
+/// The value may itself contain `=`, only the first one separates the name.
+pub fn parse_cfg_override(arg: &str) -> Result<(CompactString, CompactString), anyhow::Error> {
+    let Some((name, value)) = arg.split_once('=') else {
+        anyhow::bail!("invalid override '{arg}', expected 'name=value'");
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        anyhow::bail!("invalid override '{arg}', missing config name");
     }
+    Ok((CompactString::new(name), CompactString::new(value)))
 }
 
-impl ConfigDefault for u64 {
-    type StoredValue = u64;
+impl ConfigSet {
+    /// Push a layer of overrides onto this set, e.g. from a user or workspace config file.
+    ///
+    /// The effective value of a config comes from the highest precedence [`ConfigLayer`] that
+    /// sets it, or the most recently pushed layer if several of the same kind do, and falls back
+    /// to the registered default. Values are validated before the layer is pushed, entries that
+    /// fail are returned and left out of the layer.
+    ///
+    /// Note: recomputing the effective value replaces any update made since, e.g. with
+    /// [`ConfigSet::update`], and subscribers must not push or pop layers.
+    pub fn push_layer<I, N, V>(
+        &self,
+        layer: ConfigLayer,
+        source: ConfigSource,
+        values: I,
+    ) -> Vec<ConfigEntryError>
+    where
+        I: IntoIterator<Item = (N, V)>,
+        N: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let (values, errors) = self.parse_layer(&source, values);
 
-    fn into_stored(&self) -> Self::StoredValue {
-        *self
+        let mut layers = self.layers.lock().expect("ConfigSet layers lock poisoned");
+        let names: Vec<_> = values.keys().cloned().collect();
+        layers.push(LayerValues {
+            layer,
+            source,
+            values,
+        });
+        self.apply_layers(&layers, names);
+
+        errors
     }
 
-    fn from_dyn(val: &DynConfigValueShared) -> Self::StoredValue {
-        let DynConfigValueShared::U64(val) = val else {
-            panic!("programming error, found {val:?} for string")
+    /// Push a layer of overrides from the `[pb.config]` table of the TOML file at `path`, see
+    /// [`ConfigSet::push_layer`].
+    ///
+    /// # Errors
+    ///
+    /// * If the file can't be read.
+    /// * If the file is not valid TOML, or `pb.config` is not a table.
+    pub fn push_toml_layer(
+        &self,
+        layer: ConfigLayer,
+        path: impl AsRef<Path>,
+    ) -> Result<Vec<ConfigEntryError>, anyhow::Error> {
+        let path = path.as_ref();
+        let mut errors = Vec::new();
+        let values = read_toml_layer(path, &mut errors)?;
+        let source = ConfigSource::File(Some(path.to_path_buf()));
+        errors.extend(self.push_layer(layer, source, values));
+
+        Ok(errors)
+    }
+
+    /// Reload the layer previously pushed from the TOML file at `path`, e.g. after the file was
+    /// edited, or push a new layer if there isn't one, see [`ConfigSet::push_toml_layer`].
+    ///
+    /// The reloaded layer keeps its position, configs removed from the file fall back to the
+    /// remaining layers.
+    ///
+    /// # Errors
+    ///
+    /// * If the file can't be read.
+    /// * If the file is not valid TOML, or `pb.config` is not a table.
+    pub fn reload_toml_layer(
+        &self,
+        layer: ConfigLayer,
+        path: impl AsRef<Path>,
+    ) -> Result<Vec<ConfigEntryError>, anyhow::Error> {
+        let path = path.as_ref();
+        let mut errors = Vec::new();
+        let values = read_toml_layer(path, &mut errors)?;
+        let source = ConfigSource::File(Some(path.to_path_buf()));
+        let (values, parse_errors) = self.parse_layer(&source, values);
+        errors.extend(parse_errors);
+
+        let mut layers = self.layers.lock().expect("ConfigSet layers lock poisoned");
+        let existing = layers
+            .iter_mut()
+            .find(|existing| existing.layer == layer && existing.source == source);
+        let names: BTreeSet<_> = match existing {
+            Some(existing) => {
+                let prev = std::mem::replace(&mut existing.values, values);
+                prev.into_keys()
+                    .chain(existing.values.keys().cloned())
+                    .collect()
+            }
+            None => {
+                let names = values.keys().cloned().collect();
+                layers.push(LayerValues {
+                    layer,
+                    source,
+                    values,
+                });
+                names
+            }
         };
-        val.load(Ordering::SeqCst)
+        self.apply_layers(&layers, names);
+
+        Ok(errors)
     }
-}
 
-impl ConfigDefault for &str {
-    type StoredValue = CompactString;
+    /// Parse the values for a layer, returning the entries that failed.
+    fn parse_layer<I, N, V>(
+        &self,
+        source: &ConfigSource,
+        values: I,
+    ) -> (
+        BTreeMap<CompactString, (DynConfigValue, ConfigSource)>,
+        Vec<ConfigEntryError>,
+    )
+    where
+        I: IntoIterator<Item = (N, V)>,
+        N: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut errors = Vec::new();
+        let mut parsed = BTreeMap::new();
+        for (name, value) in values {
+            let name = CompactString::new(name.as_ref());
+            let result = self.lookup(&name).and_then(|(name, entry)| {
+                let value = entry.value.parse(value.as_ref())?;
+                Ok((name.clone(), value))
+            });
+            match result {
+                Ok((name, value)) => {
+                    parsed.insert(name, (value, source.clone()));
+                }
+                Err(error) => errors.push(ConfigEntryError { name, error }),
+            }
+        }
+        (parsed, errors)
+    }
 
-    fn into_stored(&self) -> Self::StoredValue {
-        CompactString::new(self)
+    /// Pop the most recently pushed layer, e.g. at the end of a `pb` invocation in a
+    /// sub-workspace, restoring the values from the remaining layers.
+    ///
+    /// Returns the kind of layer that was popped, or `None` if there are no layers.
+    pub fn pop_layer(&self) -> Option<ConfigLayer> {
+        let mut layers = self.layers.lock().expect("ConfigSet layers lock poisoned");
+        let popped = layers.pop()?;
+        self.apply_layers(&layers, popped.values.into_keys());
+        Some(popped.layer)
     }
 
-    fn from_dyn<'a>(val: &'a DynConfigValueShared) -> Self::StoredValue {
-        let DynConfigValueShared::String(val) = val else {
-            panic!("programming error, found {val:?} for string")
-        };
-        let read_lock = val
-            .read()
-            .expect("DynConfigValueShared::String lock poisoned");
-        read_lock.clone()
+    /// Returns the layer that supplies the effective value of the config named `name`, or
+    /// `None` if it has its registered default.
+    ///
+    /// # Errors
+    ///
+    /// * If no config named `name` exists in this set.
+    pub fn effective_layer(&self, name: &str) -> Result<Option<ConfigLayer>, anyhow::Error> {
+        let (name, _) = self.lookup(name)?;
+        let layers = self.layers.lock().expect("ConfigSet layers lock poisoned");
+        Ok(top_layer(&layers, name).map(|layer| layer.layer))
     }
-}
 
-impl ConfigDefault for String {
-    type StoredValue = CompactString;
+    /// Reset the config named `name` to its registered default, removing it from every layer.
+    ///
+    /// # Errors
+    ///
+    /// * If no config named `name` exists in this set.
+    pub fn reset(&self, name: &str) -> Result<(), anyhow::Error> {
+        let (name, _) = self.lookup(name)?;
+        let mut layers = self.layers.lock().expect("ConfigSet layers lock poisoned");
+        for layer in layers.iter_mut() {
+            layer.values.remove(name);
+        }
+        self.apply_layers(&layers, [name.clone()]);
+        Ok(())
+    }
 
-    fn into_stored(&self) -> Self::StoredValue {
-        CompactString::from(self)
+    /// Reset every config in this set to its registered default and remove all layers, e.g.
+    /// between invocations served by a long-lived `pb` daemon.
+    pub fn reset_all(&self) {
+        let mut layers = self.layers.lock().expect("ConfigSet layers lock poisoned");
+        layers.clear();
+        self.apply_layers(&layers, self.configs.keys().cloned());
     }
 
-    fn from_dyn<'a>(val: &'a DynConfigValueShared) -> Self::StoredValue {
-        let DynConfigValueShared::String(val) = val else {
-            panic!("programming error, found {val:?} for string")
-        };
-        let read_lock = val
-            .read()
-            .expect("DynConfigValueShared::String lock poisoned");
-        read_lock.clone()
+    /// Recompute and store the effective values of the configs in `names`.
+    fn apply_layers<I>(&self, layers: &[LayerValues], names: I)
+    where
+        I: IntoIterator<Item = CompactString>,
+    {
+        for name in names {
+            let entry = &self.configs[&name];
+            let (value, source) = match top_layer(layers, &name) {
+                Some(layer) => layer.values[&name].clone(),
+                None => ((*entry.default).clone(), ConfigSource::Default),
+            };
+            entry
+                .update(value, source)
+                .expect("validated when the layer was pushed");
+        }
     }
 }
 
-pub trait ConfigValue {
-    fn into_dyn(self) -> DynConfigValue;
+/// Returns the layer with the highest precedence that sets the config named `name`.
+fn top_layer<'a>(layers: &'a [LayerValues], name: &str) -> Option<&'a LayerValues> {
+    layers
+        .iter()
+        .enumerate()
+        .filter(|(_, layer)| layer.values.contains_key(name))
+        .max_by_key(|(idx, layer)| (layer.layer, *idx))
+        .map(|(_, layer)| layer)
 }
 
-impl ConfigValue for bool {
-    fn into_dyn(self) -> DynConfigValue {
-        DynConfigValue::Bool(self)
-    }
+/// Kinds of layers that can be pushed onto a [`ConfigSet`], from lowest to highest precedence.
+///
+/// All layers take precedence over the registered defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConfigLayer {
+    /// A user's config, e.g. `~/.pb/config.toml`.
+    User,
+    /// The config of the current workspace.
+    Workspace,
+    /// Environment variables.
+    Env,
+    /// Command line overrides.
+    Cli,
 }
 
-impl ConfigValue for i64 {
-    fn into_dyn(self) -> DynConfigValue {
-        DynConfigValue::I64(self)
-    }
+/// Values for a single layer pushed onto a [`ConfigSet`].
+#[derive(Debug)]
+struct LayerValues {
+    layer: ConfigLayer,
+    /// Where the layer came from, used to find it again when reloading.
+    source: ConfigSource,
+    values: BTreeMap<CompactString, (DynConfigValue, ConfigSource)>,
 }
 
-impl ConfigValue for u64 {
-    fn into_dyn(self) -> DynConfigValue {
-        DynConfigValue::U64(self)
+/// Read the `[pb.config]` table of the TOML file at `path` as the values for a layer, adding
+/// any entries that couldn't be converted to `errors`.
+fn read_toml_layer(
+    path: &Path,
+    errors: &mut Vec<ConfigEntryError>,
+) -> Result<Vec<(CompactString, String)>, anyhow::Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("failed to read {}: {err}", path.display()))?;
+
+    let mut values = Vec::new();
+    for (name, value) in toml_config_entries(&contents)? {
+        match value {
+            Ok(value) => values.push((name, value)),
+            Err(error) => errors.push(ConfigEntryError { name, error }),
+        }
     }
+    Ok(values)
 }
 
-impl ConfigValue for CompactString {
-    fn into_dyn(self) -> DynConfigValue {
-        DynConfigValue::String(self)
+/// Name of a config and its value from a TOML document, see [`toml_config_entries`].
+type TomlEntry = (CompactString, Result<String, anyhow::Error>);
+
+/// Returns the entries from the `[pb.config]` table of a TOML document, with each value
+/// converted into the string form accepted by [`ConfigSet::try_update`].
+///
+/// Nested tables are flattened into namespaced names, so `filesystem.max_handles = 5` and a
+/// `[pb.config.filesystem]` table with `max_handles = 5` both set `filesystem.max_handles`.
+fn toml_config_entries(contents: &str) -> Result<Vec<TomlEntry>, anyhow::Error> {
+    let document: toml::Table = toml::from_str(contents)?;
+    let Some(pb) = document.get("pb") else {
+        return Ok(Vec::new());
+    };
+    let toml::Value::Table(pb) = pb else {
+        anyhow::bail!("expected 'pb' to be a table");
+    };
+    let Some(configs) = pb.get("config") else {
+        return Ok(Vec::new());
+    };
+    let toml::Value::Table(configs) = configs else {
+        anyhow::bail!("expected 'pb.config' to be a table");
+    };
+    let mut entries = Vec::new();
+    flatten_toml_table(None, configs, &mut entries);
+    Ok(entries)
+}
+
+/// Add the entries of `table` to `entries`, with the names of nested tables joined by `.`.
+fn flatten_toml_table(prefix: Option<&str>, table: &toml::Table, entries: &mut Vec<TomlEntry>) {
+    for (name, value) in table {
+        let name = match prefix {
+            Some(prefix) => format_compact!("{prefix}.{name}"),
+            None => CompactString::new(name),
+        };
+        match value {
+            toml::Value::Table(table) => flatten_toml_table(Some(&name), table, entries),
+            value => entries.push((name, toml_to_string(value))),
+        }
     }
 }
 
-/// "Type erased" configuration values.
-///
-/// We prefer an enum as opposed to something like `Box<dyn Value>` because enums offer better
-/// performance and are easier to reason about.
+/// Convert a TOML value into the string form accepted by [`ConfigSet::try_update`].
+fn toml_to_string(value: &toml::Value) -> Result<String, anyhow::Error> {
+    let value = match value {
+        toml::Value::String(val) => val.clone(),
+        toml::Value::Integer(val) => val.to_string(),
+        toml::Value::Float(val) => val.to_string(),
+        toml::Value::Boolean(val) => val.to_string(),
+        toml::Value::Array(vals) => {
+            let vals = vals
+                .iter()
+                .map(|val| match val {
+                    toml::Value::String(val) => Ok(val.as_str()),
+                    other => Err(anyhow::anyhow!(
+                        "expected a list of strings, found {}",
+                        other.type_str()
+                    )),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            vals.join(",")
+        }
+        other => anyhow::bail!("unsupported value type {}", other.type_str()),
+    };
+    Ok(value)
+}
+
+/// An entry from a config file that could not be applied to a [`ConfigSet`].
 #[derive(Debug)]
-pub enum DynConfigValue {
-    Bool(bool),
-    I64(i64),
-    U64(u64),
-    String(CompactString),
+pub struct ConfigEntryError {
+    /// Name of the config.
+    pub name: CompactString,
+    /// Why the entry could not be applied.
+    pub error: anyhow::Error,
 }
 
-impl DynConfigValue {
-    pub fn into_shared(self) -> DynConfigValueShared {
-        match self {
-            DynConfigValue::Bool(val) => DynConfigValueShared::Bool(Arc::new(AtomicBool::new(val))),
-            DynConfigValue::I64(val) => DynConfigValueShared::I64(Arc::new(AtomicI64::new(val))),
-            DynConfigValue::U64(val) => DynConfigValueShared::U64(Arc::new(AtomicU64::new(val))),
-            DynConfigValue::String(val) => DynConfigValueShared::String(Arc::new(RwLock::new(val))),
+impl fmt::Display for ConfigEntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.error)
+    }
+}
+
+impl fmt::Display for ConfigSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, entry) in &*self.configs {
+            writeln!(f, "{} => {}\n\t└─ '{}'", name, entry.value, entry.desc)?;
         }
+        Ok(())
     }
 }
 
-/// Shareable instance of [`DynConfigValue`].
+/// Typed handle to a [`Config`] within a [`ConfigSet`], returned when registering the config
+/// with [`ConfigSetBuilder::register`] or [`ConfigNamespace::register`].
+///
+/// Unlike reading a [`Config`] by name, the handle can only exist for a registered config of
+/// the right type, so reads can't fail.
 #[derive(Clone, Debug)]
-pub enum DynConfigValueShared {
-    Bool(Arc<AtomicBool>),
-    I64(Arc<AtomicI64>),
-    U64(Arc<AtomicU64>),
-    String(Arc<RwLock<CompactString>>),
+pub struct ConfigHandle<V: ConfigDefault> {
+    name: CompactString,
+    entry: ConfigSetEntry,
+    _type: PhantomData<fn() -> V>,
 }
 
-impl DynConfigValueShared {
-    pub fn update(&self, value: DynConfigValue) {
-        match (self, value) {
-            (DynConfigValueShared::Bool(shared), DynConfigValue::Bool(val)) => {
+impl<V: ConfigDefault> ConfigHandle<V> {
+    /// Name of the [`Config`] this handle refers to.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Read the current value of the [`Config`].
+    pub fn read(&self) -> V::StoredValue {
+        V::from_dyn(&self.entry.value).expect("registered with this type")
+    }
+
+    /// Update the value of the [`Config`].
+    ///
+    /// # Errors
+    ///
+    /// * If the value is invalid, e.g. not one of the choices for a [`Choice`].
+    pub fn update(&self, value: V) -> Result<(), anyhow::Error> {
+        self.entry.update(value.to_dyn(), ConfigSource::Runtime)
+    }
+}
+
+/// Error for reading or updating a config named `name` as the wrong type.
+fn type_mismatch(name: &str, value: &DynConfigValueShared) -> anyhow::Error {
+    anyhow::anyhow!("config '{name}' has a different type, found {value:?}")
+}
+
+/// Details of a single config within a [`ConfigSet`], returned from [`ConfigSet::iter`].
+#[derive(Debug, Clone)]
+pub struct ConfigInfo<'a> {
+    /// Name of the config.
+    pub name: &'a str,
+    /// Description of the config.
+    pub desc: &'static str,
+    /// The value of the config when it was read.
+    pub value: DynConfigValue,
+    /// The default value of the config.
+    pub default: &'a DynConfigValue,
+    /// Where the value of the config came from.
+    pub source: ConfigSource,
+}
+
+impl<'a> ConfigInfo<'a> {
+    /// Namespace of the config, if it was registered in one, see
+    /// [`ConfigSetBuilder::namespace`].
+    ///
+    /// Configs are ordered by name, so configs in the same namespace are next to each other.
+    pub fn namespace(&self) -> Option<&'a str> {
+        self.name.split_once('.').map(|(namespace, _)| namespace)
+    }
+}
+
+/// Where the current value of a config in a [`ConfigSet`] came from.
+///
+/// Sources are recorded as values are applied, and the most recent one wins, so this explains
+/// which layer to change to get a different value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// The default value the [`Config`] was defined with.
+    #[default]
+    Default,
+    /// A `[pb.config]` table in a TOML file, `None` if the contents weren't loaded from a path.
+    File(Option<PathBuf>),
+    /// The named environment variable, see [`ConfigSet::apply_env`].
+    Env(CompactString),
+    /// A command line flag.
+    Cli,
+    /// An update made while `pb` was running, e.g. via [`ConfigSet::update`].
+    Runtime,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(Some(path)) => write!(f, "file {}", path.display()),
+            ConfigSource::File(None) => write!(f, "file"),
+            ConfigSource::Env(var) => write!(f, "env {var}"),
+            ConfigSource::Cli => write!(f, "cli"),
+            ConfigSource::Runtime => write!(f, "runtime"),
+        }
+    }
+}
+
+/// Single entry within a [`ConfigSet`].
+#[derive(Clone, Debug)]
+pub struct ConfigSetEntry {
+    value: DynConfigValueShared,
+    default: Arc<DynConfigValue>,
+    source: Arc<RwLock<ConfigSource>>,
+    desc: &'static str,
+    subscribers: Subscribers,
+}
+
+impl ConfigSetEntry {
+    /// Returns where the current value of this entry came from.
+    fn source(&self) -> ConfigSource {
+        self.source
+            .read()
+            .expect("ConfigSetEntry source lock poisoned")
+            .clone()
+    }
+
+    /// Update this entry to `value` from `source` and notify any subscribers.
+    fn update(&self, value: DynConfigValue, source: ConfigSource) -> Result<(), anyhow::Error> {
+        self.update_with(source, |shared| shared.update(value))
+    }
+
+    /// Update this entry by parsing `value` from `source` and notify any subscribers.
+    fn update_parse(&self, value: &str, source: ConfigSource) -> Result<(), anyhow::Error> {
+        self.update_with(source, |shared| shared.update_parse(value))
+    }
+
+    fn update_with<F>(&self, source: ConfigSource, f: F) -> Result<(), anyhow::Error>
+    where
+        F: FnOnce(&DynConfigValueShared) -> Result<(), anyhow::Error>,
+    {
+        {
+            // Hold the lock while updating so the value and its source always agree.
+            let mut current = self
+                .source
+                .write()
+                .expect("ConfigSetEntry source lock poisoned");
+            f(&self.value)?;
+            *current = source;
+        }
+        self.subscribers.notify(&self.value);
+        Ok(())
+    }
+}
+
+/// Type erased callback registered with [`Config::subscribe`].
+type Subscriber = Arc<dyn Fn(&DynConfigValueShared) + Send + Sync>;
+
+/// Callbacks to run when a [`ConfigSetEntry`] is updated.
+#[derive(Clone, Default)]
+struct Subscribers(Arc<Mutex<Vec<Subscriber>>>);
+
+impl Subscribers {
+    fn push(&self, subscriber: Subscriber) {
+        let mut subscribers = self.0.lock().expect("Subscribers lock poisoned");
+        subscribers.push(subscriber);
+    }
+
+    fn notify(&self, value: &DynConfigValueShared) {
+        // Don't hold the lock while running the callbacks, they may subscribe or update.
+        let subscribers = self.0.lock().expect("Subscribers lock poisoned").clone();
+        for subscriber in subscribers {
+            subscriber(value);
+        }
+    }
+}
+
+impl fmt::Debug for Subscribers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let subscribers = self.0.lock().expect("Subscribers lock poisoned");
+        f.debug_struct("Subscribers")
+            .field("len", &subscribers.len())
+            .finish()
+    }
+}
+
+/// A builder for a [`ConfigSet`].
+#[derive(Default, Debug)]
+pub struct ConfigSetBuilder {
+    configs: BTreeMap<CompactString, ConfigSetEntry>,
+    aliases: BTreeMap<CompactString, CompactString>,
+    qualified_names: BTreeMap<ConfigId, CompactString>,
+}
+
+impl ConfigSetBuilder {
+    /// Register a [`Config`] into this [`ConfigSetBuilder`] with the default value.
+    ///
+    /// Returns a [`ConfigHandle`] for reading and updating the config in the [`ConfigSet`] that
+    /// gets built.
+    pub fn register<V: ConfigDefault>(&mut self, config: &'static Config<V>) -> ConfigHandle<V> {
+        self.insert(CompactString::const_new(config.name), config)
+    }
+
+    /// Returns a [`ConfigNamespace`] that registers configs under `namespace`, e.g. a config
+    /// named `max_handles` registered in the `filesystem` namespace is named
+    /// `filesystem.max_handles`, so configs from different crates can't collide.
+    ///
+    /// # Panics
+    /// * If `namespace` is empty or contains a `.`.
+    pub fn namespace(&mut self, namespace: &'static str) -> ConfigNamespace<'_> {
+        assert!(
+            !namespace.is_empty() && !namespace.contains('.'),
+            "invalid config namespace '{namespace}'"
+        );
+        ConfigNamespace {
+            builder: self,
+            namespace,
+        }
+    }
+
+    /// Register `alias` as a deprecated name for a [`Config`], e.g. after it's been renamed.
+    ///
+    /// Updating the config by its old name, e.g. from a workspace file, forwards to `config`
+    /// and logs a deprecation warning.
+    pub fn register_alias<V: ConfigDefault>(
+        &mut self,
+        alias: &'static str,
+        config: &'static Config<V>,
+    ) -> &mut Self {
+        self.insert_alias(CompactString::const_new(alias), config);
+        self
+    }
+
+    fn insert<V: ConfigDefault>(
+        &mut self,
+        name: CompactString,
+        config: &Config<V>,
+    ) -> ConfigHandle<V> {
+        let value = config.value.to_dyn();
+        let entry = ConfigSetEntry {
+            default: Arc::new(value.clone()),
+            value: value.into_shared(),
+            source: Arc::default(),
+            desc: config.desc,
+            subscribers: Subscribers::default(),
+        };
+        let prev = self.configs.insert(name.clone(), entry.clone());
+        assert_none!(prev, "config '{name}' registered more than once");
+
+        ConfigHandle {
+            name,
+            entry,
+            _type: PhantomData,
+        }
+    }
+
+    fn insert_alias<V: ConfigDefault>(&mut self, alias: CompactString, config: &Config<V>) {
+        let target = match self.qualified_names.get(&config.id()) {
+            Some(name) => name.clone(),
+            None => CompactString::const_new(config.name),
+        };
+        let prev = self.aliases.insert(alias.clone(), target);
+        assert_none!(prev, "alias '{alias}' registered more than once");
+    }
+
+    /// Consumes this [`ConfigSetBuilder`] construting a [`ConfigSet`].
+    pub fn build(self) -> ConfigSet {
+        for (alias, target) in &self.aliases {
+            assert!(
+                !self.configs.contains_key(alias),
+                "alias '{alias}' is also registered as a config"
+            );
+            assert!(
+                self.configs.contains_key(target),
+                "alias '{alias}' refers to unregistered config '{target}'"
+            );
+        }
+
+        ConfigSet {
+            configs: Arc::new(self.configs),
+            layers: Arc::default(),
+            aliases: Arc::new(self.aliases),
+            qualified_names: Arc::new(self.qualified_names),
+        }
+    }
+}
+
+/// Registers [`Config`]s into a namespace of a [`ConfigSetBuilder`], returned from
+/// [`ConfigSetBuilder::namespace`].
+#[derive(Debug)]
+pub struct ConfigNamespace<'a> {
+    builder: &'a mut ConfigSetBuilder,
+    namespace: &'static str,
+}
+
+impl ConfigNamespace<'_> {
+    /// Register a [`Config`] in this namespace with the default value.
+    ///
+    /// Returns a [`ConfigHandle`] for the config, see [`ConfigSetBuilder::register`].
+    ///
+    /// # Panics
+    /// * If `config` was already registered in a different namespace of this builder.
+    pub fn register<V: ConfigDefault>(&mut self, config: &'static Config<V>) -> ConfigHandle<V> {
+        let name = format_compact!("{}.{}", self.namespace, config.name);
+        let qualified_name = self
+            .builder
+            .qualified_names
+            .entry(config.id())
+            .or_insert_with(|| name.clone());
+        assert_eq!(
+            *qualified_name, name,
+            "config '{}' registered in multiple namespaces",
+            config.name
+        );
+        self.builder.insert(name, config)
+    }
+
+    /// Register `alias` in this namespace as a deprecated name for a [`Config`], see
+    /// [`ConfigSetBuilder::register_alias`].
+    pub fn register_alias<V: ConfigDefault>(
+        &mut self,
+        alias: &'static str,
+        config: &'static Config<V>,
+    ) -> &mut Self {
+        let alias = format_compact!("{}.{alias}", self.namespace);
+        self.builder.insert_alias(alias, config);
+        self
+    }
+}
+
+/// Types that can be provided as a default to a [`Config`].
+pub trait ConfigDefault {
+    /// The type that actually gets stored in a [`ConfigSet`].
+    type StoredValue: ConfigValue;
+
+    fn into_stored(&self) -> Self::StoredValue;
+    /// Read the stored value from a [`DynConfigValueShared`], returning `None` if it's the wrong
+    /// type.
+    fn from_dyn(val: &DynConfigValueShared) -> Option<Self::StoredValue>;
+
+    /// Convert this value into a [`DynConfigValue`], by default via [`ConfigValue::into_dyn`].
+    fn to_dyn(&self) -> DynConfigValue {
+        self.into_stored().into_dyn()
+    }
+}
+
+impl ConfigDefault for bool {
+    type StoredValue = bool;
+
+    fn into_stored(&self) -> Self::StoredValue {
+        *self
+    }
+
+    fn from_dyn<'a>(val: &'a DynConfigValueShared) -> Option<Self::StoredValue> {
+        let DynConfigValueShared::Bool(val) = val else {
+            return None;
+        };
+        Some(val.load(Ordering::SeqCst))
+    }
+}
+
+impl ConfigDefault for i64 {
+    type StoredValue = i64;
+
+    fn into_stored(&self) -> Self::StoredValue {
+        *self
+    }
+
+    fn from_dyn(val: &DynConfigValueShared) -> Option<Self::StoredValue> {
+        let DynConfigValueShared::I64(val) = val else {
+            return None;
+        };
+        Some(val.load(Ordering::SeqCst))
+    }
+}
+
+impl ConfigDefault for u64 {
+    type StoredValue = u64;
+
+    fn into_stored(&self) -> Self::StoredValue {
+        *self
+    }
+
+    fn from_dyn(val: &DynConfigValueShared) -> Option<Self::StoredValue> {
+        let DynConfigValueShared::U64(val) = val else {
+            return None;
+        };
+        Some(val.load(Ordering::SeqCst))
+    }
+}
+
+impl ConfigDefault for f64 {
+    type StoredValue = f64;
+
+    fn into_stored(&self) -> Self::StoredValue {
+        *self
+    }
+
+    fn from_dyn(val: &DynConfigValueShared) -> Option<Self::StoredValue> {
+        let DynConfigValueShared::F64(val) = val else {
+            return None;
+        };
+        Some(f64::from_bits(val.load(Ordering::SeqCst)))
+    }
+}
+
+impl ConfigDefault for Duration {
+    type StoredValue = Duration;
+
+    fn into_stored(&self) -> Self::StoredValue {
+        *self
+    }
+
+    fn from_dyn(val: &DynConfigValueShared) -> Option<Self::StoredValue> {
+        let DynConfigValueShared::Duration(val) = val else {
+            return None;
+        };
+        Some(
+            *val.read()
+                .expect("DynConfigValueShared::Duration lock poisoned"),
+        )
+    }
+}
+
+impl ConfigDefault for &str {
+    type StoredValue = CompactString;
+
+    fn into_stored(&self) -> Self::StoredValue {
+        CompactString::new(self)
+    }
+
+    fn from_dyn<'a>(val: &'a DynConfigValueShared) -> Option<Self::StoredValue> {
+        let DynConfigValueShared::String(val) = val else {
+            return None;
+        };
+        let read_lock = val
+            .read()
+            .expect("DynConfigValueShared::String lock poisoned");
+        Some(read_lock.clone())
+    }
+}
+
+impl ConfigDefault for String {
+    type StoredValue = CompactString;
+
+    fn into_stored(&self) -> Self::StoredValue {
+        CompactString::from(self)
+    }
+
+    fn from_dyn<'a>(val: &'a DynConfigValueShared) -> Option<Self::StoredValue> {
+        let DynConfigValueShared::String(val) = val else {
+            return None;
+        };
+        let read_lock = val
+            .read()
+            .expect("DynConfigValueShared::String lock poisoned");
+        Some(read_lock.clone())
+    }
+}
+
+impl ConfigDefault for &[&str] {
+    type StoredValue = Vec<CompactString>;
+
+    fn into_stored(&self) -> Self::StoredValue {
+        self.iter().map(|val| CompactString::new(val)).collect()
+    }
+
+    fn from_dyn(val: &DynConfigValueShared) -> Option<Self::StoredValue> {
+        let DynConfigValueShared::List(val) = val else {
+            return None;
+        };
+        let read_lock = val
+            .read()
+            .expect("DynConfigValueShared::List lock poisoned");
+        Some(read_lock.clone())
+    }
+}
+
+/// A string value for a [`Config`] that is restricted to a fixed set of choices.
+///
+/// Reading the [`Config`] returns the chosen value, and [`ConfigSet::try_update`] rejects any
+/// value that isn't one of the choices.
+#[derive(Debug, Clone, Copy)]
+pub struct Choice {
+    value: &'static str,
+    choices: &'static [&'static str],
+}
+
+impl Choice {
+    /// Create a new [`Choice`] of `value` from `choices`.
+    ///
+    /// Note: `value` is validated when the [`Config`] is registered, or the [`ConfigSet`] is
+    /// updated.
+    pub const fn new(value: &'static str, choices: &'static [&'static str]) -> Self {
+        Choice { value, choices }
+    }
+}
+
+impl ConfigDefault for Choice {
+    type StoredValue = CompactString;
+
+    fn into_stored(&self) -> Self::StoredValue {
+        CompactString::new(self.value)
+    }
+
+    fn from_dyn(val: &DynConfigValueShared) -> Option<Self::StoredValue> {
+        let DynConfigValueShared::Choice { value, .. } = val else {
+            return None;
+        };
+        let read_lock = value
+            .read()
+            .expect("DynConfigValueShared::Choice lock poisoned");
+        Some(read_lock.clone())
+    }
+
+    fn to_dyn(&self) -> DynConfigValue {
+        DynConfigValue::Choice {
+            value: self.into_stored(),
+            choices: self.choices,
+        }
+    }
+}
+
+/// An optional config, where `None` means the config is unset, e.g. a remote cache URL where
+/// absence disables the feature.
+///
+/// Optional configs can be unset with [`ConfigSet::try_update`] by passing [`UNSET`] or an empty
+/// string.
+impl<V> ConfigDefault for Option<V>
+where
+    V: ConfigDefault + Default,
+    V::StoredValue: Default,
+{
+    type StoredValue = Option<V::StoredValue>;
+
+    fn into_stored(&self) -> Self::StoredValue {
+        self.as_ref().map(|val| val.into_stored())
+    }
+
+    fn from_dyn(val: &DynConfigValueShared) -> Option<Self::StoredValue> {
+        let DynConfigValueShared::Optional { set, value } = val else {
+            return None;
+        };
+        // Check the type of the inner value even if it's unset.
+        let value = V::from_dyn(value)?;
+        Some(set.load(Ordering::SeqCst).then_some(value))
+    }
+}
+
+/// Value that unsets an optional config, see [`ConfigSet::try_update`].
+pub const UNSET: &str = "unset";
+
+pub trait ConfigValue {
+    fn into_dyn(self) -> DynConfigValue;
+}
+
+impl ConfigValue for bool {
+    fn into_dyn(self) -> DynConfigValue {
+        DynConfigValue::Bool(self)
+    }
+}
+
+impl ConfigValue for i64 {
+    fn into_dyn(self) -> DynConfigValue {
+        DynConfigValue::I64(self)
+    }
+}
+
+impl ConfigValue for u64 {
+    fn into_dyn(self) -> DynConfigValue {
+        DynConfigValue::U64(self)
+    }
+}
+
+impl ConfigValue for f64 {
+    fn into_dyn(self) -> DynConfigValue {
+        DynConfigValue::F64(self)
+    }
+}
+
+impl ConfigValue for Duration {
+    fn into_dyn(self) -> DynConfigValue {
+        DynConfigValue::Duration(self)
+    }
+}
+
+impl ConfigValue for CompactString {
+    fn into_dyn(self) -> DynConfigValue {
+        DynConfigValue::String(self)
+    }
+}
+
+impl ConfigValue for Vec<CompactString> {
+    fn into_dyn(self) -> DynConfigValue {
+        DynConfigValue::List(self)
+    }
+}
+
+impl<T: ConfigValue + Default> ConfigValue for Option<T> {
+    fn into_dyn(self) -> DynConfigValue {
+        // When unset we still store a placeholder so the type of the config is known.
+        DynConfigValue::Optional {
+            set: self.is_some(),
+            value: Box::new(self.unwrap_or_default().into_dyn()),
+        }
+    }
+}
+
+/// "Type erased" configuration values.
+///
+/// We prefer an enum as opposed to something like `Box<dyn Value>` because enums offer better
+/// performance and are easier to reason about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DynConfigValue {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Duration(Duration),
+    String(CompactString),
+    List(Vec<CompactString>),
+    Choice {
+        value: CompactString,
+        choices: &'static [&'static str],
+    },
+    /// An optional value, `value` is a placeholder when it's not `set`.
+    Optional {
+        set: bool,
+        value: Box<DynConfigValue>,
+    },
+}
+
+impl DynConfigValue {
+    /// Returns this value as JSON, e.g. for `pb config list --json`.
+    ///
+    /// [`Duration`]s are formatted with [`format_duration`], and floats that can't be
+    /// represented in JSON, e.g. NaN, are `null`.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            DynConfigValue::Bool(val) => serde_json::Value::from(*val),
+            DynConfigValue::I64(val) => serde_json::Value::from(*val),
+            DynConfigValue::U64(val) => serde_json::Value::from(*val),
+            DynConfigValue::F64(val) => serde_json::Number::from_f64(*val)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::Null),
+            DynConfigValue::Duration(val) => serde_json::Value::from(format_duration(*val)),
+            DynConfigValue::String(val) => serde_json::Value::from(val.as_str()),
+            DynConfigValue::List(vals) => vals.iter().map(|val| val.as_str()).collect(),
+            DynConfigValue::Choice { value, .. } => serde_json::Value::from(value.as_str()),
+            DynConfigValue::Optional { set: true, value } => value.to_json(),
+            DynConfigValue::Optional { set: false, .. } => serde_json::Value::Null,
+        }
+    }
+
+    pub fn into_shared(self) -> DynConfigValueShared {
+        match self {
+            DynConfigValue::Bool(val) => DynConfigValueShared::Bool(Arc::new(AtomicBool::new(val))),
+            DynConfigValue::I64(val) => DynConfigValueShared::I64(Arc::new(AtomicI64::new(val))),
+            DynConfigValue::U64(val) => DynConfigValueShared::U64(Arc::new(AtomicU64::new(val))),
+            DynConfigValue::F64(val) => {
+                DynConfigValueShared::F64(Arc::new(AtomicU64::new(val.to_bits())))
+            }
+            DynConfigValue::Duration(val) => {
+                DynConfigValueShared::Duration(Arc::new(RwLock::new(val)))
+            }
+            DynConfigValue::String(val) => DynConfigValueShared::String(Arc::new(RwLock::new(val))),
+            DynConfigValue::List(val) => DynConfigValueShared::List(Arc::new(RwLock::new(val))),
+            DynConfigValue::Choice { value, choices } => {
+                assert!(
+                    choices.contains(&value.as_str()),
+                    "default '{value}' is not one of {choices:?}"
+                );
+                DynConfigValueShared::Choice {
+                    value: Arc::new(RwLock::new(value)),
+                    choices,
+                }
+            }
+            DynConfigValue::Optional { set, value } => DynConfigValueShared::Optional {
+                set: Arc::new(AtomicBool::new(set)),
+                value: Box::new(value.into_shared()),
+            },
+        }
+    }
+}
+
+/// Shareable instance of [`DynConfigValue`].
+#[derive(Clone, Debug)]
+pub enum DynConfigValueShared {
+    Bool(Arc<AtomicBool>),
+    I64(Arc<AtomicI64>),
+    U64(Arc<AtomicU64>),
+    /// There is no `AtomicF64` so we store the bits of the float.
+    F64(Arc<AtomicU64>),
+    Duration(Arc<RwLock<Duration>>),
+    String(Arc<RwLock<CompactString>>),
+    List(Arc<RwLock<Vec<CompactString>>>),
+    Choice {
+        value: Arc<RwLock<CompactString>>,
+        choices: &'static [&'static str],
+    },
+    Optional {
+        set: Arc<AtomicBool>,
+        value: Box<DynConfigValueShared>,
+    },
+}
+
+impl fmt::Display for DynConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DynConfigValue::Bool(val) => write!(f, "{val}"),
+            DynConfigValue::I64(val) => write!(f, "{val}"),
+            DynConfigValue::U64(val) => write!(f, "{val}"),
+            DynConfigValue::F64(val) => write!(f, "{val}"),
+            DynConfigValue::Duration(val) => write!(f, "{}", format_duration(*val)),
+            DynConfigValue::String(val) => write!(f, "{val}"),
+            DynConfigValue::List(vals) => write!(f, "{}", vals.join(",")),
+            DynConfigValue::Choice { value, .. } => write!(f, "{value}"),
+            DynConfigValue::Optional { set: true, value } => write!(f, "{value}"),
+            DynConfigValue::Optional { set: false, .. } => write!(f, "{UNSET}"),
+        }
+    }
+}
+
+impl DynConfigValueShared {
+    /// Returns a copy of the current value.
+    pub fn snapshot(&self) -> DynConfigValue {
+        match self {
+            DynConfigValueShared::Bool(val) => DynConfigValue::Bool(val.load(Ordering::SeqCst)),
+            DynConfigValueShared::I64(val) => DynConfigValue::I64(val.load(Ordering::SeqCst)),
+            DynConfigValueShared::U64(val) => DynConfigValue::U64(val.load(Ordering::SeqCst)),
+            DynConfigValueShared::F64(val) => {
+                DynConfigValue::F64(f64::from_bits(val.load(Ordering::SeqCst)))
+            }
+            DynConfigValueShared::Duration(val) => {
+                let read_lock = val
+                    .read()
+                    .expect("DynConfigValueShared::Duration lock poisoned");
+                DynConfigValue::Duration(*read_lock)
+            }
+            DynConfigValueShared::String(val) => {
+                let read_lock = val
+                    .read()
+                    .expect("DynConfigValueShared::String lock poisoned");
+                DynConfigValue::String(read_lock.clone())
+            }
+            DynConfigValueShared::List(val) => {
+                let read_lock = val
+                    .read()
+                    .expect("DynConfigValueShared::List lock poisoned");
+                DynConfigValue::List(read_lock.clone())
+            }
+            DynConfigValueShared::Choice { value, choices } => {
+                let read_lock = value
+                    .read()
+                    .expect("DynConfigValueShared::Choice lock poisoned");
+                DynConfigValue::Choice {
+                    value: read_lock.clone(),
+                    choices,
+                }
+            }
+            DynConfigValueShared::Optional { set, value } => DynConfigValue::Optional {
+                set: set.load(Ordering::SeqCst),
+                value: Box::new(value.snapshot()),
+            },
+        }
+    }
+
+    /// Update the shared value.
+    ///
+    /// # Errors
+    ///
+    /// * If `value` is a different type than the shared value.
+    /// * If `value` is not one of the allowed choices.
+    pub fn update(&self, value: DynConfigValue) -> Result<(), anyhow::Error> {
+        match (self, value) {
+            (DynConfigValueShared::Bool(shared), DynConfigValue::Bool(val)) => {
                 shared.store(val, Ordering::SeqCst);
             }
             (DynConfigValueShared::I64(shared), DynConfigValue::I64(val)) => {
@@ -293,18 +1515,106 @@ impl DynConfigValueShared {
             (DynConfigValueShared::U64(shared), DynConfigValue::U64(val)) => {
                 shared.store(val, Ordering::SeqCst);
             }
+            (DynConfigValueShared::F64(shared), DynConfigValue::F64(val)) => {
+                shared.store(val.to_bits(), Ordering::SeqCst);
+            }
+            (DynConfigValueShared::Duration(shared), DynConfigValue::Duration(val)) => {
+                let mut write_lock = shared
+                    .write()
+                    .expect("DynConfigValueShared::Duration lock poisoned");
+                *write_lock = val;
+            }
             (DynConfigValueShared::String(shared), DynConfigValue::String(val)) => {
                 let mut write_lock = shared
                     .write()
                     .expect("DynConfigValueShared::String lock poisoned");
                 *write_lock = val;
             }
-            (shared, val) => unreachable!("tried to update shared {shared:?} with {val:?}"),
+            (DynConfigValueShared::List(shared), DynConfigValue::List(val)) => {
+                let mut write_lock = shared
+                    .write()
+                    .expect("DynConfigValueShared::List lock poisoned");
+                *write_lock = val;
+            }
+            (
+                DynConfigValueShared::Choice {
+                    value: shared,
+                    choices,
+                },
+                DynConfigValue::Choice { value: val, .. },
+            ) => {
+                if !choices.contains(&val.as_str()) {
+                    anyhow::bail!(
+                        "invalid value '{val}', expected one of: {}",
+                        choices.join(", ")
+                    );
+                }
+                let mut write_lock = shared
+                    .write()
+                    .expect("DynConfigValueShared::Choice lock poisoned");
+                *write_lock = val;
+            }
+            (
+                DynConfigValueShared::Optional {
+                    set: shared_set,
+                    value: shared,
+                },
+                DynConfigValue::Optional { set, value: val },
+            ) => {
+                shared.update(*val)?;
+                shared_set.store(set, Ordering::SeqCst);
+            }
+            (shared, val) => anyhow::bail!("tried to update {shared:?} with {val:?}"),
         }
+
+        Ok(())
+    }
+
+    /// Parse `value` as the type of this config, without updating it.
+    pub fn parse(&self, value: &str) -> Result<DynConfigValue, anyhow::Error> {
+        let value = match self {
+            DynConfigValueShared::Bool(_) => DynConfigValue::Bool(value.parse()?),
+            DynConfigValueShared::I64(_) => DynConfigValue::I64(value.parse()?),
+            DynConfigValueShared::U64(_) => DynConfigValue::U64(value.parse()?),
+            DynConfigValueShared::F64(_) => DynConfigValue::F64(value.parse()?),
+            DynConfigValueShared::Duration(_) => DynConfigValue::Duration(parse_duration(value)?),
+            DynConfigValueShared::String(_) => DynConfigValue::String(CompactString::new(value)),
+            DynConfigValueShared::List(_) => DynConfigValue::List(parse_list(value)),
+            DynConfigValueShared::Choice { choices, .. } => {
+                if !choices.contains(&value) {
+                    anyhow::bail!(
+                        "invalid value '{value}', expected one of: {}",
+                        choices.join(", ")
+                    );
+                }
+                DynConfigValue::Choice {
+                    value: CompactString::new(value),
+                    choices,
+                }
+            }
+            DynConfigValueShared::Optional { value: shared, .. } => {
+                if value.is_empty() || value == UNSET {
+                    DynConfigValue::Optional {
+                        set: false,
+                        value: Box::new(shared.snapshot()),
+                    }
+                } else {
+                    DynConfigValue::Optional {
+                        set: true,
+                        value: Box::new(shared.parse(value)?),
+                    }
+                }
+            }
+        };
+        Ok(value)
     }
 
     pub fn update_parse(&self, value: &str) -> Result<(), anyhow::Error> {
         match self {
+            DynConfigValueShared::Optional { .. } => {
+                let val = self.parse(value)?;
+                self.update(val)?;
+            }
             DynConfigValueShared::Bool(shared) => {
                 let val: bool = value.parse()?;
                 shared.store(val, Ordering::SeqCst);
@@ -317,6 +1627,17 @@ impl DynConfigValueShared {
                 let val: u64 = value.parse()?;
                 shared.store(val, Ordering::SeqCst);
             }
+            DynConfigValueShared::F64(shared) => {
+                let val: f64 = value.parse()?;
+                shared.store(val.to_bits(), Ordering::SeqCst);
+            }
+            DynConfigValueShared::Duration(shared) => {
+                let val = parse_duration(value)?;
+                let mut write_lock = shared
+                    .write()
+                    .expect("DynConfigValueShared::Duration lock poisoned");
+                *write_lock = val;
+            }
             DynConfigValueShared::String(shared) => {
                 let mut write_lock = shared
                     .write()
@@ -324,33 +1645,122 @@ impl DynConfigValueShared {
                 write_lock.clear();
                 write_lock.push_str(value);
             }
+            DynConfigValueShared::List(shared) => {
+                let val = parse_list(value);
+                let mut write_lock = shared
+                    .write()
+                    .expect("DynConfigValueShared::List lock poisoned");
+                *write_lock = val;
+            }
+            DynConfigValueShared::Choice {
+                value: shared,
+                choices,
+            } => {
+                if !choices.contains(&value) {
+                    anyhow::bail!(
+                        "invalid value '{value}', expected one of: {}",
+                        choices.join(", ")
+                    );
+                }
+                let mut write_lock = shared
+                    .write()
+                    .expect("DynConfigValueShared::Choice lock poisoned");
+                write_lock.clear();
+                write_lock.push_str(value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for DynConfigValueShared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.snapshot().fmt(f)
+    }
+}
+
+/// Parse a comma separated list of strings, e.g. `target,.git`.
+///
+/// Whitespace around each entry is trimmed and empty entries are dropped, so an empty string
+/// is an empty list.
+pub fn parse_list(s: &str) -> Vec<CompactString> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(CompactString::new)
+        .collect()
+}
+
+/// Units supported when parsing a [`Duration`], largest first.
+const DURATION_UNITS: &[(&str, Duration)] = &[
+    ("h", Duration::from_secs(60 * 60)),
+    ("m", Duration::from_secs(60)),
+    ("s", Duration::from_secs(1)),
+    ("ms", Duration::from_millis(1)),
+    ("us", Duration::from_micros(1)),
+    ("ns", Duration::from_nanos(1)),
+];
+
+/// Parse a [`Duration`] from a string like `500ms`, `2m`, or `1h30m`.
+///
+/// # Errors
+///
+/// * If `s` is not one or more integers each followed by one of `h`, `m`, `s`, `ms`, `us`, or
+///   `ns`.
+/// * If the duration overflows.
+pub fn parse_duration(s: &str) -> Result<Duration, anyhow::Error> {
+    if s.is_empty() {
+        anyhow::bail!("empty duration");
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow::anyhow!("missing unit in duration '{s}'"))?;
+        if digits == 0 {
+            anyhow::bail!("invalid duration '{s}'");
         }
+        let amount: u32 = rest[..digits].parse()?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = &rest[..unit_len];
+        let (_, scale) = DURATION_UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .ok_or_else(|| anyhow::anyhow!("invalid unit '{unit}' in duration '{s}'"))?;
+        rest = &rest[unit_len..];
+
+        total = scale
+            .checked_mul(amount)
+            .and_then(|amount| total.checked_add(amount))
+            .ok_or_else(|| anyhow::anyhow!("duration '{s}' overflows"))?;
+    }
+
+    Ok(total)
+}
 
-        Ok(())
+/// Format a [`Duration`] in the form accepted by [`parse_duration`].
+pub fn format_duration(duration: Duration) -> String {
+    if duration.is_zero() {
+        return "0s".to_string();
     }
-}
 
-impl fmt::Display for DynConfigValueShared {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            DynConfigValueShared::Bool(val) => {
-                write!(f, "{}", val.load(Ordering::SeqCst))?;
-            }
-            DynConfigValueShared::I64(val) => {
-                write!(f, "{}", val.load(Ordering::SeqCst))?;
-            }
-            DynConfigValueShared::U64(val) => {
-                write!(f, "{}", val.load(Ordering::SeqCst))?;
-            }
-            DynConfigValueShared::String(val) => {
-                let read_lock = val
-                    .read()
-                    .expect("DynConfigValueShared::String lock poisoned");
-                write!(f, "{}", *read_lock)?;
-            }
+    let mut formatted = String::new();
+    let mut rest = duration.as_nanos();
+    for (name, scale) in DURATION_UNITS {
+        let amount = rest / scale.as_nanos();
+        if amount > 0 {
+            formatted.push_str(&format!("{amount}{name}"));
+            rest -= amount * scale.as_nanos();
         }
-        Ok(())
     }
+    formatted
 }
 
 #[cfg(test)]
@@ -361,11 +1771,29 @@ mod test {
         Config::new("test_config_a", "A test configuration value.", true);
     pub static TEST_CONFIG_B: Config<&'static str> =
         Config::new("test_config_b", "A test configuration value.", "foobar");
+    pub static TEST_CONFIG_F64: Config<f64> =
+        Config::new("test_config_f64", "A test configuration value.", 1.5);
+    pub static TEST_CONFIG_LIST: Config<&'static [&'static str]> = Config::new(
+        "test_config_list",
+        "A test configuration value.",
+        &["target", ".git"],
+    );
+    pub static TEST_CONFIG_CHOICE: Config<Choice> = Config::new(
+        "test_config_choice",
+        "A test configuration value.",
+        Choice::new("pretty", &["json", "pretty"]),
+    );
+    pub static TEST_CONFIG_DURATION: Config<Duration> = Config::new(
+        "test_config_duration",
+        "A test configuration value.",
+        Duration::from_millis(500),
+    );
 
     #[test]
     fn smoketest_read() {
         let mut config_set = ConfigSet::builder();
-        config_set.register(&TEST_CONFIG_A).register(&TEST_CONFIG_B);
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_B);
         let config_set = config_set.build();
 
         assert_eq!(TEST_CONFIG_A.read(&config_set), true);
@@ -375,7 +1803,8 @@ mod test {
     #[test]
     fn smoketest_update() {
         let mut config_set = ConfigSet::builder();
-        config_set.register(&TEST_CONFIG_A).register(&TEST_CONFIG_B);
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_B);
         let config_set = config_set.build();
         let config_set_2 = config_set.clone();
 
@@ -397,7 +1826,8 @@ mod test {
     #[test]
     fn smoketest_parse() {
         let mut config_set = ConfigSet::builder();
-        config_set.register(&TEST_CONFIG_A).register(&TEST_CONFIG_B);
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_B);
         let config_set = config_set.build();
 
         config_set.try_update("test_config_a", "false").unwrap();
@@ -408,4 +1838,713 @@ mod test {
             .unwrap();
         assert_eq!(TEST_CONFIG_B.read(&config_set), "anotha one");
     }
+
+    #[test]
+    fn smoketest_f64() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_F64);
+        let config_set = config_set.build();
+
+        assert_eq!(TEST_CONFIG_F64.read(&config_set), 1.5);
+        config_set.update(&TEST_CONFIG_F64, 0.25);
+        assert_eq!(TEST_CONFIG_F64.read(&config_set), 0.25);
+        config_set.try_update("test_config_f64", "-2e3").unwrap();
+        assert_eq!(TEST_CONFIG_F64.read(&config_set), -2000.0);
+        assert!(config_set.try_update("test_config_f64", "fast").is_err());
+        assert_eq!(
+            config_set.to_string().lines().next(),
+            Some("test_config_f64 => -2000")
+        );
+    }
+
+    #[test]
+    fn smoketest_duration() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_DURATION);
+        let config_set = config_set.build();
+
+        assert_eq!(
+            TEST_CONFIG_DURATION.read(&config_set),
+            Duration::from_millis(500)
+        );
+        config_set.try_update("test_config_duration", "2m").unwrap();
+        assert_eq!(
+            TEST_CONFIG_DURATION.read(&config_set),
+            Duration::from_secs(120)
+        );
+        assert!(config_set.try_update("test_config_duration", "2").is_err());
+        assert!(
+            config_set
+                .try_update("test_config_duration", "2 weeks")
+                .is_err()
+        );
+        assert_eq!(
+            config_set.to_string().lines().next(),
+            Some("test_config_duration => 2m")
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let roundtrip = |s: &str, expected: Duration| {
+            let duration = parse_duration(s).unwrap();
+            assert_eq!(duration, expected);
+            assert_eq!(format_duration(duration), s);
+        };
+        roundtrip("0s", Duration::ZERO);
+        roundtrip("500ms", Duration::from_millis(500));
+        roundtrip("1h30m", Duration::from_secs(90 * 60));
+        roundtrip("1s250us", Duration::new(1, 250_000));
+        roundtrip("7ns", Duration::from_nanos(7));
+
+        // Non-canonical forms are accepted.
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("0ms").unwrap(), Duration::ZERO);
+
+        for invalid in ["", "ms", "10", "1.5s", "-1s", "10x", "1s 2ms"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn smoketest_list() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_LIST);
+        let config_set = config_set.build();
+
+        assert_eq!(TEST_CONFIG_LIST.read(&config_set), ["target", ".git"]);
+        config_set
+            .try_update("test_config_list", " -Copt-level=3, ,-g ")
+            .unwrap();
+        assert_eq!(TEST_CONFIG_LIST.read(&config_set), ["-Copt-level=3", "-g"]);
+        assert_eq!(
+            config_set.to_string().lines().next(),
+            Some("test_config_list => -Copt-level=3,-g")
+        );
+
+        config_set.try_update("test_config_list", "").unwrap();
+        assert!(TEST_CONFIG_LIST.read(&config_set).is_empty());
+        config_set.update(&TEST_CONFIG_LIST, &["node_modules"]);
+        assert_eq!(TEST_CONFIG_LIST.read(&config_set), ["node_modules"]);
+    }
+
+    #[test]
+    fn smoketest_choice() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_CHOICE);
+        let config_set = config_set.build();
+
+        assert_eq!(TEST_CONFIG_CHOICE.read(&config_set), "pretty");
+        config_set.try_update("test_config_choice", "json").unwrap();
+        assert_eq!(TEST_CONFIG_CHOICE.read(&config_set), "json");
+
+        let err = config_set
+            .try_update("test_config_choice", "yaml")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value 'yaml', expected one of: json, pretty"
+        );
+        assert_eq!(TEST_CONFIG_CHOICE.read(&config_set), "json");
+
+        config_set.update(&TEST_CONFIG_CHOICE, Choice::new("pretty", &[]));
+        assert_eq!(TEST_CONFIG_CHOICE.read(&config_set), "pretty");
+    }
+
+    #[test]
+    #[should_panic(expected = "default 'yaml' is not one of")]
+    fn test_invalid_choice_default() {
+        static INVALID: Config<Choice> = Config::new(
+            "invalid",
+            "An invalid configuration value.",
+            Choice::new("yaml", &["json", "pretty"]),
+        );
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&INVALID);
+        config_set.build();
+    }
+
+    #[test]
+    fn smoketest_load_toml() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_B);
+        config_set.register(&TEST_CONFIG_F64);
+        config_set.register(&TEST_CONFIG_LIST);
+        config_set.register(&TEST_CONFIG_DURATION);
+        let config_set = config_set.build();
+
+        let contents = r#"
+            [workspace]
+            name = "example"
+
+            [pb.config]
+            test_config_a = false
+            test_config_b = "from toml"
+            test_config_f64 = 3
+            test_config_list = ["a", "b"]
+            test_config_duration = "not a duration"
+            test_config_missing = 1
+        "#;
+        let errors = config_set.load_toml(contents).unwrap();
+
+        assert!(!TEST_CONFIG_A.read(&config_set));
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "from toml");
+        assert_eq!(TEST_CONFIG_F64.read(&config_set), 3.0);
+        assert_eq!(TEST_CONFIG_LIST.read(&config_set), ["a", "b"]);
+        assert_eq!(
+            TEST_CONFIG_DURATION.read(&config_set),
+            Duration::from_millis(500)
+        );
+        let names: Vec<_> = errors.iter().map(|error| error.name.as_str()).collect();
+        assert_eq!(names, ["test_config_duration", "test_config_missing"]);
+
+        // Documents without a `[pb.config]` table don't change anything.
+        assert!(config_set.load_toml("[workspace]").unwrap().is_empty());
+        assert!(config_set.load_toml("pb = 1").is_err());
+        assert!(config_set.load_toml("[pb").is_err());
+    }
+
+    #[test]
+    fn smoketest_apply_env() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_B);
+        let config_set = config_set.build();
+
+        let vars = [
+            ("PB_TEST_CONFIG_A", "false"),
+            ("PB_TEST_CONFIG_B", "from env"),
+            ("PB_TEST_CONFIG_MISSING", "1"),
+            ("TEST_CONFIG_B", "ignored"),
+        ]
+        .map(|(key, value)| (OsString::from(key), OsString::from(value)));
+        let errors = config_set.apply_vars("PB_", vars);
+
+        assert!(!TEST_CONFIG_A.read(&config_set));
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "from env");
+        let names: Vec<_> = errors.iter().map(|error| error.name.as_str()).collect();
+        assert_eq!(names, ["test_config_missing"]);
+    }
+
+    #[test]
+    fn smoketest_subscribe() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_B);
+        let config_set = config_set.build();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        TEST_CONFIG_B.subscribe(&config_set, move |value| tx.send(value).unwrap());
+
+        // Updates through a clone of the set are also observed.
+        let config_set_2 = config_set.clone();
+        config_set_2.update(&TEST_CONFIG_B, "first");
+        config_set.try_update("test_config_b", "second").unwrap();
+        assert!(
+            config_set
+                .try_update("test_config_b_typo", "third")
+                .is_err()
+        );
+        config_set.update(&TEST_CONFIG_A, false);
+
+        let values: Vec<_> = rx.try_iter().collect();
+        assert_eq!(values, ["first", "second"]);
+    }
+
+    #[test]
+    fn test_typed_access() {
+        static WRONG_TYPE: Config<u64> =
+            Config::new("test_config_a", "Same name, different type.", 1);
+        static UNREGISTERED: Config<bool> =
+            Config::new("unregistered", "A test configuration value.", true);
+
+        let mut config_set = ConfigSet::builder();
+        let handle = config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_CHOICE);
+        let config_set = config_set.build();
+
+        assert!(TEST_CONFIG_A.try_read(&config_set).unwrap());
+        let err = WRONG_TYPE.try_read(&config_set).unwrap_err();
+        assert!(err.to_string().contains("different type"));
+        let err = UNREGISTERED.try_read(&config_set).unwrap_err();
+        assert_eq!(err.to_string(), "config 'unregistered' is not registered");
+
+        assert!(config_set.try_update_typed(&WRONG_TYPE, 2).is_err());
+        assert!(config_set.try_update_typed(&UNREGISTERED, false).is_err());
+        assert!(
+            config_set
+                .try_update_typed(&TEST_CONFIG_CHOICE, Choice::new("yaml", &[]))
+                .is_err()
+        );
+        config_set.try_update_typed(&TEST_CONFIG_A, false).unwrap();
+        assert!(!TEST_CONFIG_A.read(&config_set));
+
+        // Handles come from registering, so they always have the right type.
+        handle.update(true).unwrap();
+        assert!(handle.read());
+        assert!(TEST_CONFIG_A.read(&config_set));
+    }
+
+    #[test]
+    fn smoketest_to_json() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_LIST);
+        config_set.register(&TEST_CONFIG_DURATION);
+        let config_set = config_set.build();
+        config_set.update(&TEST_CONFIG_A, false);
+
+        let info: Vec<_> = config_set.iter().collect();
+        assert_eq!(info[0].name, "test_config_a");
+        assert_eq!(info[0].value, DynConfigValue::Bool(false));
+        assert_eq!(*info[0].default, DynConfigValue::Bool(true));
+
+        let json = config_set.to_json();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {
+                    "name": "test_config_a",
+                    "description": "A test configuration value.",
+                    "value": false,
+                    "default": true,
+                    "source": "runtime",
+                },
+                {
+                    "name": "test_config_duration",
+                    "description": "A test configuration value.",
+                    "value": "500ms",
+                    "default": "500ms",
+                    "source": "default",
+                },
+                {
+                    "name": "test_config_list",
+                    "description": "A test configuration value.",
+                    "value": ["target", ".git"],
+                    "default": ["target", ".git"],
+                    "source": "default",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_config_source() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_F64);
+        config_set.register(&TEST_CONFIG_LIST);
+        let config_set = config_set.build();
+        assert_eq!(
+            config_set.source("test_config_a").unwrap(),
+            ConfigSource::Default
+        );
+
+        let errors = config_set
+            .load_toml("[pb.config]\ntest_config_a = false\n")
+            .unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(
+            config_set.source("test_config_a").unwrap(),
+            ConfigSource::File(None)
+        );
+
+        let vars = [(OsString::from("PB_TEST_CONFIG_F64"), OsString::from("2.5"))];
+        assert!(config_set.apply_vars("PB_", vars).is_empty());
+        assert_eq!(
+            config_set.source("test_config_f64").unwrap(),
+            ConfigSource::Env(CompactString::const_new("PB_TEST_CONFIG_F64"))
+        );
+
+        config_set
+            .try_update_from("test_config_list", "a,b", ConfigSource::Cli)
+            .unwrap();
+        assert_eq!(
+            config_set.source("test_config_list").unwrap(),
+            ConfigSource::Cli
+        );
+
+        // A failed update doesn't change the source.
+        assert!(config_set.try_update("test_config_f64", "nope").is_err());
+        assert_eq!(
+            config_set.source("test_config_f64").unwrap().to_string(),
+            "env PB_TEST_CONFIG_F64"
+        );
+
+        config_set.update(&TEST_CONFIG_A, true);
+        assert_eq!(
+            config_set.source("test_config_a").unwrap(),
+            ConfigSource::Runtime
+        );
+        assert_eq!(config_set.to_json()[0]["source"], "runtime");
+    }
+
+    #[test]
+    fn smoketest_apply_cli() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_B);
+        config_set.register(&TEST_CONFIG_F64);
+        let config_set = config_set.build();
+
+        let errors = config_set.apply_cli([
+            "test_config_a=false",
+            "test_config_b=key=value",
+            "test_config_f64=oops",
+            "not_a_config=1",
+            "missing_equals",
+            "test_config_f64=2.5",
+        ]);
+        let errors: Vec<_> = errors.iter().map(|err| err.name.as_str()).collect();
+        assert_eq!(
+            errors,
+            ["test_config_f64", "not_a_config", "missing_equals"]
+        );
+
+        assert!(!TEST_CONFIG_A.read(&config_set));
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "key=value");
+        assert_eq!(TEST_CONFIG_F64.read(&config_set), 2.5);
+        assert_eq!(
+            config_set.source("test_config_b").unwrap(),
+            ConfigSource::Cli
+        );
+
+        assert!(parse_cfg_override("=1").is_err());
+        let help = config_set.cli_help();
+        assert!(help.starts_with("--cfg test_config_a=<value>\n"));
+        assert!(help.contains("(default: foobar)"));
+    }
+
+    #[test]
+    fn test_layers() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_B);
+        config_set.register(&TEST_CONFIG_F64);
+        let config_set = config_set.build();
+
+        let errors = config_set.push_layer(
+            ConfigLayer::Cli,
+            ConfigSource::Cli,
+            [("test_config_b", "from_cli")],
+        );
+        assert!(errors.is_empty());
+        let errors = config_set.push_layer(
+            ConfigLayer::User,
+            ConfigSource::File(None),
+            [
+                ("test_config_a", "false"),
+                ("test_config_b", "from_user"),
+                ("test_config_f64", "nope"),
+            ],
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].name, "test_config_f64");
+
+        // The CLI layer wins even though the user layer was pushed later.
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "from_cli");
+        assert!(!TEST_CONFIG_A.read(&config_set));
+        assert_eq!(
+            config_set.effective_layer("test_config_a").unwrap(),
+            Some(ConfigLayer::User)
+        );
+        assert_eq!(config_set.effective_layer("test_config_f64").unwrap(), None);
+
+        // A sub-workspace overrides the user layer, until it's popped.
+        config_set.push_layer(
+            ConfigLayer::Workspace,
+            ConfigSource::File(None),
+            [("test_config_a", "true")],
+        );
+        assert!(TEST_CONFIG_A.read(&config_set));
+        assert_eq!(config_set.pop_layer(), Some(ConfigLayer::Workspace));
+        assert!(!TEST_CONFIG_A.read(&config_set));
+
+        assert_eq!(config_set.pop_layer(), Some(ConfigLayer::User));
+        assert!(TEST_CONFIG_A.read(&config_set));
+        assert_eq!(
+            config_set.source("test_config_a").unwrap(),
+            ConfigSource::Default
+        );
+        assert_eq!(config_set.pop_layer(), Some(ConfigLayer::Cli));
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "foobar");
+        assert_eq!(config_set.pop_layer(), None);
+    }
+
+    #[test]
+    fn test_alias() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_B);
+        config_set.register_alias("old_config_b", &TEST_CONFIG_B);
+        let config_set = config_set.build();
+
+        config_set.try_update("old_config_b", "renamed").unwrap();
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "renamed");
+
+        let errors = config_set.push_layer(
+            ConfigLayer::Workspace,
+            ConfigSource::File(None),
+            [("old_config_b", "layered")],
+        );
+        assert!(errors.is_empty());
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "layered");
+        assert_eq!(
+            config_set.effective_layer("test_config_b").unwrap(),
+            Some(ConfigLayer::Workspace)
+        );
+        config_set.pop_layer();
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "foobar");
+
+        // Aliases aren't listed as configs.
+        assert_eq!(config_set.iter().count(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "refers to unregistered config")]
+    fn test_alias_unregistered() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register_alias("old_config_a", &TEST_CONFIG_A);
+        config_set.build();
+    }
+
+    #[test]
+    fn test_optional() {
+        static REMOTE_CACHE: Config<Option<&'static str>> =
+            Config::new("remote_cache", "A test configuration value.", None);
+        static JOBS: Config<Option<u64>> =
+            Config::new("jobs", "A test configuration value.", Some(8));
+
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&REMOTE_CACHE);
+        config_set.register(&JOBS);
+        let config_set = config_set.build();
+
+        assert_eq!(REMOTE_CACHE.read(&config_set), None);
+        assert_eq!(JOBS.read(&config_set), Some(8));
+        assert_eq!(config_set.to_string().lines().next(), Some("jobs => 8"));
+
+        config_set
+            .try_update("remote_cache", "https://cache.example.com")
+            .unwrap();
+        assert_eq!(
+            REMOTE_CACHE.read(&config_set).as_deref(),
+            Some("https://cache.example.com")
+        );
+        config_set.try_update("remote_cache", UNSET).unwrap();
+        assert_eq!(REMOTE_CACHE.read(&config_set), None);
+
+        config_set.try_update("jobs", "").unwrap();
+        assert_eq!(JOBS.read(&config_set), None);
+        assert!(config_set.try_update("jobs", "many").is_err());
+        assert_eq!(JOBS.read(&config_set), None);
+        config_set.update(&JOBS, Some(2));
+        assert_eq!(JOBS.read(&config_set), Some(2));
+
+        let json = config_set.to_json();
+        assert_eq!(json[1]["value"], serde_json::Value::Null);
+        assert_eq!(json[0]["default"], 8);
+
+        // The inner type is still checked when the config is unset.
+        static WRONG: Config<Option<bool>> = Config::new("remote_cache", "", None);
+        assert!(WRONG.try_read(&config_set).is_err());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_B);
+        config_set.register(&TEST_CONFIG_F64);
+        let config_set = config_set.build();
+
+        config_set.push_layer(
+            ConfigLayer::Cli,
+            ConfigSource::Cli,
+            [("test_config_a", "false"), ("test_config_b", "from_cli")],
+        );
+        config_set.update(&TEST_CONFIG_F64, 3.0);
+
+        config_set.reset("test_config_a").unwrap();
+        assert!(TEST_CONFIG_A.read(&config_set));
+        assert_eq!(config_set.effective_layer("test_config_a").unwrap(), None);
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "from_cli");
+        assert!(config_set.reset("not_a_config").is_err());
+
+        // Popping the layer doesn't bring back the reset value.
+        config_set.push_layer(
+            ConfigLayer::User,
+            ConfigSource::File(None),
+            [("test_config_a", "false")],
+        );
+        assert!(!TEST_CONFIG_A.read(&config_set));
+        config_set.pop_layer();
+        assert!(TEST_CONFIG_A.read(&config_set));
+
+        config_set.reset_all();
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "foobar");
+        assert_eq!(TEST_CONFIG_F64.read(&config_set), 1.5);
+        assert!(
+            config_set
+                .iter()
+                .all(|info| info.source == ConfigSource::Default)
+        );
+        assert_eq!(config_set.pop_layer(), None);
+    }
+
+    #[test]
+    fn test_reload_toml_layer() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("config.toml");
+
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_A);
+        config_set.register(&TEST_CONFIG_B);
+        config_set.register(&TEST_CONFIG_F64);
+        let config_set = config_set.build();
+
+        std::fs::write(
+            &path,
+            "[pb.config]\ntest_config_a = false\ntest_config_b = \"user\"\n",
+        )
+        .unwrap();
+        let errors = config_set
+            .push_toml_layer(ConfigLayer::User, &path)
+            .unwrap();
+        assert!(errors.is_empty());
+        config_set.push_layer(
+            ConfigLayer::Cli,
+            ConfigSource::Cli,
+            [("test_config_f64", "4.5")],
+        );
+
+        std::fs::write(
+            &path,
+            "[pb.config]\ntest_config_b = \"edited\"\ntest_config_f64 = 2.0\nnope = 1\n",
+        )
+        .unwrap();
+        let errors = config_set
+            .reload_toml_layer(ConfigLayer::User, &path)
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].name, "nope");
+
+        // Removed from the file, so back to the default.
+        assert!(TEST_CONFIG_A.read(&config_set));
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "edited");
+        // The CLI layer still wins.
+        assert_eq!(TEST_CONFIG_F64.read(&config_set), 4.5);
+
+        // The reloaded layer kept its position.
+        assert_eq!(config_set.pop_layer(), Some(ConfigLayer::Cli));
+        assert_eq!(config_set.pop_layer(), Some(ConfigLayer::User));
+        assert_eq!(config_set.pop_layer(), None);
+    }
+
+    #[test]
+    fn test_namespace() {
+        static MAX_HANDLES: Config<u64> =
+            Config::new("max_handles", "A test configuration value.", 64);
+        static JOBS: Config<u64> = Config::new("jobs", "A test configuration value.", 8);
+
+        let mut builder = ConfigSet::builder();
+        builder.register(&TEST_CONFIG_A);
+        let mut filesystem = builder.namespace("filesystem");
+        let max_handles = filesystem.register(&MAX_HANDLES);
+        filesystem.register_alias("handles", &MAX_HANDLES);
+        builder.namespace("engine").register(&JOBS);
+        let config_set = builder.build();
+
+        assert_eq!(config_set.name(&MAX_HANDLES), "filesystem.max_handles");
+        assert_eq!(MAX_HANDLES.name(), "max_handles");
+        assert_eq!(MAX_HANDLES.read(&config_set), 64);
+        config_set.try_update("engine.jobs", "4").unwrap();
+        assert_eq!(JOBS.read(&config_set), 4);
+        assert!(config_set.try_update("jobs", "4").is_err());
+        config_set.try_update("filesystem.handles", "128").unwrap();
+        assert_eq!(MAX_HANDLES.read(&config_set), 128);
+        assert_eq!(max_handles.name(), "filesystem.max_handles");
+        assert_eq!(max_handles.read(), 128);
+
+        let names: Vec<_> = config_set
+            .iter()
+            .map(|info| (info.namespace(), info.name))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                (Some("engine"), "engine.jobs"),
+                (Some("filesystem"), "filesystem.max_handles"),
+                (None, "test_config_a"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_namespace_toml_and_env() {
+        static MAX_HANDLES: Config<u64> =
+            Config::new("max_handles", "A test configuration value.", 64);
+
+        let mut builder = ConfigSet::builder();
+        builder.register(&TEST_CONFIG_A);
+        builder.namespace("filesystem").register(&MAX_HANDLES);
+        let config_set = builder.build();
+
+        let errors = config_set
+            .load_toml("[pb.config]\nfilesystem.max_handles = 5\n")
+            .unwrap();
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(MAX_HANDLES.read(&config_set), 5);
+
+        let errors = config_set
+            .load_toml(
+                "[pb.config]\ntest_config_a = false\n[pb.config.filesystem]\nmax_handles = 6\n",
+            )
+            .unwrap();
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(MAX_HANDLES.read(&config_set), 6);
+        assert!(!TEST_CONFIG_A.read(&config_set));
+
+        let vars = [
+            ("PB_FILESYSTEM_MAX_HANDLES", "7"),
+            ("PB_FILESYSTEM_MISSING", "1"),
+        ]
+        .map(|(key, value)| (OsString::from(key), OsString::from(value)));
+        let errors = config_set.apply_vars("PB_", vars);
+        assert_eq!(MAX_HANDLES.read(&config_set), 7);
+        let names: Vec<_> = errors.iter().map(|error| error.name.as_str()).collect();
+        assert_eq!(names, ["filesystem_missing"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "registered in multiple namespaces")]
+    fn test_namespace_conflict() {
+        static SHARED: Config<bool> = Config::new("shared", "A test configuration value.", true);
+
+        let mut builder = ConfigSet::builder();
+        builder.namespace("a").register(&SHARED);
+        builder.namespace("b").register(&SHARED);
+    }
+
+    #[test]
+    fn test_namespace_per_set() {
+        static SHARED: Config<u64> = Config::new("shared", "A test configuration value.", 1);
+
+        let mut builder = ConfigSet::builder();
+        builder.namespace("a").register(&SHARED);
+        let namespaced = builder.build();
+
+        // Registering in a namespace doesn't rename the config for other sets.
+        let mut builder = ConfigSet::builder();
+        builder.register(&SHARED);
+        let plain = builder.build();
+
+        assert_eq!(namespaced.name(&SHARED), "a.shared");
+        assert_eq!(plain.name(&SHARED), "shared");
+        namespaced.try_update("a.shared", "2").unwrap();
+        plain.try_update("shared", "3").unwrap();
+        assert_eq!(SHARED.read(&namespaced), 2);
+        assert_eq!(SHARED.read(&plain), 3);
+    }
 }
@@ -0,0 +1,193 @@
+//! Hot-reloading of config files into a live [`ConfigSet`].
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{DebounceEventResult, Debouncer};
+
+use crate::{ConfigLayer, ConfigSet};
+
+/// How long writes to a config file need to settle before we reload it.
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Watches TOML config files and reloads them into a [`ConfigSet`] when they change, returned
+/// from [`ConfigSet::watch_toml_layers`].
+///
+/// The files stop being watched when this is dropped.
+pub struct ConfigWatcher {
+    _debouncer: Debouncer<RecommendedWatcher>,
+}
+
+impl std::fmt::Debug for ConfigWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConfigWatcher").finish_non_exhaustive()
+    }
+}
+
+impl ConfigSet {
+    /// Watch the TOML config files in `files`, e.g. the workspace and user config, and reload
+    /// each into its layer with [`ConfigSet::reload_toml_layer`] whenever it changes.
+    ///
+    /// Entries that fail to apply, or files that can't be read or parsed, are logged and
+    /// skipped, leaving the previous values in place.
+    ///
+    /// # Errors
+    ///
+    /// * If the directory containing one of the files doesn't exist.
+    /// * If the platform file watcher fails to start.
+    pub fn watch_toml_layers<I>(&self, files: I) -> Result<ConfigWatcher, anyhow::Error>
+    where
+        I: IntoIterator<Item = (ConfigLayer, PathBuf)>,
+    {
+        let mut layers = BTreeMap::new();
+        let mut dirs = BTreeSet::new();
+        for (layer, path) in files {
+            // Watch the parent directory since editors often replace the file on save.
+            let (dir, event_path) = watch_path(&path)?;
+            dirs.insert(dir);
+            // Reload with the path as provided, so it matches the source of the pushed layer.
+            layers.insert(event_path, (layer, path));
+        }
+
+        let set = self.clone();
+        let mut debouncer =
+            notify_debouncer_mini::new_debouncer(DEBOUNCE_TIMEOUT, move |result| {
+                set.handle_events(&layers, result)
+            })?;
+        for dir in dirs {
+            debouncer
+                .watcher()
+                .watch(&dir, RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(ConfigWatcher {
+            _debouncer: debouncer,
+        })
+    }
+
+    fn handle_events(
+        &self,
+        layers: &BTreeMap<PathBuf, (ConfigLayer, PathBuf)>,
+        result: DebounceEventResult,
+    ) {
+        let events = match result {
+            Ok(events) => events,
+            Err(err) => {
+                tracing::warn!(?err, "error watching config files");
+                return;
+            }
+        };
+
+        let changed: BTreeSet<_> = events
+            .iter()
+            .filter_map(|event| layers.get(&event.path))
+            .collect();
+        for (layer, path) in changed {
+            match self.reload_toml_layer(*layer, path) {
+                Ok(errors) => {
+                    for error in errors {
+                        tracing::warn!(path = %path.display(), "skipping config, {error}");
+                    }
+                    tracing::info!(path = %path.display(), ?layer, "reloaded configs");
+                }
+                Err(err) => {
+                    tracing::warn!(path = %path.display(), "failed to reload configs, {err}");
+                }
+            }
+        }
+    }
+}
+
+/// Returns the canonical directory to watch for `path`, and the path of the file within it as
+/// it'll be reported in file events.
+///
+/// Note: The canonical path is only for matching events, layers are keyed by the path they
+/// were pushed with.
+fn watch_path(path: &Path) -> Result<(PathBuf, PathBuf), anyhow::Error> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("{} is not a file", path.display()))?;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let dir = std::fs::canonicalize(dir)
+        .map_err(|err| anyhow::anyhow!("failed to watch {}: {err}", dir.display()))?;
+    let path = dir.join(file_name);
+    Ok((dir, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Instant;
+
+    use crate::{ConfigLayer, ConfigSet, test::TEST_CONFIG_B};
+
+    fn wait_for(config_set: &ConfigSet, value: &str) {
+        let deadline = Instant::now() + std::time::Duration::from_secs(10);
+        while TEST_CONFIG_B.read(config_set) != value {
+            assert!(Instant::now() < deadline, "config was never reloaded");
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+    }
+
+    fn layer_count(config_set: &ConfigSet) -> usize {
+        std::iter::from_fn(|| config_set.pop_layer()).count()
+    }
+
+    #[test]
+    fn smoketest_watch_toml_layers() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("config.toml");
+        std::fs::write(&path, "[pb.config]\ntest_config_b = \"initial\"\n").unwrap();
+
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_B);
+        let config_set = config_set.build();
+        config_set
+            .push_toml_layer(ConfigLayer::Workspace, &path)
+            .unwrap();
+        assert_eq!(TEST_CONFIG_B.read(&config_set), "initial");
+
+        let _watcher = config_set
+            .watch_toml_layers([(ConfigLayer::Workspace, path.clone())])
+            .unwrap();
+        std::fs::write(&path, "[pb.config]\ntest_config_b = \"edited\"\n").unwrap();
+        wait_for(&config_set, "edited");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn smoketest_watch_toml_layers_symlinked_dir() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let real_dir = temp.path().join("real");
+        std::fs::create_dir(&real_dir).unwrap();
+        let link_dir = temp.path().join("link");
+        std::os::unix::fs::symlink(&real_dir, &link_dir).unwrap();
+
+        let path = link_dir.join("config.toml");
+        let write = |path: &Path, value: &str| {
+            std::fs::write(path, format!("[pb.config]\ntest_config_b = \"{value}\"\n")).unwrap()
+        };
+        write(&path, "initial");
+
+        let mut config_set = ConfigSet::builder();
+        config_set.register(&TEST_CONFIG_B);
+        let config_set = config_set.build();
+        config_set
+            .push_toml_layer(ConfigLayer::Workspace, &path)
+            .unwrap();
+
+        let _watcher = config_set
+            .watch_toml_layers([(ConfigLayer::Workspace, path.clone())])
+            .unwrap();
+        write(&path, "edited");
+        wait_for(&config_set, "edited");
+
+        // The existing layer was reloaded, not pushed again.
+        assert_eq!(layer_count(&config_set), 1);
+    }
+}
@@ -9,7 +9,6 @@ use pb_build_tree::BuildTree;
 use pb_cfg::ConfigSet;
 use pb_filesystem::locations::repositories::RepositoryDirectory;
 use pb_filesystem::{filesystem::Filesystem, locations::scratch::ScratchDirectory};
-use pb_ore::iter::LendingIterator;
 use pb_rules_host::HostState;
 
 use crate::defs::{WorkspaceSpec, WORKSPACE_FILENAME};
@@ -154,14 +153,7 @@ impl Engine {
 
         let tree = handle
             .tree()
-            .with_data(|stat, mut reader| {
-                let mut hasher = pb_ore::hash::Xxh3Hasher::new();
-                while let Some(read) = reader.next() {
-                    let data = read?;
-                    hasher.update(data);
-                }
-                Ok(hasher.digest())
-            })
+            .with_data(|_stat, mut reader| reader.digest::<pb_ore::hash::Xxh3Hasher>())
             .await?;
 
         // Ok(tree)
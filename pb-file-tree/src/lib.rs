@@ -1,13 +1,14 @@
-use std::{
-    path::PathBuf,
-    sync::{Arc, Mutex},
-};
+use std::{path::PathBuf, sync::Arc};
 
 use notify::{FsEventWatcher, RecursiveMode, Watcher};
 use notify_debouncer_mini::Debouncer;
 use pb_filesystem::{
-    FileStat, filesystem::Filesystem, handle::internal::ReadIterator, tree::MetadataTree,
+    FileStat,
+    filesystem::Filesystem,
+    handle::internal::ReadIterator,
+    tree::{MetadataTree, TreeEventKind},
 };
+use tokio::sync::Mutex;
 
 pub type FileWork<T> = Option<
     Arc<
@@ -50,7 +51,7 @@ impl<T: Clone + Send + 'static> ContinualMetadataTree<T> {
             .await?;
 
         let mut tree_builder = root_dir.tree();
-        if let Some(ignore) = ignore {
+        if let Some(ignore) = ignore.clone() {
             tree_builder = tree_builder.ignore(ignore);
         }
         // if let Some(work) = file_work.as_ref() {
@@ -64,13 +65,18 @@ impl<T: Clone + Send + 'static> ContinualMetadataTree<T> {
         let tree = Arc::new(Mutex::new(initial_tree));
 
         let tree_ = Arc::clone(&tree);
+        let filesystem_ = filesystem.clone();
+        let runtime = tokio::runtime::Handle::current();
         let watcher = std::thread::spawn(move || {
             let tree = tree_;
+            let filesystem = filesystem_;
             loop {
                 let events = match rx.recv() {
                     Ok(Ok(events)) => events,
                     Ok(Err(err)) => {
                         tracing::warn!(?err, "got an error, closing file watcher");
+                        // We can't tell what changed anymore.
+                        filesystem.clear_stat_cache();
                         return;
                     }
                     Err(err) => {
@@ -79,6 +85,26 @@ impl<T: Clone + Send + 'static> ContinualMetadataTree<T> {
                     }
                 };
                 tracing::info!(?events, "got events!");
+                for event in &events {
+                    filesystem.invalidate_stat(&event.path);
+                }
+
+                // The debouncer doesn't tell us what happened, so re-stat every path.
+                runtime.block_on(async {
+                    let mut tree = tree.lock().await;
+                    for event in &events {
+                        let mut builder = root_dir.tree();
+                        if let Some(ignore) = ignore.clone() {
+                            builder = builder.ignore(ignore);
+                        }
+                        let result = tree
+                            .apply_event(builder, &event.path, TreeEventKind::Changed)
+                            .await;
+                        if let Err(err) = result {
+                            tracing::warn!(?err, path = ?event.path, "failed to update tree");
+                        }
+                    }
+                });
             }
         });
 
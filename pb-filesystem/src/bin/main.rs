@@ -6,7 +6,7 @@ use std::{
 
 use notify::{RecursiveMode, Watcher};
 use pb_filesystem::filesystem::Filesystem;
-use pb_ore::iter::LendingIterator;
+use pb_ore::hash::Xxh3Hasher;
 
 use tracing_subscriber::EnvFilter;
 
@@ -35,12 +35,7 @@ async fn main() {
         .ignore(ignore_set)
         .with_data(move |_stat, mut reader| {
             num_files_.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            let mut hasher = xxhash_rust::xxh3::Xxh3Default::new();
-            while let Some(read) = reader.next() {
-                let data = read?;
-                hasher.update(data);
-            }
-            Ok(hasher.digest())
+            reader.digest::<Xxh3Hasher>()
         })
         .await
         .unwrap();
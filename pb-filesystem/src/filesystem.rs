@@ -1,14 +1,19 @@
-use futures::FutureExt;
+use pb_ore::iter::LendingIterator;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::future::Future;
-use std::path::PathBuf;
-use std::sync::Arc;
-use tokio::sync::Semaphore;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
-use crate::handle::{HandleBuilder, HandleLocation};
-use crate::platform::PlatformPathType;
+use crate::handle::{HandleBuilder, HandleLocation, TempFile};
+use crate::metrics::{FilesystemMetrics, MetricsSnapshot};
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::platform::UringDriver;
+use crate::platform::{OpenOptions, PlatformHandleType, PlatformPathType};
+use crate::stat_cache::StatCache;
 
 use super::handle::{DroppedHandle, Handle};
 use super::platform::{FilesystemPlatform, Platform, PlatformPath};
@@ -22,20 +27,101 @@ use super::FileStat;
 #[derive(Clone)]
 pub struct Filesystem {
     /// Pool to spawn blocking work on.
-    worker: FilesystemWorker,
+    pub(crate) worker: FilesystemWorker,
     /// The number of file system handles that are allowed to be open at once.
     permits: Arc<Semaphore>,
     /// Queue of handles that have been dropped but not yet closed.
     drops_tx: crossbeam::channel::Sender<DroppedHandle>,
+    /// Cache for [`Filesystem::stat`], if enabled.
+    stat_cache: Option<Arc<StatCache>>,
 }
 
 impl Filesystem {
     pub fn new(num_threads: usize, max_handles: usize) -> Self {
+        Filesystem::with_pool(WorkerPoolConfig::Rayon { num_threads }, max_handles)
+    }
+
+    /// Run blocking operations on the blocking pool of an existing Tokio runtime, instead of
+    /// spawning a dedicated thread pool.
+    pub fn new_with_tokio(runtime: tokio::runtime::Handle, max_handles: usize) -> Self {
+        Filesystem::with_pool(WorkerPoolConfig::Tokio(runtime), max_handles)
+    }
+
+    /// Run blocking operations on the pool described by `config`, allowing at most
+    /// `max_handles` handles to be open at once.
+    pub fn with_pool(config: WorkerPoolConfig, max_handles: usize) -> Self {
         let (drops_tx, drops_rx) = crossbeam::channel::unbounded();
         Filesystem {
-            worker: FilesystemWorker::new(num_threads, drops_rx),
+            worker: FilesystemWorker::new(config, drops_rx),
             permits: Arc::new(Semaphore::new(max_handles)),
             drops_tx,
+            stat_cache: None,
+        }
+    }
+
+    /// Submit the operations used when walking a tree (`stat`, `open`, and `read`) via an
+    /// `io_uring` with `entries` slots, so concurrent operations get batched together.
+    ///
+    /// Should be called before opening any handles, existing handles will continue to use the
+    /// thread pool. Fails if `io_uring` is not supported by the kernel.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn with_io_uring(mut self, entries: u32) -> Result<Self, crate::Error> {
+        let driver = UringDriver::new(entries)?;
+        self.worker.uring = Some(Arc::new(driver));
+        Ok(self)
+    }
+
+    /// Fail operations that take longer than `timeout` with [`crate::Error::TimedOut`], e.g.
+    /// because they're blocked on an unresponsive network filesystem.
+    ///
+    /// Should be called before opening any handles, existing handles will continue to wait
+    /// indefinitely.
+    pub fn with_operation_timeout(mut self, timeout: Duration) -> Self {
+        self.worker.timeout = Some(timeout);
+        self
+    }
+
+    /// Run operations, including those on handles opened from the returned [`Filesystem`], at
+    /// `priority`. Defaults to [`Priority::Interactive`].
+    ///
+    /// Call on a clone to get a [`Filesystem`] for a specific kind of work, e.g. executing
+    /// rules, that shares the worker pool and handle limit with the original.
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.worker.priority = priority;
+        self
+    }
+
+    /// Retry operations that fail with a transient error, see [`crate::Error::is_transient`],
+    /// according to `policy`. Defaults to [`RetryPolicy::default`].
+    ///
+    /// Only operations that are safe to repeat get retried, e.g. opening or stating a path,
+    /// but not reading from or writing to a handle.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.worker.retry_policy = policy;
+        self
+    }
+
+    /// Cache up to `capacity` results of [`Filesystem::stat`], keyed by path.
+    ///
+    /// Changes made through this [`Filesystem`] by path, e.g. [`Filesystem::remove_file`],
+    /// invalidate the cache, but any other change must be reported via
+    /// [`Filesystem::invalidate_stat`], e.g. from a file watcher.
+    pub fn with_stat_cache(mut self, capacity: usize) -> Self {
+        self.stat_cache = Some(Arc::new(StatCache::new(capacity)));
+        self
+    }
+
+    /// Invalidate any cached stat for `path`, everything beneath it, and its parent directory.
+    pub fn invalidate_stat(&self, path: &Path) {
+        if let Some(cache) = &self.stat_cache {
+            cache.invalidate(path);
+        }
+    }
+
+    /// Invalidate every cached stat, e.g. when a file watcher dropped events.
+    pub fn clear_stat_cache(&self) {
+        if let Some(cache) = &self.stat_cache {
+            cache.clear();
         }
     }
 
@@ -43,6 +129,12 @@ impl Filesystem {
         self.permits.available_permits()
     }
 
+    /// Returns a snapshot of the metrics collected for all operations run by this
+    /// [`Filesystem`], and any of its clones.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.worker.metrics.snapshot()
+    }
+
     pub fn open<P: Into<PathBuf>>(&self, path: P) -> HandleBuilder {
         HandleBuilder::new(
             self.worker.clone(),
@@ -52,6 +144,11 @@ impl Filesystem {
         )
     }
 
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = handle.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
     pub async fn close(&self, handle: Handle) -> Result<(), crate::Error> {
         let (handle, permit) = handle.into_parts();
         self.worker
@@ -61,10 +158,321 @@ impl Filesystem {
         Ok(())
     }
 
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(path = ?path, duration_us = tracing::field::Empty)
+    )]
     pub async fn stat(&self, path: PathBuf) -> Result<FileStat, crate::Error> {
+        let Some(cache) = &self.stat_cache else {
+            return self.stat_uncached(path).await;
+        };
+        if let Some(stat) = cache.get(&path) {
+            self.worker.metrics.record_stat_cache_hit();
+            return Ok(stat);
+        }
+        self.worker.metrics.record_stat_cache_miss();
+
+        let generation = cache.generation();
+        let stat = self.stat_uncached(path.clone()).await?;
+        cache.insert(path, stat, generation);
+        Ok(stat)
+    }
+
+    async fn stat_uncached(&self, path: PathBuf) -> Result<FileStat, crate::Error> {
+        let path = PlatformPathType::try_new(path)?;
+        self.worker.retry(|| self.worker.stat(path.clone())).await
+    }
+
+    /// Copy the file at `from` to `to`, which must not already exist.
+    ///
+    /// Uses the fastest method the platform supports, e.g. cloning the file on a copy-on-write
+    /// filesystem, and falls back to reading and writing the data.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(from = ?from, to = ?to, duration_us = tracing::field::Empty)
+    )]
+    pub async fn copy(&self, from: PathBuf, to: PathBuf) -> Result<(), crate::Error> {
+        let result = self.copy_inner(from, to.clone()).await;
+        // Invalidate even if we failed, we might have created the file.
+        self.invalidate_stat(&to);
+        result
+    }
+
+    async fn copy_inner(&self, from: PathBuf, to: PathBuf) -> Result<(), crate::Error> {
+        let from_path = PlatformPathType::try_new(from.clone())?;
+        let to_path = PlatformPathType::try_new(to.clone())?;
+        let result = self
+            .worker
+            .run(|| FilesystemPlatform::clonefile(from_path, to_path))
+            .await;
+        match result {
+            Ok(()) => return Ok(()),
+            Err(crate::Error::Unsupported) => (),
+            Err(err) => return Err(err),
+        }
+
+        let (from, _stat) = self.open(from).as_file().await?;
+        let (to, _stat) = self
+            .open(to)
+            .as_file()
+            .with_create()
+            .with_exclusive()
+            .await?;
+
+        let (from_inner, to_inner) = (from.to_inner(), to.to_inner());
+        let metrics = Arc::clone(&self.worker.metrics);
+        let result = self
+            .worker
+            .run(move || FilesystemPlatform::fcopy(from_inner, to_inner))
+            .await;
+        match result {
+            Ok(()) => (),
+            Err(crate::Error::Unsupported) => {
+                from.read_with(move |mut reader| {
+                    let mut offset = 0;
+                    while let Some(result) = reader.next() {
+                        let mut bytes = result?;
+                        while !bytes.is_empty() {
+                            let written = FilesystemPlatform::write(to_inner, bytes, offset)?;
+                            metrics.record_write(written);
+                            bytes = &bytes[written..];
+                            offset += written;
+                        }
+                    }
+                    Ok(())
+                })
+                .await?;
+            }
+            Err(err) => return Err(err),
+        }
+
+        from.close().await?;
+        to.close().await?;
+        Ok(())
+    }
+
+    /// Create a [`TempFile`] in the directory at `dir`, that only becomes visible once
+    /// persisted.
+    pub async fn tempfile_in<P: Into<PathBuf>>(&self, dir: P) -> Result<TempFile, crate::Error> {
+        let dir = dir.into();
+        let directory = self
+            .open(dir.clone())
+            .as_directory()
+            .diagnostics("tempfile-directory")
+            .await?;
+        TempFile::new(directory, dir).await
+    }
+
+    /// Remove the file, or symlink, at the provided path.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(path = ?path, duration_us = tracing::field::Empty)
+    )]
+    pub async fn remove_file(&self, path: PathBuf) -> Result<(), crate::Error> {
+        let platform_path = PlatformPathType::try_new(path.clone())?;
+        self.worker
+            .retry(|| {
+                let path = platform_path.clone();
+                self.worker.run(move || FilesystemPlatform::unlink(path))
+            })
+            .await?;
+        self.invalidate_stat(&path);
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(path = ?path, duration_us = tracing::field::Empty)
+    )]
+    pub async fn readlink(&self, path: PathBuf) -> Result<PathBuf, crate::Error> {
         let path = PlatformPathType::try_new(path)?;
-        let result = self.worker.run(|| FilesystemPlatform::stat(path)).await?;
-        Ok(result)
+        let target = self
+            .worker
+            .retry(|| {
+                let path = path.clone();
+                self.worker.run(move || FilesystemPlatform::readlink(path))
+            })
+            .await?;
+        Ok(PathBuf::from(target.into_inner()))
+    }
+
+    /// Returns the absolute path of `path`, which must exist, with all symlinks resolved.
+    ///
+    /// Two paths that refer to the same file canonicalize to the same path, unless the file
+    /// is hardlinked.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(path = ?path, duration_us = tracing::field::Empty)
+    )]
+    pub async fn canonicalize(&self, path: PathBuf) -> Result<PathBuf, crate::Error> {
+        let path = PlatformPathType::try_new(path)?;
+        let resolved = self
+            .worker
+            .retry(|| {
+                let path = path.clone();
+                self.worker.run(move || FilesystemPlatform::realpath(path))
+            })
+            .await?;
+        Ok(PathBuf::from(resolved.into_inner()))
+    }
+
+    /// Removes `.` components, and resolves `..` components against the preceding component,
+    /// without accessing the filesystem.
+    ///
+    /// Note: Unlike [`Filesystem::canonicalize`] symlinks aren't resolved, so `link/..` is
+    /// normalized to the directory containing `link` and not the parent of its target.
+    pub fn normalize(path: &Path) -> PathBuf {
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => (),
+                Component::ParentDir => match normalized.components().next_back() {
+                    // The parent of the root is the root.
+                    Some(Component::RootDir | Component::Prefix(_)) => (),
+                    Some(Component::Normal(_)) => {
+                        normalized.pop();
+                    }
+                    // Leading `..`s of a relative path can't be resolved.
+                    Some(Component::ParentDir | Component::CurDir) | None => {
+                        normalized.push(Component::ParentDir);
+                    }
+                },
+                component => normalized.push(component),
+            }
+        }
+        if normalized.as_os_str().is_empty() {
+            normalized.push(Component::CurDir);
+        }
+        normalized
+    }
+}
+
+/// Priority of filesystem operations.
+///
+/// When the worker pool is busy, queued operations with a higher priority run before those
+/// with a lower priority, regardless of when they were queued.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Work nothing is waiting on, e.g. scanning the entire workspace.
+    Background,
+    /// Work needed by currently executing rules.
+    Rule,
+    /// Work a user is actively waiting on.
+    #[default]
+    Interactive,
+}
+
+impl Priority {
+    /// All of the priorities, from highest to lowest.
+    const ALL: [Priority; 3] = [Priority::Interactive, Priority::Rule, Priority::Background];
+}
+
+/// Thread pool that blocking filesystem operations get run on, see [`Filesystem::with_pool`].
+#[derive(Debug, Clone)]
+pub enum WorkerPoolConfig {
+    /// A dedicated [`rayon`] pool with `num_threads` threads.
+    Rayon { num_threads: usize },
+    /// The blocking pool of an existing Tokio runtime.
+    ///
+    /// Note: One of the runtime's blocking threads is used to close dropped handles, for as
+    /// long as the [`Filesystem`] is alive.
+    Tokio(tokio::runtime::Handle),
+}
+
+/// Policy for retrying operations that fail with a transient error, see
+/// [`Filesystem::with_retry_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of times to retry an operation after it first fails.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for every retry after that.
+    pub initial_backoff: Duration,
+    /// Maximum delay between two retries.
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Never retry, errors are returned immediately.
+    pub const NEVER: RetryPolicy = RetryPolicy {
+        max_retries: 0,
+        initial_backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+    };
+
+    /// Returns how long to wait before retry number `retry`, starting at 0.
+    ///
+    /// The delay is chosen at random between zero and the exponential backoff, so operations
+    /// that failed at the same time, e.g. because a network filesystem hiccuped, don't all
+    /// retry at the same time.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        backoff.mul_f64(rand::random::<f64>())
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Close a handle that was opened for a caller that went away, see
+/// [`FilesystemWorker::run_with_cleanup`].
+pub(crate) fn close_abandoned(handle: PlatformHandleType) {
+    if let Err(err) = FilesystemPlatform::close(handle) {
+        tracing::warn!("failed to close abandoned handle, err: {err}");
+    }
+}
+
+/// Close [`DroppedHandle`]s as they're received, until all of the senders go away.
+fn close_dropped_handles(drops_rx: crossbeam::channel::Receiver<DroppedHandle>) {
+    let mut handles = Vec::new();
+
+    loop {
+        // Block until there is a dropped handle.
+        match drops_rx.recv() {
+            Ok(dropped_handle) => handles.push(dropped_handle),
+            Err(notice) => {
+                tracing::info!(?notice, "drops sender went away, shutting down");
+                return;
+            }
+        }
+
+        // Collect all of the currently queued handles, if any.
+        handles.extend(drops_rx.try_iter());
+
+        // Drop all of the handles.
+        for dropped_handle in handles.drain(..) {
+            let DroppedHandle {
+                inner,
+                permit,
+                diagnostics,
+            } = dropped_handle;
+
+            // Close the handle.
+            let result = FilesystemPlatform::close(inner);
+            // Drop our permit.
+            drop(permit);
+
+            match result {
+                Ok(()) => tracing::info!("async closed handle for: {diagnostics:?}"),
+                Err(err) => {
+                    tracing::warn!("failed to async close handle for: {diagnostics:?}, err: {err}")
+                }
+            }
+        }
     }
 }
 
@@ -76,93 +484,341 @@ impl Filesystem {
 pub struct FilesystemWorker {
     /// Thread pool for spawning I/O.
     pool: Arc<WorkerPool>,
+    /// Operations waiting for a thread in the pool, shared by all priorities.
+    queue: Arc<PriorityQueue>,
+    /// Priority that operations get queued at.
+    priority: Priority,
+    /// Maximum amount of time to wait for an operation to complete, if any.
+    timeout: Option<Duration>,
+    /// How to retry operations that fail with a transient error.
+    retry_policy: RetryPolicy,
+    /// Counters and latencies for the operations we run.
+    pub(crate) metrics: Arc<FilesystemMetrics>,
+    /// Ring to submit batchable operations to, if enabled.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    uring: Option<Arc<UringDriver>>,
 }
 
 impl FilesystemWorker {
-    fn new(size: usize, drops_rx: crossbeam::channel::Receiver<DroppedHandle>) -> Self {
-        let thread_pool = rayon::ThreadPoolBuilder::new()
-            .num_threads(size)
-            .build()
-            .expect("failed to create threadpool");
-
-        thread_pool.spawn(move || {
-            let mut handles = Vec::new();
-
-            loop {
-                // Block until there is a dropped handle.
-                match drops_rx.recv() {
-                    Ok(dropped_handle) => handles.push(dropped_handle),
-                    Err(notice) => {
-                        tracing::info!(?notice, "drops sender went away, shutting down");
-                        return;
-                    }
-                }
-
-                // Collect all of the currently queued handles, if any.
-                handles.extend(drops_rx.try_iter());
-
-                // Drop all of the handles.
-                for dropped_handle in handles.drain(..) {
-                    let DroppedHandle {
-                        inner,
-                        permit,
-                        diagnostics,
-                    } = dropped_handle;
-
-                    // Close the handle.
-                    let result = FilesystemPlatform::close(inner);
-                    // Drop our permit.
-                    drop(permit);
-
-                    match result {
-                        Ok(()) => tracing::info!("async closed handle for: {diagnostics:?}"),
-                        Err(err) => tracing::warn!(
-                            "failed to async close handle for: {diagnostics:?}, err: {err}"
-                        ),
-                    }
+    fn new(
+        config: WorkerPoolConfig,
+        drops_rx: crossbeam::channel::Receiver<DroppedHandle>,
+    ) -> Self {
+        let pool = match config {
+            WorkerPoolConfig::Rayon { num_threads } => {
+                let thread_pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(num_threads)
+                    .build()
+                    .expect("failed to create threadpool");
+                thread_pool.spawn(move || close_dropped_handles(drops_rx));
+                WorkerPool::Rayon { pool: thread_pool }
+            }
+            WorkerPoolConfig::Tokio(runtime) => {
+                let drop_task = runtime.spawn_blocking(move || close_dropped_handles(drops_rx));
+                WorkerPool::Tokio {
+                    runtime,
+                    _drop_task: drop_task,
                 }
             }
-        });
+        };
 
-        let pool = WorkerPool::Rayon { pool: thread_pool };
         FilesystemWorker {
             pool: Arc::new(pool),
+            queue: Arc::default(),
+            priority: Priority::default(),
+            timeout: None,
+            retry_policy: RetryPolicy::default(),
+            metrics: Arc::default(),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            uring: None,
+        }
+    }
+
+    /// Get the metadata for the file at `path`.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(duration_us = tracing::field::Empty)
+    )]
+    pub async fn stat(&self, path: PlatformPathType) -> Result<FileStat, crate::Error> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(uring) = &self.uring {
+            return uring.stat(path).await;
+        }
+        self.run(|| FilesystemPlatform::stat(path)).await
+    }
+
+    /// Open the file at `path`.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(duration_us = tracing::field::Empty)
+    )]
+    pub async fn open(
+        &self,
+        path: PlatformPathType,
+        options: OpenOptions,
+    ) -> Result<PlatformHandleType, crate::Error> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(uring) = &self.uring {
+            let handle = uring.open(path, options).await?;
+            self.metrics.record_open();
+            return Ok(handle);
+        }
+        let handle = self
+            .run_with_cleanup(
+                move || FilesystemPlatform::open(path, options),
+                close_abandoned,
+            )
+            .await?;
+        self.metrics.record_open();
+        Ok(handle)
+    }
+
+    /// Open the file at `path`, returning the handle and its metadata.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(duration_us = tracing::field::Empty)
+    )]
+    pub async fn open_and_stat(
+        &self,
+        path: PlatformPathType,
+        options: OpenOptions,
+    ) -> Result<(PlatformHandleType, FileStat), crate::Error> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(uring) = &self.uring {
+            let handle = uring.open(path, options).await?;
+            self.metrics.record_open();
+            return match uring.fstat(handle).await {
+                Ok(stat) => Ok((handle, stat)),
+                Err(err) => {
+                    // Don't leak the handle, but report the original error.
+                    let _ = self.run(move || FilesystemPlatform::close(handle)).await;
+                    Err(err)
+                }
+            };
+        }
+        let (handle, stat) = self
+            .run_with_cleanup(
+                move || {
+                    let handle = FilesystemPlatform::open(path, options)?;
+                    let stat = FilesystemPlatform::fstat(handle)?;
+                    Ok((handle, stat))
+                },
+                |(handle, _stat)| close_abandoned(handle),
+            )
+            .await?;
+        self.metrics.record_open();
+        Ok((handle, stat))
+    }
+
+    /// Read up to `len` bytes from `handle` starting at `offset`.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(duration_us = tracing::field::Empty)
+    )]
+    pub async fn read(
+        &self,
+        handle: PlatformHandleType,
+        len: usize,
+        offset: usize,
+    ) -> Result<Vec<u8>, crate::Error> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(uring) = &self.uring {
+            let buf = uring.read(handle, len, offset).await?;
+            self.metrics.record_read(buf.len());
+            return Ok(buf);
+        }
+        let metrics = Arc::clone(&self.metrics);
+        self.run(move || {
+            let mut buf = vec![0; len];
+            let read = FilesystemPlatform::read(handle, &mut buf[..], offset)?;
+            metrics.record_read(read);
+            buf.truncate(read);
+            Ok(buf)
+        })
+        .await
+    }
+
+    /// Run the operation returned by `op` until it succeeds, fails with an error that isn't
+    /// transient, or we run out of retries.
+    ///
+    /// Note: `op` must be safe to repeat.
+    pub async fn retry<T, F, Fut>(&self, mut op: F) -> Result<T, crate::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, crate::Error>>,
+    {
+        let mut retries = 0;
+        loop {
+            match op().await {
+                Err(err) if err.is_transient() && retries < self.retry_policy.max_retries => {
+                    let backoff = self.retry_policy.backoff(retries);
+                    tracing::warn!(
+                        retries,
+                        ?backoff,
+                        "retrying filesystem operation, err: {err}"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
         }
     }
 
-    pub fn run<T, W>(&self, work: W) -> impl Future<Output = T> + 'static
+    /// Wait for a permit to open a handle, recording how long we waited.
+    pub(crate) async fn acquire_permit(&self, permits: Arc<Semaphore>) -> OwnedSemaphorePermit {
+        let start = Instant::now();
+        let permit = Semaphore::acquire_owned(permits)
+            .await
+            .expect("failed to acquire permit");
+        self.metrics.record_permit_wait(start.elapsed());
+        permit
+    }
+
+    /// Run `work` on the pool, failing with [`crate::Error::TimedOut`] if it doesn't complete
+    /// within the operation timeout, if one is configured.
+    ///
+    /// Dropping the returned future before `work` has started cancels it. Once started
+    /// blocking I/O can't be interrupted, so `work` runs to completion in the background.
+    pub fn run<T, W>(&self, work: W) -> impl Future<Output = Result<T, crate::Error>> + 'static
     where
         T: Send + 'static,
-        W: FnOnce() -> T + Send + 'static,
+        W: FnOnce() -> Result<T, crate::Error> + Send + 'static,
     {
-        self.run_typed(work)
-            .map(|result| result.expect("worker pool shutting down"))
+        self.run_inner(self.timeout, work, drop)
     }
 
-    /// TODO document why this exists, and why it's nice to be able to name our return type.
+    /// Like [`FilesystemWorker::run`], but calls `cleanup` with the result of `work` if the
+    /// caller went away before it completed, e.g. to close a handle that was opened.
+    pub fn run_with_cleanup<T, W, C>(
+        &self,
+        work: W,
+        cleanup: C,
+    ) -> impl Future<Output = Result<T, crate::Error>> + 'static
+    where
+        T: Send + 'static,
+        W: FnOnce() -> Result<T, crate::Error> + Send + 'static,
+        C: FnOnce(T) + Send + 'static,
+    {
+        self.run_inner(self.timeout, work, cleanup)
+    }
+
+    /// Like [`FilesystemWorker::run`], but with a timeout specific to this operation.
+    pub fn run_with_timeout<T, W>(
+        &self,
+        timeout: Option<Duration>,
+        work: W,
+    ) -> impl Future<Output = Result<T, crate::Error>> + 'static
+    where
+        T: Send + 'static,
+        W: FnOnce() -> Result<T, crate::Error> + Send + 'static,
+    {
+        self.run_inner(timeout, work, drop)
+    }
+
+    fn run_inner<T, W, C>(
+        &self,
+        timeout: Option<Duration>,
+        work: W,
+        cleanup: C,
+    ) -> impl Future<Output = Result<T, crate::Error>> + 'static
+    where
+        T: Send + 'static,
+        W: FnOnce() -> Result<T, crate::Error> + Send + 'static,
+        C: FnOnce(T) + Send + 'static,
+    {
+        let result_rx = self.dispatch(work, |result| {
+            if let Ok(value) = result {
+                cleanup(value);
+            }
+        });
+        async move {
+            let result = match timeout {
+                None => result_rx.await,
+                Some(timeout) => match tokio::time::timeout(timeout, result_rx).await {
+                    Ok(result) => result,
+                    Err(_elapsed) => return Err(crate::Error::TimedOut),
+                },
+            };
+            result.expect("worker pool shutting down")
+        }
+    }
+
+    /// Like [`FilesystemWorker::run`], but returns the receiving end of a channel so callers
+    /// can name the type of the returned future.
+    ///
+    /// Dropping the receiver before `work` has started cancels it, and no timeout is applied.
     pub fn run_typed<T, W>(&self, work: W) -> tokio::sync::oneshot::Receiver<T>
     where
         T: Send + 'static,
         W: FnOnce() -> T + Send + 'static,
+    {
+        self.dispatch(work, drop)
+    }
+
+    /// Run `work` on the pool, unless the receiver has already been dropped, calling
+    /// `abandoned` with the result if the receiver is dropped while `work` is running.
+    fn dispatch<T, W, A>(&self, work: W, abandoned: A) -> tokio::sync::oneshot::Receiver<T>
+    where
+        T: Send + 'static,
+        W: FnOnce() -> T + Send + 'static,
+        A: FnOnce(T) + Send + 'static,
     {
         let (tx, rx) = tokio::sync::oneshot::channel();
+        let metrics = Arc::clone(&self.metrics);
+        // Run the work in the span of the operation that dispatched it, if any.
+        let span = tracing::Span::current();
+        let queued = Instant::now();
+        self.queue.push(self.priority, move || {
+            let started = Instant::now();
+            metrics.record_queue_wait(started.duration_since(queued));
+            if tx.is_closed() {
+                tracing::debug!("skipping cancelled filesystem operation");
+                return;
+            }
+            let result = span.in_scope(work);
+            let duration = started.elapsed();
+            metrics.record_operation(duration);
+            // No-op if the span doesn't have a `duration_us` field.
+            span.record("duration_us", duration.as_micros());
+            // Release the span before waking the caller, so it can close.
+            drop(span);
+            if let Err(result) = tx.send(result) {
+                tracing::warn!("filesystem operation completed after its caller went away");
+                abandoned(result);
+            }
+        });
+        // Every spawned task runs exactly one operation, whichever has the highest priority
+        // when a thread becomes available, so every queued operation eventually runs.
+        let queue = Arc::clone(&self.queue);
+        self.spawn(move || queue.run_next());
+        rx
+    }
+
+    /// Returns a [`FilesystemWorker`] that shares the same pool but queues operations at
+    /// `priority`.
+    pub fn with_priority(&self, priority: Priority) -> FilesystemWorker {
+        FilesystemWorker {
+            priority,
+            ..self.clone()
+        }
+    }
+
+    /// Run `work` on the pool without waiting for it to complete, it's never cancelled.
+    pub fn spawn<W>(&self, work: W)
+    where
+        W: FnOnce() + Send + 'static,
+    {
         match &*self.pool {
             WorkerPool::Tokio { runtime, .. } => {
-                runtime.spawn_blocking(|| {
-                    let result = work();
-                    // We don't care about the sender going away.
-                    let _ = tx.send(result);
-                });
-            }
-            WorkerPool::Rayon { pool } => {
-                pool.spawn(|| {
-                    let result = work();
-                    // We don't care about the sender going away.
-                    let _ = tx.send(result);
-                });
+                runtime.spawn_blocking(work);
             }
+            WorkerPool::Rayon { pool } => pool.spawn(work),
         }
-        rx
     }
 }
 
@@ -172,6 +828,41 @@ impl fmt::Debug for FilesystemWorker {
     }
 }
 
+/// An operation queued to run on a [`WorkerPool`].
+type QueuedWork = Box<dyn FnOnce() + Send>;
+
+/// Operations waiting to run on a [`WorkerPool`], by [`Priority`].
+#[derive(Default)]
+struct PriorityQueue {
+    /// Operations for each priority, in the order of [`Priority::ALL`].
+    queues: Mutex<[VecDeque<QueuedWork>; Priority::ALL.len()]>,
+}
+
+impl PriorityQueue {
+    fn push<W>(&self, priority: Priority, work: W)
+    where
+        W: FnOnce() + Send + 'static,
+    {
+        let idx = Priority::ALL
+            .iter()
+            .position(|p| *p == priority)
+            .expect("all priorities");
+        let mut queues = self.queues.lock().expect("lock poisoned");
+        queues[idx].push_back(Box::new(work));
+    }
+
+    /// Run the queued operation with the highest priority, if any.
+    fn run_next(&self) {
+        let work = {
+            let mut queues = self.queues.lock().expect("lock poisoned");
+            queues.iter_mut().find_map(|queue| queue.pop_front())
+        };
+        if let Some(work) = work {
+            work();
+        }
+    }
+}
+
 #[derive(Debug)]
 enum WorkerPool {
     Tokio {
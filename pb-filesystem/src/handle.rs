@@ -1,22 +1,28 @@
 //! Module that defines a strongly typed filesystem handle.
 
 use futures::future::{Future, TryFutureExt};
+use pb_ore::hash::StreamingHasher;
+use pb_ore::iter::LendingIterator;
 use pb_types::Timespec;
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::Instrument;
 
 use std::borrow::Cow;
 use std::future::IntoFuture;
+use std::io::IoSlice;
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::ptr::NonNull;
 use std::sync::Arc;
 
-use crate::filesystem::BlockPool;
+use crate::filesystem::{close_abandoned, BlockPool};
 use crate::platform::{OpenOptions, PlatformFilenameType, PlatformPathType};
 use crate::{DirectoryEntry, FileType};
 
 use super::filesystem::FilesystemWorker;
 use super::platform::{
-    FilesystemPlatform, Platform, PlatformFilename, PlatformHandleType, PlatformPath,
+    FilesystemPlatform, Platform, PlatformDirStreamType, PlatformFilename, PlatformHandleType,
+    PlatformPath,
 };
 use super::FileStat;
 
@@ -25,6 +31,9 @@ pub type FileHandle = Handle<FileKind>;
 /// [`Handle`] to a directory.
 pub type DirectoryHandle = Handle<DirectoryKind>;
 
+/// Largest xattr value [`Handle::getxattr`] can read, matches `XATTR_SIZE_MAX` on Linux.
+pub const MAX_XATTR_VALUE_SIZE: usize = 64 * 1024;
+
 /// Enum wrapper around all the different kinds of handles.
 pub enum HandleKind {
     File(FileHandle),
@@ -66,6 +75,11 @@ pub struct Handle<Kind = UnknownKind> {
 
 impl<A> Handle<A> {
     /// Get metadata about this handle.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
     pub async fn stat(&self) -> Result<FileStat, crate::Error> {
         let inner = self.to_inner();
         let result = self
@@ -76,6 +90,11 @@ impl<A> Handle<A> {
     }
 
     /// Flush any buffered state of this file out to disk.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
     pub async fn fsync(&self) -> Result<(), crate::Error> {
         let inner = self.to_inner();
         let () = self
@@ -86,6 +105,11 @@ impl<A> Handle<A> {
     }
 
     /// Set the specified xattr on the file.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
     pub async fn setxattr(&mut self, name: String, data: Vec<u8>) -> Result<(), crate::Error> {
         let inner = self.to_inner();
         let name = PlatformFilenameType::try_new(name)?;
@@ -96,12 +120,101 @@ impl<A> Handle<A> {
         Ok(())
     }
 
+    /// Set the mode, i.e. permissions, of the file.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn set_mode(&mut self, mode: u32) -> Result<(), crate::Error> {
+        let inner = self.to_inner();
+        let () = self
+            .worker
+            .run(move || FilesystemPlatform::fchmod(inner, mode))
+            .await?;
+        Ok(())
+    }
+
+    /// Set the user and group that own the file.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn set_owner(&mut self, user: u32, group: u32) -> Result<(), crate::Error> {
+        let inner = self.to_inner();
+        let () = self
+            .worker
+            .run(move || FilesystemPlatform::fchown(inner, user, group))
+            .await?;
+        Ok(())
+    }
+
+    /// List the names of all the xattrs on the file.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn listxattr(&self) -> Result<Vec<String>, crate::Error> {
+        let inner = self.to_inner();
+        let names = self
+            .worker
+            .run(move || FilesystemPlatform::flistxattr(inner))
+            .await?;
+        Ok(names)
+    }
+
+    /// Read the value of the specified xattr on the file.
+    ///
+    /// Note: Values larger than [`MAX_XATTR_VALUE_SIZE`] fail to be read.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn getxattr(&self, name: String) -> Result<Vec<u8>, crate::Error> {
+        let inner = self.to_inner();
+        let name = PlatformFilenameType::try_new(name)?;
+        let data = self
+            .worker
+            .run(move || {
+                let mut buf = vec![0u8; MAX_XATTR_VALUE_SIZE];
+                let len = FilesystemPlatform::fgetxattr(inner, name, &mut buf[..])?;
+                buf.truncate(len);
+                Ok(buf)
+            })
+            .await?;
+        Ok(data)
+    }
+
+    /// Remove the specified xattr from the file.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn removexattr(&mut self, name: String) -> Result<(), crate::Error> {
+        let inner = self.to_inner();
+        let name = PlatformFilenameType::try_new(name)?;
+        let () = self
+            .worker
+            .run(move || FilesystemPlatform::fremovexattr(inner, name))
+            .await?;
+        Ok(())
+    }
+
     /// Set the mtime on the file.
     pub async fn setmtime(&mut self, _time: Timespec) -> Result<(), crate::Error> {
         todo!()
     }
 
     /// Close the filesystem handle, releasing its resources.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
     pub async fn close(mut self) -> Result<(), crate::Error> {
         let inner = self
             .inner
@@ -146,6 +259,11 @@ impl<A> Handle<A> {
 
 impl Handle<DirectoryKind> {
     /// List all of the files in the directory.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
     pub async fn list(&self) -> Result<Vec<DirectoryEntry>, crate::Error> {
         let inner = self.to_inner();
         let files = self
@@ -155,6 +273,37 @@ impl Handle<DirectoryKind> {
         Ok(files)
     }
 
+    /// List the files in the directory in batches, without reading them all into memory.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn list_batched(&self) -> Result<DirectoryStream, crate::Error> {
+        let permit = self
+            .worker
+            .acquire_permit(Arc::clone(&self.kind.permits))
+            .await;
+        let inner = self.to_inner();
+        let stream = self
+            .worker
+            .run_with_cleanup(
+                move || FilesystemPlatform::opendir(inner),
+                |stream| {
+                    if let Err(err) = FilesystemPlatform::closedir(stream) {
+                        tracing::warn!("failed to close abandoned directory stream, err: {err}");
+                    }
+                },
+            )
+            .await?;
+
+        Ok(DirectoryStream {
+            stream: Some(stream),
+            permit: Some(permit),
+            worker: self.worker.clone(),
+        })
+    }
+
     /// Open the file relative to this directory.
     pub fn openat(&self, filename: String) -> HandleBuilder {
         let directory = self.to_inner();
@@ -170,6 +319,11 @@ impl Handle<DirectoryKind> {
     }
 
     /// Stat the file relative to this directory.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
     pub async fn fstatat(&self, filename: String) -> Result<FileStat, crate::Error> {
         let inner = self.to_inner();
         let name = PlatformFilenameType::try_new(filename)?;
@@ -179,26 +333,226 @@ impl Handle<DirectoryKind> {
             .await?;
         Ok(stat)
     }
+
+    /// Remove the file, or symlink, relative to this directory.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn removeat(&self, filename: String) -> Result<(), crate::Error> {
+        let inner = self.to_inner();
+        let name = PlatformFilenameType::try_new(filename)?;
+        self.worker
+            .run(move || FilesystemPlatform::unlinkat(inner, name))
+            .await?;
+        Ok(())
+    }
+
+    /// Remove the empty directory relative to this directory.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn removedirat(&self, filename: String) -> Result<(), crate::Error> {
+        let inner = self.to_inner();
+        let name = PlatformFilenameType::try_new(filename)?;
+        self.worker
+            .run(move || FilesystemPlatform::rmdirat(inner, name))
+            .await?;
+        Ok(())
+    }
+
+    /// Create a hard link at `to_filename` in `to_directory` for the file relative to this
+    /// directory.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn hardlinkat(
+        &self,
+        filename: String,
+        to_directory: &DirectoryHandle,
+        to_filename: String,
+    ) -> Result<(), crate::Error> {
+        let from_inner = self.to_inner();
+        let from_name = PlatformFilenameType::try_new(filename)?;
+        let to_inner = to_directory.to_inner();
+        let to_name = PlatformFilenameType::try_new(to_filename)?;
+        self.worker
+            .run(move || FilesystemPlatform::linkat(from_inner, from_name, to_inner, to_name))
+            .await?;
+        Ok(())
+    }
+
+    /// Read the target of the symlink relative to this directory.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn readlinkat(&self, filename: String) -> Result<PathBuf, crate::Error> {
+        let inner = self.to_inner();
+        let name = PlatformFilenameType::try_new(filename)?;
+        let target = self
+            .worker
+            .run(move || FilesystemPlatform::readlinkat(inner, name))
+            .await?;
+        Ok(PathBuf::from(target.into_inner()))
+    }
+
+    /// Create a symlink relative to this directory that points to `target`.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn symlinkat(&self, target: PathBuf, filename: String) -> Result<(), crate::Error> {
+        let inner = self.to_inner();
+        let target = PlatformPathType::try_new(target)?;
+        let name = PlatformFilenameType::try_new(filename)?;
+        self.worker
+            .run(move || FilesystemPlatform::symlinkat(target, inner, name))
+            .await?;
+        Ok(())
+    }
 }
 
 impl Handle<FileKind> {
     /// Write the provided data to the file.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
     pub async fn write(&mut self, data: Vec<u8>, offset: usize) -> Result<(), crate::Error> {
         let inner = self.to_inner();
-        let _result = self
-            .worker
-            .run(move || FilesystemPlatform::write(inner, &data[..], offset))
+        let metrics = Arc::clone(&self.worker.metrics);
+        self.worker
+            .run(move || {
+                let written = FilesystemPlatform::write(inner, &data[..], offset)?;
+                metrics.record_write(written);
+                Ok(())
+            })
+            .await
+    }
+
+    /// Write all of `bufs`, in order, starting at `offset`.
+    ///
+    /// Writes as many of the buffers as possible with a single syscall, which is cheaper than
+    /// calling [`FileHandle::write`] for each one.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn write_vectored(
+        &mut self,
+        bufs: Vec<Vec<u8>>,
+        offset: usize,
+    ) -> Result<(), crate::Error> {
+        let inner = self.to_inner();
+        let metrics = Arc::clone(&self.worker.metrics);
+        self.worker
+            .run(move || {
+                let mut slices: Vec<_> = bufs.iter().map(|buf| IoSlice::new(buf)).collect();
+                let mut remaining = &mut slices[..];
+                let mut offset = offset;
+                while !remaining.is_empty() {
+                    let written = FilesystemPlatform::writev(inner, remaining, offset)?;
+                    metrics.record_write(written);
+                    offset += written;
+                    IoSlice::advance_slices(&mut remaining, written);
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    /// Reserve disk space for the first `len` bytes of the file, extending it with zeros if
+    /// necessary.
+    ///
+    /// Useful when the final size of a file is known up front, e.g. a download, so we fail
+    /// with [`crate::Error::NoSpace`] before writing anything instead of part way through.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn allocate(&mut self, len: usize) -> Result<(), crate::Error> {
+        let inner = self.to_inner();
+        self.worker
+            .run(move || FilesystemPlatform::fallocate(inner, len))
             .await?;
         Ok(())
     }
 
+    /// Truncate, or extend with zeros, the file to `len` bytes.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async fn truncate(&mut self, len: usize) -> Result<(), crate::Error> {
+        let inner = self.to_inner();
+        self.worker
+            .run(move || FilesystemPlatform::ftruncate(inner, len))
+            .await?;
+        Ok(())
+    }
+
+    /// Map the contents of the file into memory, read-only.
+    ///
+    /// Returns [`Error::Unsupported`] if the file can't be mapped, e.g. it's on a filesystem
+    /// that doesn't support it, in which case use [`FileHandle::read_with`] instead.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure the file isn't modified, by this or any other process, while the
+    /// returned [`FileMapping`] is alive. Writes change the contents behind the `&[u8]` it
+    /// derefs to, and reading past the end of a truncated file crashes the process.
+    ///
+    /// [`Error::Unsupported`]: crate::Error::Unsupported
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
+    pub async unsafe fn map_readonly(&self) -> Result<FileMapping, crate::Error> {
+        let inner = self.to_inner();
+        let mapping = self
+            .worker
+            .run(move || {
+                let stat = FilesystemPlatform::fstat(inner)?;
+                let len = usize::try_from(stat.size)
+                    .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+                // Mapping 0 bytes is an error, so don't bother.
+                let ptr = match len {
+                    0 => None,
+                    len => Some(FilesystemPlatform::mmap(inner, len)?),
+                };
+                Ok::<_, crate::Error>(FileMapping { ptr, len })
+            })
+            .await?;
+        Ok(mapping)
+    }
+
     /// Read some bytes from the file into the provided buffer, in a blocking fashion.
     pub fn read_blocking(&self, buf: &mut [u8], offset: usize) -> Result<usize, crate::Error> {
         let inner = self.to_inner();
-        FilesystemPlatform::read(inner, buf, offset)
+        let read = FilesystemPlatform::read(inner, buf, offset)?;
+        self.worker.metrics.record_read(read);
+        Ok(read)
     }
 
     /// Read the contents of the file executing some work on the worker's thread pool.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(diagnostics = self.diagnostics.as_deref(), duration_us = tracing::field::Empty)
+    )]
     pub async fn read_with<'a, R, F>(&self, work: F) -> Result<R, crate::Error>
     where
         R: Send + 'static,
@@ -214,18 +568,66 @@ impl Handle<FileKind> {
             .optimal_blocksize
             .unwrap_or(4096)
             .saturating_mul(8);
+        let metrics = Arc::clone(&self.worker.metrics);
 
         self.worker
             .run(move || {
                 let result = BlockPool::BLOCK_POOL.with_borrow_mut(|pool| {
                     let block = pool.get_block(block_size);
-                    let byte_iter = internal::ReadIterator::new(inner, block);
+                    let byte_iter = internal::ReadIterator::new(inner, block, &metrics);
                     work(byte_iter)
                 });
                 result
             })
             .await
     }
+
+    /// Stream the contents of the file through a new `H` on the worker's thread pool, and
+    /// return its hash.
+    pub async fn digest<H>(&self) -> Result<H::Output, crate::Error>
+    where
+        H: StreamingHasher,
+        H::Output: Send + 'static,
+    {
+        self.read_with(|mut reader| reader.digest::<H>()).await
+    }
+
+    /// Read the entire contents of the file.
+    pub async fn read_to_vec(&self) -> Result<Vec<u8>, crate::Error> {
+        self.read_with(|mut reader| {
+            let mut contents = Vec::new();
+            while let Some(bytes) = reader.next() {
+                contents.extend_from_slice(bytes?);
+            }
+            Ok(contents)
+        })
+        .await
+    }
+
+    /// Read the entire contents of the file, which must be valid UTF-8.
+    pub async fn read_to_string(&self) -> Result<String, crate::Error> {
+        let contents = self.read_to_vec().await?;
+        String::from_utf8(contents).map_err(|err| crate::Error::InvalidData(err.to_string().into()))
+    }
+
+    /// Returns a [`FileWriter`] that buffers data and writes it sequentially, starting at the
+    /// beginning of the file.
+    ///
+    /// Note: Any buffered data is dropped if [`FileWriter::flush`] isn't called.
+    pub fn writer(&mut self) -> FileWriter<'_> {
+        // Buffer the same amount of data we read at once in `read_with`.
+        let capacity = self
+            .kind
+            .optimal_blocksize
+            .unwrap_or(4096)
+            .saturating_mul(8);
+        FileWriter {
+            handle: self,
+            buffer: Vec::with_capacity(capacity),
+            capacity,
+            offset: 0,
+        }
+    }
 }
 
 impl<K> Drop for Handle<K> {
@@ -307,6 +709,8 @@ pub struct HandleBuilder<Details = UnknownDetails> {
     pub(crate) permits: Arc<Semaphore>,
     /// Reason this [`Handle`] was opened.
     pub(crate) diagnostics: Option<Cow<'static, str>>,
+    /// Fail to open the [`Handle`] if the location is a symlink.
+    pub(crate) no_follow: bool,
 
     /// Location we're opening.
     pub(crate) location: HandleLocation,
@@ -326,6 +730,7 @@ impl HandleBuilder<UnknownDetails> {
             drops_tx,
             permits,
             diagnostics: None,
+            no_follow: false,
             location,
             details: UnknownDetails,
         }
@@ -339,6 +744,36 @@ impl<D> HandleBuilder<D> {
         self
     }
 
+    /// Do not follow a symlink when opening, instead fail with
+    /// [`Error::TooManySymlinks`].
+    ///
+    /// Only the last component of the location is checked, symlinks in parent directories
+    /// are still followed.
+    ///
+    /// [`Error::TooManySymlinks`]: crate::Error::TooManySymlinks
+    pub fn no_follow(mut self) -> Self {
+        self.no_follow = true;
+        self
+    }
+
+    /// Returns the provided [`OpenOptions`] with any options from this builder applied.
+    fn open_options(&self, mut options: OpenOptions) -> OpenOptions {
+        if self.no_follow {
+            options |= OpenOptions::NO_FOLLOW;
+        }
+        options
+    }
+
+    /// Returns the span that opening the [`Handle`] runs in.
+    fn span(&self) -> tracing::Span {
+        tracing::debug_span!(
+            "open",
+            location = ?self.location,
+            diagnostics = self.diagnostics.as_deref(),
+            duration_us = tracing::field::Empty,
+        )
+    }
+
     /// Open a file with this [`HandleBuilder`].
     pub fn as_file(self) -> HandleBuilder<FileDetails> {
         HandleBuilder {
@@ -346,6 +781,7 @@ impl<D> HandleBuilder<D> {
             drops_tx: self.drops_tx,
             permits: self.permits,
             diagnostics: self.diagnostics,
+            no_follow: self.no_follow,
             location: self.location,
             details: FileDetails::default(),
         }
@@ -358,6 +794,7 @@ impl<D> HandleBuilder<D> {
             drops_tx: self.drops_tx,
             permits: self.permits,
             diagnostics: self.diagnostics,
+            no_follow: self.no_follow,
             location: self.location,
             details: DirectoryDetails { create: false },
         }
@@ -403,19 +840,25 @@ impl IntoFuture for HandleBuilder {
     type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send + Sync + 'static>>;
 
     fn into_future(self) -> Self::IntoFuture {
+        let span = self.span();
         let fut = async move {
-            let permit = Semaphore::acquire_owned(self.permits)
-                .await
-                .expect("failed to acquire permit");
-
             // Open this handle with just read only perms.
-            let options = OpenOptions::READ_ONLY;
+            let options = self.open_options(OpenOptions::READ_ONLY);
+
+            let permit = self.worker.acquire_permit(self.permits).await;
+
             let handle = match self.location {
                 HandleLocation::Path(path) => {
                     let path = PlatformPathType::try_new(path)?;
                     let handle = self
                         .worker
-                        .run(move || FilesystemPlatform::open(path, options))
+                        .retry(|| {
+                            let path = path.clone();
+                            self.worker.run_with_cleanup(
+                                move || FilesystemPlatform::open(path, options),
+                                close_abandoned,
+                            )
+                        })
                         .await?;
                     handle
                 }
@@ -426,12 +869,19 @@ impl IntoFuture for HandleBuilder {
                     let filename = PlatformFilenameType::try_new(filename)?;
                     let handle = self
                         .worker
-                        .run(move || FilesystemPlatform::openat(directory, filename, options))
+                        .retry(|| {
+                            let filename = filename.clone();
+                            self.worker.run_with_cleanup(
+                                move || FilesystemPlatform::openat(directory, filename, options),
+                                close_abandoned,
+                            )
+                        })
                         .await?;
                     handle
                 }
             };
 
+            self.worker.metrics.record_open();
             let handle = Handle {
                 inner: Some(handle),
                 permit: Some(permit),
@@ -443,7 +893,7 @@ impl IntoFuture for HandleBuilder {
 
             Ok(handle)
         };
-        Box::pin(fut)
+        Box::pin(fut.instrument(span))
     }
 }
 
@@ -452,20 +902,26 @@ impl IntoFuture for HandleBuilder<FileDetails> {
     type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send + Sync + 'static>>;
 
     fn into_future(self) -> Self::IntoFuture {
+        let span = self.span();
         let fut = async move {
-            let permit = Semaphore::acquire_owned(self.permits)
-                .await
-                .expect("failed to acquire permit");
+            let flags = self.open_options(self.details.flags);
+            let permit = self.worker.acquire_permit(self.permits).await;
 
             let (handle, stat) = match self.location {
                 HandleLocation::Path(path) => {
                     let path = PlatformPathType::try_new(path)?;
+                    let work = move || {
+                        let handle = FilesystemPlatform::open(path, flags)?;
+                        // TODO(parkmycar): Always stating a file when opening feels wasteful?
+                        let stat = FilesystemPlatform::fstat(handle)?;
+                        Ok((handle, stat))
+                    };
                     self.worker
-                        .run(move || {
-                            let handle = FilesystemPlatform::open(path, self.details.flags)?;
-                            // TODO(parkmycar): Always stating a file when opening feels wasteful?
-                            let stat = FilesystemPlatform::fstat(handle.clone())?;
-                            Ok((handle, stat))
+                        .retry(|| {
+                            self.worker
+                                .run_with_cleanup(work.clone(), |(handle, _stat)| {
+                                    close_abandoned(handle)
+                                })
                         })
                         .await?
                 }
@@ -474,20 +930,23 @@ impl IntoFuture for HandleBuilder<FileDetails> {
                     filename,
                 } => {
                     let filename = PlatformFilenameType::try_new(filename)?;
+                    let work = move || {
+                        let handle = FilesystemPlatform::openat(directory, filename, flags)?;
+                        // TODO(parkmycar): Always stating a file when opening feels wasteful?
+                        let stat = FilesystemPlatform::fstat(handle)?;
+                        Ok((handle, stat))
+                    };
                     self.worker
-                        .run(move || {
-                            let handle = FilesystemPlatform::openat(
-                                directory,
-                                filename,
-                                self.details.flags,
-                            )?;
-                            // TODO(parkmycar): Always stating a file when opening feels wasteful?
-                            let stat = FilesystemPlatform::fstat(handle.clone())?;
-                            Ok((handle, stat))
+                        .retry(|| {
+                            self.worker
+                                .run_with_cleanup(work.clone(), |(handle, _stat)| {
+                                    close_abandoned(handle)
+                                })
                         })
                         .await?
                 }
             };
+            self.worker.metrics.record_open();
 
             if stat.kind != FileType::File {
                 Err(crate::Error::NotAFile("todo".into()))
@@ -506,7 +965,7 @@ impl IntoFuture for HandleBuilder<FileDetails> {
                 Ok((handle, stat))
             }
         };
-        Box::pin(fut)
+        Box::pin(fut.instrument(span))
     }
 }
 
@@ -515,13 +974,13 @@ impl IntoFuture for HandleBuilder<DirectoryDetails> {
     type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send + Sync + 'static>>;
 
     fn into_future(self) -> Self::IntoFuture {
+        let span = self.span();
         let fut = async move {
             let kind = DirectoryKind {
                 permits: Arc::clone(&self.permits),
             };
-            let permit = Semaphore::acquire_owned(self.permits)
-                .await
-                .expect("failed to acquire permit");
+            let options = self.open_options(OpenOptions::DIRECTORY);
+            let permit = self.worker.acquire_permit(self.permits).await;
 
             // First create the directory.
             if self.details.create {
@@ -529,7 +988,10 @@ impl IntoFuture for HandleBuilder<DirectoryDetails> {
                     HandleLocation::Path(path) => {
                         let path = PlatformPathType::try_new(path.clone())?;
                         self.worker
-                            .run(move || FilesystemPlatform::mkdir(path))
+                            .retry(|| {
+                                let path = path.clone();
+                                self.worker.run(move || FilesystemPlatform::mkdir(path))
+                            })
                             .await?;
                     }
                     HandleLocation::At {
@@ -539,20 +1001,29 @@ impl IntoFuture for HandleBuilder<DirectoryDetails> {
                         let directory = directory.clone();
                         let filename = PlatformFilenameType::try_new(filename.clone())?;
                         self.worker
-                            .run(move || FilesystemPlatform::mkdirat(directory, filename))
+                            .retry(|| {
+                                let filename = filename.clone();
+                                self.worker
+                                    .run(move || FilesystemPlatform::mkdirat(directory, filename))
+                            })
                             .await?;
                     }
                 }
             }
 
             // Then open a handle to it.
-            let options = OpenOptions::DIRECTORY;
             let handle = match self.location {
                 HandleLocation::Path(path) => {
                     let path = PlatformPathType::try_new(path)?;
                     let handle = self
                         .worker
-                        .run(move || FilesystemPlatform::open(path, options))
+                        .retry(|| {
+                            let path = path.clone();
+                            self.worker.run_with_cleanup(
+                                move || FilesystemPlatform::open(path, options),
+                                close_abandoned,
+                            )
+                        })
                         .await?;
                     handle
                 }
@@ -563,12 +1034,19 @@ impl IntoFuture for HandleBuilder<DirectoryDetails> {
                     let filename = PlatformFilenameType::try_new(filename)?;
                     let handle = self
                         .worker
-                        .run(move || FilesystemPlatform::openat(directory, filename, options))
+                        .retry(|| {
+                            let filename = filename.clone();
+                            self.worker.run_with_cleanup(
+                                move || FilesystemPlatform::openat(directory, filename, options),
+                                close_abandoned,
+                            )
+                        })
                         .await?;
                     handle
                 }
             };
 
+            self.worker.metrics.record_open();
             let handle = Handle {
                 inner: Some(handle),
                 permit: Some(permit),
@@ -580,7 +1058,392 @@ impl IntoFuture for HandleBuilder<DirectoryDetails> {
 
             Ok(handle)
         };
-        Box::pin(fut)
+        Box::pin(fut.instrument(span))
+    }
+}
+
+/// Read-only contents of a file mapped into memory, see [`FileHandle::map_readonly`].
+pub struct FileMapping {
+    /// Start of the mapping, `None` if the file was empty.
+    ptr: Option<NonNull<u8>>,
+    /// Length of the mapping.
+    len: usize,
+}
+
+// SAFETY: The mapping is read-only and owned by this type, and the caller of
+// `FileHandle::map_readonly` guarantees the file isn't modified while it's mapped.
+unsafe impl Send for FileMapping {}
+unsafe impl Sync for FileMapping {}
+
+impl std::ops::Deref for FileMapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self.ptr {
+            // SAFETY: `ptr` points to `len` bytes that stay mapped for the life of `self`, and
+            // the caller of `FileHandle::map_readonly` guarantees they aren't modified.
+            Some(ptr) => unsafe { std::slice::from_raw_parts(ptr.as_ptr(), self.len) },
+            None => &[],
+        }
+    }
+}
+
+impl Drop for FileMapping {
+    fn drop(&mut self) {
+        if let Some(ptr) = self.ptr.take() {
+            // SAFETY: `ptr` and `len` came from `mmap` and the mapping can't be used anymore.
+            if let Err(err) = unsafe { FilesystemPlatform::munmap(ptr, self.len) } {
+                tracing::warn!("failed to unmap file, err: {err}");
+            }
+        }
+    }
+}
+
+/// File that isn't visible until it's persisted, see [`Filesystem::tempfile_in`].
+///
+/// When supported the file is anonymous, so it never leaks, even if we crash. Otherwise it's
+/// a hidden file with a random name that gets removed when dropped.
+///
+/// [`Filesystem::tempfile_in`]: crate::filesystem::Filesystem::tempfile_in
+pub struct TempFile {
+    /// Handle to the file, `None` once persisted.
+    file: Option<FileHandle>,
+    /// Directory the file will get persisted into.
+    directory: DirectoryHandle,
+    /// Path of the directory, used to cleanup named files.
+    directory_path: PathBuf,
+    /// Name of the file if it isn't anonymous.
+    filename: Option<String>,
+}
+
+impl TempFile {
+    pub(crate) async fn new(
+        directory: DirectoryHandle,
+        directory_path: PathBuf,
+    ) -> Result<Self, crate::Error> {
+        let permit = directory
+            .worker
+            .acquire_permit(Arc::clone(&directory.kind.permits))
+            .await;
+        let dir_inner = directory.to_inner();
+        let result = directory
+            .worker
+            .run_with_cleanup(
+                move || {
+                    let handle = FilesystemPlatform::tmpfileat(dir_inner)?;
+                    let stat = FilesystemPlatform::fstat(handle)?;
+                    Ok((handle, stat))
+                },
+                |(handle, _stat)| close_abandoned(handle),
+            )
+            .await;
+
+        let (file, filename) = match result {
+            Ok((handle, stat)) => {
+                directory.worker.metrics.record_open();
+                let file = Handle {
+                    inner: Some(handle),
+                    permit: Some(permit),
+                    worker: directory.worker.clone(),
+                    drops_tx: directory.drops_tx.clone(),
+                    diagnostics: Some(Cow::Borrowed("tempfile")),
+                    kind: FileKind {
+                        optimal_blocksize: stat.optimal_blocksize,
+                    },
+                };
+                (file, None)
+            }
+            Err(crate::Error::Unsupported) => {
+                drop(permit);
+                let filename = format!(".pb-tmp-{}", uuid::Uuid::new_v4());
+                let (file, _stat) = directory
+                    .openat(filename.clone())
+                    .as_file()
+                    .with_create()
+                    .with_exclusive()
+                    .diagnostics("tempfile")
+                    .await?;
+                (file, Some(filename))
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(TempFile {
+            file: Some(file),
+            directory,
+            directory_path,
+            filename,
+        })
+    }
+
+    /// Atomically link the file into place at `filename` in the directory it was created in.
+    ///
+    /// Fails with [`crate::Error::AlreadyExists`] if there is already a file at `filename`.
+    pub async fn persist(mut self, filename: String) -> Result<FileHandle, crate::Error> {
+        let file = self.file.take().expect("only taken when persisting");
+        let file_inner = file.to_inner();
+        let dir_inner = self.directory.to_inner();
+        let to_filename = PlatformFilenameType::try_new(filename)?;
+
+        match &self.filename {
+            None => {
+                self.directory
+                    .worker
+                    .run(move || FilesystemPlatform::flinkat(file_inner, dir_inner, to_filename))
+                    .await?;
+            }
+            Some(filename) => {
+                let from_filename = PlatformFilenameType::try_new(filename.clone())?;
+                // Link instead of rename so we never replace an existing file.
+                self.directory
+                    .worker
+                    .run(move || {
+                        FilesystemPlatform::linkat(
+                            dir_inner,
+                            from_filename.clone(),
+                            dir_inner,
+                            to_filename,
+                        )?;
+                        FilesystemPlatform::unlinkat(dir_inner, from_filename)
+                    })
+                    .await?;
+                self.filename = None;
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Atomically move the file into place at `filename` in the directory it was created in,
+    /// replacing any existing file.
+    ///
+    /// Anything opening `filename` sees either the previous file or this one, never a missing
+    /// or partially written file. An anonymous file is first linked at a hidden name, since it
+    /// can only be renamed once it has one.
+    pub async fn persist_replacing(mut self, filename: String) -> Result<FileHandle, crate::Error> {
+        let dir_inner = self.directory.to_inner();
+        let to_filename = PlatformFilenameType::try_new(filename)?;
+
+        let from_filename = match &self.filename {
+            Some(filename) => filename.clone(),
+            None => {
+                let filename = format!(".pb-tmp-{}", uuid::Uuid::new_v4());
+                let hidden_filename = PlatformFilenameType::try_new(filename.clone())?;
+                let file_inner = self.to_inner();
+                self.directory
+                    .worker
+                    .run(move || {
+                        FilesystemPlatform::flinkat(file_inner, dir_inner, hidden_filename)
+                    })
+                    .await?;
+                // The file is named now, so it gets removed if renaming fails.
+                self.filename = Some(filename.clone());
+                filename
+            }
+        };
+        let from_filename = PlatformFilenameType::try_new(from_filename)?;
+        self.directory
+            .worker
+            .run(move || {
+                FilesystemPlatform::renameat(dir_inner, from_filename, dir_inner, to_filename)
+            })
+            .await?;
+        self.filename = None;
+
+        Ok(self.file.take().expect("only taken when persisting"))
+    }
+}
+
+impl std::ops::Deref for TempFile {
+    type Target = FileHandle;
+
+    fn deref(&self) -> &FileHandle {
+        self.file.as_ref().expect("only taken when persisting")
+    }
+}
+
+impl std::ops::DerefMut for TempFile {
+    fn deref_mut(&mut self) -> &mut FileHandle {
+        self.file.as_mut().expect("only taken when persisting")
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(filename) = self.filename.take() {
+            let path = self.directory_path.join(filename);
+            // The file gets removed on the worker, we don't need to wait for it.
+            self.directory.worker.spawn(move || {
+                let result = PlatformPathType::try_new(path).and_then(FilesystemPlatform::unlink);
+                if let Err(err) = result {
+                    tracing::warn!("failed to remove temporary file, err: {err}");
+                }
+            });
+        }
+    }
+}
+
+/// Buffered sequential writer for a file, see [`FileHandle::writer`].
+pub struct FileWriter<'a> {
+    /// File we're writing to.
+    handle: &'a mut FileHandle,
+    /// Data that hasn't been written to the file yet.
+    buffer: Vec<u8>,
+    /// Amount of data we buffer before writing to the file.
+    capacity: usize,
+    /// Offset in the file that `buffer` will get written to.
+    offset: usize,
+}
+
+impl FileWriter<'_> {
+    /// Append `data` to the file, writing it once enough data has been buffered.
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), crate::Error> {
+        if self.buffer.len() + data.len() > self.capacity {
+            self.flush().await?;
+        }
+
+        if data.len() >= self.capacity {
+            // Skip the buffer for large writes.
+            self.write_at_offset(data.to_vec()).await?;
+        } else {
+            self.buffer.extend_from_slice(data);
+        }
+        Ok(())
+    }
+
+    /// Write any buffered data to the file.
+    pub async fn flush(&mut self) -> Result<(), crate::Error> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let buffer = std::mem::take(&mut self.buffer);
+        let mut buffer = self.write_at_offset(buffer).await?;
+        // Re-use our allocation.
+        buffer.clear();
+        self.buffer = buffer;
+        Ok(())
+    }
+
+    /// Total number of bytes written, including any that are buffered.
+    pub fn len(&self) -> usize {
+        self.offset + self.buffer.len()
+    }
+
+    /// Returns `true` if nothing has been written.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write all of `data` at our current offset, returning `data` so it can be re-used.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(
+            diagnostics = self.handle.diagnostics.as_deref(),
+            duration_us = tracing::field::Empty,
+        )
+    )]
+    async fn write_at_offset(&mut self, data: Vec<u8>) -> Result<Vec<u8>, crate::Error> {
+        let inner = self.handle.to_inner();
+        let offset = self.offset;
+        let metrics = Arc::clone(&self.handle.worker.metrics);
+        let data = self
+            .handle
+            .worker
+            .run(move || {
+                let mut written = 0;
+                while written < data.len() {
+                    let n = FilesystemPlatform::write(inner, &data[written..], offset + written)?;
+                    metrics.record_write(n);
+                    written += n;
+                }
+                Ok::<_, crate::Error>(data)
+            })
+            .await?;
+        self.offset += data.len();
+        Ok(data)
+    }
+}
+
+impl Drop for FileWriter<'_> {
+    fn drop(&mut self) {
+        if !self.buffer.is_empty() {
+            tracing::warn!(
+                "dropped {} unflushed bytes, diagnostics: {:?}",
+                self.buffer.len(),
+                self.handle.diagnostics
+            );
+        }
+    }
+}
+
+/// Stream of the entries in a directory, see [`DirectoryHandle::list_batched`].
+pub struct DirectoryStream {
+    /// The platform specific directory stream, `None` once closed.
+    stream: Option<PlatformDirStreamType>,
+    /// Permit we keep open for the life of the stream for resource management.
+    permit: Option<OwnedSemaphorePermit>,
+    /// Worker that runs I/O operations.
+    worker: FilesystemWorker,
+}
+
+impl DirectoryStream {
+    /// Returns the next batch of entries, or `None` once all of the entries have been read.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(duration_us = tracing::field::Empty)
+    )]
+    pub async fn next_batch(&mut self) -> Result<Option<Vec<DirectoryEntry>>, crate::Error> {
+        let Some(stream) = self.stream else {
+            return Ok(None);
+        };
+        let entries = self
+            .worker
+            .run(move || FilesystemPlatform::readdir(stream))
+            .await?;
+
+        if entries.is_empty() {
+            self.close_inner().await?;
+            Ok(None)
+        } else {
+            Ok(Some(entries))
+        }
+    }
+
+    /// Close the stream, releasing its resources.
+    pub async fn close(mut self) -> Result<(), crate::Error> {
+        self.close_inner().await
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(duration_us = tracing::field::Empty)
+    )]
+    async fn close_inner(&mut self) -> Result<(), crate::Error> {
+        if let Some(stream) = self.stream.take() {
+            self.worker
+                .run(move || FilesystemPlatform::closedir(stream))
+                .await?;
+        }
+        drop(self.permit.take());
+        Ok(())
+    }
+}
+
+impl Drop for DirectoryStream {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            let permit = self.permit.take();
+            // The stream gets closed on the worker, we don't need to wait for it.
+            self.worker.spawn(move || {
+                if let Err(err) = FilesystemPlatform::closedir(stream) {
+                    tracing::warn!("failed to async close directory stream, err: {err}");
+                }
+                drop(permit);
+            });
+        }
     }
 }
 
@@ -596,7 +1459,9 @@ pub(crate) struct DroppedHandle {
 
 pub mod internal {
     use crate::filesystem::Block;
+    use crate::metrics::FilesystemMetrics;
     use crate::platform::{FilesystemPlatform, Platform, PlatformHandleType};
+    use pb_ore::hash::StreamingHasher;
     use pb_ore::iter::LendingIterator;
 
     /// A [`LendingIterator`] that reads from a [`Handle`] and returns byte slices.
@@ -607,6 +1472,8 @@ pub mod internal {
         handle: PlatformHandleType,
         /// Re-usable block of memory for I/O.
         block: &'a mut Block,
+        /// Where we record each read.
+        metrics: &'a FilesystemMetrics,
         /// Current offset into the file that we're reading from.
         offset: usize,
         /// Is the iterator complete.
@@ -614,14 +1481,28 @@ pub mod internal {
     }
 
     impl<'a> ReadIterator<'a> {
-        pub fn new(handle: PlatformHandleType, block: &'a mut Block) -> Self {
+        pub(crate) fn new(
+            handle: PlatformHandleType,
+            block: &'a mut Block,
+            metrics: &'a FilesystemMetrics,
+        ) -> Self {
             ReadIterator {
                 handle,
                 block,
+                metrics,
                 offset: 0,
                 done: false,
             }
         }
+
+        /// Read the rest of the file, feeding it through a new `H`, and return its hash.
+        pub fn digest<H: StreamingHasher>(&mut self) -> Result<H::Output, crate::Error> {
+            let mut hasher = H::default();
+            while let Some(data) = self.next() {
+                hasher.update(data?);
+            }
+            Ok(hasher.finish())
+        }
     }
 
     impl<'r> LendingIterator for ReadIterator<'r> {
@@ -642,7 +1523,11 @@ pub mod internal {
 
             // Read the next chunk.
             let block_size = self.block.size();
-            match FilesystemPlatform::read(self.handle.clone(), self.block.as_mut(), self.offset) {
+            let result = FilesystemPlatform::read(self.handle, self.block.as_mut(), self.offset);
+            if let Ok(bytes_read) = result {
+                self.metrics.record_read(bytes_read);
+            }
+            match result {
                 // Read less bytes than the size of the buffer, we're done!
                 Ok(bytes_read) if bytes_read < block_size => {
                     self.done = true;
@@ -0,0 +1,109 @@
+//! Hierarchical `.gitignore` style rules, see [`TreeBuilder::respect_gitignore`].
+//!
+//! [`TreeBuilder::respect_gitignore`]: crate::tree::TreeBuilder::respect_gitignore
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// Names of the files we read ignore rules from, rules in later files take precedence.
+pub(crate) const IGNORE_FILES: [&str; 2] = [".gitignore", ".pbignore"];
+
+/// Ignore rules from a single directory, chained to the rules from its parents.
+#[derive(Debug)]
+pub(crate) struct IgnoreRules {
+    /// Rules from the parent directories, which have a lower precedence than ours.
+    parent: Option<Rc<IgnoreRules>>,
+    /// Directory the rules were read from, patterns are relative to it.
+    root: PathBuf,
+    /// Compiled patterns, indexes line up with `rules`.
+    globs: GlobSet,
+    /// Rules in the order they were defined, later rules take precedence.
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    /// Pattern started with a `!`, so matching paths are re-included.
+    negated: bool,
+    /// Pattern ended with a `/`, so it only matches directories.
+    dir_only: bool,
+}
+
+impl IgnoreRules {
+    /// Parse the `contents` of an ignore file in the directory `root`.
+    ///
+    /// Invalid patterns are skipped, like git does.
+    pub(crate) fn new(parent: Option<Rc<IgnoreRules>>, root: PathBuf, contents: &str) -> Self {
+        let mut globs = GlobSetBuilder::new();
+        let mut rules = Vec::new();
+
+        for line in contents.lines() {
+            // Trailing spaces are ignored unless they're escaped.
+            let mut pattern = line.trim_end_matches(' ');
+            if pattern.ends_with('\\') && line.len() > pattern.len() {
+                pattern = &line[..pattern.len() + 1];
+            }
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+
+            let negated = pattern.starts_with('!');
+            let pattern = pattern.strip_prefix('!').unwrap_or(pattern);
+            let dir_only = pattern.ends_with('/');
+            let pattern = pattern.trim_end_matches('/');
+            // Patterns with a separator are relative to `root`, otherwise they match at any
+            // depth.
+            let glob = match pattern.contains('/') {
+                true => pattern.trim_start_matches('/').to_string(),
+                false => format!("**/{pattern}"),
+            };
+
+            match GlobBuilder::new(&glob).literal_separator(true).build() {
+                Ok(glob) => {
+                    globs.add(glob);
+                    rules.push(Rule { negated, dir_only });
+                }
+                Err(err) => tracing::warn!(?root, line, ?err, "skipping invalid ignore pattern"),
+            }
+        }
+
+        let globs = globs.build().unwrap_or_else(|err| {
+            tracing::warn!(?root, ?err, "failed to build ignore rules");
+            rules.clear();
+            GlobSet::empty()
+        });
+
+        IgnoreRules {
+            parent,
+            root,
+            globs,
+            rules,
+        }
+    }
+
+    /// Returns if `path` is ignored by these rules, or the rules of any parent directory.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut current = Some(self);
+        while let Some(rules) = current {
+            if let Some(ignored) = rules.matches(path, is_dir) {
+                return ignored;
+            }
+            current = rules.parent.as_deref();
+        }
+        false
+    }
+
+    /// Returns if the last rule in this directory that matches `path` ignores it, `None` if
+    /// no rule matches.
+    fn matches(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        self.globs
+            .matches(relative)
+            .into_iter()
+            .filter(|idx| is_dir || !self.rules[*idx].dir_only)
+            .max()
+            .map(|idx| !self.rules[idx].negated)
+    }
+}
@@ -2,8 +2,11 @@
 
 pub mod filesystem;
 pub mod handle;
+mod ignore;
 pub mod locations;
+pub mod metrics;
 pub mod platform;
+mod stat_cache;
 pub mod tree;
 
 #[cfg(test)]
@@ -16,10 +19,48 @@ use pb_types::Timespec;
 pub enum Error {
     #[error("Operation is not permitted")]
     PermissionDenied,
+    #[error("Permission denied")]
+    AccessDenied,
     #[error("No such file or directory")]
     NotFound,
     #[error("No such process")]
     NoProcess,
+    #[error("Interrupted system call")]
+    Interrupted,
+    #[error("Bad file descriptor")]
+    BadHandle,
+    #[error("Resource temporarily unavailable")]
+    WouldBlock,
+    #[error("Device or resource busy")]
+    Busy,
+    #[error("Too many levels of symbolic links")]
+    TooManySymlinks,
+    #[error("File already exists")]
+    AlreadyExists,
+    #[error("Invalid cross-device link")]
+    CrossesDevices,
+    #[error("Not a directory")]
+    NotADirectory,
+    #[error("Is a directory")]
+    IsADirectory,
+    #[error("Directory not empty")]
+    NotEmpty,
+    #[error("Invalid argument")]
+    InvalidInput,
+    #[error("Too many open files")]
+    TooManyOpenFiles,
+    #[error("File name too long")]
+    NameTooLong,
+    #[error("Read-only file system")]
+    ReadOnly,
+    #[error("Stale file handle")]
+    StaleHandle,
+    #[error("Operation is not supported")]
+    Unsupported,
+    #[error("Operation timed out")]
+    TimedOut,
+    #[error("No space left on device")]
+    NoSpace,
     #[error("Invalid or unexpected data was returned: {0}")]
     InvalidData(Box<str>),
     #[error("Attempted to open a resource as a file, that wasn't a file")]
@@ -28,8 +69,19 @@ pub enum Error {
     Unknown(String),
 }
 
+impl Error {
+    /// Returns `true` if the error is likely to go away if the operation is retried, e.g. a
+    /// network filesystem that briefly lost its connection.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::Interrupted | Error::WouldBlock | Error::StaleHandle
+        )
+    }
+}
+
 /// Metadata about a file that is used to detect changes.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, serde::Serialize, serde::Deserialize)]
 pub struct FileStat {
     /// Size of a file in bytes.
     pub size: u64,
@@ -37,6 +89,8 @@ pub struct FileStat {
     pub kind: FileType,
     /// Inode number of the file.
     pub inode: u64,
+    /// ID of the device containing the file.
+    pub device: u64,
     /// File mode/permissions.
     pub mode: u32,
     /// User ID of the file owner.
@@ -56,7 +110,7 @@ pub struct FileStat {
 }
 
 /// Kind of object on the filesystem.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum FileType {
     File,
     Directory,
@@ -1,13 +1,21 @@
 use std::future::Future;
-use std::ops::{Deref, DerefMut};
+use std::ops::Deref;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use derivative::Derivative;
+use pb_ore::cast::CastFrom;
+use pb_types::Timespec;
 
 use crate::filesystem::Filesystem;
-use crate::handle::{DirectoryHandle, DirectoryKind, FileKind};
-use crate::platform::{FilesystemPlatform, Platform, PlatformFilename};
+use crate::handle::{DirectoryHandle, DirectoryKind, DroppedHandle, FileKind, Handle};
+use crate::platform::{
+    FilesystemPlatform, OpenOptions, Platform, PlatformFilename, PlatformFilenameType,
+    PlatformHandleType,
+};
+use crate::FileType;
 
 static SCRATCH_DIRECTORY_NAME: &str = "scratch";
 
@@ -16,6 +24,51 @@ static SCRATCH_XATTR_TAG_RULESET_NAME: &str = "org.pb.scratch.rule_set";
 /// Name for the extended attribute that includes a general comment about this scratch file.
 static SCRATCH_XATTR_TAG_COMMENT_NAME: &str = "org.pb.scratch.comment";
 
+/// Default for [`ScratchOptions::max_leak_age`], one week.
+pub const DEFAULT_MAX_LEAK_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Options for a [`ScratchDirectory`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScratchOptions {
+    /// Leaked entries last modified longer ago than this are deleted when the
+    /// [`ScratchDirectory`] is created, `None` keeps them forever.
+    pub max_leak_age: Option<Duration>,
+    /// Maximum number of bytes the [`ScratchDirectory`] may hold, `None` for no limit.
+    ///
+    /// Counts the bytes written through a [`ScratchFileHandle`], the contents of scratch
+    /// directories once they're released, and the size of leaked entries. When a write would
+    /// exceed the budget, the oldest leaked entries are evicted to make room, and if that's not
+    /// enough the write fails with [`crate::Error::NoSpace`].
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for ScratchOptions {
+    fn default() -> Self {
+        ScratchOptions {
+            max_leak_age: Some(DEFAULT_MAX_LEAK_AGE),
+            max_bytes: None,
+        }
+    }
+}
+
+/// An entry left behind in the [`ScratchDirectory`], e.g. because the process crashed or a
+/// [`ScratchHandle`] was dropped before the entry was persisted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeakedScratchEntry {
+    /// Name of the entry in the scratch directory.
+    pub name: String,
+    /// Kind of the entry.
+    pub kind: FileType,
+    /// Size of the entry in bytes, for directories this includes the size of their contents.
+    pub size: u64,
+    /// Last time the entry was modified.
+    pub mtime: Timespec,
+    /// Ruleset the entry was tagged with, see [`ScratchHandle::tag_ruleset`].
+    pub ruleset: Option<String>,
+    /// Comment the entry was tagged with, see [`ScratchHandle::tag_comment`].
+    pub comment: Option<String>,
+}
+
 /// A "scratch" directory that can be used to store transient files.
 ///
 /// A common use-case for a [`ScratchDirectory`] is to download a file into the
@@ -23,7 +76,10 @@ static SCRATCH_XATTR_TAG_COMMENT_NAME: &str = "org.pb.scratch.comment";
 /// way if the download only partially completes we're not left with a
 /// corrupted file.
 ///
-/// TODO: Add automatic tracking of leaked scratch files.
+/// Entries that were never persisted are leaked, on creation the scratch
+/// directory reports them and removes the ones older than
+/// [`ScratchOptions::max_leak_age`]. The space used can be limited with
+/// [`ScratchOptions::max_bytes`], so a runaway rule can't fill the disk.
 #[derive(Derivative)]
 #[derivative(Debug, Clone)]
 pub struct ScratchDirectory {
@@ -35,24 +91,52 @@ pub struct ScratchDirectory {
     /// Handle to our filesystem abstraction.
     #[derivative(Debug = "ignore")]
     filesystem: Filesystem,
+    /// Bytes stored in the scratch directory and the leaked entries we can evict.
+    usage: Arc<ScratchUsage>,
 }
 
 impl ScratchDirectory {
     /// Create a new [`ScratchDirectory`] at `root_path /`[`SCRATCH_DIRECTORY_NAME`].
     pub async fn new(root: PathBuf, filesystem: Filesystem) -> Result<Self, crate::Error> {
+        Self::with_options(root, filesystem, ScratchOptions::default()).await
+    }
+
+    /// Like [`ScratchDirectory::new`], but with the provided [`ScratchOptions`].
+    pub async fn with_options(
+        root: PathBuf,
+        filesystem: Filesystem,
+        options: ScratchOptions,
+    ) -> Result<Self, crate::Error> {
         let root_path = root.join(SCRATCH_DIRECTORY_NAME);
-        tracing::info!(?root_path, "starting Scratch Directory");
+        tracing::info!(?root_path, ?options, "starting Scratch Directory");
 
-        // TODO: Implement automatic cleanup.
         let root_handle = filesystem.open(root_path.clone()).as_directory().await?;
+        let mut leaked = cleanup_leaked(&root_handle, options.max_leak_age).await?;
+        leaked.sort_by_key(|entry| entry.mtime);
+        let usage = ScratchUsage {
+            used: AtomicU64::new(leaked.iter().map(|entry| entry.size).sum()),
+            max: options.max_bytes,
+            leaked: Mutex::new(leaked),
+        };
 
         Ok(ScratchDirectory {
             root_path,
             root_handle: Arc::new(root_handle),
             filesystem,
+            usage: Arc::new(usage),
         })
     }
 
+    /// Leaked entries that haven't been removed or evicted, oldest first.
+    pub fn leaked(&self) -> Vec<LeakedScratchEntry> {
+        self.usage.leaked.lock().expect("poisoned").clone()
+    }
+
+    /// Number of bytes currently counted against [`ScratchOptions::max_bytes`].
+    pub fn used_bytes(&self) -> u64 {
+        self.usage.used.load(Ordering::SeqCst)
+    }
+
     /// Create a new file in the scratch space with a random name.
     pub fn file(&self) -> impl Future<Output = Result<ScratchFileHandle, crate::Error>> + 'static {
         let filename = uuid::Uuid::new_v4().to_string();
@@ -62,15 +146,18 @@ impl ScratchDirectory {
             .as_file()
             .with_create();
         let root_handle = Arc::clone(&self.root_handle);
+        let usage = Arc::clone(&self.usage);
 
         async move {
             tracing::debug!(?filename, "creating new scratch file");
             let (inner, _stat) = builder.await?;
-            Ok(ScratchHandle {
+            Ok(ScratchHandle::new(
                 inner,
                 root_handle,
                 filename,
-            })
+                FileType::File,
+                usage,
+            ))
         }
     }
 
@@ -85,76 +172,137 @@ impl ScratchDirectory {
             .as_directory()
             .with_create();
         let root_handle = Arc::clone(&self.root_handle);
+        let usage = Arc::clone(&self.usage);
 
         async move {
             tracing::debug!(?filename, "creating new scratch directory");
-            Ok(ScratchHandle {
-                inner: builder.await?,
+            Ok(ScratchHandle::new(
+                builder.await?,
                 root_handle,
                 filename,
-            })
+                FileType::Directory,
+                usage,
+            ))
         }
     }
 }
 
 /// A resource in the [`ScratchDirectory`].
+///
+/// Dropping the handle without persisting the resource leaks it, it then gets reported and
+/// counted against the budget like an entry leaked by a previous run, see
+/// [`ScratchDirectory::leaked`].
 pub struct ScratchHandle<Kind> {
-    /// Handle to the resource in the scratch directory.
-    inner: crate::handle::Handle<Kind>,
+    /// Handle to the resource in the scratch directory, `None` once persisted or leaked.
+    inner: Option<crate::handle::Handle<Kind>>,
     /// Handle to the root of the [`ScratchDirectory`].
     root_handle: Arc<DirectoryHandle>,
     /// Name of this resource.
     filename: String,
+    /// Kind of this resource.
+    kind: FileType,
+    /// Usage of the [`ScratchDirectory`] this resource counts against.
+    usage: Arc<ScratchUsage>,
+    /// Number of bytes this resource counts against the [`ScratchDirectory`] budget.
+    len: u64,
+    /// Ruleset this resource was tagged with, see [`ScratchHandle::tag_ruleset`].
+    ruleset: Option<String>,
+    /// Comment this resource was tagged with, see [`ScratchHandle::tag_comment`].
+    comment: Option<String>,
 }
 
 impl<K> ScratchHandle<K> {
-    /// Returns a mutable reference to the inner handle.
-    pub fn inner_mut(&mut self) -> &mut crate::handle::Handle<K> {
-        &mut self.inner
+    fn new(
+        inner: crate::handle::Handle<K>,
+        root_handle: Arc<DirectoryHandle>,
+        filename: String,
+        kind: FileType,
+        usage: Arc<ScratchUsage>,
+    ) -> Self {
+        ScratchHandle {
+            inner: Some(inner),
+            root_handle,
+            filename,
+            kind,
+            usage,
+            len: 0,
+            ruleset: None,
+            comment: None,
+        }
     }
 
-    pub fn into_inner(self) -> crate::handle::Handle<K> {
+    fn inner_mut(&mut self) -> &mut crate::handle::Handle<K> {
         self.inner
+            .as_mut()
+            .expect("only taken when persisting or leaking")
+    }
+
+    /// Returns the inner handle, leaking the resource in the scratch directory.
+    ///
+    /// The resource is still in use, so it's taken out of the [`ScratchOptions::max_bytes`]
+    /// budget rather than tracked as an evictable leaked entry, the caller becomes responsible
+    /// for its space. It's only reported as leaked by the next [`ScratchDirectory`] created.
+    pub fn into_inner(mut self) -> crate::handle::Handle<K> {
+        self.usage.release(self.len);
+        self.inner
+            .take()
+            .expect("only taken when persisting or leaking")
     }
 
     /// Tag this [`ScratchHandle`] with the ruleset that created it.
     pub async fn tag_ruleset(&mut self, name: &str) -> Result<(), crate::Error> {
         tracing::debug!(filename = ?self.filename, ?name, "tagging scratch file with ruleset");
-        self.inner
+        self.inner_mut()
             .setxattr(
                 SCRATCH_XATTR_TAG_RULESET_NAME.to_string(),
                 name.as_bytes().to_vec(),
             )
             .await?;
+        self.ruleset = Some(name.to_string());
         Ok(())
     }
 
     /// Tag this [`ScratchHandle`] with a general comment.
     pub async fn tag_comment(&mut self, comment: &str) -> Result<(), crate::Error> {
         tracing::debug!(filename = ?self.filename, ?comment, "tagging scratch file with comment");
-        self.inner
+        self.inner_mut()
             .setxattr(
                 SCRATCH_XATTR_TAG_COMMENT_NAME.to_string(),
                 comment.as_bytes().to_vec(),
             )
             .await?;
+        self.comment = Some(comment.to_string());
         Ok(())
     }
 
+    /// See [`crate::handle::Handle::setxattr`].
+    pub async fn setxattr(&mut self, name: String, data: Vec<u8>) -> Result<(), crate::Error> {
+        self.inner_mut().setxattr(name, data).await
+    }
+
+    /// See [`crate::handle::Handle::removexattr`].
+    pub async fn removexattr(&mut self, name: String) -> Result<(), crate::Error> {
+        self.inner_mut().removexattr(name).await
+    }
+
+    /// See [`crate::handle::Handle::set_mode`].
+    pub async fn set_mode(&mut self, mode: u32) -> Result<(), crate::Error> {
+        self.inner_mut().set_mode(mode).await
+    }
+
+    /// See [`crate::handle::Handle::setmtime`].
+    pub async fn setmtime(&mut self, time: Timespec) -> Result<(), crate::Error> {
+        self.inner_mut().setmtime(time).await
+    }
+
     /// Durably persist a resource in the [`ScratchDirectory`] by moving it
     /// outside the scratch space.
     pub async fn persistat(
-        self,
+        mut self,
         to_handle: &DirectoryHandle,
         to_filename: String,
     ) -> Result<crate::handle::Handle<K>, crate::Error> {
-        let ScratchHandle {
-            inner,
-            root_handle,
-            filename: from_filename,
-        } = self;
-
-        let from_filename = PlatformFilename::try_new(from_filename)?;
+        let from_filename = PlatformFilename::try_new(self.filename.clone())?;
         let to_filename = PlatformFilename::try_new(to_filename)?;
         tracing::debug!(
             ?from_filename,
@@ -162,20 +310,136 @@ impl<K> ScratchHandle<K> {
             "durably persist a scratch resource"
         );
 
+        let from_handle = self.root_handle.to_inner();
         let to_handle = to_handle.to_inner();
-        inner
+        self.inner_mut()
             .worker
             .run(move || {
-                FilesystemPlatform::renameat(
-                    root_handle.to_inner(),
-                    from_filename,
-                    to_handle,
-                    to_filename,
-                )
+                FilesystemPlatform::renameat(from_handle, from_filename, to_handle, to_filename)
             })
             .await?;
+        // The resource no longer lives in the scratch directory.
+        self.usage.release(self.len);
 
-        Ok(inner)
+        Ok(self
+            .inner
+            .take()
+            .expect("only taken when persisting or leaking"))
+    }
+
+    /// Record the resource as leaked so it can be evicted to make room.
+    ///
+    /// Files count their bytes as they're written, but the contents of a directory are
+    /// written through other handles, so they're counted once the directory is released.
+    fn leak(&mut self, mut inner: crate::handle::Handle<K>) {
+        let entry = LeakedScratchEntry {
+            name: self.filename.clone(),
+            kind: self.kind,
+            size: self.len,
+            mtime: Timespec::from(SystemTime::now()),
+            ruleset: self.ruleset.take(),
+            comment: self.comment.take(),
+        };
+        tracing::debug!(name = ?entry.name, kind = ?entry.kind, "leaking scratch resource");
+
+        if self.kind != FileType::Directory {
+            self.usage.leaked.lock().expect("poisoned").push(entry);
+            return;
+        }
+        // Take ownership of the platform handle so it stays open until we've sized the
+        // directory, then close it like any other dropped handle.
+        let (Some(handle), Some(permit)) = (inner.inner.take(), inner.permit.take()) else {
+            self.usage.leaked.lock().expect("poisoned").push(entry);
+            return;
+        };
+        let diagnostics = inner.diagnostics.take();
+        let drops_tx = inner.drops_tx.clone();
+        let usage = Arc::clone(&self.usage);
+        inner.worker.spawn(move || {
+            let mut entry = entry;
+            let size = FilesystemPlatform::fstat(handle)
+                .and_then(|stat| Ok(stat.size + contents_size(handle)?));
+            match size {
+                Ok(size) => {
+                    usage.record(size);
+                    entry.size += size;
+                }
+                Err(err) => {
+                    tracing::warn!(name = ?entry.name, ?err, "failed to size leaked scratch directory");
+                }
+            }
+            usage.leaked.lock().expect("poisoned").push(entry);
+
+            let dropped = DroppedHandle {
+                inner: handle,
+                permit,
+                diagnostics,
+            };
+            let _ = drops_tx.send(dropped);
+        });
+    }
+}
+
+impl ScratchHandle<FileKind> {
+    /// Write the provided data to the file, counting it against the
+    /// [`ScratchOptions::max_bytes`] budget.
+    pub async fn write(&mut self, data: Vec<u8>, offset: usize) -> Result<(), crate::Error> {
+        let end = u64::cast_from(offset) + u64::cast_from(data.len());
+        self.grow(end, |inner| inner.write(data, offset)).await
+    }
+
+    /// Write all of `bufs`, in order, starting at `offset`, counting them against the
+    /// [`ScratchOptions::max_bytes`] budget.
+    pub async fn write_vectored(
+        &mut self,
+        bufs: Vec<Vec<u8>>,
+        offset: usize,
+    ) -> Result<(), crate::Error> {
+        let len: usize = bufs.iter().map(Vec::len).sum();
+        let end = u64::cast_from(offset) + u64::cast_from(len);
+        self.grow(end, |inner| inner.write_vectored(bufs, offset))
+            .await
+    }
+
+    /// Reserve disk space for the first `len` bytes of the file, counting them against the
+    /// [`ScratchOptions::max_bytes`] budget.
+    pub async fn allocate(&mut self, len: usize) -> Result<(), crate::Error> {
+        self.grow(u64::cast_from(len), |inner| inner.allocate(len))
+            .await
+    }
+
+    /// Truncate, or extend with zeros, the file to `len` bytes, adjusting how much of the
+    /// [`ScratchOptions::max_bytes`] budget it uses.
+    pub async fn truncate(&mut self, len: usize) -> Result<(), crate::Error> {
+        let end = u64::cast_from(len);
+        self.grow(end, |inner| inner.truncate(len)).await?;
+        if end < self.len {
+            self.usage.release(self.len - end);
+            self.len = end;
+        }
+        Ok(())
+    }
+
+    /// Reserve room for the file to extend to `end` bytes, then run `op`.
+    async fn grow<'a, F, Fut>(&'a mut self, end: u64, op: F) -> Result<(), crate::Error>
+    where
+        F: FnOnce(&'a mut crate::handle::FileHandle) -> Fut,
+        Fut: Future<Output = Result<(), crate::Error>> + 'a,
+    {
+        let growth = end.saturating_sub(self.len);
+        self.usage.reserve(&self.root_handle, growth).await?;
+
+        let inner = self
+            .inner
+            .as_mut()
+            .expect("only taken when persisting or leaking");
+        if let Err(err) = op(inner).await {
+            self.usage.release(growth);
+            return Err(err);
+        }
+        self.len = self.len.max(end);
+
+        Ok(())
     }
 }
 
@@ -183,15 +447,279 @@ impl<K> Deref for ScratchHandle<K> {
     type Target = crate::handle::Handle<K>;
 
     fn deref(&self) -> &Self::Target {
-        &self.inner
+        self.inner
+            .as_ref()
+            .expect("only taken when persisting or leaking")
     }
 }
 
-impl<K> DerefMut for ScratchHandle<K> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.inner
+impl<K> Drop for ScratchHandle<K> {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            self.leak(inner);
+        }
     }
 }
 
 pub type ScratchFileHandle = ScratchHandle<FileKind>;
 pub type ScratchDirectoryHandle = ScratchHandle<DirectoryKind>;
+
+/// Tracks the bytes stored in a [`ScratchDirectory`], see [`ScratchOptions::max_bytes`].
+#[derive(Debug)]
+struct ScratchUsage {
+    /// Bytes currently stored in the scratch directory.
+    used: AtomicU64,
+    /// Maximum number of bytes we allow to be stored.
+    max: Option<u64>,
+    /// Leaked entries that can be evicted to make room, oldest first.
+    leaked: Mutex<Vec<LeakedScratchEntry>>,
+}
+
+impl ScratchUsage {
+    /// Count `bytes` against the budget, evicting leaked entries to make room if necessary.
+    ///
+    /// Returns [`crate::Error::NoSpace`] if there isn't enough room even after evicting
+    /// every leaked entry.
+    async fn reserve(&self, root_handle: &DirectoryHandle, bytes: u64) -> Result<(), crate::Error> {
+        while !self.try_reserve(bytes) {
+            let oldest = {
+                let mut leaked = self.leaked.lock().expect("poisoned");
+                (!leaked.is_empty()).then(|| leaked.remove(0))
+            };
+            let Some(entry) = oldest else {
+                let used = self.used.load(Ordering::SeqCst);
+                tracing::warn!(bytes, used, max = ?self.max, "scratch directory is full");
+                return Err(crate::Error::NoSpace);
+            };
+
+            match remove_entry(root_handle, entry.name.clone(), entry.kind).await {
+                Ok(()) => {
+                    tracing::info!(name = ?entry.name, size = entry.size, "evicted leaked scratch entry");
+                    self.release(entry.size);
+                }
+                Err(err) => {
+                    tracing::warn!(name = ?entry.name, ?err, "failed to evict leaked scratch entry");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Count `bytes` against the budget, returns `false` if they don't fit.
+    fn try_reserve(&self, bytes: u64) -> bool {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                let used = used.checked_add(bytes)?;
+                match self.max {
+                    Some(max) if used > max => None,
+                    _ => Some(used),
+                }
+            })
+            .is_ok()
+    }
+
+    /// Count `bytes` that are already stored against the budget, even if they don't fit.
+    fn record(&self, bytes: u64) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                Some(used.saturating_add(bytes))
+            });
+    }
+
+    /// Stop counting `bytes` against the budget.
+    fn release(&self, bytes: u64) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                Some(used.saturating_sub(bytes))
+            });
+    }
+}
+
+/// Report every entry in the scratch directory, all of which were leaked by a previous run,
+/// removing the ones last modified longer than `max_age` ago.
+///
+/// Returns the leaked entries that were kept.
+async fn cleanup_leaked(
+    root_handle: &DirectoryHandle,
+    max_age: Option<Duration>,
+) -> Result<Vec<LeakedScratchEntry>, crate::Error> {
+    let now = Timespec::from(SystemTime::now());
+    let mut leaked = Vec::new();
+
+    for entry in root_handle.list().await? {
+        let stat = match root_handle.fstatat(entry.name.clone()).await {
+            Ok(stat) => stat,
+            // Someone else cleaned it up in the meantime.
+            Err(crate::Error::NotFound) => continue,
+            Err(err) => return Err(err),
+        };
+        let (ruleset, comment) = read_tags(root_handle, &entry.name, stat.kind).await;
+        let size = match stat.kind {
+            FileType::Directory => match leaked_contents_size(root_handle, &entry.name).await {
+                Ok(size) => stat.size + size,
+                Err(err) => {
+                    tracing::warn!(name = ?entry.name, ?err, "failed to size leaked scratch directory");
+                    stat.size
+                }
+            },
+            _ => stat.size,
+        };
+        let age = now.duration_since(stat.mtime).unwrap_or_default();
+        tracing::warn!(
+            name = ?entry.name,
+            kind = ?stat.kind,
+            size,
+            ?age,
+            ?ruleset,
+            ?comment,
+            "found leaked scratch entry"
+        );
+
+        let leaked_entry = LeakedScratchEntry {
+            name: entry.name,
+            kind: stat.kind,
+            size,
+            mtime: stat.mtime,
+            ruleset,
+            comment,
+        };
+        if max_age.is_some_and(|max_age| age > max_age) {
+            match remove_entry(root_handle, leaked_entry.name.clone(), stat.kind).await {
+                Ok(()) => {
+                    tracing::info!(name = ?leaked_entry.name, "removed leaked scratch entry");
+                    continue;
+                }
+                Err(err) => {
+                    tracing::warn!(name = ?leaked_entry.name, ?err, "failed to remove leaked scratch entry");
+                }
+            }
+        }
+        leaked.push(leaked_entry);
+    }
+
+    Ok(leaked)
+}
+
+/// Size of everything within the leaked directory `name` in the scratch directory.
+async fn leaked_contents_size(
+    root_handle: &DirectoryHandle,
+    name: &str,
+) -> Result<u64, crate::Error> {
+    let handle = root_handle
+        .openat(name.to_string())
+        .no_follow()
+        .as_directory()
+        .await?;
+    let inner = handle.to_inner();
+    let size = handle.worker.run(move || contents_size(inner)).await;
+    handle.close().await?;
+    size
+}
+
+/// Recursively sum the size of every entry within the directory `handle`.
+///
+/// Note: This blocks, it should be run on a [`crate::filesystem::FilesystemWorker`].
+fn contents_size(handle: PlatformHandleType) -> Result<u64, crate::Error> {
+    let mut size = 0;
+    for entry in FilesystemPlatform::listdir(handle)? {
+        let filename = PlatformFilenameType::try_new(entry.name)?;
+        let stat = match FilesystemPlatform::fstatat(handle, filename.clone()) {
+            Ok(stat) => stat,
+            // Removed while we were walking the directory.
+            Err(crate::Error::NotFound) => continue,
+            Err(err) => return Err(err),
+        };
+        size += stat.size;
+
+        if stat.kind == FileType::Directory {
+            let options = OpenOptions::READ_ONLY | OpenOptions::DIRECTORY | OpenOptions::NO_FOLLOW;
+            let child = FilesystemPlatform::openat(handle, filename, options)?;
+            let child_size = contents_size(child);
+            FilesystemPlatform::close(child)?;
+            size += child_size?;
+        }
+    }
+    Ok(size)
+}
+
+/// Read the ruleset and comment tags from the entry `name` in the scratch directory.
+///
+/// Tags are only informational, so failing to read them isn't an error.
+async fn read_tags(
+    root_handle: &DirectoryHandle,
+    name: &str,
+    kind: FileType,
+) -> (Option<String>, Option<String>) {
+    async fn read<K>(handle: Handle<K>) -> Result<(Option<String>, Option<String>), crate::Error> {
+        let names = handle.listxattr().await?;
+        let mut tags = [None, None];
+        let tag_names = [
+            SCRATCH_XATTR_TAG_RULESET_NAME,
+            SCRATCH_XATTR_TAG_COMMENT_NAME,
+        ];
+        for (tag, tag_name) in tags.iter_mut().zip(tag_names) {
+            if names.iter().any(|name| name == tag_name) {
+                let value = handle.getxattr(tag_name.to_string()).await?;
+                *tag = Some(String::from_utf8_lossy(&value).into_owned());
+            }
+        }
+        handle.close().await?;
+        let [ruleset, comment] = tags;
+        Ok((ruleset, comment))
+    }
+
+    let builder = root_handle.openat(name.to_string()).no_follow();
+    let result = match kind {
+        FileType::File => match builder.as_file().await {
+            Ok((handle, _stat)) => read(handle).await,
+            Err(err) => Err(err),
+        },
+        FileType::Directory => match builder.as_directory().await {
+            Ok(handle) => read(handle).await,
+            Err(err) => Err(err),
+        },
+        _ => return (None, None),
+    };
+    result.unwrap_or_else(|err| {
+        tracing::debug!(?name, ?err, "failed to read scratch entry tags");
+        (None, None)
+    })
+}
+
+/// Remove the entry `name` from the scratch directory, including everything in it if it's a
+/// directory.
+async fn remove_entry(
+    root_handle: &DirectoryHandle,
+    name: String,
+    kind: FileType,
+) -> Result<(), crate::Error> {
+    if kind != FileType::Directory {
+        return root_handle.removeat(name).await;
+    }
+
+    // Collect directories parents first, then remove them children first.
+    let mut directories = vec![name];
+    let mut idx = 0;
+    while let Some(path) = directories.get(idx).cloned() {
+        let handle = root_handle
+            .openat(path.clone())
+            .no_follow()
+            .as_directory()
+            .await?;
+        for entry in handle.list().await? {
+            match entry.kind {
+                FileType::Directory => directories.push(format!("{path}/{}", entry.name)),
+                _ => handle.removeat(entry.name).await?,
+            }
+        }
+        handle.close().await?;
+        idx += 1;
+    }
+    for path in directories.into_iter().rev() {
+        root_handle.removedirat(path).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,199 @@
+//! Metrics collected while running filesystem operations.
+//!
+//! Everything is recorded with relaxed atomics so collecting metrics is cheap enough to
+//! always leave on. Use [`Filesystem::metrics`] to get a [`MetricsSnapshot`].
+//!
+//! [`Filesystem::metrics`]: crate::filesystem::Filesystem::metrics
+
+use pb_ore::cast::CastFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Number of buckets in a [`Histogram`].
+///
+/// Bucket `i` counts durations less than `2^i` microseconds, so the last bucket catches
+/// everything longer than ~18 minutes.
+const NUM_BUCKETS: usize = 31;
+
+/// Counters and histograms shared by all clones of a [`FilesystemWorker`].
+///
+/// [`FilesystemWorker`]: crate::filesystem::FilesystemWorker
+#[derive(Debug, Default)]
+pub(crate) struct FilesystemMetrics {
+    /// Number of files and directories opened.
+    opens: AtomicU64,
+    /// Number of read syscalls.
+    reads: AtomicU64,
+    /// Total bytes returned by read syscalls.
+    bytes_read: AtomicU64,
+    /// Number of write syscalls.
+    writes: AtomicU64,
+    /// Total bytes accepted by write syscalls.
+    bytes_written: AtomicU64,
+    /// Number of stats served from the stat cache.
+    stat_cache_hits: AtomicU64,
+    /// Number of stats that missed the stat cache.
+    stat_cache_misses: AtomicU64,
+    /// Time an operation spent queued before a worker thread picked it up.
+    queue_wait: Histogram,
+    /// Time an operation spent running on a worker thread.
+    operation_latency: Histogram,
+    /// Time spent waiting for a permit to open a handle.
+    permit_wait: Histogram,
+}
+
+impl FilesystemMetrics {
+    pub(crate) fn record_open(&self) {
+        self.opens.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_read(&self, bytes: usize) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read
+            .fetch_add(u64::cast_from(bytes), Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_write(&self, bytes: usize) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(u64::cast_from(bytes), Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_stat_cache_hit(&self) {
+        self.stat_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_stat_cache_miss(&self) {
+        self.stat_cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_queue_wait(&self, duration: Duration) {
+        self.queue_wait.record(duration);
+    }
+
+    pub(crate) fn record_operation(&self, duration: Duration) {
+        self.operation_latency.record(duration);
+    }
+
+    pub(crate) fn record_permit_wait(&self, duration: Duration) {
+        self.permit_wait.record(duration);
+    }
+
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            opens: self.opens.load(Ordering::Relaxed),
+            reads: self.reads.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            stat_cache_hits: self.stat_cache_hits.load(Ordering::Relaxed),
+            stat_cache_misses: self.stat_cache_misses.load(Ordering::Relaxed),
+            queue_wait: self.queue_wait.snapshot(),
+            operation_latency: self.operation_latency.snapshot(),
+            permit_wait: self.permit_wait.snapshot(),
+        }
+    }
+}
+
+/// Point in time copy of the metrics for a [`Filesystem`].
+///
+/// Comparing `queue_wait` to `operation_latency` gives a rough idea of whether we're I/O
+/// bound, a growing queue means the worker threads can't keep up, while a large
+/// `permit_wait` means we're limited by the number of open handles.
+///
+/// [`Filesystem`]: crate::filesystem::Filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Number of files and directories opened.
+    pub opens: u64,
+    /// Number of read syscalls.
+    pub reads: u64,
+    /// Total bytes read.
+    pub bytes_read: u64,
+    /// Number of write syscalls.
+    pub writes: u64,
+    /// Total bytes written.
+    pub bytes_written: u64,
+    /// Number of stats served from the stat cache.
+    pub stat_cache_hits: u64,
+    /// Number of stats that missed the stat cache.
+    pub stat_cache_misses: u64,
+    /// Time operations spent queued before a worker thread picked them up.
+    pub queue_wait: HistogramSnapshot,
+    /// Time operations spent running on a worker thread.
+    pub operation_latency: HistogramSnapshot,
+    /// Time spent waiting for a permit to open a handle.
+    pub permit_wait: HistogramSnapshot,
+}
+
+/// Histogram of durations with exponentially sized buckets.
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; NUM_BUCKETS],
+    /// Sum of all recorded durations, in microseconds.
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn record(&self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let bucket = usize::try_from(u64::BITS - micros.leading_zeros()).expect("fits in usize");
+        self.buckets[bucket.min(NUM_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let buckets: Vec<_> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(idx, count)| {
+                let upper_bound = if idx == NUM_BUCKETS - 1 {
+                    Duration::MAX
+                } else {
+                    Duration::from_micros(1 << idx)
+                };
+                (upper_bound, count.load(Ordering::Relaxed))
+            })
+            .collect();
+        HistogramSnapshot {
+            count: buckets.iter().map(|(_, count)| count).sum(),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+            buckets,
+        }
+    }
+}
+
+/// Point in time copy of a histogram of durations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// Total number of recorded durations.
+    pub count: u64,
+    /// Sum of all recorded durations.
+    pub sum: Duration,
+    /// Exclusive upper bound of each bucket, and the number of durations that fell in it.
+    pub buckets: Vec<(Duration, u64)>,
+}
+
+impl HistogramSnapshot {
+    /// Average of all recorded durations, `None` if nothing was recorded.
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.count).unwrap_or(u32::MAX);
+        self.sum.checked_div(count)
+    }
+
+    /// Returns the upper bound of the bucket containing the `quantile`, e.g. `0.99` for the
+    /// 99th percentile, `None` if nothing was recorded.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        // Precision loss is fine, the buckets are coarse anyway.
+        let target = ((self.count as f64) * quantile.clamp(0.0, 1.0)).ceil() as u64;
+        let mut seen = 0;
+        self.buckets.iter().find_map(|(upper_bound, count)| {
+            seen += count;
+            (seen >= target.max(1)).then_some(*upper_bound)
+        })
+    }
+}
@@ -1,14 +1,15 @@
 //! Abstract interface for a specific platform, e.g. `darwin`, `unix`, etc.
 
 use bitflags::bitflags;
-use std::{fmt::Debug, path::PathBuf};
+use std::io::{IoSlice, IoSliceMut};
+use std::{fmt::Debug, path::PathBuf, ptr::NonNull};
 
 use crate::{DirectoryEntry, Error, FileStat};
 
 mod todo;
 
 bitflags! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy)]
     pub struct OpenOptions: u32 {
         const READ_ONLY = 0b0000_0001;
         const READ_WRITE = 0b0000_0010;
@@ -20,6 +21,8 @@ bitflags! {
 
         /// Restrict opening to just directories.
         const DIRECTORY = 0b0100_0000;
+        /// Fail if the last component of the path is a symlink.
+        const NO_FOLLOW = 0b1000_0000;
     }
 }
 
@@ -48,16 +51,66 @@ pub trait Platform {
     fn mkdir(path: Self::Path) -> Result<(), Error>;
     fn mkdirat(handle: Self::Handle, filename: Self::Filename) -> Result<(), Error>;
 
+    fn unlink(path: Self::Path) -> Result<(), Error>;
+    fn unlinkat(handle: Self::Handle, filename: Self::Filename) -> Result<(), Error>;
+    /// Remove the empty directory `filename` relative to `handle`.
+    fn rmdirat(handle: Self::Handle, filename: Self::Filename) -> Result<(), Error>;
+
     fn stat(path: Self::Path) -> Result<FileStat, Error>;
     fn fstat(handle: Self::Handle) -> Result<FileStat, Error>;
     fn fstatat(handle: Self::Handle, filename: Self::Filename) -> Result<FileStat, Error>;
 
     fn fsync(handle: Self::Handle) -> Result<(), Error>;
 
+    fn fchmod(handle: Self::Handle, mode: u32) -> Result<(), Error>;
+    fn fchown(handle: Self::Handle, user: u32, group: u32) -> Result<(), Error>;
+
     fn listdir(handle: Self::Handle) -> Result<Vec<DirectoryEntry>, Error>;
+    fn opendir(handle: Self::Handle) -> Result<Self::DirStream, Error>;
+    /// Read the next batch of entries from the stream, returns an empty batch once all of the
+    /// entries have been read.
+    fn readdir(stream: Self::DirStream) -> Result<Vec<DirectoryEntry>, Error>;
+    fn closedir(stream: Self::DirStream) -> Result<(), Error>;
 
     fn read(handle: Self::Handle, buf: &mut [u8], offset: usize) -> Result<usize, Error>;
     fn write(handle: Self::Handle, data: &[u8], offset: usize) -> Result<usize, Error>;
+    /// Read into each of `bufs` in order with a single syscall, starting at `offset`.
+    fn readv(
+        handle: Self::Handle,
+        bufs: &mut [IoSliceMut<'_>],
+        offset: usize,
+    ) -> Result<usize, Error>;
+    /// Write each of `bufs` in order with a single syscall, starting at `offset`.
+    ///
+    /// Like [`Platform::write`] this may write less than all of the data.
+    fn writev(handle: Self::Handle, bufs: &[IoSlice<'_>], offset: usize) -> Result<usize, Error>;
+    fn ftruncate(handle: Self::Handle, len: usize) -> Result<(), Error>;
+    /// Reserve disk space for the first `len` bytes of the file, extending it if necessary.
+    ///
+    /// Returns [`Error::NoSpace`] if there isn't enough space available.
+    fn fallocate(handle: Self::Handle, len: usize) -> Result<(), Error>;
+
+    /// Map the first `len` bytes of the file into memory, read-only.
+    ///
+    /// Returns [`Error::Unsupported`] if the file can't be mapped.
+    fn mmap(handle: Self::Handle, len: usize) -> Result<NonNull<u8>, Error>;
+    /// Unmap memory previously mapped with [`Platform::mmap`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must have been returned from, and passed to, [`Platform::mmap`] and the
+    /// memory must not be used after this call.
+    unsafe fn munmap(ptr: NonNull<u8>, len: usize) -> Result<(), Error>;
+
+    /// Create `to` as a copy-on-write clone of `from`.
+    ///
+    /// Returns [`Error::Unsupported`] if the filesystem does not support cloning.
+    fn clonefile(from: Self::Path, to: Self::Path) -> Result<(), Error>;
+    /// Copy the contents of `from` into `to` using a platform accelerated path.
+    ///
+    /// Returns [`Error::Unsupported`] if the files can't be copied this way, in which case the
+    /// caller should fallback to reading and writing the data.
+    fn fcopy(from: Self::Handle, to: Self::Handle) -> Result<(), Error>;
 
     fn rename(from: Self::Path, to: Self::Path) -> Result<(), Error>;
     fn renameat(
@@ -67,6 +120,28 @@ pub trait Platform {
         to_filename: Self::Filename,
     ) -> Result<(), Error>;
 
+    fn linkat(
+        from_handle: Self::Handle,
+        from_filename: Self::Filename,
+        to_handle: Self::Handle,
+        to_filename: Self::Filename,
+    ) -> Result<(), Error>;
+
+    /// Create an anonymous file in the directory, which isn't visible until it's linked into
+    /// place with [`Platform::flinkat`].
+    ///
+    /// Returns [`Error::Unsupported`] if the platform, or filesystem, doesn't support them.
+    fn tmpfileat(handle: Self::Handle) -> Result<Self::Handle, Error>;
+    /// Create a link at `to_filename` for the file, which can be anonymous, referenced by
+    /// `handle`.
+    ///
+    /// Returns [`Error::AlreadyExists`] if there is already a file at `to_filename`.
+    fn flinkat(
+        handle: Self::Handle,
+        to_handle: Self::Handle,
+        to_filename: Self::Filename,
+    ) -> Result<(), Error>;
+
     fn swapat(
         from_handle: Self::Handle,
         from_filename: Self::Filename,
@@ -80,6 +155,19 @@ pub trait Platform {
         name: Self::Filename,
         buf: &mut [u8],
     ) -> Result<usize, Error>;
+    fn flistxattr(handle: Self::Handle) -> Result<Vec<String>, Error>;
+    fn fremovexattr(handle: Self::Handle, name: Self::Filename) -> Result<(), Error>;
+
+    fn readlink(path: Self::Path) -> Result<Self::Path, Error>;
+    /// Resolve `path`, which must exist, to an absolute path without any symlinks, `.`, or
+    /// `..` components.
+    fn realpath(path: Self::Path) -> Result<Self::Path, Error>;
+    fn readlinkat(handle: Self::Handle, filename: Self::Filename) -> Result<Self::Path, Error>;
+    fn symlinkat(
+        target: Self::Path,
+        handle: Self::Handle,
+        filename: Self::Filename,
+    ) -> Result<(), Error>;
 
     fn fgetpath(handle: Self::Handle) -> Result<Self::Path, Error>;
 
@@ -94,8 +182,34 @@ pub trait PlatformFilename: Debug + Clone {
     fn try_new(val: String) -> Result<Self, crate::Error>;
 }
 
+/// Read all of the remaining entries from a [`Platform::DirStream`].
+pub(crate) fn readdir_all<P: Platform>(stream: P::DirStream) -> Result<Vec<DirectoryEntry>, Error> {
+    let mut entries = Vec::new();
+    loop {
+        let batch = P::readdir(stream.clone())?;
+        if batch.is_empty() {
+            return Ok(entries);
+        }
+        entries.extend(batch);
+    }
+}
+
+/// Parse the nul separated list of names returned when listing xattrs.
+pub(crate) fn parse_xattr_names(buf: &[u8]) -> Result<Vec<String>, Error> {
+    buf.split(|b| *b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            std::str::from_utf8(name)
+                .map(|name| name.to_string())
+                .map_err(|err| Error::InvalidData(err.to_string().into()))
+        })
+        .collect()
+}
+
 /// Type alias for the [`Platform::Handle`] associated type for the current [`FilesystemPlatform`].
 pub type PlatformHandleType = <FilesystemPlatform as Platform>::Handle;
+/// Type alias for the [`Platform::DirStream`] associated type for the current [`FilesystemPlatform`].
+pub type PlatformDirStreamType = <FilesystemPlatform as Platform>::DirStream;
 /// Type alias for the [`Platform::Path`] associated type for the current [`FilesystemPlatform`].
 pub type PlatformPathType = <FilesystemPlatform as Platform>::Path;
 /// Type alias for the [`Platform::Filename`] associated type for the current [`FilesystemPlatform`].
@@ -105,6 +219,11 @@ cfg_if::cfg_if! {
     if #[cfg(target_os = "macos")] {
         mod darwin;
         pub use darwin::DarwinPlatform as FilesystemPlatform;
+    } else if #[cfg(target_os = "linux")] {
+        mod linux;
+        pub use linux::LinuxPlatform as FilesystemPlatform;
+        #[cfg(feature = "io-uring")]
+        pub use linux::UringDriver;
     } else {
         pub use todo::TodoPlatform as FilesystemPlatform;
     }
@@ -1,6 +1,8 @@
 use pb_ore::cast::CastFrom;
 use pb_types::Timespec;
 use std::ffi::{c_uint, CStr, CString};
+use std::io::{IoSlice, IoSliceMut};
+use std::ptr::NonNull;
 
 use crate::platform::darwin::path::DarwinFilename;
 use crate::platform::darwin::types::{rlimit, DarwinDirStream, DarwinHandle};
@@ -21,6 +23,17 @@ static LISTDIR_IGNORED_NAMES: &[&str] = &[".", ".."];
 
 pub struct DarwinPlatform;
 
+/// Calls `f` until it doesn't fail with `EINTR`.
+fn retry_interrupted<T: Copy + PartialEq + From<i8>>(mut f: impl FnMut() -> T) -> T {
+    loop {
+        let result = f();
+        let errno = std::io::Error::last_os_error().raw_os_error();
+        if result != T::from(-1) || errno != Some(types::constants::EINTR) {
+            return result;
+        }
+    }
+}
+
 fn check_result(val: types::c_int) -> Result<types::c_int, crate::Error> {
     if val == -1 {
         // TODO: Maybe read errno directly.
@@ -31,6 +44,14 @@ fn check_result(val: types::c_int) -> Result<types::c_int, crate::Error> {
     }
 }
 
+/// Like [`check_result`], but for syscalls that return a size.
+fn check_size(val: isize) -> Result<usize, crate::Error> {
+    usize::try_from(val).map_err(|_| {
+        let err = std::io::Error::last_os_error().raw_os_error();
+        crate::Error::from_darwin_sys(err.unwrap_or(-1))
+    })
+}
+
 impl Platform for DarwinPlatform {
     type Path = DarwinPath;
     type Filename = DarwinFilename;
@@ -55,6 +76,9 @@ impl Platform for DarwinPlatform {
             flags |= types::flags::O_TRUNC;
             flags |= types::flags::O_RDWR;
         }
+        if options.contains(OpenOptions::NO_FOLLOW) {
+            flags |= types::flags::O_NOFOLLOW;
+        }
 
         // If we're creating a file make sure it's writeable.
         let mode = if (flags & types::flags::O_CREAT) > 0 {
@@ -64,9 +88,9 @@ impl Platform for DarwinPlatform {
         };
 
         let result = if mode != 0 {
-            unsafe { syscalls::open(path.into_raw(), flags, mode) }
+            retry_interrupted(|| unsafe { syscalls::open(path.into_raw(), flags, mode) })
         } else {
-            unsafe { syscalls::open(path.into_raw(), flags) }
+            retry_interrupted(|| unsafe { syscalls::open(path.into_raw(), flags) })
         };
         let fd = check_result(result)?;
         let handle = DarwinHandle::from_raw(fd);
@@ -95,6 +119,9 @@ impl Platform for DarwinPlatform {
             flags |= types::flags::O_TRUNC;
             flags |= types::flags::O_RDWR;
         }
+        if options.contains(OpenOptions::NO_FOLLOW) {
+            flags |= types::flags::O_NOFOLLOW;
+        }
 
         // If we're creating a file make sure it's writeable.
         let mode = if (flags & types::flags::O_CREAT) > 0 {
@@ -104,9 +131,13 @@ impl Platform for DarwinPlatform {
         };
 
         let result = if mode != 0 {
-            unsafe { syscalls::openat(handle.into_raw(), filename.into_raw(), flags, mode) }
+            retry_interrupted(|| unsafe {
+                syscalls::openat(handle.into_raw(), filename.into_raw(), flags, mode)
+            })
         } else {
-            unsafe { syscalls::openat(handle.into_raw(), filename.into_raw(), flags) }
+            retry_interrupted(|| unsafe {
+                syscalls::openat(handle.into_raw(), filename.into_raw(), flags)
+            })
         };
         let fd = check_result(result)?;
         let handle = DarwinHandle::from_raw(fd);
@@ -140,6 +171,33 @@ impl Platform for DarwinPlatform {
         Ok(())
     }
 
+    fn unlink(path: Self::Path) -> Result<(), crate::Error> {
+        let path = CString::from(path);
+        let result = unsafe { syscalls::unlink(path.as_ptr()) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn unlinkat(handle: Self::Handle, filename: Self::Filename) -> Result<(), crate::Error> {
+        let filename = CString::from(filename);
+        let result = unsafe { syscalls::unlinkat(handle.into_raw(), filename.as_ptr(), 0) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn rmdirat(handle: Self::Handle, filename: Self::Filename) -> Result<(), crate::Error> {
+        let filename = CString::from(filename);
+        let result = unsafe {
+            syscalls::unlinkat(
+                handle.into_raw(),
+                filename.as_ptr(),
+                types::flags::AT_REMOVEDIR,
+            )
+        };
+        check_result(result)?;
+        Ok(())
+    }
+
     fn stat(path: Self::Path) -> Result<FileStat, crate::Error> {
         let path = CString::from(path);
         let mut raw_stat = types::stat::default();
@@ -180,12 +238,38 @@ impl Platform for DarwinPlatform {
     }
 
     fn fsync(handle: Self::Handle) -> Result<(), crate::Error> {
-        let result = unsafe { syscalls::fsync(handle.into_raw()) };
+        let result = retry_interrupted(|| unsafe { syscalls::fsync(handle.into_raw()) });
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn fchmod(handle: Self::Handle, mode: u32) -> Result<(), crate::Error> {
+        let mode = u16::try_from(mode).map_err(|_| {
+            let msg = format!("invalid mode: {mode:o}").into();
+            crate::Error::InvalidData(msg)
+        })?;
+        let result = unsafe { syscalls::fchmod(handle.into_raw(), mode) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn fchown(handle: Self::Handle, user: u32, group: u32) -> Result<(), crate::Error> {
+        let result = unsafe { syscalls::fchown(handle.into_raw(), user, group) };
         check_result(result)?;
         Ok(())
     }
 
     fn listdir(handle: Self::Handle) -> Result<Vec<DirectoryEntry>, crate::Error> {
+        let dir_stream = DarwinPlatform::opendir(handle)?;
+        let entries = crate::platform::readdir_all::<DarwinPlatform>(dir_stream);
+
+        // Done listing! Close the directory stream.
+        DarwinPlatform::closedir(dir_stream)?;
+
+        entries
+    }
+
+    fn opendir(handle: Self::Handle) -> Result<Self::DirStream, crate::Error> {
         // Duplicate the file handle because `fopendir` moves ownership of the
         // handle to the system.
         let result = unsafe { syscalls::dup(handle.into_raw()) };
@@ -194,52 +278,202 @@ impl Platform for DarwinPlatform {
         // Create a directory stream.
         let dir_stream = unsafe { syscalls::fdopendir(dup_handle) };
         if dir_stream.is_null() {
+            unsafe { syscalls::close(dup_handle) };
             return Err(crate::Error::Unknown("failed to open directory".into()));
         }
 
+        Ok(DarwinDirStream { inner: dir_stream })
+    }
+
+    fn readdir(stream: Self::DirStream) -> Result<Vec<DirectoryEntry>, crate::Error> {
         let mut entries = Vec::new();
-        let mut dirent = unsafe { syscalls::readdir(dir_stream) };
 
-        while !dirent.is_null() {
+        while entries.len() < types::constants::READDIR_BATCH_SIZE {
+            let dirent = unsafe { syscalls::readdir(stream.inner) };
+            if dirent.is_null() {
+                break;
+            }
+
             let entry = DirectoryEntry::try_from(unsafe { *dirent })?;
             if !LISTDIR_IGNORED_NAMES.contains(&&*entry.name) {
                 entries.push(entry);
             }
-            dirent = unsafe { syscalls::readdir(dir_stream) };
         }
 
-        // Done listing! Close the directory stream.
-        unsafe { syscalls::closedir(dir_stream) };
-
         Ok(entries)
     }
 
+    fn closedir(stream: Self::DirStream) -> Result<(), crate::Error> {
+        let result = unsafe { syscalls::closedir(stream.inner) };
+        check_result(result)?;
+        Ok(())
+    }
+
     fn read(handle: Self::Handle, buf: &mut [u8], offset: usize) -> Result<usize, crate::Error> {
         let buf_ptr = buf.as_mut_ptr();
         let buf_len = buf.len();
-        let offset = offset.try_into().expect("TODO");
+        let offset = i64::try_from(offset)
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
 
-        let result = unsafe { syscalls::pread(handle.into_raw(), buf_ptr, buf_len, offset) };
-        if result < 0 {
-            Err(crate::Error::Unknown("TODO".to_string()))
-        } else {
-            let bytes_read = result.try_into().expect("checked that we're positive");
-            Ok(bytes_read)
-        }
+        let result = retry_interrupted(|| unsafe {
+            syscalls::pread(handle.into_raw(), buf_ptr, buf_len, offset)
+        });
+        check_size(result)
     }
 
     fn write(handle: Self::Handle, data: &[u8], offset: usize) -> Result<usize, crate::Error> {
         let data_ptr = data.as_ptr();
         let data_len = data.len();
-        let offset = offset.try_into().expect("TODO");
+        let offset = i64::try_from(offset)
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
 
-        let result = unsafe { syscalls::pwrite(handle.into_raw(), data_ptr, data_len, offset) };
-        if result < 0 {
-            Err(crate::Error::Unknown("TODO".to_string()))
-        } else {
-            let bytes_written = result.try_into().expect("checked that we're positive");
-            Ok(bytes_written)
+        let result = retry_interrupted(|| unsafe {
+            syscalls::pwrite(handle.into_raw(), data_ptr, data_len, offset)
+        });
+        check_size(result)
+    }
+
+    fn readv(
+        handle: Self::Handle,
+        bufs: &mut [IoSliceMut<'_>],
+        offset: usize,
+    ) -> Result<usize, crate::Error> {
+        // Any buffers past the limit are left for the caller to retry, like a short read.
+        let count = bufs.len().min(types::constants::IOV_MAX);
+        let offset = i64::try_from(offset)
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+
+        // Note: `IoSliceMut` is guaranteed to be ABI compatible with `iovec`.
+        let iov = bufs.as_ptr().cast::<types::iovec>();
+        let count = types::c_int::try_from(count).expect("known to fit");
+        let result = retry_interrupted(|| unsafe {
+            syscalls::preadv(handle.into_raw(), iov, count, offset)
+        });
+        check_size(result)
+    }
+
+    fn writev(
+        handle: Self::Handle,
+        bufs: &[IoSlice<'_>],
+        offset: usize,
+    ) -> Result<usize, crate::Error> {
+        // Any buffers past the limit are left for the caller to retry, like a short write.
+        let count = bufs.len().min(types::constants::IOV_MAX);
+        let offset = i64::try_from(offset)
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+
+        // Note: `IoSlice` is guaranteed to be ABI compatible with `iovec`.
+        let iov = bufs.as_ptr().cast::<types::iovec>();
+        let count = types::c_int::try_from(count).expect("known to fit");
+        let result = retry_interrupted(|| unsafe {
+            syscalls::pwritev(handle.into_raw(), iov, count, offset)
+        });
+        check_size(result)
+    }
+
+    fn ftruncate(handle: Self::Handle, len: usize) -> Result<(), crate::Error> {
+        let len =
+            i64::try_from(len).map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+        let result = retry_interrupted(|| unsafe { syscalls::ftruncate(handle.into_raw(), len) });
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn fallocate(handle: Self::Handle, len: usize) -> Result<(), crate::Error> {
+        let stat = DarwinPlatform::fstat(handle)?;
+        let size = usize::cast_from(stat.size);
+        // Never shrink the file.
+        if len <= size {
+            return Ok(());
+        }
+        let additional = i64::try_from(len - size)
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+
+        // Try to allocate contiguous space first, falling back to any space.
+        let mut store = types::fstore {
+            fst_flags: types::flags::F_ALLOCATECONTIG | types::flags::F_ALLOCATEALL,
+            fst_posmode: types::flags::F_PEOFPOSMODE,
+            fst_offset: 0,
+            fst_length: additional,
+            fst_bytesalloc: 0,
+        };
+        let result = unsafe {
+            syscalls::fcntl(
+                handle.into_raw(),
+                types::flags::F_PREALLOCATE,
+                &mut store as *mut types::fstore,
+            )
+        };
+        if result == -1 {
+            store.fst_flags = types::flags::F_ALLOCATEALL;
+            let result = unsafe {
+                syscalls::fcntl(
+                    handle.into_raw(),
+                    types::flags::F_PREALLOCATE,
+                    &mut store as *mut types::fstore,
+                )
+            };
+            check_result(result)?;
+        }
+
+        // Unlike `fallocate`, `F_PREALLOCATE` doesn't change the size of the file.
+        DarwinPlatform::ftruncate(handle, len)
+    }
+
+    fn mmap(handle: Self::Handle, len: usize) -> Result<NonNull<u8>, crate::Error> {
+        let result = unsafe {
+            syscalls::mmap(
+                std::ptr::null_mut(),
+                len,
+                types::flags::PROT_READ,
+                types::flags::MAP_PRIVATE,
+                handle.into_raw(),
+                0,
+            )
+        };
+        if result == types::flags::MAP_FAILED {
+            // Filesystems that don't support mapping return `ENODEV`.
+            let err = std::io::Error::last_os_error().raw_os_error().unwrap_or(-1);
+            if err == types::constants::ENODEV {
+                return Err(crate::Error::Unsupported);
+            }
+            return Err(crate::Error::from_darwin_sys(err));
         }
+        Ok(NonNull::new(result).expect("mmap returned null"))
+    }
+
+    unsafe fn munmap(ptr: NonNull<u8>, len: usize) -> Result<(), crate::Error> {
+        let result = unsafe { syscalls::munmap(ptr.as_ptr(), len) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn clonefile(from: Self::Path, to: Self::Path) -> Result<(), crate::Error> {
+        let from = CString::from(from);
+        let to = CString::from(to);
+
+        let result = unsafe { syscalls::clonefile(from.as_ptr(), to.as_ptr(), 0) };
+        if result == -1 {
+            let err = std::io::Error::last_os_error().raw_os_error().unwrap_or(-1);
+            if types::constants::CLONE_UNSUPPORTED_ERRNOS.contains(&err) {
+                return Err(crate::Error::Unsupported);
+            }
+            return Err(crate::Error::from_darwin_sys(err));
+        }
+        Ok(())
+    }
+
+    fn fcopy(from: Self::Handle, to: Self::Handle) -> Result<(), crate::Error> {
+        let result = unsafe {
+            syscalls::fcopyfile(
+                from.into_raw(),
+                to.into_raw(),
+                std::ptr::null_mut(),
+                types::flags::COPYFILE_DATA,
+            )
+        };
+        check_result(result)?;
+        Ok(())
     }
 
     fn rename(from: Self::Path, to: Self::Path) -> Result<(), crate::Error> {
@@ -272,6 +506,42 @@ impl Platform for DarwinPlatform {
         Ok(())
     }
 
+    fn linkat(
+        from_handle: Self::Handle,
+        from_filename: Self::Filename,
+        to_handle: Self::Handle,
+        to_filename: Self::Filename,
+    ) -> Result<(), crate::Error> {
+        let from = CString::from(from_filename);
+        let to = CString::from(to_filename);
+
+        let result = unsafe {
+            syscalls::linkat(
+                from_handle.into_raw(),
+                from.as_ptr(),
+                to_handle.into_raw(),
+                to.as_ptr(),
+                0,
+            )
+        };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn tmpfileat(_handle: Self::Handle) -> Result<Self::Handle, crate::Error> {
+        // Darwin has no equivalent of `O_TMPFILE`.
+        Err(crate::Error::Unsupported)
+    }
+
+    fn flinkat(
+        _handle: Self::Handle,
+        _to_handle: Self::Handle,
+        _to_filename: Self::Filename,
+    ) -> Result<(), crate::Error> {
+        // There is no way to link a file from just a file descriptor.
+        Err(crate::Error::Unsupported)
+    }
+
     fn swapat(
         from_handle: Self::Handle,
         from_filename: Self::Filename,
@@ -366,6 +636,96 @@ impl Platform for DarwinPlatform {
         Ok(bytes_read.try_into().expect("known positive"))
     }
 
+    fn flistxattr(handle: Self::Handle) -> Result<Vec<String>, crate::Error> {
+        // TODO: expose these options.
+        let options = 0;
+
+        loop {
+            let result = unsafe {
+                syscalls::flistxattr(handle.into_raw(), std::ptr::null_mut(), 0, options)
+            };
+            let size = check_result(result.try_into().expect("TODO"))?;
+            if size == 0 {
+                return Ok(Vec::new());
+            }
+
+            let mut buffer = vec![0u8; usize::try_from(size).expect("known positive")];
+            let result = unsafe {
+                syscalls::flistxattr(
+                    handle.into_raw(),
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    options,
+                )
+            };
+            if result == -1 {
+                // An xattr was added since we got the size, try again.
+                let err = std::io::Error::last_os_error().raw_os_error().unwrap_or(-1);
+                if err == types::constants::ERANGE {
+                    continue;
+                }
+                return Err(crate::Error::from_darwin_sys(err));
+            }
+            buffer.truncate(usize::try_from(result).expect("known positive"));
+
+            return crate::platform::parse_xattr_names(&buffer);
+        }
+    }
+
+    fn fremovexattr(handle: Self::Handle, name: Self::Filename) -> Result<(), crate::Error> {
+        let name = CString::from(name);
+
+        // TODO: expose these options.
+        let options = 0;
+
+        let result = unsafe { syscalls::fremovexattr(handle.into_raw(), name.as_ptr(), options) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn readlink(path: Self::Path) -> Result<Self::Path, crate::Error> {
+        let path = CString::from(path);
+        DarwinPlatform::readlinkat_raw(types::flags::AT_FDCWD, &path)
+    }
+
+    fn realpath(path: Self::Path) -> Result<Self::Path, crate::Error> {
+        let path = CString::from(path);
+        let mut buffer = vec![0u8; types::constants::MAXPATHLEN];
+        let result = unsafe { syscalls::realpath(path.as_ptr(), buffer.as_mut_ptr()) };
+        if result.is_null() {
+            let err = std::io::Error::last_os_error().raw_os_error();
+            return Err(crate::Error::from_darwin_sys(err.unwrap_or(-1)));
+        }
+
+        let resolved = CStr::from_bytes_until_nul(&buffer[..])
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?
+            .to_str()
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+        DarwinPath::try_new(resolved.into())
+    }
+
+    fn readlinkat(
+        handle: Self::Handle,
+        filename: Self::Filename,
+    ) -> Result<Self::Path, crate::Error> {
+        let filename = CString::from(filename);
+        DarwinPlatform::readlinkat_raw(handle.into_raw(), &filename)
+    }
+
+    fn symlinkat(
+        target: Self::Path,
+        handle: Self::Handle,
+        filename: Self::Filename,
+    ) -> Result<(), crate::Error> {
+        let target = CString::from(target);
+        let filename = CString::from(filename);
+
+        let result =
+            unsafe { syscalls::symlinkat(target.as_ptr(), handle.into_raw(), filename.as_ptr()) };
+        check_result(result)?;
+        Ok(())
+    }
+
     fn fgetpath(handle: Self::Handle) -> Result<Self::Path, crate::Error> {
         let buffer = vec![0u8; types::constants::MAXPATHLEN * 4];
         let result =
@@ -391,6 +751,33 @@ impl Platform for DarwinPlatform {
     }
 }
 
+impl DarwinPlatform {
+    /// Read the target of the symlink at `path`, relative to `handle`.
+    fn readlinkat_raw(
+        handle: types::file_descriptor,
+        path: &CString,
+    ) -> Result<DarwinPath, crate::Error> {
+        let mut buffer = vec![0u8; types::constants::MAXPATHLEN];
+
+        let result = unsafe {
+            syscalls::readlinkat(handle, path.as_ptr(), buffer.as_mut_ptr(), buffer.len())
+        };
+        let len = usize::try_from(result).map_err(|_| {
+            let err = std::io::Error::last_os_error().raw_os_error();
+            crate::Error::from_darwin_sys(err.unwrap_or(-1))
+        })?;
+        if len == buffer.len() {
+            let msg = format!("target of {} was truncated", path.to_string_lossy()).into();
+            return Err(crate::Error::InvalidData(msg));
+        }
+        buffer.truncate(len);
+
+        let target = String::from_utf8(buffer)
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+        DarwinPath::try_new(target.into())
+    }
+}
+
 impl TryFrom<types::stat> for FileStat {
     type Error = crate::Error;
 
@@ -433,6 +820,8 @@ impl TryFrom<types::stat> for FileStat {
             size,
             kind,
             inode: stat.st_ino,
+            // `dev_t` is signed, but it's an opaque ID so we only care about the bits.
+            device: u64::from(u32::from_ne_bytes(stat.st_dev.to_ne_bytes())),
             mode: u32::cast_from(stat.st_mode),
             user: stat.st_uid,
             group: stat.st_gid,
@@ -484,6 +873,25 @@ impl crate::Error {
             1 => crate::Error::PermissionDenied,
             2 => crate::Error::NotFound,
             3 => crate::Error::NoProcess,
+            4 => crate::Error::Interrupted,
+            9 => crate::Error::BadHandle,
+            13 => crate::Error::AccessDenied,
+            16 => crate::Error::Busy,
+            17 => crate::Error::AlreadyExists,
+            18 => crate::Error::CrossesDevices,
+            20 => crate::Error::NotADirectory,
+            21 => crate::Error::IsADirectory,
+            22 => crate::Error::InvalidInput,
+            23 | 24 => crate::Error::TooManyOpenFiles,
+            // Running out of quota is the same as running out of space for our purposes.
+            28 | 69 => crate::Error::NoSpace,
+            30 => crate::Error::ReadOnly,
+            35 => crate::Error::WouldBlock,
+            45 | 102 => crate::Error::Unsupported,
+            62 => crate::Error::TooManySymlinks,
+            63 => crate::Error::NameTooLong,
+            66 => crate::Error::NotEmpty,
+            70 => crate::Error::StaleHandle,
             x => crate::Error::Unknown(x.to_string()),
         }
     }
@@ -26,9 +26,32 @@ unsafe extern "C" {
     /// Make a directory at the specified path relative to the provided file descriptor.
     pub unsafe fn mkdirat(fildes: file_descriptor, path: *const c_char, mode: u16) -> c_int;
 
+    /// Remove the link at the specified path.
+    pub unsafe fn unlink(path: *const c_char) -> c_int;
+    /// Remove the link at the specified path relative to the provided file descriptor.
+    ///
+    /// Directories are only removed if `flags` contains [`AT_REMOVEDIR`].
+    ///
+    /// [`AT_REMOVEDIR`]: super::types::flags::AT_REMOVEDIR
+    pub unsafe fn unlinkat(fildes: file_descriptor, path: *const c_char, flags: c_int) -> c_int;
+
     /// Read `nbytes` from the provided file descriptor into `buf`.
     pub unsafe fn pread(fildes: file_descriptor, buf: *mut u8, nbytes: usize, offset: i64)
         -> isize;
+    /// Read into `iovcnt` buffers described by `iov`, from the provided file descriptor.
+    pub unsafe fn preadv(
+        fildes: file_descriptor,
+        iov: *const types::iovec,
+        iovcnt: c_int,
+        offset: i64,
+    ) -> isize;
+    /// Write the `iovcnt` buffers described by `iov` to the provided file descriptor.
+    pub unsafe fn pwritev(
+        fildes: file_descriptor,
+        iov: *const types::iovec,
+        iovcnt: c_int,
+        offset: i64,
+    ) -> isize;
     /// Write `nbytes` to the provided file descriptor.
     pub unsafe fn pwrite(
         fildes: file_descriptor,
@@ -37,6 +60,53 @@ unsafe extern "C" {
         offset: i64,
     ) -> isize;
 
+    /// Create a copy-on-write clone of `src` at `dst`.
+    pub unsafe fn clonefile(src: *const c_char, dst: *const c_char, flags: u32) -> c_int;
+    /// Copy the contents of `from` to `to`, `flags` determines what gets copied, e.g.
+    /// [`COPYFILE_DATA`].
+    ///
+    /// [`COPYFILE_DATA`]: super::types::flags::COPYFILE_DATA
+    pub unsafe fn fcopyfile(
+        from: file_descriptor,
+        to: file_descriptor,
+        state: *mut (),
+        flags: u32,
+    ) -> c_int;
+
+    /// Create a new link at `new` relative to `newfd`, for the file at `old` relative to
+    /// `oldfd`.
+    ///
+    /// If `old` is a symlink the link is created for the symlink itself, unless `flags`
+    /// contains [`AT_SYMLINK_FOLLOW`].
+    ///
+    /// [`AT_SYMLINK_FOLLOW`]: super::types::flags::AT_SYMLINK_FOLLOW
+    pub unsafe fn linkat(
+        oldfd: file_descriptor,
+        old: *const c_char,
+        newfd: file_descriptor,
+        new: *const c_char,
+        flags: c_int,
+    ) -> c_int;
+
+    /// Truncate, or extend, the provided file descriptor to `length` bytes.
+    pub unsafe fn ftruncate(fildes: file_descriptor, length: i64) -> c_int;
+
+    /// Map `len` bytes of the provided file descriptor, starting at `offset`, into memory.
+    ///
+    /// Returns [`MAP_FAILED`] on error.
+    ///
+    /// [`MAP_FAILED`]: super::types::flags::MAP_FAILED
+    pub unsafe fn mmap(
+        addr: *mut u8,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fildes: file_descriptor,
+        offset: i64,
+    ) -> *mut u8;
+    /// Remove a mapping previously created with [`mmap`].
+    pub unsafe fn munmap(addr: *mut u8, len: usize) -> c_int;
+
     /// Rename the link at `old` to `new`.
     pub unsafe fn rename(old: *const c_char, new: *const c_char) -> c_int;
     /// Rename the link at `old` relative to `oldfd`, to `new` relative to `newfd`.
@@ -73,6 +143,22 @@ unsafe extern "C" {
         position: u32,
         options: types::c_int,
     ) -> i32;
+    /// List the names of the extended attributes for the provided file descriptor into
+    /// `namebuf`, each name is nul terminated.
+    ///
+    /// If `namebuf` is null the size of the buffer required is returned.
+    pub unsafe fn flistxattr(
+        fildes: file_descriptor,
+        namebuf: *mut u8,
+        size: usize,
+        options: types::c_int,
+    ) -> isize;
+    /// Remove an extended attribute from the provided file descriptor.
+    pub unsafe fn fremovexattr(
+        fildes: file_descriptor,
+        name: *const c_char,
+        options: types::c_int,
+    ) -> c_int;
 
     /// Returns statistics about the file at `path`.
     pub unsafe fn stat(path: *const c_char, buf: *mut types::stat) -> c_int;
@@ -101,7 +187,15 @@ unsafe extern "C" {
     /// Internally the disk may have it's own in-memory buffers. To guarantee a file is
     /// made durable see [`fcntl`].
     pub unsafe fn fsync(fildes: file_descriptor) -> c_int;
+    /// Change the mode of the provided file descriptor.
+    pub unsafe fn fchmod(fildes: file_descriptor, mode: u16) -> c_int;
+    /// Change the owner and group of the provided file descriptor.
+    pub unsafe fn fchown(fildes: file_descriptor, owner: u32, group: u32) -> c_int;
     /// File control.
+    ///
+    /// Note: Also used to preallocate space for a file, see [`fstore`].
+    ///
+    /// [`fstore`]: super::types::fstore
     pub unsafe fn fcntl(fildes: file_descriptor, cmd: c_int, ...) -> c_int;
     /// Duplicate a file descriptor.
     pub unsafe fn dup(fildes: file_descriptor) -> file_descriptor;
@@ -113,6 +207,27 @@ unsafe extern "C" {
     /// Close the directory stream and the associated file descriptor.
     pub unsafe fn closedir(dirp: dir_stream) -> c_int;
 
+    /// Resolve `path` to an absolute path without symlinks into `resolved_path`, which must be
+    /// at least `MAXPATHLEN` bytes.
+    ///
+    /// Returns null on failure.
+    pub unsafe fn realpath(path: *const c_char, resolved_path: *mut u8) -> *mut u8;
+    /// Read the target of the symbolic link at `path`, relative to the provided file
+    /// descriptor, into `buf`, without a nul terminator.
+    pub unsafe fn readlinkat(
+        fildes: file_descriptor,
+        path: *const c_char,
+        buf: *mut u8,
+        bufsize: usize,
+    ) -> isize;
+    /// Create a symbolic link at `path`, relative to the provided file descriptor, that points
+    /// to `target`.
+    pub unsafe fn symlinkat(
+        target: *const c_char,
+        fildes: file_descriptor,
+        path: *const c_char,
+    ) -> c_int;
+
     /// Get resource limits for the current process.
     pub unsafe fn getrlimit(resource: c_int, limits: *mut rlimit) -> c_int;
 }
@@ -43,6 +43,9 @@ pub struct DarwinDirStream {
 }
 pub(crate) type dir_stream = *const ();
 
+// SAFETY: A `DIR` can be used from any thread, we never use one from multiple threads at once.
+unsafe impl Send for DarwinDirStream {}
+
 pub(crate) mod flags {
     use super::*;
 
@@ -68,11 +71,28 @@ pub(crate) mod flags {
     pub const O_DIRECTORY: c_int = 0x00100000;
     /// Open the directory for searching only.
     pub const O_SEARCH: c_int = O_EXEC | O_DIRECTORY;
+    /// Fail if the last component of the path is a symlink.
+    pub const O_NOFOLLOW: c_int = 0x00000100;
+
+    /// Use the current working directory for the `*at` family of functions.
+    pub const AT_FDCWD: c_int = -2;
 
     /// Act on the symlink itself, do not follow it.
     pub const AT_SYMLINK_NOFOLLOW: c_int = 0x0020;
     /// Act on the target of the symlink.
     pub const AT_SYMLINK_FOLLOW: c_int = 0x0040;
+    /// Remove a directory instead of a file, for `unlinkat`.
+    pub const AT_REMOVEDIR: c_int = 0x0080;
+
+    /// Pages may be read, for `mmap`.
+    pub const PROT_READ: c_int = 0x1;
+    /// Changes to the mapping are private to the process, for `mmap`.
+    pub const MAP_PRIVATE: c_int = 0x2;
+    /// Returned from `mmap` on failure.
+    pub const MAP_FAILED: *mut u8 = !0 as *mut u8;
+
+    /// Copy the data of a file, for `fcopyfile`.
+    pub const COPYFILE_DATA: u32 = 1 << 3;
     /// Path should not contain any symlinks.
     pub const AT_SYMLINK_NOFOLLOW_ANY: c_int = 0x0800;
 
@@ -146,6 +166,14 @@ pub(crate) mod flags {
 
     /// Return the full path of the file descriptor.
     pub const F_GETPATH: c_int = 50;
+    /// Preallocate disk space for a file.
+    pub const F_PREALLOCATE: c_int = 42;
+    /// Allocate contiguous space, for `F_PREALLOCATE`.
+    pub const F_ALLOCATECONTIG: c_uint = 0x00000002;
+    /// Allocate all of the requested space or none of it, for `F_PREALLOCATE`.
+    pub const F_ALLOCATEALL: c_uint = 0x00000004;
+    /// Allocate from the physical end of the file, for `F_PREALLOCATE`.
+    pub const F_PEOFPOSMODE: c_int = 3;
 
     /// Cause the source and target to be atomically swapped, on supported filesystems.
     pub const RENAME_SWAP: c_uint = 0x00000002;
@@ -190,6 +218,19 @@ pub(crate) mod mode {
 }
 
 pub(crate) mod constants {
+    /// Error returned when a syscall was interrupted by a signal, `EINTR`.
+    pub const EINTR: super::c_int = 4;
+    /// Maximum number of buffers for a single call to `preadv` or `pwritev`.
+    pub const IOV_MAX: usize = 1024;
+    /// Maximum number of entries we return from a single call to `readdir`.
+    pub const READDIR_BATCH_SIZE: usize = 1024;
+    /// Error returned when a file can't be mapped into memory, `ENODEV`.
+    pub const ENODEV: super::c_int = 19;
+    /// Error returned when a buffer is too small for the result, `ERANGE`.
+    pub const ERANGE: super::c_int = 34;
+    /// Errors from `clonefile` that mean the file can't be cloned, `EXDEV` and `ENOTSUP`.
+    pub const CLONE_UNSUPPORTED_ERRNOS: &[super::c_int] = &[18, 45];
+
     /// Maximum length of a path, in characters(?);
     pub const MAXPATHLEN: usize = 1024;
 
@@ -259,8 +300,30 @@ impl Default for dirent {
     }
 }
 
+/// Request passed to `fcntl` with `F_PREALLOCATE`.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone)]
+pub struct fstore {
+    pub fst_flags: c_uint,
+    pub fst_posmode: c_int,
+    pub fst_offset: i64,
+    pub fst_length: i64,
+    /// Number of bytes that were actually allocated.
+    pub fst_bytesalloc: i64,
+}
+
 pub type rlim_t = u64;
 
+/// Buffer passed to `preadv` and `pwritev`.
+///
+/// Has the same layout as [`std::io::IoSlice`] and [`std::io::IoSliceMut`].
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct iovec {
+    pub iov_base: *mut u8,
+    pub iov_len: usize,
+}
+
 /// Limits returned from `getrlimit`.
 #[repr(C)]
 #[derive(Default, Debug, Copy, Clone)]
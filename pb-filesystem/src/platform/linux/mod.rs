@@ -0,0 +1,925 @@
+use pb_ore::cast::CastFrom;
+use pb_types::Timespec;
+use std::ffi::{CStr, CString};
+use std::io::{IoSlice, IoSliceMut};
+use std::ptr::NonNull;
+
+use crate::platform::linux::path::LinuxFilename;
+use crate::platform::linux::types::{rlimit, LinuxDirStream, LinuxHandle};
+use crate::platform::{OpenOptions, Platform, PlatformPath};
+use crate::{DirectoryEntry, FileStat, FileType};
+
+mod path;
+mod syscalls;
+mod types;
+#[cfg(feature = "io-uring")]
+mod uring;
+
+#[cfg(test)]
+mod tests;
+
+pub use path::LinuxPath;
+#[cfg(feature = "io-uring")]
+pub use uring::UringDriver;
+
+/// Filenames that we ignore when listing a directory.
+static LISTDIR_IGNORED_NAMES: &[&str] = &[".", ".."];
+
+pub struct LinuxPlatform;
+
+fn check_result(val: types::c_int) -> Result<types::c_int, crate::Error> {
+    if val == -1 {
+        Err(last_error())
+    } else {
+        Ok(val)
+    }
+}
+
+/// Like [`check_result`], but for syscalls that return a size.
+fn check_size(val: isize) -> Result<usize, crate::Error> {
+    usize::try_from(val).map_err(|_| last_error())
+}
+
+/// Calls `f` until it doesn't fail with `EINTR`.
+fn retry_interrupted<T: Copy + PartialEq + From<i8>>(mut f: impl FnMut() -> T) -> T {
+    loop {
+        let result = f();
+        let errno = std::io::Error::last_os_error().raw_os_error();
+        if result != T::from(-1) || errno != Some(types::constants::EINTR) {
+            return result;
+        }
+    }
+}
+
+fn last_error() -> crate::Error {
+    let err = std::io::Error::last_os_error().raw_os_error();
+    crate::Error::from_linux_sys(err.unwrap_or(-1))
+}
+
+/// Like [`last_error`], but returns [`crate::Error::Unsupported`] for errors that mean an
+/// accelerated copy isn't possible.
+fn last_copy_error() -> crate::Error {
+    let err = std::io::Error::last_os_error().raw_os_error().unwrap_or(-1);
+    if types::constants::COPY_UNSUPPORTED_ERRNOS.contains(&err) {
+        crate::Error::Unsupported
+    } else {
+        crate::Error::from_linux_sys(err)
+    }
+}
+
+/// Returns the flags to pass to `open` for the provided [`OpenOptions`].
+fn open_flags(options: &OpenOptions) -> types::c_int {
+    let mut flags = types::flags::O_RDONLY | types::flags::O_CLOEXEC;
+
+    let writes =
+        OpenOptions::READ_WRITE | OpenOptions::APPEND | OpenOptions::CREATE | OpenOptions::TRUNCATE;
+    if options.intersects(writes) {
+        flags |= types::flags::O_RDWR;
+    }
+    if options.contains(OpenOptions::APPEND) {
+        flags |= types::flags::O_APPEND;
+    }
+    if options.contains(OpenOptions::CREATE) {
+        flags |= types::flags::O_CREAT;
+    }
+    if options.contains(OpenOptions::EXCLUSIVE) {
+        flags |= types::flags::O_EXCL;
+    }
+    if options.contains(OpenOptions::TRUNCATE) {
+        flags |= types::flags::O_TRUNC;
+    }
+    if options.contains(OpenOptions::DIRECTORY) {
+        flags |= types::flags::O_DIRECTORY;
+    }
+    if options.contains(OpenOptions::NO_FOLLOW) {
+        flags |= types::flags::O_NOFOLLOW;
+    }
+
+    flags
+}
+
+/// Returns the full name of an xattr, Linux requires a namespace so we default to `user.`.
+fn xattr_name(name: LinuxFilename) -> CString {
+    let has_namespace = types::constants::XATTR_NAMESPACES
+        .iter()
+        .any(|namespace| name.as_str().starts_with(namespace));
+    if has_namespace {
+        CString::from(name)
+    } else {
+        let name = format!(
+            "{}{}",
+            types::constants::XATTR_DEFAULT_NAMESPACE,
+            name.as_str()
+        );
+        CString::new(name).expect("checked for nul bytes")
+    }
+}
+
+impl LinuxPlatform {
+    fn statx(
+        handle: types::file_descriptor,
+        path: &CString,
+        flags: types::c_int,
+    ) -> Result<FileStat, crate::Error> {
+        let mut raw_stat = types::statx::default();
+
+        let result = unsafe {
+            syscalls::statx(
+                handle,
+                path.as_ptr(),
+                flags,
+                types::flags::STATX_BASIC_STATS,
+                &mut raw_stat as *mut _,
+            )
+        };
+        check_result(result)?;
+
+        let metadata = FileStat::try_from(raw_stat)?;
+        Ok(metadata)
+    }
+
+    /// Read the target of the symlink at `path`, relative to `handle`.
+    fn readlinkat_raw(
+        handle: types::file_descriptor,
+        path: &CString,
+    ) -> Result<LinuxPath, crate::Error> {
+        let mut buffer = vec![0u8; types::constants::PATH_MAX];
+
+        let result = unsafe {
+            syscalls::readlinkat(handle, path.as_ptr(), buffer.as_mut_ptr(), buffer.len())
+        };
+        let len = check_size(result)?;
+        if len == buffer.len() {
+            let msg = format!("target of {} was truncated", path.to_string_lossy()).into();
+            return Err(crate::Error::InvalidData(msg));
+        }
+        buffer.truncate(len);
+
+        let target = String::from_utf8(buffer)
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+        LinuxPath::try_new(target.into())
+    }
+}
+
+impl Platform for LinuxPlatform {
+    type Path = LinuxPath;
+    type Filename = LinuxFilename;
+
+    type Handle = LinuxHandle;
+    type DirStream = LinuxDirStream;
+
+    fn open(path: Self::Path, options: OpenOptions) -> Result<Self::Handle, crate::Error> {
+        let path = CString::from(path);
+        let flags = open_flags(&options);
+
+        let result = if (flags & types::flags::O_CREAT) > 0 {
+            let mode = types::mode::DEFAULT_FILE_MODE as types::c_uint;
+            retry_interrupted(|| unsafe { syscalls::open(path.as_ptr(), flags, mode) })
+        } else {
+            retry_interrupted(|| unsafe { syscalls::open(path.as_ptr(), flags) })
+        };
+        let fd = check_result(result)?;
+
+        Ok(LinuxHandle::from_raw(fd))
+    }
+
+    fn openat(
+        handle: Self::Handle,
+        filename: Self::Filename,
+        options: OpenOptions,
+    ) -> Result<Self::Handle, crate::Error> {
+        let filename = CString::from(filename);
+        let flags = open_flags(&options);
+
+        let result = if (flags & types::flags::O_CREAT) > 0 {
+            let mode = types::mode::DEFAULT_FILE_MODE as types::c_uint;
+            retry_interrupted(|| unsafe {
+                syscalls::openat(handle.into_raw(), filename.as_ptr(), flags, mode)
+            })
+        } else {
+            retry_interrupted(|| unsafe {
+                syscalls::openat(handle.into_raw(), filename.as_ptr(), flags)
+            })
+        };
+        let fd = check_result(result)?;
+
+        Ok(LinuxHandle::from_raw(fd))
+    }
+
+    fn close(handle: Self::Handle) -> Result<(), crate::Error> {
+        let result = unsafe { syscalls::close(handle.into_raw()) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn mkdir(path: Self::Path) -> Result<(), crate::Error> {
+        let path = CString::from(path);
+        let result = unsafe { syscalls::mkdir(path.as_ptr(), types::mode::DEFAULT_DIR_MODE) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn mkdirat(handle: Self::Handle, filename: Self::Filename) -> Result<(), crate::Error> {
+        let filename = CString::from(filename);
+        let result = unsafe {
+            syscalls::mkdirat(
+                handle.into_raw(),
+                filename.as_ptr(),
+                types::mode::DEFAULT_DIR_MODE,
+            )
+        };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn unlink(path: Self::Path) -> Result<(), crate::Error> {
+        let path = CString::from(path);
+        let result = unsafe { syscalls::unlink(path.as_ptr()) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn unlinkat(handle: Self::Handle, filename: Self::Filename) -> Result<(), crate::Error> {
+        let filename = CString::from(filename);
+        let result = unsafe { syscalls::unlinkat(handle.into_raw(), filename.as_ptr(), 0) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn rmdirat(handle: Self::Handle, filename: Self::Filename) -> Result<(), crate::Error> {
+        let filename = CString::from(filename);
+        let result = unsafe {
+            syscalls::unlinkat(
+                handle.into_raw(),
+                filename.as_ptr(),
+                types::flags::AT_REMOVEDIR,
+            )
+        };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn stat(path: Self::Path) -> Result<FileStat, crate::Error> {
+        let path = CString::from(path);
+        LinuxPlatform::statx(types::flags::AT_FDCWD, &path, 0)
+    }
+
+    fn fstat(handle: Self::Handle) -> Result<FileStat, crate::Error> {
+        let empty = CString::default();
+        LinuxPlatform::statx(handle.into_raw(), &empty, types::flags::AT_EMPTY_PATH)
+    }
+
+    fn fstatat(handle: Self::Handle, filename: Self::Filename) -> Result<FileStat, crate::Error> {
+        let filename = CString::from(filename);
+        LinuxPlatform::statx(
+            handle.into_raw(),
+            &filename,
+            types::flags::AT_SYMLINK_NOFOLLOW,
+        )
+    }
+
+    fn fsync(handle: Self::Handle) -> Result<(), crate::Error> {
+        let result = retry_interrupted(|| unsafe { syscalls::fsync(handle.into_raw()) });
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn fchmod(handle: Self::Handle, mode: u32) -> Result<(), crate::Error> {
+        let result = unsafe { syscalls::fchmod(handle.into_raw(), mode) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn fchown(handle: Self::Handle, user: u32, group: u32) -> Result<(), crate::Error> {
+        let result = unsafe { syscalls::fchown(handle.into_raw(), user, group) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn listdir(handle: Self::Handle) -> Result<Vec<DirectoryEntry>, crate::Error> {
+        let dir_stream = LinuxPlatform::opendir(handle)?;
+        let entries = crate::platform::readdir_all::<LinuxPlatform>(dir_stream);
+
+        // Done listing! Close our file descriptor.
+        LinuxPlatform::closedir(dir_stream)?;
+
+        entries
+    }
+
+    fn opendir(handle: Self::Handle) -> Result<Self::DirStream, crate::Error> {
+        // Re-open the directory so we get our own offset, `getdents64` reads from the current
+        // offset of the file descriptor which is shared with any duplicates.
+        let current = CString::new(".").expect("known valid");
+        let flags = types::flags::O_RDONLY | types::flags::O_DIRECTORY | types::flags::O_CLOEXEC;
+        let result = retry_interrupted(|| unsafe {
+            syscalls::openat(handle.into_raw(), current.as_ptr(), flags)
+        });
+        Ok(LinuxDirStream {
+            inner: check_result(result)?,
+        })
+    }
+
+    fn readdir(stream: Self::DirStream) -> Result<Vec<DirectoryEntry>, crate::Error> {
+        let mut buffer = vec![0u8; types::constants::GETDENTS_BUFFER_SIZE];
+
+        loop {
+            let result =
+                unsafe { syscalls::getdents64(stream.inner, buffer.as_mut_ptr(), buffer.len()) };
+            let bytes_read = check_size(result)?;
+            if bytes_read == 0 {
+                return Ok(Vec::new());
+            }
+
+            // Skip batches that only contained ignored names, an empty batch means we're done.
+            let entries = parse_dirents(stream.inner, &buffer[..bytes_read])?;
+            if !entries.is_empty() {
+                return Ok(entries);
+            }
+        }
+    }
+
+    fn closedir(stream: Self::DirStream) -> Result<(), crate::Error> {
+        let result = unsafe { syscalls::close(stream.inner) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn read(handle: Self::Handle, buf: &mut [u8], offset: usize) -> Result<usize, crate::Error> {
+        let buf_ptr = buf.as_mut_ptr();
+        let buf_len = buf.len();
+        let offset = i64::try_from(offset)
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+
+        let result = retry_interrupted(|| unsafe {
+            syscalls::pread(handle.into_raw(), buf_ptr, buf_len, offset)
+        });
+        check_size(result)
+    }
+
+    fn write(handle: Self::Handle, data: &[u8], offset: usize) -> Result<usize, crate::Error> {
+        let data_ptr = data.as_ptr();
+        let data_len = data.len();
+        let offset = i64::try_from(offset)
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+
+        let result = retry_interrupted(|| unsafe {
+            syscalls::pwrite(handle.into_raw(), data_ptr, data_len, offset)
+        });
+        check_size(result)
+    }
+
+    fn readv(
+        handle: Self::Handle,
+        bufs: &mut [IoSliceMut<'_>],
+        offset: usize,
+    ) -> Result<usize, crate::Error> {
+        // Any buffers past the limit are left for the caller to retry, like a short read.
+        let count = bufs.len().min(types::constants::IOV_MAX);
+        let offset = i64::try_from(offset)
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+
+        // Note: `IoSliceMut` is guaranteed to be ABI compatible with `iovec`.
+        let iov = bufs.as_ptr().cast::<types::iovec>();
+        let count = types::c_int::try_from(count).expect("known to fit");
+        let result = retry_interrupted(|| unsafe {
+            syscalls::preadv(handle.into_raw(), iov, count, offset)
+        });
+        check_size(result)
+    }
+
+    fn writev(
+        handle: Self::Handle,
+        bufs: &[IoSlice<'_>],
+        offset: usize,
+    ) -> Result<usize, crate::Error> {
+        // Any buffers past the limit are left for the caller to retry, like a short write.
+        let count = bufs.len().min(types::constants::IOV_MAX);
+        let offset = i64::try_from(offset)
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+
+        // Note: `IoSlice` is guaranteed to be ABI compatible with `iovec`.
+        let iov = bufs.as_ptr().cast::<types::iovec>();
+        let count = types::c_int::try_from(count).expect("known to fit");
+        let result = retry_interrupted(|| unsafe {
+            syscalls::pwritev(handle.into_raw(), iov, count, offset)
+        });
+        check_size(result)
+    }
+
+    fn ftruncate(handle: Self::Handle, len: usize) -> Result<(), crate::Error> {
+        let len =
+            i64::try_from(len).map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+        let result = retry_interrupted(|| unsafe { syscalls::ftruncate(handle.into_raw(), len) });
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn fallocate(handle: Self::Handle, len: usize) -> Result<(), crate::Error> {
+        let len =
+            i64::try_from(len).map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+        let result =
+            retry_interrupted(|| unsafe { syscalls::fallocate(handle.into_raw(), 0, 0, len) });
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn mmap(handle: Self::Handle, len: usize) -> Result<NonNull<u8>, crate::Error> {
+        let result = unsafe {
+            syscalls::mmap(
+                std::ptr::null_mut(),
+                len,
+                types::flags::PROT_READ,
+                types::flags::MAP_PRIVATE,
+                handle.into_raw(),
+                0,
+            )
+        };
+        if result == types::flags::MAP_FAILED {
+            // Filesystems that don't support mapping return `ENODEV`.
+            let err = std::io::Error::last_os_error().raw_os_error().unwrap_or(-1);
+            if err == types::constants::ENODEV {
+                return Err(crate::Error::Unsupported);
+            }
+            return Err(crate::Error::from_linux_sys(err));
+        }
+        Ok(NonNull::new(result).expect("mmap returned null"))
+    }
+
+    unsafe fn munmap(ptr: NonNull<u8>, len: usize) -> Result<(), crate::Error> {
+        let result = unsafe { syscalls::munmap(ptr.as_ptr(), len) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn clonefile(from: Self::Path, to: Self::Path) -> Result<(), crate::Error> {
+        let from = LinuxPlatform::open(from, OpenOptions::READ_ONLY)?;
+        let result = LinuxPlatform::open(to.clone(), OpenOptions::CREATE | OpenOptions::EXCLUSIVE)
+            .and_then(|to_handle| {
+                let result = unsafe {
+                    syscalls::ioctl(to_handle.into_raw(), types::flags::FICLONE, from.into_raw())
+                };
+                let result = if result == -1 {
+                    Err(last_copy_error())
+                } else {
+                    Ok(())
+                };
+                LinuxPlatform::close(to_handle)?;
+
+                // Don't leave behind an empty file if we failed to clone.
+                if result.is_err() {
+                    LinuxPlatform::unlink(to)?;
+                }
+                result
+            });
+        LinuxPlatform::close(from)?;
+
+        result
+    }
+
+    fn fcopy(from: Self::Handle, to: Self::Handle) -> Result<(), crate::Error> {
+        // Try to share the data first, otherwise copy it in the kernel.
+        let result =
+            unsafe { syscalls::ioctl(to.into_raw(), types::flags::FICLONE, from.into_raw()) };
+        if result == 0 {
+            return Ok(());
+        }
+        match last_copy_error() {
+            crate::Error::Unsupported => (),
+            err => return Err(err),
+        }
+
+        let mut off_in = 0i64;
+        let mut off_out = 0i64;
+        loop {
+            let result = unsafe {
+                syscalls::copy_file_range(
+                    from.into_raw(),
+                    &mut off_in as *mut _,
+                    to.into_raw(),
+                    &mut off_out as *mut _,
+                    types::constants::COPY_FILE_RANGE_CHUNK_SIZE,
+                    0,
+                )
+            };
+            match result {
+                -1 if off_out == 0 => return Err(last_copy_error()),
+                -1 => return Err(last_error()),
+                0 => return Ok(()),
+                _ => (),
+            }
+        }
+    }
+
+    fn rename(from: Self::Path, to: Self::Path) -> Result<(), crate::Error> {
+        let from = CString::from(from);
+        let to = CString::from(to);
+
+        let result = unsafe { syscalls::rename(from.as_ptr(), to.as_ptr()) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn renameat(
+        from_handle: Self::Handle,
+        from_filename: Self::Filename,
+        to_handle: Self::Handle,
+        to_filename: Self::Filename,
+    ) -> Result<(), crate::Error> {
+        let from = CString::from(from_filename);
+        let to = CString::from(to_filename);
+
+        let result = unsafe {
+            syscalls::renameat(
+                from_handle.into_raw(),
+                from.as_ptr(),
+                to_handle.into_raw(),
+                to.as_ptr(),
+            )
+        };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn linkat(
+        from_handle: Self::Handle,
+        from_filename: Self::Filename,
+        to_handle: Self::Handle,
+        to_filename: Self::Filename,
+    ) -> Result<(), crate::Error> {
+        let from = CString::from(from_filename);
+        let to = CString::from(to_filename);
+
+        let result = unsafe {
+            syscalls::linkat(
+                from_handle.into_raw(),
+                from.as_ptr(),
+                to_handle.into_raw(),
+                to.as_ptr(),
+                0,
+            )
+        };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn tmpfileat(handle: Self::Handle) -> Result<Self::Handle, crate::Error> {
+        let flags = types::flags::O_TMPFILE | types::flags::O_RDWR | types::flags::O_CLOEXEC;
+        let mode = types::mode::DEFAULT_FILE_MODE as types::c_uint;
+        let result = retry_interrupted(|| unsafe {
+            syscalls::openat(handle.into_raw(), c".".as_ptr(), flags, mode)
+        });
+        if result == -1 {
+            let err = std::io::Error::last_os_error().raw_os_error().unwrap_or(-1);
+            // Older kernels, and some filesystems, don't support `O_TMPFILE`.
+            if types::constants::TMPFILE_UNSUPPORTED_ERRNOS.contains(&err) {
+                return Err(crate::Error::Unsupported);
+            }
+            return Err(crate::Error::from_linux_sys(err));
+        }
+        Ok(LinuxHandle::from_raw(result))
+    }
+
+    fn flinkat(
+        handle: Self::Handle,
+        to_handle: Self::Handle,
+        to_filename: Self::Filename,
+    ) -> Result<(), crate::Error> {
+        // Linking with `AT_EMPTY_PATH` requires `CAP_DAC_READ_SEARCH`, linking the path in
+        // `/proc` does not.
+        let from = format!("/proc/self/fd/{}", handle.into_raw());
+        let from = CString::new(from).expect("no nul bytes");
+        let to = CString::from(to_filename);
+
+        let result = unsafe {
+            syscalls::linkat(
+                types::flags::AT_FDCWD,
+                from.as_ptr(),
+                to_handle.into_raw(),
+                to.as_ptr(),
+                types::flags::AT_SYMLINK_FOLLOW,
+            )
+        };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn swapat(
+        from_handle: Self::Handle,
+        from_filename: Self::Filename,
+        to_handle: Self::Handle,
+        to_filename: Self::Filename,
+    ) -> Result<(), crate::Error> {
+        let from = CString::from(from_filename);
+        let to = CString::from(to_filename);
+
+        let result = unsafe {
+            syscalls::renameat2(
+                from_handle.into_raw(),
+                from.as_ptr(),
+                to_handle.into_raw(),
+                to.as_ptr(),
+                types::flags::RENAME_EXCHANGE,
+            )
+        };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn fsetxattr(
+        handle: Self::Handle,
+        name: Self::Filename,
+        data: &[u8],
+    ) -> Result<(), crate::Error> {
+        let name = xattr_name(name);
+
+        // TODO: expose these options.
+        let flags = 0;
+
+        let result = unsafe {
+            syscalls::fsetxattr(
+                handle.into_raw(),
+                name.as_ptr(),
+                data.as_ptr(),
+                data.len(),
+                flags,
+            )
+        };
+        check_result(result)?;
+
+        Ok(())
+    }
+
+    fn fgetxattr(
+        handle: Self::Handle,
+        name: Self::Filename,
+        buf: &mut [u8],
+    ) -> Result<usize, crate::Error> {
+        let name = xattr_name(name);
+
+        // Note: If this buffer cannot fit the xattr then we get back error 34 "result too large".
+        let result = unsafe {
+            syscalls::fgetxattr(
+                handle.into_raw(),
+                name.as_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+            )
+        };
+        check_size(result)
+    }
+
+    fn readlink(path: Self::Path) -> Result<Self::Path, crate::Error> {
+        let path = CString::from(path);
+        LinuxPlatform::readlinkat_raw(types::flags::AT_FDCWD, &path)
+    }
+
+    fn realpath(path: Self::Path) -> Result<Self::Path, crate::Error> {
+        let path = CString::from(path);
+        let mut buffer = vec![0u8; types::constants::PATH_MAX];
+        let result = unsafe { syscalls::realpath(path.as_ptr(), buffer.as_mut_ptr()) };
+        if result.is_null() {
+            return Err(last_error());
+        }
+
+        let resolved = CStr::from_bytes_until_nul(&buffer[..])
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?
+            .to_str()
+            .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+        LinuxPath::try_new(resolved.into())
+    }
+
+    fn readlinkat(
+        handle: Self::Handle,
+        filename: Self::Filename,
+    ) -> Result<Self::Path, crate::Error> {
+        let filename = CString::from(filename);
+        LinuxPlatform::readlinkat_raw(handle.into_raw(), &filename)
+    }
+
+    fn symlinkat(
+        target: Self::Path,
+        handle: Self::Handle,
+        filename: Self::Filename,
+    ) -> Result<(), crate::Error> {
+        let target = CString::from(target);
+        let filename = CString::from(filename);
+
+        let result =
+            unsafe { syscalls::symlinkat(target.as_ptr(), handle.into_raw(), filename.as_ptr()) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn flistxattr(handle: Self::Handle) -> Result<Vec<String>, crate::Error> {
+        loop {
+            let result =
+                unsafe { syscalls::flistxattr(handle.into_raw(), std::ptr::null_mut(), 0) };
+            let size = check_size(result)?;
+            if size == 0 {
+                return Ok(Vec::new());
+            }
+
+            let mut buffer = vec![0u8; size];
+            let result =
+                unsafe { syscalls::flistxattr(handle.into_raw(), buffer.as_mut_ptr(), size) };
+            if result == -1 {
+                // An xattr was added since we got the size, try again.
+                let err = std::io::Error::last_os_error().raw_os_error().unwrap_or(-1);
+                if err == types::constants::ERANGE {
+                    continue;
+                }
+                return Err(crate::Error::from_linux_sys(err));
+            }
+            buffer.truncate(check_size(result)?);
+
+            // Strip the namespace we add by default so names round trip.
+            let names = crate::platform::parse_xattr_names(&buffer)?
+                .into_iter()
+                .map(
+                    |name| match name.strip_prefix(types::constants::XATTR_DEFAULT_NAMESPACE) {
+                        Some(name) => name.to_string(),
+                        None => name,
+                    },
+                )
+                .collect();
+            return Ok(names);
+        }
+    }
+
+    fn fremovexattr(handle: Self::Handle, name: Self::Filename) -> Result<(), crate::Error> {
+        let name = xattr_name(name);
+        let result = unsafe { syscalls::fremovexattr(handle.into_raw(), name.as_ptr()) };
+        check_result(result)?;
+        Ok(())
+    }
+
+    fn fgetpath(handle: Self::Handle) -> Result<Self::Path, crate::Error> {
+        let link =
+            CString::new(format!("/proc/self/fd/{}", handle.into_raw())).expect("known valid");
+        LinuxPlatform::readlinkat_raw(types::flags::AT_FDCWD, &link)
+    }
+
+    fn file_handle_max() -> Result<usize, crate::Error> {
+        let mut limits = rlimit::default();
+        let result =
+            unsafe { syscalls::getrlimit(types::flags::RLIMIT_NOFILE, &mut limits as *mut _) };
+        check_result(result)?;
+
+        Ok(usize::cast_from(limits.rlim_cur))
+    }
+}
+
+/// Parse all of the `linux_dirent64` records in `buffer`, which were read from the directory
+/// `dirfd`, skipping any ignored names.
+fn parse_dirents(
+    dirfd: types::file_descriptor,
+    buffer: &[u8],
+) -> Result<Vec<DirectoryEntry>, crate::Error> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let (entry, reclen) = parse_dirent64(dirfd, &buffer[offset..])?;
+        let entry = entry.filter(|entry| !LISTDIR_IGNORED_NAMES.contains(&&*entry.name));
+        entries.extend(entry);
+        offset += reclen;
+    }
+    Ok(entries)
+}
+
+/// Parse a single `linux_dirent64` record from the start of `record`, returning the entry and
+/// the length of the record.
+///
+/// If the filesystem doesn't report the type of the entry it's stat-ed relative to `dirfd`, in
+/// which case the entry is `None` if it was removed since the directory was read.
+fn parse_dirent64(
+    dirfd: types::file_descriptor,
+    record: &[u8],
+) -> Result<(Option<DirectoryEntry>, usize), crate::Error> {
+    if record.len() < types::DIRENT64_NAME_OFFSET {
+        let msg = format!("truncated dirent, {} bytes", record.len()).into();
+        return Err(crate::Error::InvalidData(msg));
+    }
+    // SAFETY: We checked above that the record is large enough for the header, and
+    // `read_unaligned` doesn't require alignment.
+    let dirent =
+        unsafe { std::ptr::read_unaligned(record.as_ptr() as *const types::linux_dirent64) };
+
+    let reclen = usize::from(dirent.d_reclen);
+    if reclen < types::DIRENT64_NAME_OFFSET || reclen > record.len() {
+        let msg = format!("invalid dirent length {reclen}").into();
+        return Err(crate::Error::InvalidData(msg));
+    }
+    let name = &record[types::DIRENT64_NAME_OFFSET..reclen];
+    let name_len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+    let name = std::str::from_utf8(&name[..name_len])
+        .map_err(|err| crate::Error::InvalidData(err.to_string().into()))?;
+
+    let kind = match dirent.d_type {
+        types::flags::DT_DIR => FileType::Directory,
+        types::flags::DT_LNK => FileType::Symlink,
+        types::flags::DT_REG => FileType::File,
+        // Not every filesystem records the type in the directory, e.g. XFS without `ftype` or
+        // some network filesystems, so we have to ask.
+        types::flags::DT_UNKNOWN => {
+            let filename = CString::new(name).expect("name stops at the first nul");
+            let flags = types::flags::AT_SYMLINK_NOFOLLOW;
+            match LinuxPlatform::statx(dirfd, &filename, flags) {
+                Ok(stat) => stat.kind,
+                Err(crate::Error::NotFound) => return Ok((None, reclen)),
+                Err(err) => return Err(err),
+            }
+        }
+        kind => {
+            tracing::warn!(kind, "falling back to file");
+            FileType::File
+        }
+    };
+
+    let entry = DirectoryEntry {
+        inode: dirent.d_ino,
+        name: name.to_string(),
+        kind,
+    };
+    Ok((Some(entry), reclen))
+}
+
+impl TryFrom<types::statx> for FileStat {
+    type Error = crate::Error;
+
+    fn try_from(stat: types::statx) -> Result<Self, Self::Error> {
+        let mtime = Timespec {
+            secs: stat.stx_mtime.tv_sec,
+            nanos: i64::from(stat.stx_mtime.tv_nsec),
+        };
+        let ctime = Timespec {
+            secs: stat.stx_ctime.tv_sec,
+            nanos: i64::from(stat.stx_ctime.tv_nsec),
+        };
+
+        let masked_kind = stat.stx_mode & types::flags::S_IFMT;
+        let kind = if masked_kind == types::flags::S_IFLNK {
+            FileType::Symlink
+        } else if masked_kind == types::flags::S_IFDIR {
+            FileType::Directory
+        } else if masked_kind == types::flags::S_IFREG {
+            FileType::File
+        } else {
+            tracing::warn!(?masked_kind, "falling back to file");
+            FileType::File
+        };
+
+        let optimal_blocksize = match stat.stx_blksize {
+            0 => None,
+            x => Some(usize::cast_from(x)),
+        };
+
+        let metadata = FileStat {
+            size: stat.stx_size,
+            kind,
+            inode: stat.stx_ino,
+            device: (u64::from(stat.stx_dev_major) << 32) | u64::from(stat.stx_dev_minor),
+            mode: u32::from(stat.stx_mode),
+            user: stat.stx_uid,
+            group: stat.stx_gid,
+            mtime,
+            ctime,
+            optimal_blocksize,
+        };
+        Ok(metadata)
+    }
+}
+
+impl crate::Error {
+    /// Create an [`Error`] from the value returned by a system call.
+    ///
+    /// Derived from `asm-generic/errno-base.h` on Linux.
+    ///
+    /// [`Error`]: crate::Error
+    pub fn from_linux_sys(val: types::c_int) -> Self {
+        match val {
+            1 => crate::Error::PermissionDenied,
+            2 => crate::Error::NotFound,
+            3 => crate::Error::NoProcess,
+            4 => crate::Error::Interrupted,
+            9 => crate::Error::BadHandle,
+            11 => crate::Error::WouldBlock,
+            13 => crate::Error::AccessDenied,
+            16 => crate::Error::Busy,
+            17 => crate::Error::AlreadyExists,
+            18 => crate::Error::CrossesDevices,
+            20 => crate::Error::NotADirectory,
+            21 => crate::Error::IsADirectory,
+            22 => crate::Error::InvalidInput,
+            23 | 24 => crate::Error::TooManyOpenFiles,
+            // Running out of quota is the same as running out of space for our purposes.
+            28 | 122 => crate::Error::NoSpace,
+            30 => crate::Error::ReadOnly,
+            36 => crate::Error::NameTooLong,
+            39 => crate::Error::NotEmpty,
+            40 => crate::Error::TooManySymlinks,
+            95 => crate::Error::Unsupported,
+            116 => crate::Error::StaleHandle,
+            x => crate::Error::Unknown(x.to_string()),
+        }
+    }
+}
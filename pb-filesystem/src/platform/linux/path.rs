@@ -0,0 +1,72 @@
+//! Linux specific paths.
+
+use std::{ffi::CString, path::PathBuf};
+
+use crate::platform::{PlatformFilename, PlatformPath};
+
+/// Paths for common Linux filesystems, e.g. ext4, XFS, and Btrfs.
+///
+/// * Case sensitive.
+/// * No normalization, filenames are arbitrary bytes other than `/` and nul.
+///
+/// We only support UTF-8 paths, matching the other platforms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinuxPath {
+    inner: String,
+}
+
+impl LinuxPath {
+    pub(crate) fn into_inner(self) -> String {
+        self.inner
+    }
+}
+
+impl PlatformPath for LinuxPath {
+    fn try_new(val: PathBuf) -> Result<Self, crate::Error> {
+        let inner = val
+            .into_os_string()
+            .into_string()
+            .map_err(|val| crate::Error::InvalidData(format!("non UTF-8 path {val:?}").into()))?;
+        if inner.contains('\0') {
+            let msg = format!("path contains a nul byte {inner:?}").into();
+            return Err(crate::Error::InvalidData(msg));
+        }
+        Ok(LinuxPath { inner })
+    }
+}
+
+impl From<LinuxPath> for CString {
+    fn from(path: LinuxPath) -> Self {
+        CString::new(path.inner).expect("checked for nul bytes")
+    }
+}
+
+/// Individual component of a [`LinuxPath`].
+///
+/// See documentation on [`LinuxPath`] for the specifics.
+#[derive(Debug, Clone)]
+pub struct LinuxFilename {
+    inner: String,
+}
+
+impl LinuxFilename {
+    pub(crate) fn as_str(&self) -> &str {
+        &self.inner
+    }
+}
+
+impl PlatformFilename for LinuxFilename {
+    fn try_new(val: String) -> Result<Self, crate::Error> {
+        if val.contains('\0') {
+            let msg = format!("filename contains a nul byte {val:?}").into();
+            return Err(crate::Error::InvalidData(msg));
+        }
+        Ok(LinuxFilename { inner: val })
+    }
+}
+
+impl From<LinuxFilename> for CString {
+    fn from(filename: LinuxFilename) -> Self {
+        CString::new(filename.inner).expect("checked for nul bytes")
+    }
+}
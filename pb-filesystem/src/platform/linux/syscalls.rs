@@ -0,0 +1,213 @@
+//! Syscalls used for the Linux platform.
+//!
+//! These are the wrappers exported by glibc, `statx` and `renameat2` require glibc 2.28 and
+//! `getdents64` requires glibc 2.30.
+
+use crate::platform::linux::types::rlimit;
+
+use super::types::{self, c_char, c_int, c_uint, c_ulong, file_descriptor};
+
+unsafe extern "C" {
+    /// Open the file at `path` with the provided flags.
+    ///
+    /// When creating a file we require an additional `mode` argument.
+    pub unsafe fn open(path: *const c_char, flags: c_int, ...) -> c_int;
+    /// Open the file at the path relative to the provided file descriptor.
+    ///
+    /// When creating a file we require an additional `mode` argument.
+    pub unsafe fn openat(fildes: file_descriptor, path: *const c_char, flags: c_int, ...) -> c_int;
+    /// Close a file handle.
+    pub unsafe fn close(fildes: file_descriptor) -> c_int;
+
+    /// Make a directory at the specified path.
+    pub unsafe fn mkdir(path: *const c_char, mode: u32) -> c_int;
+    /// Make a directory at the specified path relative to the provided file descriptor.
+    pub unsafe fn mkdirat(fildes: file_descriptor, path: *const c_char, mode: u32) -> c_int;
+
+    /// Remove the link at the specified path.
+    pub unsafe fn unlink(path: *const c_char) -> c_int;
+    /// Remove the link at the specified path relative to the provided file descriptor.
+    ///
+    /// Directories are only removed if `flags` contains [`AT_REMOVEDIR`].
+    ///
+    /// [`AT_REMOVEDIR`]: super::types::flags::AT_REMOVEDIR
+    pub unsafe fn unlinkat(fildes: file_descriptor, path: *const c_char, flags: c_int) -> c_int;
+
+    /// Read `nbytes` from the provided file descriptor into `buf`.
+    pub unsafe fn pread(fildes: file_descriptor, buf: *mut u8, nbytes: usize, offset: i64)
+        -> isize;
+    /// Read into `iovcnt` buffers described by `iov`, from the provided file descriptor.
+    pub unsafe fn preadv(
+        fildes: file_descriptor,
+        iov: *const types::iovec,
+        iovcnt: c_int,
+        offset: i64,
+    ) -> isize;
+    /// Write the `iovcnt` buffers described by `iov` to the provided file descriptor.
+    pub unsafe fn pwritev(
+        fildes: file_descriptor,
+        iov: *const types::iovec,
+        iovcnt: c_int,
+        offset: i64,
+    ) -> isize;
+    /// Write `nbytes` to the provided file descriptor.
+    pub unsafe fn pwrite(
+        fildes: file_descriptor,
+        buf: *const u8,
+        nbytes: usize,
+        offset: i64,
+    ) -> isize;
+    /// Reposition the offset of the provided file descriptor.
+    pub unsafe fn lseek(fildes: file_descriptor, offset: i64, whence: c_int) -> i64;
+
+    /// Copy `len` bytes from `fd_in` to `fd_out` within the kernel.
+    ///
+    /// If `off_in` or `off_out` are not null they're used, and updated, instead of the file
+    /// offset of the respective file descriptor.
+    pub unsafe fn copy_file_range(
+        fd_in: file_descriptor,
+        off_in: *mut i64,
+        fd_out: file_descriptor,
+        off_out: *mut i64,
+        len: usize,
+        flags: c_uint,
+    ) -> isize;
+    /// Device specific control of a file descriptor, e.g. [`FICLONE`].
+    ///
+    /// [`FICLONE`]: super::types::flags::FICLONE
+    pub unsafe fn ioctl(fildes: file_descriptor, request: c_ulong, ...) -> c_int;
+
+    /// Create a new link at `new` relative to `newfd`, for the file at `old` relative to
+    /// `oldfd`.
+    ///
+    /// If `old` is a symlink the link is created for the symlink itself, unless `flags`
+    /// contains [`AT_SYMLINK_FOLLOW`].
+    ///
+    /// [`AT_SYMLINK_FOLLOW`]: super::types::flags::AT_SYMLINK_FOLLOW
+    pub unsafe fn linkat(
+        oldfd: file_descriptor,
+        old: *const c_char,
+        newfd: file_descriptor,
+        new: *const c_char,
+        flags: c_int,
+    ) -> c_int;
+
+    /// Truncate, or extend, the provided file descriptor to `length` bytes.
+    pub unsafe fn ftruncate(fildes: file_descriptor, length: i64) -> c_int;
+    /// Allocate disk space for `len` bytes of the provided file descriptor, starting at `offset`.
+    pub unsafe fn fallocate(fildes: file_descriptor, mode: c_int, offset: i64, len: i64) -> c_int;
+
+    /// Map `len` bytes of the provided file descriptor, starting at `offset`, into memory.
+    ///
+    /// Returns [`MAP_FAILED`] on error.
+    ///
+    /// [`MAP_FAILED`]: super::types::flags::MAP_FAILED
+    pub unsafe fn mmap(
+        addr: *mut u8,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fildes: file_descriptor,
+        offset: i64,
+    ) -> *mut u8;
+    /// Remove a mapping previously created with [`mmap`].
+    pub unsafe fn munmap(addr: *mut u8, len: usize) -> c_int;
+
+    /// Rename the link at `old` to `new`.
+    pub unsafe fn rename(old: *const c_char, new: *const c_char) -> c_int;
+    /// Rename the link at `old` relative to `oldfd`, to `new` relative to `newfd`.
+    pub unsafe fn renameat(
+        oldfd: file_descriptor,
+        old: *const c_char,
+        newfd: file_descriptor,
+        new: *const c_char,
+    ) -> c_int;
+    /// Like [`renameat`] but with additional flags, e.g. [`RENAME_EXCHANGE`].
+    ///
+    /// [`RENAME_EXCHANGE`]: super::types::flags::RENAME_EXCHANGE
+    pub unsafe fn renameat2(
+        oldfd: file_descriptor,
+        old: *const c_char,
+        newfd: file_descriptor,
+        new: *const c_char,
+        flags: c_uint,
+    ) -> c_int;
+
+    /// Get an extended attribute value.
+    pub unsafe fn fgetxattr(
+        fildes: file_descriptor,
+        name: *const c_char,
+        value: *mut u8,
+        size: usize,
+    ) -> isize;
+    /// Set an extended attribute value for the provided file descriptor.
+    pub unsafe fn fsetxattr(
+        fildes: file_descriptor,
+        name: *const c_char,
+        value: *const u8,
+        size: usize,
+        flags: c_int,
+    ) -> c_int;
+
+    /// List the names of the extended attributes for the provided file descriptor into `list`,
+    /// each name is nul terminated.
+    ///
+    /// If `size` is 0 the size of the buffer required is returned.
+    pub unsafe fn flistxattr(fildes: file_descriptor, list: *mut u8, size: usize) -> isize;
+    /// Remove an extended attribute from the provided file descriptor.
+    pub unsafe fn fremovexattr(fildes: file_descriptor, name: *const c_char) -> c_int;
+
+    /// Returns statistics about the file at the path relative to the provided file descriptor.
+    ///
+    /// The value for `flags` can be bitwise OR of the following:
+    /// 1. [`AT_SYMLINK_NOFOLLOW`]
+    /// 2. [`AT_EMPTY_PATH`], if `path` is empty the status of `fildes` will be returned.
+    ///
+    /// [`AT_SYMLINK_NOFOLLOW`]: super::types::flags::AT_SYMLINK_NOFOLLOW
+    /// [`AT_EMPTY_PATH`]: super::types::flags::AT_EMPTY_PATH
+    pub unsafe fn statx(
+        fildes: file_descriptor,
+        path: *const c_char,
+        flags: c_int,
+        mask: c_uint,
+        buf: *mut types::statx,
+    ) -> c_int;
+
+    /// Sync the buffered content of a file to disk.
+    pub unsafe fn fsync(fildes: file_descriptor) -> c_int;
+
+    /// Change the mode of the provided file descriptor.
+    pub unsafe fn fchmod(fildes: file_descriptor, mode: u32) -> c_int;
+    /// Change the owner and group of the provided file descriptor.
+    pub unsafe fn fchown(fildes: file_descriptor, owner: u32, group: u32) -> c_int;
+
+    /// Read directory entries from the provided file descriptor into `buf`.
+    ///
+    /// Returns the number of bytes read, or 0 at the end of the directory.
+    pub unsafe fn getdents64(fildes: file_descriptor, buf: *mut u8, nbytes: usize) -> isize;
+
+    /// Read the target of the symbolic link at `path` into `buf`, without a nul terminator.
+    pub unsafe fn readlink(path: *const c_char, buf: *mut u8, bufsize: usize) -> isize;
+    /// Resolve `path` to an absolute path without symlinks into `resolved_path`, which must be
+    /// at least `PATH_MAX` bytes.
+    ///
+    /// Returns null on failure.
+    pub unsafe fn realpath(path: *const c_char, resolved_path: *mut u8) -> *mut u8;
+    /// Like [`readlink`] but with `path` relative to the provided file descriptor.
+    pub unsafe fn readlinkat(
+        fildes: file_descriptor,
+        path: *const c_char,
+        buf: *mut u8,
+        bufsize: usize,
+    ) -> isize;
+    /// Create a symbolic link at `path`, relative to the provided file descriptor, that points
+    /// to `target`.
+    pub unsafe fn symlinkat(
+        target: *const c_char,
+        fildes: file_descriptor,
+        path: *const c_char,
+    ) -> c_int;
+
+    /// Get resource limits for the current process.
+    pub unsafe fn getrlimit(resource: c_int, limits: *mut rlimit) -> c_int;
+}
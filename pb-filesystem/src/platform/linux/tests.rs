@@ -0,0 +1,267 @@
+use std::io::{IoSlice, IoSliceMut};
+
+use crate::platform::linux::path::LinuxFilename;
+use crate::platform::linux::types::flags::DT_UNKNOWN;
+use crate::platform::linux::types::DIRENT64_NAME_OFFSET;
+use crate::platform::linux::LinuxPath;
+use crate::platform::{OpenOptions, Platform, PlatformFilename, PlatformPath};
+use crate::FileType;
+
+use super::LinuxPlatform;
+
+#[test]
+fn smoketest_xattr() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("test-xattr");
+
+    let path = LinuxPath::try_new(path).unwrap();
+    let file = LinuxPlatform::open(path, OpenOptions::CREATE).unwrap();
+
+    // Names without a namespace get put in the `user.` namespace.
+    let xattr_name = LinuxFilename::try_new("com.pb.test".to_string()).unwrap();
+    let xattr_value = b"123456789";
+
+    // Some filesystems, e.g. tmpfs on older kernels, don't support user xattrs.
+    match LinuxPlatform::fsetxattr(file, xattr_name.clone(), b"123456789") {
+        Ok(()) => (),
+        Err(crate::Error::Unsupported) => return,
+        Err(err) => panic!("{err}"),
+    }
+    LinuxPlatform::fsync(file).unwrap();
+    let mut buf = [0u8; 10];
+    let bytes_read = LinuxPlatform::fgetxattr(file, xattr_name, &mut buf[..]).unwrap();
+
+    assert_eq!(bytes_read, 9);
+    assert_eq!(&buf[..9], &xattr_value[..]);
+    LinuxPlatform::close(file).unwrap();
+}
+
+#[test]
+fn smoketest_getpath() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("test-getpath");
+
+    let path = LinuxPath::try_new(path).unwrap();
+    let file = LinuxPlatform::open(path.clone(), OpenOptions::CREATE).unwrap();
+    let rnd_path = LinuxPlatform::fgetpath(file).unwrap();
+
+    let is_suffix = rnd_path
+        .into_inner()
+        .as_str()
+        .strip_suffix(&path.into_inner())
+        .is_some();
+    assert!(is_suffix);
+    LinuxPlatform::close(file).unwrap();
+}
+
+#[test]
+fn smoketest_listdir_and_stat() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::write(temp.path().join("a.txt"), b"hello").unwrap();
+    std::fs::create_dir(temp.path().join("b")).unwrap();
+
+    let path = LinuxPath::try_new(temp.path().to_path_buf()).unwrap();
+    let dir = LinuxPlatform::open(path, OpenOptions::DIRECTORY).unwrap();
+
+    // Listing twice returns the same entries.
+    for _ in 0..2 {
+        let mut entries = LinuxPlatform::listdir(dir).unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        let entries: Vec<_> = entries.iter().map(|e| (e.name.as_str(), e.kind)).collect();
+        assert_eq!(
+            entries,
+            [("a.txt", FileType::File), ("b", FileType::Directory)]
+        );
+    }
+
+    let filename = LinuxFilename::try_new("a.txt".to_string()).unwrap();
+    let stat = LinuxPlatform::fstatat(dir, filename).unwrap();
+    assert_eq!(stat.size, 5);
+    assert_eq!(stat.kind, FileType::File);
+
+    let stat = LinuxPlatform::fstat(dir).unwrap();
+    assert_eq!(stat.kind, FileType::Directory);
+    LinuxPlatform::close(dir).unwrap();
+}
+
+#[test]
+fn smoketest_dirent_unknown_type() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::write(temp.path().join("a.txt"), b"hello").unwrap();
+    std::fs::create_dir(temp.path().join("b")).unwrap();
+    std::os::unix::fs::symlink("a.txt", temp.path().join("c")).unwrap();
+
+    let path = LinuxPath::try_new(temp.path().to_path_buf()).unwrap();
+    let dir = LinuxPlatform::open(path, OpenOptions::DIRECTORY).unwrap();
+
+    // Records as returned by a filesystem that doesn't report types, including one for an
+    // entry that has since been removed.
+    let mut buffer = Vec::new();
+    for name in ["a.txt", "b", "c", "removed"] {
+        let reclen = (DIRENT64_NAME_OFFSET + name.len() + 1).next_multiple_of(8);
+        buffer.extend(1u64.to_ne_bytes());
+        buffer.extend(0i64.to_ne_bytes());
+        buffer.extend(u16::try_from(reclen).unwrap().to_ne_bytes());
+        buffer.push(DT_UNKNOWN);
+        buffer.extend(name.as_bytes());
+        buffer.resize(buffer.len() + reclen - DIRENT64_NAME_OFFSET - name.len(), 0);
+    }
+
+    let entries = super::parse_dirents(dir.into_raw(), &buffer).unwrap();
+    let entries: Vec<_> = entries.iter().map(|e| (e.name.as_str(), e.kind)).collect();
+    assert_eq!(
+        entries,
+        [
+            ("a.txt", FileType::File),
+            ("b", FileType::Directory),
+            ("c", FileType::Symlink)
+        ]
+    );
+    LinuxPlatform::close(dir).unwrap();
+}
+
+#[test]
+fn smoketest_swapat() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::write(temp.path().join("a"), b"a").unwrap();
+    std::fs::write(temp.path().join("b"), b"b").unwrap();
+
+    let path = LinuxPath::try_new(temp.path().to_path_buf()).unwrap();
+    let dir = LinuxPlatform::open(path, OpenOptions::DIRECTORY).unwrap();
+    let a = LinuxFilename::try_new("a".to_string()).unwrap();
+    let b = LinuxFilename::try_new("b".to_string()).unwrap();
+    LinuxPlatform::swapat(dir, a, dir, b).unwrap();
+
+    assert_eq!(std::fs::read(temp.path().join("a")).unwrap(), b"b");
+    assert_eq!(std::fs::read(temp.path().join("b")).unwrap(), b"a");
+    LinuxPlatform::close(dir).unwrap();
+}
+
+#[test]
+fn smoketest_symlink() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::write(temp.path().join("target.txt"), b"hello").unwrap();
+
+    let path = LinuxPath::try_new(temp.path().to_path_buf()).unwrap();
+    let dir = LinuxPlatform::open(path, OpenOptions::DIRECTORY).unwrap();
+    let target = LinuxPath::try_new("target.txt".into()).unwrap();
+    let link = LinuxFilename::try_new("link".to_string()).unwrap();
+    LinuxPlatform::symlinkat(target.clone(), dir, link.clone()).unwrap();
+
+    let rnd_target = LinuxPlatform::readlinkat(dir, link.clone()).unwrap();
+    assert_eq!(rnd_target, target);
+    let stat = LinuxPlatform::fstatat(dir, link.clone()).unwrap();
+    assert_eq!(stat.kind, FileType::Symlink);
+
+    // Opening follows the link by default.
+    let file = LinuxPlatform::openat(dir, link.clone(), OpenOptions::READ_ONLY).unwrap();
+    assert_eq!(LinuxPlatform::fstat(file).unwrap().size, 5);
+    LinuxPlatform::close(file).unwrap();
+
+    let result = LinuxPlatform::openat(dir, link, OpenOptions::NO_FOLLOW);
+    assert!(matches!(result, Err(crate::Error::TooManySymlinks)));
+    LinuxPlatform::close(dir).unwrap();
+}
+
+#[test]
+fn smoketest_fcopy() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::write(temp.path().join("a"), b"hello world").unwrap();
+
+    let a = LinuxPath::try_new(temp.path().join("a")).unwrap();
+    let b = LinuxPath::try_new(temp.path().join("b")).unwrap();
+    let from = LinuxPlatform::open(a, OpenOptions::READ_ONLY).unwrap();
+    let to = LinuxPlatform::open(b, OpenOptions::CREATE).unwrap();
+
+    // Not all filesystems support `copy_file_range`, e.g. older kernels.
+    match LinuxPlatform::fcopy(from, to) {
+        Ok(()) => assert_eq!(
+            std::fs::read(temp.path().join("b")).unwrap(),
+            b"hello world"
+        ),
+        Err(crate::Error::Unsupported) => (),
+        Err(err) => panic!("{err}"),
+    }
+    LinuxPlatform::close(from).unwrap();
+    LinuxPlatform::close(to).unwrap();
+}
+
+#[test]
+fn smoketest_errors() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(temp.path().join("dir")).unwrap();
+    std::fs::write(temp.path().join("dir/file"), b"").unwrap();
+
+    let dir = LinuxPath::try_new(temp.path().join("dir")).unwrap();
+    let result = LinuxPlatform::mkdir(dir.clone());
+    assert!(matches!(result, Err(crate::Error::AlreadyExists)));
+
+    let file = LinuxPath::try_new(temp.path().join("dir/file")).unwrap();
+    let result = LinuxPlatform::open(file, OpenOptions::DIRECTORY);
+    assert!(matches!(result, Err(crate::Error::NotADirectory)));
+
+    let result = LinuxPlatform::open(dir.clone(), OpenOptions::READ_WRITE);
+    assert!(matches!(result, Err(crate::Error::IsADirectory)));
+
+    let handle = LinuxPlatform::open(dir, OpenOptions::DIRECTORY).unwrap();
+    let long = LinuxFilename::try_new("a".repeat(300)).unwrap();
+    let result = LinuxPlatform::openat(handle, long, OpenOptions::CREATE);
+    assert!(matches!(result, Err(crate::Error::NameTooLong)));
+    LinuxPlatform::close(handle).unwrap();
+
+    let root = LinuxPath::try_new(temp.path().to_path_buf()).unwrap();
+    let root = LinuxPlatform::open(root, OpenOptions::DIRECTORY).unwrap();
+    let result = LinuxPlatform::unlinkat(root, LinuxFilename::try_new("dir".to_string()).unwrap());
+    assert!(matches!(result, Err(crate::Error::IsADirectory)));
+    LinuxPlatform::close(root).unwrap();
+}
+
+#[test]
+fn smoketest_vectored() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = LinuxPath::try_new(temp.path().join("test-vectored")).unwrap();
+    let file = LinuxPlatform::open(path, OpenOptions::CREATE).unwrap();
+
+    let bufs = [
+        IoSlice::new(b"hello"),
+        IoSlice::new(b""),
+        IoSlice::new(b" world"),
+    ];
+    let written = LinuxPlatform::writev(file, &bufs, 2).unwrap();
+    assert_eq!(written, 11);
+
+    let (mut a, mut b) = ([0u8; 4], [0u8; 16]);
+    let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+    let read = LinuxPlatform::readv(file, &mut bufs, 0).unwrap();
+    assert_eq!(read, 13);
+    assert_eq!(&a, b"\0\0he");
+    assert_eq!(&b[..9], b"llo world");
+
+    LinuxPlatform::close(file).unwrap();
+}
+
+#[test]
+fn smoketest_list_remove_xattr() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = LinuxPath::try_new(temp.path().join("test-xattr")).unwrap();
+    let file = LinuxPlatform::open(path, OpenOptions::CREATE).unwrap();
+
+    let names = ["org.pb.scratch.a", "org.pb.scratch.b"];
+    for name in names {
+        let name = LinuxFilename::try_new(name.to_string()).unwrap();
+        match LinuxPlatform::fsetxattr(file, name, b"1") {
+            Ok(()) => (),
+            Err(crate::Error::Unsupported) => return,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    let mut listed = LinuxPlatform::flistxattr(file).unwrap();
+    listed.sort();
+    assert_eq!(listed, names);
+
+    let name = LinuxFilename::try_new(names[0].to_string()).unwrap();
+    LinuxPlatform::fremovexattr(file, name).unwrap();
+    assert_eq!(LinuxPlatform::flistxattr(file).unwrap(), [names[1]]);
+    LinuxPlatform::close(file).unwrap();
+}
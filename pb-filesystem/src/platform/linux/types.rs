@@ -0,0 +1,283 @@
+#![allow(non_camel_case_types)]
+
+//! Types used by the Linux platform.
+
+pub(crate) use std::ffi::{c_char, c_int, c_uint, c_ulong};
+
+#[derive(Debug, Copy, Clone)]
+pub struct LinuxHandle {
+    inner: file_descriptor,
+}
+pub(crate) type file_descriptor = c_int;
+
+impl LinuxHandle {
+    pub fn from_raw(val: file_descriptor) -> Self {
+        LinuxHandle { inner: val }
+    }
+
+    pub fn into_raw(self) -> file_descriptor {
+        self.inner
+    }
+}
+
+/// Linux reads directories with `getdents64` on a file descriptor, so there is no separate
+/// directory stream type.
+#[derive(Debug, Copy, Clone)]
+pub struct LinuxDirStream {
+    pub(crate) inner: file_descriptor,
+}
+
+pub(crate) mod flags {
+    use super::*;
+
+    /// Open for reading only.
+    pub const O_RDONLY: c_int = 0o0;
+    /// Open for writing only.
+    pub const O_WRONLY: c_int = 0o1;
+    /// Open for reading and writing.
+    pub const O_RDWR: c_int = 0o2;
+    /// Mask for the above modes.
+    pub const O_ACCMODE: c_int = 0o3;
+
+    /// Create the file if it doesn't exist.
+    pub const O_CREAT: c_int = 0o100;
+    /// Error if the file already exists.
+    pub const O_EXCL: c_int = 0o200;
+    /// Truncate the file to 0 length.
+    pub const O_TRUNC: c_int = 0o1000;
+    /// Append all writes to the end of the file.
+    pub const O_APPEND: c_int = 0o2000;
+    /// Close the file descriptor when calling `exec`.
+    pub const O_CLOEXEC: c_int = 0o2000000;
+
+    /// Restrict opening to just directories.
+    #[cfg(any(target_arch = "aarch64", target_arch = "arm"))]
+    pub const O_DIRECTORY: c_int = 0o40000;
+    /// Restrict opening to just directories.
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "arm")))]
+    pub const O_DIRECTORY: c_int = 0o200000;
+
+    /// Create an unnamed file in the provided directory.
+    pub const O_TMPFILE: c_int = 0o20000000 | O_DIRECTORY;
+
+    /// Fail if the last component of the path is a symlink.
+    #[cfg(any(target_arch = "aarch64", target_arch = "arm"))]
+    pub const O_NOFOLLOW: c_int = 0o100000;
+    /// Fail if the last component of the path is a symlink.
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "arm")))]
+    pub const O_NOFOLLOW: c_int = 0o400000;
+
+    /// Use the current working directory for the `*at` family of functions.
+    pub const AT_FDCWD: c_int = -100;
+    /// Act on the symlink itself, do not follow it.
+    pub const AT_SYMLINK_NOFOLLOW: c_int = 0x100;
+    /// Act on the target of the symlink.
+    pub const AT_SYMLINK_FOLLOW: c_int = 0x400;
+    /// Remove a directory instead of a file, for `unlinkat`.
+    pub const AT_REMOVEDIR: c_int = 0x200;
+    /// Operate on the file descriptor itself if the path is empty.
+    pub const AT_EMPTY_PATH: c_int = 0x1000;
+
+    /// Request all of the fields from `statx` that are also returned by `stat`.
+    pub const STATX_BASIC_STATS: c_uint = 0x07ff;
+
+    /// Mask for `stx_mode` that contains filetype information.
+    pub const S_IFMT: u16 = 0o170000;
+
+    /// Named pipe (FIFO).
+    pub const S_IFIFO: u16 = 0o010000;
+    /// Character special.
+    pub const S_IFCHR: u16 = 0o020000;
+    /// Directory.
+    pub const S_IFDIR: u16 = 0o040000;
+    /// Block special.
+    pub const S_IFBLK: u16 = 0o060000;
+    /// Regular file.
+    pub const S_IFREG: u16 = 0o100000;
+    /// Symbolic link.
+    pub const S_IFLNK: u16 = 0o120000;
+    /// Socket.
+    pub const S_IFSOCK: u16 = 0o140000;
+
+    /// Unknown filetype, from `getdents64`.
+    pub const DT_UNKNOWN: u8 = 0;
+    /// Named pipe (FIFO), from `getdents64`.
+    pub const DT_FIFO: u8 = 1;
+    /// Character special, from `getdents64`.
+    pub const DT_CHR: u8 = 2;
+    /// Directory, from `getdents64`.
+    pub const DT_DIR: u8 = 4;
+    /// Block special, from `getdents64`.
+    pub const DT_BLK: u8 = 6;
+    /// Regular file, from `getdents64`.
+    pub const DT_REG: u8 = 8;
+    /// Symbolic link, from `getdents64`.
+    pub const DT_LNK: u8 = 10;
+    /// Socket, from `getdents64`.
+    pub const DT_SOCK: u8 = 12;
+
+    /// Number of open files.
+    pub const RLIMIT_NOFILE: c_int = 7;
+
+    /// Set the value but fail if the attr already exists.
+    pub const XATTR_CREATE: c_int = 0x1;
+    /// Set the value but fail if the attr does not already exists.
+    pub const XATTR_REPLACE: c_int = 0x2;
+
+    /// Don't overwrite the destination of a rename.
+    pub const RENAME_NOREPLACE: c_uint = 1 << 0;
+    /// Atomically exchange the source and destination of a rename.
+    pub const RENAME_EXCHANGE: c_uint = 1 << 1;
+
+    /// Pages may be read, for `mmap`.
+    pub const PROT_READ: c_int = 0x1;
+    /// Changes to the mapping are private to the process, for `mmap`.
+    pub const MAP_PRIVATE: c_int = 0x2;
+    /// Returned from `mmap` on failure.
+    pub const MAP_FAILED: *mut u8 = !0 as *mut u8;
+
+    /// Seek relative to the start of the file.
+    pub const SEEK_SET: c_int = 0;
+
+    /// `ioctl` request to share the data of a file with another, i.e. a reflink.
+    pub const FICLONE: c_ulong = 0x40049409;
+}
+
+pub(crate) mod mode {
+    /// Default mode for newly created files.
+    pub const DEFAULT_FILE_MODE: u32 = S_IRUSR | S_IWUSR | S_IRGRP | S_IWGRP | S_IROTH | S_IWOTH;
+    /// Default mode for newly created directories.
+    pub const DEFAULT_DIR_MODE: u32 = DEFAULT_FILE_MODE | S_IRWXU | S_IRWXG;
+
+    /// RWX mask for owner.
+    pub const S_IRWXU: u32 = 0o0000700;
+    /// R for owner.
+    pub const S_IRUSR: u32 = 0o0000400;
+    /// W for owner.
+    pub const S_IWUSR: u32 = 0o0000200;
+    /// X for owner.
+    pub const S_IXUSR: u32 = 0o0000100;
+
+    /// RWX mask for group.
+    pub const S_IRWXG: u32 = 0o0000070;
+    /// R for group.
+    pub const S_IRGRP: u32 = 0o0000040;
+    /// W for group.
+    pub const S_IWGRP: u32 = 0o0000020;
+    /// X for group.
+    pub const S_IXGRP: u32 = 0o0000010;
+
+    /// RWX mask for other.
+    pub const S_IRWXO: u32 = 0o0000007;
+    /// R for other.
+    pub const S_IROTH: u32 = 0o0000004;
+    /// W for other.
+    pub const S_IWOTH: u32 = 0o0000002;
+    /// X for other.
+    pub const S_IXOTH: u32 = 0o0000001;
+}
+
+pub(crate) mod constants {
+    /// Error returned when a syscall was interrupted by a signal, `EINTR`.
+    pub const EINTR: super::c_int = 4;
+    /// Maximum number of buffers for a single call to `preadv` or `pwritev`.
+    pub const IOV_MAX: usize = 1024;
+    /// Errors from opening with `O_TMPFILE` that mean it isn't supported, `EISDIR` and
+    /// `EOPNOTSUPP`.
+    pub const TMPFILE_UNSUPPORTED_ERRNOS: &[super::c_int] = &[21, 95];
+    /// Maximum length of a path in bytes, including the nul terminator.
+    pub const PATH_MAX: usize = 4096;
+
+    /// Size of the buffer we read directory entries into.
+    pub const GETDENTS_BUFFER_SIZE: usize = 32 * 1024;
+
+    /// Maximum number of bytes we request in a single call to `copy_file_range`.
+    pub const COPY_FILE_RANGE_CHUNK_SIZE: usize = 1 << 30;
+    /// Errors from `FICLONE` and `copy_file_range` that mean the files can't be copied with
+    /// them, `EXDEV`, `EINVAL`, `ENOTTY`, `ENOSYS`, and `EOPNOTSUPP`.
+    pub const COPY_UNSUPPORTED_ERRNOS: &[super::c_int] = &[18, 22, 25, 38, 95];
+
+    /// Namespaces an xattr name can have, names without one get [`XATTR_DEFAULT_NAMESPACE`].
+    pub const XATTR_NAMESPACES: &[&str] = &["user.", "trusted.", "security.", "system."];
+    /// Namespace for xattrs that any user can set on files they own.
+    pub const XATTR_DEFAULT_NAMESPACE: &str = "user.";
+
+    /// Error returned when a file can't be mapped into memory, `ENODEV`.
+    pub const ENODEV: super::c_int = 19;
+    /// Error returned when a buffer is too small for the result, `ERANGE`.
+    pub const ERANGE: super::c_int = 34;
+}
+
+/// Timestamp returned as part of [`statx`].
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct statx_timestamp {
+    pub tv_sec: i64,
+    pub tv_nsec: u32,
+    pub __reserved: i32,
+}
+
+/// Data returned by calls to `statx`.
+///
+/// Newer kernels return more fields in the spare space at the end, but the size of the struct
+/// is fixed.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct statx {
+    pub stx_mask: u32,
+    pub stx_blksize: u32,
+    pub stx_attributes: u64,
+    pub stx_nlink: u32,
+    pub stx_uid: u32,
+    pub stx_gid: u32,
+    pub stx_mode: u16,
+    pub __spare0: u16,
+    pub stx_ino: u64,
+    pub stx_size: u64,
+    pub stx_blocks: u64,
+    pub stx_attributes_mask: u64,
+    pub stx_atime: statx_timestamp,
+    pub stx_btime: statx_timestamp,
+    pub stx_ctime: statx_timestamp,
+    pub stx_mtime: statx_timestamp,
+    pub stx_rdev_major: u32,
+    pub stx_rdev_minor: u32,
+    pub stx_dev_major: u32,
+    pub stx_dev_minor: u32,
+    pub __spare2: [u64; 14],
+}
+
+/// Header of a directory entry returned from `getdents64`, followed by the nul terminated
+/// name of the entry.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct linux_dirent64 {
+    pub d_ino: u64,
+    pub d_off: i64,
+    pub d_reclen: u16,
+    pub d_type: u8,
+}
+
+/// Offset of the name within a [`linux_dirent64`] record.
+pub const DIRENT64_NAME_OFFSET: usize = 19;
+
+pub type rlim_t = u64;
+
+/// Buffer passed to `preadv` and `pwritev`.
+///
+/// Has the same layout as [`std::io::IoSlice`] and [`std::io::IoSliceMut`].
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct iovec {
+    pub iov_base: *mut u8,
+    pub iov_len: usize,
+}
+
+/// Limits returned from `getrlimit`.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone)]
+pub struct rlimit {
+    /// Current (soft) limit.
+    pub(crate) rlim_cur: rlim_t,
+    pub(crate) rlim_max: rlim_t,
+}
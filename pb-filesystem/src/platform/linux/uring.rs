@@ -0,0 +1,264 @@
+//! Filesystem operations submitted in batches via `io_uring`.
+//!
+//! A single thread owns the ring. Callers queue operations over a channel and the thread
+//! pushes everything that is queued into the submission queue at once, so many concurrent
+//! operations (e.g. all of the `stat` calls for a directory during a tree walk) only cost a
+//! single syscall.
+
+use io_uring::{opcode, squeue, IoUring};
+use pb_ore::cast::CastFrom;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::CString;
+use tokio::sync::oneshot;
+
+use crate::platform::linux::types::{self, LinuxHandle};
+use crate::platform::linux::{open_flags, LinuxPath, LinuxPlatform};
+use crate::platform::{OpenOptions, Platform};
+use crate::FileStat;
+
+/// Handle to a thread that submits operations to an `io_uring`.
+#[derive(Debug)]
+pub struct UringDriver {
+    /// Queue of operations for the driver thread to submit.
+    tx: crossbeam::channel::Sender<UringOp>,
+}
+
+impl UringDriver {
+    /// Create a ring with `entries` slots in its submission queue, and spawn a thread to drive
+    /// it.
+    ///
+    /// Fails if the kernel doesn't support `io_uring`, or it has been disabled.
+    pub fn new(entries: u32) -> Result<Self, crate::Error> {
+        let ring = IoUring::new(entries).map_err(io_error)?;
+        let (tx, rx) = crossbeam::channel::unbounded();
+        std::thread::Builder::new()
+            .name("pb-io-uring".to_string())
+            .spawn(move || drive_ring(ring, rx))
+            .map_err(io_error)?;
+
+        Ok(UringDriver { tx })
+    }
+
+    /// Get the metadata for the file at `path`, following symlinks.
+    pub async fn stat(&self, path: LinuxPath) -> Result<FileStat, crate::Error> {
+        let path = CString::from(path);
+        self.submit(|tx| UringOp::Stat {
+            dirfd: types::flags::AT_FDCWD,
+            path,
+            flags: 0,
+            buf: Box::default(),
+            tx,
+        })
+        .await
+    }
+
+    /// Get the metadata for the file referenced by `handle`.
+    pub async fn fstat(&self, handle: LinuxHandle) -> Result<FileStat, crate::Error> {
+        self.submit(|tx| UringOp::Stat {
+            dirfd: handle.into_raw(),
+            path: CString::default(),
+            flags: types::flags::AT_EMPTY_PATH,
+            buf: Box::default(),
+            tx,
+        })
+        .await
+    }
+
+    /// Open the file at `path`.
+    pub async fn open(
+        &self,
+        path: LinuxPath,
+        options: OpenOptions,
+    ) -> Result<LinuxHandle, crate::Error> {
+        let path = CString::from(path);
+        let flags = open_flags(&options);
+        self.submit(|tx| UringOp::Open { path, flags, tx }).await
+    }
+
+    /// Read up to `len` bytes from `handle` starting at `offset`.
+    pub async fn read(
+        &self,
+        handle: LinuxHandle,
+        len: usize,
+        offset: usize,
+    ) -> Result<Vec<u8>, crate::Error> {
+        self.submit(|tx| UringOp::Read {
+            handle,
+            buf: vec![0; len],
+            offset: u64::cast_from(offset),
+            tx,
+        })
+        .await
+    }
+
+    async fn submit<T>(
+        &self,
+        op: impl FnOnce(oneshot::Sender<Result<T, crate::Error>>) -> UringOp,
+    ) -> Result<T, crate::Error> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(op(tx)).map_err(|_| driver_gone())?;
+        rx.await.map_err(|_| driver_gone())?
+    }
+}
+
+/// An operation queued for, or in-flight on, the ring.
+///
+/// Owns all of the memory the kernel reads from or writes to, so it must be kept alive until
+/// the operation completes.
+enum UringOp {
+    Stat {
+        dirfd: types::file_descriptor,
+        path: CString,
+        flags: types::c_int,
+        buf: Box<types::statx>,
+        tx: oneshot::Sender<Result<FileStat, crate::Error>>,
+    },
+    Open {
+        path: CString,
+        flags: types::c_int,
+        tx: oneshot::Sender<Result<LinuxHandle, crate::Error>>,
+    },
+    Read {
+        handle: LinuxHandle,
+        buf: Vec<u8>,
+        offset: u64,
+        tx: oneshot::Sender<Result<Vec<u8>, crate::Error>>,
+    },
+}
+
+impl UringOp {
+    /// Returns the submission queue entry for this operation.
+    ///
+    /// The entry points into heap memory owned by `self`, so it stays valid if `self` is moved.
+    fn entry(&mut self) -> squeue::Entry {
+        match self {
+            UringOp::Stat {
+                dirfd,
+                path,
+                flags,
+                buf,
+                ..
+            } => {
+                let buf: *mut types::statx = &mut **buf;
+                opcode::Statx::new(io_uring::types::Fd(*dirfd), path.as_ptr(), buf.cast())
+                    .flags(*flags)
+                    .mask(types::flags::STATX_BASIC_STATS)
+                    .build()
+            }
+            UringOp::Open { path, flags, .. } => {
+                opcode::OpenAt::new(io_uring::types::Fd(types::flags::AT_FDCWD), path.as_ptr())
+                    .flags(*flags)
+                    .mode(types::mode::DEFAULT_FILE_MODE)
+                    .build()
+            }
+            UringOp::Read {
+                handle,
+                buf,
+                offset,
+                ..
+            } => {
+                let len = u32::try_from(buf.len()).unwrap_or(u32::MAX);
+                opcode::Read::new(
+                    io_uring::types::Fd(handle.into_raw()),
+                    buf.as_mut_ptr(),
+                    len,
+                )
+                .offset(*offset)
+                .build()
+            }
+        }
+    }
+
+    /// Complete this operation with the `result` from its completion queue entry.
+    fn complete(self, result: i32) {
+        let result = match u32::try_from(result) {
+            Ok(val) => Ok(val),
+            Err(_) => Err(crate::Error::from_linux_sys(-result)),
+        };
+
+        // We don't care about the receiver going away.
+        match self {
+            UringOp::Stat { buf, tx, .. } => {
+                let _ = tx.send(result.and_then(|_| FileStat::try_from(*buf)));
+            }
+            UringOp::Open { tx, .. } => {
+                let fd = result.map(|fd| i32::try_from(fd).expect("fd fits in i32"));
+                if let Err(Ok(handle)) = tx.send(fd.map(LinuxHandle::from_raw)) {
+                    // Don't leak the handle if the caller went away.
+                    let _ = LinuxPlatform::close(handle);
+                }
+            }
+            UringOp::Read { mut buf, tx, .. } => {
+                let _ = tx.send(result.map(|read| {
+                    buf.truncate(usize::cast_from(read));
+                    buf
+                }));
+            }
+        }
+    }
+}
+
+/// Submit queued operations to the ring until all of the senders go away.
+fn drive_ring(mut ring: IoUring, rx: crossbeam::channel::Receiver<UringOp>) {
+    // Never have more operations in-flight than the completion queue can hold.
+    let max_in_flight = usize::cast_from(ring.params().cq_entries());
+    let mut pending = VecDeque::new();
+    let mut in_flight = HashMap::new();
+    let mut next_id = 0u64;
+
+    loop {
+        // Block until there is an operation, if we're otherwise idle.
+        if in_flight.is_empty() && pending.is_empty() {
+            match rx.recv() {
+                Ok(op) => pending.push_back(op),
+                Err(notice) => {
+                    tracing::info!(?notice, "io_uring sender went away, shutting down");
+                    return;
+                }
+            }
+        }
+
+        // Collect all of the currently queued operations, if any.
+        pending.extend(rx.try_iter());
+
+        // Fill the submission queue.
+        while in_flight.len() < max_in_flight && !ring.submission().is_full() {
+            let Some(mut op) = pending.pop_front() else {
+                break;
+            };
+            let entry = op.entry().user_data(next_id);
+            // SAFETY: All of the memory referenced by the entry is owned by `op`, which we
+            // keep alive in `in_flight` until the operation completes.
+            unsafe { ring.submission().push(&entry) }.expect("checked for space");
+            in_flight.insert(next_id, op);
+            next_id = next_id.wrapping_add(1);
+        }
+
+        match ring.submit_and_wait(1) {
+            Ok(_) => (),
+            // Interrupted, or the completion queue needs to be drained, both are retryable.
+            Err(err) if matches!(err.raw_os_error(), Some(4 | 16)) => (),
+            Err(err) => {
+                tracing::error!(?err, "failed to submit to io_uring, shutting down");
+                // The kernel may still write into the memory for in-flight operations.
+                std::mem::forget(in_flight);
+                return;
+            }
+        }
+
+        for entry in ring.completion() {
+            let op = in_flight
+                .remove(&entry.user_data())
+                .expect("completion for unknown operation");
+            op.complete(entry.result());
+        }
+    }
+}
+
+fn io_error(err: std::io::Error) -> crate::Error {
+    crate::Error::from_linux_sys(err.raw_os_error().unwrap_or(-1))
+}
+
+fn driver_gone() -> crate::Error {
+    crate::Error::Unknown("io_uring driver shut down".to_string())
+}
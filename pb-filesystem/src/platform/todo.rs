@@ -1,6 +1,8 @@
 //! Placeholder Platform that uses `todo!(...)` for all implementations.
 
+use std::io::{IoSlice, IoSliceMut};
 use std::path::PathBuf;
+use std::ptr::NonNull;
 
 use crate::platform::{OpenOptions, Platform, PlatformFilename, PlatformPath};
 use crate::DirectoryEntry;
@@ -35,6 +37,16 @@ impl Platform for TodoPlatform {
         todo!("mkdirat")
     }
 
+    fn unlink(_path: Self::Path) -> Result<(), crate::Error> {
+        todo!("unlink")
+    }
+    fn unlinkat(_handle: Self::Handle, _filename: Self::Filename) -> Result<(), crate::Error> {
+        todo!("unlinkat")
+    }
+    fn rmdirat(_handle: Self::Handle, _filename: Self::Filename) -> Result<(), crate::Error> {
+        todo!("rmdirat")
+    }
+
     fn stat(_path: PathBuf) -> Result<crate::FileStat, crate::Error> {
         todo!("stat")
     }
@@ -54,10 +66,30 @@ impl Platform for TodoPlatform {
         todo!("fsync")
     }
 
+    fn fchmod(_handle: Self::Handle, _mode: u32) -> Result<(), crate::Error> {
+        todo!("fchmod")
+    }
+
+    fn fchown(_handle: Self::Handle, _user: u32, _group: u32) -> Result<(), crate::Error> {
+        todo!("fchown")
+    }
+
     fn listdir(_handle: Self::Handle) -> Result<Vec<DirectoryEntry>, crate::Error> {
         todo!("listdir")
     }
 
+    fn opendir(_handle: Self::Handle) -> Result<Self::DirStream, crate::Error> {
+        todo!("opendir")
+    }
+
+    fn readdir(_stream: Self::DirStream) -> Result<Vec<DirectoryEntry>, crate::Error> {
+        todo!("readdir")
+    }
+
+    fn closedir(_stream: Self::DirStream) -> Result<(), crate::Error> {
+        todo!("closedir")
+    }
+
     fn read(_stream: Self::Handle, _buf: &mut [u8], _offset: usize) -> Result<usize, crate::Error> {
         todo!("read")
     }
@@ -66,6 +98,46 @@ impl Platform for TodoPlatform {
         todo!("write")
     }
 
+    fn clonefile(_from: Self::Path, _to: Self::Path) -> Result<(), crate::Error> {
+        todo!("clonefile")
+    }
+
+    fn fcopy(_from: Self::Handle, _to: Self::Handle) -> Result<(), crate::Error> {
+        todo!("fcopy")
+    }
+
+    fn readv(
+        _handle: Self::Handle,
+        _bufs: &mut [IoSliceMut<'_>],
+        _offset: usize,
+    ) -> Result<usize, crate::Error> {
+        todo!("readv")
+    }
+
+    fn writev(
+        _handle: Self::Handle,
+        _bufs: &[IoSlice<'_>],
+        _offset: usize,
+    ) -> Result<usize, crate::Error> {
+        todo!("writev")
+    }
+
+    fn ftruncate(_handle: Self::Handle, _len: usize) -> Result<(), crate::Error> {
+        todo!("ftruncate")
+    }
+
+    fn fallocate(_handle: Self::Handle, _len: usize) -> Result<(), crate::Error> {
+        todo!("fallocate")
+    }
+
+    fn mmap(_handle: Self::Handle, _len: usize) -> Result<NonNull<u8>, crate::Error> {
+        todo!("mmap")
+    }
+
+    unsafe fn munmap(_ptr: NonNull<u8>, _len: usize) -> Result<(), crate::Error> {
+        todo!("munmap")
+    }
+
     fn rename(_from: Self::Path, _to: Self::Path) -> Result<(), crate::Error> {
         todo!("rename")
     }
@@ -79,6 +151,27 @@ impl Platform for TodoPlatform {
         todo!("renameat")
     }
 
+    fn linkat(
+        _from_handle: Self::Handle,
+        _from_filename: Self::Filename,
+        _to_handle: Self::Handle,
+        _to_filename: Self::Filename,
+    ) -> Result<(), crate::Error> {
+        todo!("linkat")
+    }
+
+    fn tmpfileat(_handle: Self::Handle) -> Result<Self::Handle, crate::Error> {
+        todo!("tmpfileat")
+    }
+
+    fn flinkat(
+        _handle: Self::Handle,
+        _to_handle: Self::Handle,
+        _to_filename: Self::Filename,
+    ) -> Result<(), crate::Error> {
+        todo!("flinkat")
+    }
+
     fn swapat(
         _from_handle: Self::Handle,
         _from_filename: Self::Filename,
@@ -103,6 +196,33 @@ impl Platform for TodoPlatform {
         todo!("fgetxattr")
     }
 
+    fn readlink(_path: Self::Path) -> Result<Self::Path, crate::Error> {
+        todo!("readlink")
+    }
+    fn realpath(_path: Self::Path) -> Result<Self::Path, crate::Error> {
+        todo!("realpath")
+    }
+    fn readlinkat(
+        _handle: Self::Handle,
+        _filename: Self::Filename,
+    ) -> Result<Self::Path, crate::Error> {
+        todo!("readlinkat")
+    }
+    fn symlinkat(
+        _target: Self::Path,
+        _handle: Self::Handle,
+        _filename: Self::Filename,
+    ) -> Result<(), crate::Error> {
+        todo!("symlinkat")
+    }
+
+    fn flistxattr(_handle: Self::Handle) -> Result<Vec<String>, crate::Error> {
+        todo!("flistxattr")
+    }
+    fn fremovexattr(_handle: Self::Handle, _name: Self::Filename) -> Result<(), crate::Error> {
+        todo!("fremovexattr")
+    }
+
     fn fgetpath(_handle: Self::Handle) -> Result<Self::Path, crate::Error> {
         todo!("fgetpath")
     }
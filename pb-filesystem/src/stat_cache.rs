@@ -0,0 +1,90 @@
+//! Cache of [`FileStat`]s keyed by path, see [`Filesystem::with_stat_cache`].
+//!
+//! [`Filesystem::with_stat_cache`]: crate::filesystem::Filesystem::with_stat_cache
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::FileStat;
+
+/// Cache of [`FileStat`]s, that must be invalidated when the file changes.
+#[derive(Debug)]
+pub(crate) struct StatCache {
+    inner: Mutex<StatCacheInner>,
+    /// Maximum number of entries to cache.
+    capacity: usize,
+}
+
+#[derive(Debug, Default)]
+struct StatCacheInner {
+    /// Cached stats, ordered so we can cheaply invalidate everything under a directory.
+    entries: BTreeMap<PathBuf, FileStat>,
+    /// Incremented on every invalidation.
+    ///
+    /// A stat that was started before an invalidation might have observed the old state of
+    /// the file, so it must not get cached.
+    generation: u64,
+}
+
+impl StatCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        StatCache {
+            inner: Mutex::new(StatCacheInner::default()),
+            capacity,
+        }
+    }
+
+    /// Returns the cached stat for `path`, if any.
+    pub(crate) fn get(&self, path: &Path) -> Option<FileStat> {
+        let inner = self.inner.lock().expect("lock poisoned");
+        inner.entries.get(path).copied()
+    }
+
+    /// Returns the current generation, which must be passed to [`StatCache::insert`].
+    pub(crate) fn generation(&self) -> u64 {
+        self.inner.lock().expect("lock poisoned").generation
+    }
+
+    /// Cache `stat` for `path`, unless an invalidation happened since `generation`.
+    ///
+    /// Evicts an arbitrary entry if the cache is full.
+    pub(crate) fn insert(&self, path: PathBuf, stat: FileStat, generation: u64) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        if inner.generation != generation || self.capacity == 0 {
+            return;
+        }
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&path) {
+            inner.entries.pop_first();
+        }
+        inner.entries.insert(path, stat);
+    }
+
+    /// Invalidate `path`, everything beneath it, and its parent directory whose metadata
+    /// changes when entries are added or removed.
+    pub(crate) fn invalidate(&self, path: &Path) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        inner.generation = inner.generation.wrapping_add(1);
+
+        let beneath: Vec<_> = inner
+            .entries
+            .range::<Path, _>((Bound::Included(path), Bound::Unbounded))
+            .take_while(|(entry, _)| entry.starts_with(path))
+            .map(|(entry, _)| entry.clone())
+            .collect();
+        for entry in beneath {
+            inner.entries.remove(&entry);
+        }
+        if let Some(parent) = path.parent() {
+            inner.entries.remove(parent);
+        }
+    }
+
+    /// Invalidate every entry.
+    pub(crate) fn clear(&self) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        inner.generation = inner.generation.wrapping_add(1);
+        inner.entries.clear();
+    }
+}
@@ -1,8 +1,16 @@
 use std::env::temp_dir;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use pb_ore::hash::Xxh3Hasher;
 use pb_ore::iter::LendingIterator;
+use pb_types::Xxh64Hash;
 
-use crate::filesystem::Filesystem;
+use crate::filesystem::{Filesystem, Priority, RetryPolicy};
+use crate::locations::scratch::{ScratchDirectory, ScratchOptions};
+use crate::tree::{MetadataTree, SymlinkPolicy, TreeEventKind, TreeProgress};
+use crate::{FileStat, FileType};
 
 impl Filesystem {
     fn new_test() -> Filesystem {
@@ -102,3 +110,1424 @@ async fn smoketest_tree() {
     let tree = handle.tree().await.unwrap();
     println!("{tree}")
 }
+
+#[tokio::test]
+async fn smoketest_tree_symlinks() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(temp.path().join("dir")).unwrap();
+    std::fs::write(temp.path().join("dir/a.txt"), b"a").unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let handle = filesystem.open(temp.path()).as_directory().await.unwrap();
+    handle
+        .symlinkat("dir".into(), "dir_link".to_string())
+        .await
+        .unwrap();
+    handle
+        .symlinkat("dir/a.txt".into(), "file_link".to_string())
+        .await
+        .unwrap();
+    // A symlink back to our root should not get followed forever.
+    handle
+        .symlinkat("..".into(), "dir/parent_link".to_string())
+        .await
+        .unwrap();
+
+    let target = handle.readlinkat("dir_link".to_string()).await.unwrap();
+    assert_eq!(target, PathBuf::from("dir"));
+
+    // Opening a symlink with `no_follow` fails.
+    let result = handle
+        .openat("file_link".to_string())
+        .as_file()
+        .no_follow()
+        .await;
+    assert!(matches!(result, Err(crate::Error::TooManySymlinks)));
+
+    let skipped = handle.tree().await.unwrap();
+    assert!(skipped.symlinks().is_empty());
+    assert_eq!(skipped.into_parts().0.len(), 1);
+
+    // Includes `dir_link/a.txt` and `file_link`, but not `dir/parent_link`.
+    let followed = handle.tree().symlinks(SymlinkPolicy::Follow).await.unwrap();
+    assert_eq!(followed.into_parts().0.len(), 3);
+
+    let recorded = handle
+        .tree()
+        .symlinks(SymlinkPolicy::RecordTarget)
+        .await
+        .unwrap();
+    let symlinks: Vec<_> = recorded
+        .symlinks()
+        .iter()
+        .map(|(path, target)| (path.to_str().unwrap(), target.to_str().unwrap()))
+        .collect();
+    assert_eq!(
+        symlinks,
+        [
+            ("dir/parent_link", ".."),
+            ("dir_link", "dir"),
+            ("file_link", "dir/a.txt")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn smoketest_tree_limits() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(temp.path().join("a/b")).unwrap();
+    std::fs::write(temp.path().join("top.txt"), b"top").unwrap();
+    std::fs::write(temp.path().join("a/mid.txt"), b"mid").unwrap();
+    std::fs::write(temp.path().join("a/b/bottom.txt"), b"bottom").unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let handle = filesystem.open(temp.path()).as_directory().await.unwrap();
+
+    let everything = handle.tree().await.unwrap();
+    assert_eq!(everything.into_parts().0.len(), 3);
+
+    for (depth, expected) in [(0, 0), (1, 1), (2, 2), (3, 3)] {
+        let tree = handle.tree().max_depth(depth).await.unwrap();
+        assert_eq!(tree.into_parts().0.len(), expected, "depth {depth}");
+    }
+
+    // Everything in the temp directory is on the same filesystem.
+    let same = handle.tree().same_filesystem(true).await.unwrap();
+    assert_eq!(same.into_parts().0.len(), 3);
+    let root_stat = handle.stat().await.unwrap();
+    let file_stat = filesystem
+        .stat(temp.path().join("a/b/bottom.txt"))
+        .await
+        .unwrap();
+    assert_eq!(root_stat.device, file_stat.device);
+}
+
+#[tokio::test]
+async fn smoketest_tree_gitignore() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let root = temp.path();
+    for dir in [".git", "build", "sub"] {
+        std::fs::create_dir(root.join(dir)).unwrap();
+    }
+    std::fs::write(
+        root.join(".gitignore"),
+        "# comment\n*.log\nbuild/\n!keep.log\n/anchored.txt\n",
+    )
+    .unwrap();
+    std::fs::write(root.join("sub/.pbignore"), "a.txt\n!*.log\n").unwrap();
+    for file in [
+        ".git/HEAD",
+        "a.txt",
+        "x.log",
+        "keep.log",
+        "anchored.txt",
+        "build/out.txt",
+        "sub/a.txt",
+        "sub/y.log",
+        "sub/anchored.txt",
+        // Only directories match `build/`.
+        "sub/build",
+    ] {
+        std::fs::write(root.join(file), file).unwrap();
+    }
+
+    let filesystem = Filesystem::new_test();
+    let handle = filesystem.open(root).as_directory().await.unwrap();
+
+    let everything = handle.tree().await.unwrap();
+    assert_eq!(everything.into_parts().0.len(), 12);
+
+    // .gitignore, a.txt, keep.log, sub/.pbignore, sub/y.log, sub/anchored.txt, sub/build
+    let tree = handle.tree().respect_gitignore().await.unwrap();
+    assert_eq!(tree.into_parts().0.len(), 7);
+}
+
+#[tokio::test]
+async fn smoketest_tree_progress() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(temp.path().join("a/b")).unwrap();
+    std::fs::write(temp.path().join("one.txt"), b"one").unwrap();
+    std::fs::write(temp.path().join("a/two.txt"), b"two!").unwrap();
+    std::fs::write(temp.path().join("a/b/three.txt"), b"three").unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let handle = filesystem.open(temp.path()).as_directory().await.unwrap();
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let reports_ = Arc::clone(&reports);
+    handle
+        .tree()
+        .with_data(|_stat, mut reader| reader.digest::<Xxh3Hasher>())
+        .progress(move |progress| reports_.lock().unwrap().push(progress))
+        .await
+        .unwrap();
+
+    let reports = std::mem::take(&mut *reports.lock().unwrap());
+    // Once per directory and file.
+    assert_eq!(reports.len(), 6);
+    assert_eq!(
+        reports.last().copied(),
+        Some(TreeProgress {
+            directories: 3,
+            files: 3,
+            bytes_read: 12,
+        })
+    );
+
+    // Nothing is read without `with_data`.
+    let last = Arc::new(Mutex::new(None));
+    let last_ = Arc::clone(&last);
+    handle
+        .tree()
+        .progress(move |progress| *last_.lock().unwrap() = Some(progress))
+        .await
+        .unwrap();
+    let last = last.lock().unwrap().unwrap();
+    assert_eq!((last.files, last.bytes_read), (3, 0));
+}
+
+#[tokio::test]
+async fn smoketest_tree_concurrency() {
+    let temp = tempfile::TempDir::new().unwrap();
+    for dir in 0..10 {
+        let dir_path = temp.path().join(format!("dir{dir}/nested"));
+        std::fs::create_dir_all(&dir_path).unwrap();
+        for file in 0..50 {
+            std::fs::write(dir_path.join(format!("{file}.txt")), file.to_string()).unwrap();
+        }
+    }
+    std::fs::write(temp.path().join("root.txt"), b"root").unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let handle = filesystem.open(temp.path()).as_directory().await.unwrap();
+
+    let expected = handle.tree().await.unwrap().to_string();
+    for concurrency in [0, 1, 3, 1000] {
+        let tree = handle
+            .tree()
+            .with_data(|_stat, mut reader| reader.digest::<Xxh3Hasher>())
+            .concurrency(concurrency)
+            .await
+            .unwrap();
+        assert_eq!(tree.to_string(), expected, "concurrency {concurrency}");
+        assert_eq!(tree.into_parts().0.len(), 501);
+    }
+}
+
+#[tokio::test]
+async fn smoketest_tree_lookup() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(temp.path().join("a/b")).unwrap();
+    std::fs::create_dir(temp.path().join("empty")).unwrap();
+    std::fs::write(temp.path().join("top.txt"), b"top").unwrap();
+    std::fs::write(temp.path().join("a/one.txt"), b"one").unwrap();
+    std::fs::write(temp.path().join("a/b/two.txt"), b"two").unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let handle = filesystem.open(temp.path()).as_directory().await.unwrap();
+    let tree = handle
+        .tree()
+        .with_data(|_stat, mut reader| reader.digest::<Xxh3Hasher>())
+        .await
+        .unwrap();
+
+    let (stat, hash) = tree.get("a/b/two.txt").unwrap();
+    assert_eq!(stat.size, 3);
+    assert_eq!(*hash, Xxh64Hash::new(xxhash_rust::xxh3::xxh3_64(b"two")));
+    assert!(tree.get("./top.txt").is_some());
+    // Directories, missing files, and paths outside of the tree.
+    assert!(tree.get("a").is_none());
+    assert!(tree.get("a/missing.txt").is_none());
+    assert!(tree.get("../top.txt").is_none());
+
+    let mut files: Vec<_> = tree.iter_files().map(|(path, _)| path).collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            PathBuf::from("a/b/two.txt"),
+            PathBuf::from("a/one.txt"),
+            PathBuf::from("top.txt"),
+        ]
+    );
+
+    let mut under_a: Vec<_> = tree.files_under("a").map(|(path, _)| path).collect();
+    under_a.sort();
+    assert_eq!(
+        under_a,
+        [PathBuf::from("a/b/two.txt"), PathBuf::from("a/one.txt")]
+    );
+    let single: Vec<_> = tree.files_under("top.txt").map(|(path, _)| path).collect();
+    assert_eq!(single, [PathBuf::from("top.txt")]);
+    assert_eq!(tree.files_under("empty").count(), 0);
+    assert_eq!(tree.files_under("missing").count(), 0);
+}
+
+#[tokio::test]
+async fn smoketest_tree_diff() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let root = temp.path();
+    std::fs::create_dir(root.join("dir")).unwrap();
+    for file in [
+        "same.txt",
+        "rewritten.txt",
+        "changed.txt",
+        "dir/removed.txt",
+    ] {
+        std::fs::write(root.join(file), file).unwrap();
+    }
+
+    let filesystem = Filesystem::new_test();
+    let handle = filesystem.open(root).as_directory().await.unwrap();
+    let before_stats = handle.tree().await.unwrap();
+    let before = handle
+        .tree()
+        .with_data(|_stat, mut reader| reader.digest::<Xxh3Hasher>())
+        .await
+        .unwrap();
+    assert!(before.diff(&before).is_empty());
+
+    std::fs::write(root.join("rewritten.txt"), "rewritten.txt").unwrap();
+    std::fs::write(root.join("changed.txt"), "something else").unwrap();
+    std::fs::remove_file(root.join("dir/removed.txt")).unwrap();
+    std::fs::write(root.join("dir/added.txt"), "added").unwrap();
+
+    let after = handle
+        .tree()
+        .with_data(|_stat, mut reader| reader.digest::<Xxh3Hasher>())
+        .await
+        .unwrap();
+    let diff = before.diff(&after);
+    assert_eq!(diff.added, [PathBuf::from("dir/added.txt")]);
+    assert_eq!(diff.removed, [PathBuf::from("dir/removed.txt")]);
+    // Rewriting a file with the same contents doesn't change its hash.
+    assert_eq!(diff.modified, [PathBuf::from("changed.txt")]);
+
+    // Without hashes we rely on the stat, so a different size is a modification.
+    let after_stats = handle.tree().await.unwrap();
+    let diff = before_stats.diff(&after_stats);
+    assert!(diff.modified.contains(&PathBuf::from("changed.txt")));
+    assert!(!diff.modified.contains(&PathBuf::from("same.txt")));
+
+    // Reversing the diff swaps added and removed.
+    let reversed = after.diff_by(&before, |old, new| old.1 != new.1);
+    assert_eq!(reversed.added, [PathBuf::from("dir/removed.txt")]);
+    assert_eq!(reversed.removed, [PathBuf::from("dir/added.txt")]);
+}
+
+#[tokio::test]
+async fn smoketest_tree_persist() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let root = temp.path().join("root");
+    std::fs::create_dir_all(root.join("a/b")).unwrap();
+    std::fs::create_dir(root.join("empty")).unwrap();
+    std::fs::write(root.join("top.txt"), b"top").unwrap();
+    std::fs::write(root.join("a/b/nested.txt"), b"nested").unwrap();
+    std::os::unix::fs::symlink("top.txt", root.join("link")).unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let handle = filesystem.open(&root).as_directory().await.unwrap();
+    let tree = handle
+        .tree()
+        .with_data(|_stat, mut reader| reader.digest::<Xxh3Hasher>())
+        .symlinks(SymlinkPolicy::RecordTarget)
+        .await
+        .unwrap();
+
+    let snapshot = temp.path().join("tree.snapshot");
+    tree.save(&filesystem, snapshot.clone()).await.unwrap();
+    // Saving again replaces the previous snapshot.
+    tree.save(&filesystem, snapshot.clone()).await.unwrap();
+    let loaded: MetadataTree<(FileStat, Xxh64Hash)> =
+        MetadataTree::load(&filesystem, snapshot).await.unwrap();
+
+    assert_eq!(loaded.root_path(), tree.root_path());
+    assert_eq!(loaded.symlinks(), tree.symlinks());
+    assert!(tree.diff(&loaded).is_empty());
+    assert!(loaded.diff(&tree).is_empty());
+    let (stat, hash) = loaded.get("a/b/nested.txt").unwrap();
+    assert_eq!(stat.size, 6);
+    assert_eq!(*hash, Xxh64Hash::new(xxhash_rust::xxh3::xxh3_64(b"nested")));
+    // Empty directories are preserved.
+    assert_eq!(
+        loaded.into_parts().0.node_count(),
+        tree.into_parts().0.node_count()
+    );
+
+    // Plain stats round trip too.
+    let stats = handle.tree().await.unwrap();
+    let decoded = MetadataTree::<FileStat>::from_bytes(&stats.to_bytes().unwrap()).unwrap();
+    assert!(stats.diff(&decoded).is_empty());
+
+    // Garbage and other versions are rejected.
+    let result = MetadataTree::<FileStat>::from_bytes(b"not a tree");
+    assert!(matches!(result, Err(crate::Error::InvalidData(_))));
+    let mut bytes = stats.to_bytes().unwrap();
+    bytes[4] += 1;
+    let result = MetadataTree::<FileStat>::from_bytes(&bytes);
+    assert!(matches!(result, Err(crate::Error::InvalidData(_))));
+}
+
+#[tokio::test]
+async fn smoketest_tree_apply_event() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let root = temp.path().join("root");
+    std::fs::create_dir_all(root.join("a")).unwrap();
+    std::fs::write(root.join("a/one.txt"), b"one").unwrap();
+    std::fs::write(root.join("removed.txt"), b"removed").unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let handle = filesystem.open(&root).as_directory().await.unwrap();
+    let hash = |_stat: &FileStat, mut reader: crate::handle::internal::ReadIterator| {
+        reader.digest::<Xxh3Hasher>()
+    };
+    let mut tree = handle.tree().with_data(hash).await.unwrap();
+
+    // Modified file, by absolute path.
+    std::fs::write(root.join("a/one.txt"), b"changed").unwrap();
+    tree.apply_event(
+        handle.tree().with_data(hash),
+        &root.join("a/one.txt"),
+        TreeEventKind::Changed,
+    )
+    .await
+    .unwrap();
+    let (_stat, digest) = tree.get("a/one.txt").unwrap();
+    assert_eq!(
+        *digest,
+        Xxh64Hash::new(xxhash_rust::xxh3::xxh3_64(b"changed"))
+    );
+
+    // New directory with contents, e.g. moved into the tree.
+    std::fs::create_dir_all(root.join("new/deep")).unwrap();
+    std::fs::write(root.join("new/deep/file.txt"), b"deep").unwrap();
+    tree.apply_event(
+        handle.tree().with_data(hash),
+        Path::new("new"),
+        TreeEventKind::Changed,
+    )
+    .await
+    .unwrap();
+
+    // New file whose parents are missing from the tree.
+    std::fs::create_dir_all(root.join("x/y")).unwrap();
+    std::fs::write(root.join("x/y/z.txt"), b"z").unwrap();
+    tree.apply_event(
+        handle.tree().with_data(hash),
+        Path::new("x/y/z.txt"),
+        TreeEventKind::Changed,
+    )
+    .await
+    .unwrap();
+
+    // Removed file, reported as a change.
+    std::fs::remove_file(root.join("removed.txt")).unwrap();
+    tree.apply_event(
+        handle.tree().with_data(hash),
+        Path::new("removed.txt"),
+        TreeEventKind::Changed,
+    )
+    .await
+    .unwrap();
+
+    // Events outside of the tree are ignored.
+    tree.apply_event(
+        handle.tree().with_data(hash),
+        temp.path(),
+        TreeEventKind::Removed,
+    )
+    .await
+    .unwrap();
+
+    let fresh = handle.tree().with_data(hash).await.unwrap();
+    assert!(fresh.diff(&tree).is_empty(), "{:?}", fresh.diff(&tree));
+    assert!(tree.diff(&fresh).is_empty());
+    assert!(tree.get("new/deep/file.txt").is_some());
+    assert!(tree.get("x/y/z.txt").is_some());
+
+    // Removing a directory removes everything beneath it.
+    tree.apply_event(
+        handle.tree().with_data(hash),
+        Path::new("new"),
+        TreeEventKind::Removed,
+    )
+    .await
+    .unwrap();
+    assert_eq!(tree.files_under("new").count(), 0);
+    assert_eq!(tree.iter_files().count(), 2);
+}
+
+#[tokio::test]
+async fn smoketest_tree_apply_event_symlink_cycle() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(temp.path().join("a/b")).unwrap();
+    std::fs::write(temp.path().join("a/b/file.txt"), b"file").unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let handle = filesystem.open(temp.path()).as_directory().await.unwrap();
+    let mut tree = handle.tree().symlinks(SymlinkPolicy::Follow).await.unwrap();
+
+    // Symlinks to a parent directory and to the root of the tree.
+    handle
+        .symlinkat("..".into(), "a/b/parent_link".to_string())
+        .await
+        .unwrap();
+    handle
+        .symlinkat("../..".into(), "a/b/root_link".to_string())
+        .await
+        .unwrap();
+    for link in ["a/b/parent_link", "a/b/root_link"] {
+        tree.apply_event(
+            handle.tree().symlinks(SymlinkPolicy::Follow),
+            Path::new(link),
+            TreeEventKind::Changed,
+        )
+        .await
+        .unwrap();
+    }
+
+    let fresh = handle.tree().symlinks(SymlinkPolicy::Follow).await.unwrap();
+    assert!(fresh.diff(&tree).is_empty(), "{:?}", fresh.diff(&tree));
+    assert!(tree.diff(&fresh).is_empty());
+    assert_eq!(tree.iter_files().count(), 1);
+    assert!(tree.get("a/b/parent_link/b/file.txt").is_none());
+}
+
+#[tokio::test]
+async fn smoketest_remove() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::write(temp.path().join("a.txt"), b"a").unwrap();
+    std::fs::write(temp.path().join("b.txt"), b"b").unwrap();
+    std::fs::create_dir(temp.path().join("dir")).unwrap();
+
+    let filesystem = Filesystem::new_test();
+    filesystem
+        .remove_file(temp.path().join("a.txt"))
+        .await
+        .unwrap();
+    assert!(!temp.path().join("a.txt").exists());
+
+    let handle = filesystem.open(temp.path()).as_directory().await.unwrap();
+    handle.removeat("b.txt".to_string()).await.unwrap();
+    assert!(!temp.path().join("b.txt").exists());
+
+    let result = handle.removeat("b.txt".to_string()).await;
+    assert!(matches!(result, Err(crate::Error::NotFound)));
+    // Directories don't get removed.
+    assert!(handle.removeat("dir".to_string()).await.is_err());
+    assert!(temp.path().join("dir").exists());
+}
+
+#[tokio::test]
+async fn smoketest_copy() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let content = b"i am some data that will get copied".repeat(1024);
+    std::fs::write(temp.path().join("a.txt"), &content).unwrap();
+
+    let filesystem = Filesystem::new_test();
+    filesystem
+        .copy(temp.path().join("a.txt"), temp.path().join("b.txt"))
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(temp.path().join("b.txt")).unwrap(), content);
+
+    // The destination must not already exist.
+    let result = filesystem
+        .copy(temp.path().join("a.txt"), temp.path().join("b.txt"))
+        .await;
+    assert!(matches!(result, Err(crate::Error::AlreadyExists)));
+}
+
+#[tokio::test]
+async fn smoketest_hardlink() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(temp.path().join("store")).unwrap();
+    std::fs::create_dir(temp.path().join("sandbox")).unwrap();
+    std::fs::write(temp.path().join("store/abc"), b"content").unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let store = filesystem
+        .open(temp.path().join("store"))
+        .as_directory()
+        .await
+        .unwrap();
+    let sandbox = filesystem
+        .open(temp.path().join("sandbox"))
+        .as_directory()
+        .await
+        .unwrap();
+    store
+        .hardlinkat("abc".to_string(), &sandbox, "out.txt".to_string())
+        .await
+        .unwrap();
+
+    let original = store.fstatat("abc".to_string()).await.unwrap();
+    let link = sandbox.fstatat("out.txt".to_string()).await.unwrap();
+    assert_eq!(original.inode, link.inode);
+
+    let result = store
+        .hardlinkat("abc".to_string(), &sandbox, "out.txt".to_string())
+        .await;
+    assert!(matches!(result, Err(crate::Error::AlreadyExists)));
+}
+
+#[tokio::test]
+async fn smoketest_truncate() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("test-truncate.txt");
+
+    let filesystem = Filesystem::new_test();
+    let (mut handle, _stat) = filesystem
+        .open(&path)
+        .as_file()
+        .with_create()
+        .await
+        .unwrap();
+    handle.write(b"hello world".to_vec(), 0).await.unwrap();
+
+    handle.truncate(5).await.unwrap();
+    assert_eq!(handle.stat().await.unwrap().size, 5);
+    assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+
+    handle.truncate(0).await.unwrap();
+    handle.write(b"retry".to_vec(), 0).await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"retry");
+}
+
+#[tokio::test]
+async fn smoketest_writer() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("test-writer.txt");
+
+    let filesystem = Filesystem::new_test();
+    let (mut handle, _stat) = filesystem
+        .open(&path)
+        .as_file()
+        .with_create()
+        .await
+        .unwrap();
+
+    let mut expected = String::new();
+    let mut writer = handle.writer();
+    for i in 0..10_000 {
+        let line = format!("line {i}\n");
+        writer.write(line.as_bytes()).await.unwrap();
+        expected.push_str(&line);
+    }
+    // Larger than the buffer.
+    let large = "x".repeat(1024 * 1024);
+    writer.write(large.as_bytes()).await.unwrap();
+    expected.push_str(&large);
+    writer.write(b"end").await.unwrap();
+    expected.push_str("end");
+    assert_eq!(writer.len(), expected.len());
+    writer.flush().await.unwrap();
+    drop(writer);
+
+    assert_eq!(handle.read_to_string().await.unwrap(), expected);
+    assert_eq!(handle.read_to_vec().await.unwrap(), expected.as_bytes());
+
+    // Invalid UTF-8.
+    handle.write(vec![0xff, 0xfe], 0).await.unwrap();
+    assert!(handle.read_to_string().await.is_err());
+}
+
+#[tokio::test]
+async fn smoketest_write_vectored() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("test-write-vectored.txt");
+
+    let filesystem = Filesystem::new_test();
+    let (mut handle, _stat) = filesystem
+        .open(&path)
+        .as_file()
+        .with_create()
+        .await
+        .unwrap();
+
+    // More buffers than fit in a single syscall.
+    let bufs: Vec<_> = (0..3000).map(|i| format!("{i},").into_bytes()).collect();
+    let expected = bufs.concat();
+    handle.write_vectored(bufs, 0).await.unwrap();
+    handle
+        .write_vectored(
+            vec![b"a".to_vec(), Vec::new(), b"b".to_vec()],
+            expected.len(),
+        )
+        .await
+        .unwrap();
+
+    let contents = handle.read_to_vec().await.unwrap();
+    assert_eq!(&contents[..expected.len()], &expected[..]);
+    assert_eq!(&contents[expected.len()..], b"ab");
+}
+
+#[tokio::test]
+async fn smoketest_digest() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("test-digest.txt");
+
+    let filesystem = Filesystem::new_test();
+    let (mut handle, _stat) = filesystem
+        .open(&path)
+        .as_file()
+        .with_create()
+        .await
+        .unwrap();
+
+    // Spans multiple blocks.
+    let contents: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    handle.write(contents.clone(), 0).await.unwrap();
+
+    let digest = handle.digest::<Xxh3Hasher>().await.unwrap();
+    assert_eq!(
+        digest,
+        Xxh64Hash::new(xxhash_rust::xxh3::xxh3_64(&contents))
+    );
+}
+
+#[tokio::test]
+async fn smoketest_tempfile() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let filesystem = Filesystem::new_test();
+
+    let mut tempfile = filesystem.tempfile_in(temp.path()).await.unwrap();
+    tempfile.write(b"hello world".to_vec(), 0).await.unwrap();
+    // Nothing is visible until we persist, unless we had to fallback to a hidden file.
+    assert!(visible_files(temp.path()).is_empty());
+
+    let file = tempfile.persist("a.txt".to_string()).await.unwrap();
+    assert_eq!(file.read_to_string().await.unwrap(), "hello world");
+    assert_eq!(
+        std::fs::read(temp.path().join("a.txt")).unwrap(),
+        b"hello world"
+    );
+
+    // Persisting never replaces an existing file.
+    let tempfile = filesystem.tempfile_in(temp.path()).await.unwrap();
+    let result = tempfile.persist("a.txt".to_string()).await;
+    assert!(matches!(result, Err(crate::Error::AlreadyExists)));
+
+    // Unless we explicitly replace it.
+    let mut tempfile = filesystem.tempfile_in(temp.path()).await.unwrap();
+    tempfile.write(b"goodbye".to_vec(), 0).await.unwrap();
+    let replaced = tempfile
+        .persist_replacing("a.txt".to_string())
+        .await
+        .unwrap();
+    assert_eq!(replaced.read_to_string().await.unwrap(), "goodbye");
+    assert_eq!(
+        std::fs::read(temp.path().join("a.txt")).unwrap(),
+        b"goodbye"
+    );
+    // The previously persisted file still refers to the old contents.
+    assert_eq!(file.read_to_string().await.unwrap(), "hello world");
+
+    drop(file);
+    drop(replaced);
+    assert_eq!(visible_files(temp.path()), ["a.txt"]);
+}
+
+/// Returns the names of files in `dir`, excluding any hidden temporary files.
+fn visible_files(dir: &std::path::Path) -> Vec<String> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| !name.starts_with(".pb-tmp-"))
+        .collect()
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn smoketest_operation_timeout() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let fifo = temp.path().join("fifo");
+    let status = std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let filesystem = Filesystem::new_test().with_operation_timeout(Duration::from_millis(50));
+
+    // Opening a FIFO blocks until there is a writer, like a hung network filesystem.
+    let result = filesystem.open(&fifo).as_file().await;
+    assert!(matches!(result, Err(crate::Error::TimedOut)));
+
+    // Queued behind the blocked open, this never gets to run.
+    let result = filesystem.stat(temp.path().to_path_buf()).await;
+    assert!(matches!(result, Err(crate::Error::TimedOut)));
+
+    // Unblock the open, after which operations succeed again.
+    std::fs::OpenOptions::new().write(true).open(&fifo).unwrap();
+    let stat = filesystem.stat(temp.path().to_path_buf()).await.unwrap();
+    assert_eq!(stat.kind, crate::FileType::Directory);
+}
+
+#[tokio::test]
+async fn smoketest_allocate() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("test-allocate.txt");
+
+    let filesystem = Filesystem::new_test();
+    let (mut handle, _stat) = filesystem
+        .open(&path)
+        .as_file()
+        .with_create()
+        .await
+        .unwrap();
+    handle.allocate(1024 * 1024).await.unwrap();
+    assert_eq!(handle.stat().await.unwrap().size, 1024 * 1024);
+
+    // Allocating less than the current size doesn't shrink the file.
+    handle.write(b"hello".to_vec(), 0).await.unwrap();
+    handle.allocate(5).await.unwrap();
+    assert_eq!(handle.stat().await.unwrap().size, 1024 * 1024);
+    assert_eq!(&std::fs::read(&path).unwrap()[..5], b"hello");
+}
+
+#[tokio::test]
+async fn smoketest_mode_and_owner() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("test-mode");
+
+    let filesystem = Filesystem::new_test();
+    let (mut handle, stat) = filesystem.open(path).as_file().with_create().await.unwrap();
+
+    handle.set_mode(0o755).await.unwrap();
+    assert_eq!(handle.stat().await.unwrap().mode & 0o777, 0o755);
+    handle.set_mode(0o600).await.unwrap();
+    assert_eq!(handle.stat().await.unwrap().mode & 0o777, 0o600);
+
+    // Setting the owner to the current owner is always allowed.
+    handle.set_owner(stat.user, stat.group).await.unwrap();
+    let new_stat = handle.stat().await.unwrap();
+    assert_eq!((new_stat.user, new_stat.group), (stat.user, stat.group));
+}
+
+#[tokio::test]
+async fn smoketest_list_batched() {
+    let temp = tempfile::TempDir::new().unwrap();
+    for i in 0..2000 {
+        std::fs::write(temp.path().join(format!("file-{i}")), b"").unwrap();
+    }
+
+    let filesystem = Filesystem::new_test();
+    let handle = filesystem.open(temp.path()).as_directory().await.unwrap();
+
+    let mut stream = handle.list_batched().await.unwrap();
+    let mut batches = 0;
+    let mut names = Vec::new();
+    while let Some(batch) = stream.next_batch().await.unwrap() {
+        batches += 1;
+        names.extend(batch.into_iter().map(|entry| entry.name));
+    }
+    assert!(stream.next_batch().await.unwrap().is_none());
+    assert!(batches > 1);
+
+    let mut listed: Vec<_> = handle
+        .list()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    names.sort();
+    listed.sort();
+    assert_eq!(names.len(), 2000);
+    assert_eq!(names, listed);
+
+    // Closing a stream part way through releases its permit.
+    let permits = filesystem.available_permits();
+    let mut stream = handle.list_batched().await.unwrap();
+    stream.next_batch().await.unwrap();
+    assert_eq!(filesystem.available_permits(), permits - 1);
+    stream.close().await.unwrap();
+    assert_eq!(filesystem.available_permits(), permits);
+}
+
+#[tokio::test]
+async fn smoketest_map_readonly() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let content = b"i am some data that will get mapped".repeat(1024);
+    std::fs::write(temp.path().join("a.txt"), &content).unwrap();
+    std::fs::write(temp.path().join("empty.txt"), b"").unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let (handle, _stat) = filesystem
+        .open(temp.path().join("a.txt"))
+        .as_file()
+        .await
+        .unwrap();
+    // SAFETY: Nothing modifies the file while it's mapped.
+    let mapping = unsafe { handle.map_readonly() }.await.unwrap();
+    // The mapping outlives the handle.
+    handle.close().await.unwrap();
+    assert_eq!(&mapping[..], &content[..]);
+
+    let (handle, _stat) = filesystem
+        .open(temp.path().join("empty.txt"))
+        .as_file()
+        .await
+        .unwrap();
+    // SAFETY: Nothing modifies the file while it's mapped.
+    let mapping = unsafe { handle.map_readonly() }.await.unwrap();
+    assert!(mapping.is_empty());
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+#[tokio::test]
+async fn smoketest_io_uring() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(temp.path().join("nested")).unwrap();
+    for i in 0..100 {
+        std::fs::write(temp.path().join(format!("{i}.txt")), b"top").unwrap();
+        std::fs::write(temp.path().join(format!("nested/{i}.txt")), b"nested").unwrap();
+    }
+
+    let filesystem = match Filesystem::new_test().with_io_uring(64) {
+        Ok(filesystem) => filesystem,
+        Err(err) => {
+            println!("io_uring not available, skipping: {err}");
+            return;
+        }
+    };
+    let handle = filesystem.open(temp.path()).as_directory().await.unwrap();
+
+    let tree = handle.tree().await.unwrap();
+    assert_eq!(tree.into_parts().0.len(), 200);
+
+    let tree = handle
+        .tree()
+        .with_data(|_stat, mut reader| {
+            let mut len = 0;
+            while let Some(bytes) = reader.next() {
+                len += bytes?.len();
+            }
+            Ok(len)
+        })
+        .await
+        .unwrap();
+    let (trie, _strings) = tree.into_parts();
+    let total: usize = trie.iter().map(|(_path, (_stat, len))| len).sum();
+    assert_eq!(total, 100 * "top".len() + 100 * "nested".len());
+
+    let result = filesystem.stat(temp.path().join("missing")).await;
+    assert!(matches!(result, Err(crate::Error::NotFound)));
+}
+
+#[tokio::test]
+async fn smoketest_metrics() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("metrics.txt");
+
+    let filesystem = Filesystem::new_test();
+    let before = filesystem.metrics();
+    assert_eq!(before.opens, 0);
+    assert_eq!(before.queue_wait.count, 0);
+    assert_eq!(before.queue_wait.quantile(0.5), None);
+
+    let (mut handle, _stat) = filesystem.open(path).as_file().with_create().await.unwrap();
+    handle.write(b"hello metrics".to_vec(), 0).await.unwrap();
+    let data = handle.read_to_vec().await.unwrap();
+    assert_eq!(data, b"hello metrics");
+
+    let after = filesystem.metrics();
+    assert_eq!(after.opens, 1);
+    assert_eq!(after.writes, 1);
+    assert_eq!(after.bytes_written, 13);
+    assert!(after.reads >= 1);
+    assert_eq!(after.bytes_read, 13);
+    assert_eq!(after.permit_wait.count, 1);
+    // Open, write, and read all ran on the worker pool.
+    assert_eq!(after.queue_wait.count, 3);
+    assert_eq!(after.operation_latency.count, 3);
+    assert!(after.operation_latency.mean().is_some());
+    assert!(
+        after.operation_latency.quantile(0.99).unwrap() >= after.operation_latency.mean().unwrap()
+    );
+
+    // Clones share metrics.
+    assert_eq!(filesystem.clone().metrics(), after);
+}
+
+#[tokio::test]
+async fn smoketest_tracing_spans() {
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let output = Output::default();
+    let writer = output.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let temp = tempfile::TempDir::new().unwrap();
+    let filesystem = Filesystem::new_test();
+    let (mut handle, _stat) = filesystem
+        .open(temp.path().join("traced.txt"))
+        .as_file()
+        .with_create()
+        .diagnostics("my-rule")
+        .await
+        .unwrap();
+    handle.write(b"traced".to_vec(), 0).await.unwrap();
+    handle.close().await.unwrap();
+
+    let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    let closed: Vec<_> = output
+        .lines()
+        .filter(|line| line.contains("close"))
+        .collect();
+    for op in ["open", "write", "close"] {
+        let line = closed
+            .iter()
+            .find(|line| line.contains(&format!(" {op}{{")))
+            .unwrap_or_else(|| panic!("no span for {op} in:\n{output}"));
+        assert!(line.contains("diagnostics=\"my-rule\""), "{line}");
+        assert!(line.contains("duration_us="), "{line}");
+    }
+}
+
+#[tokio::test]
+async fn smoketest_retry_policy() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    let policy = RetryPolicy {
+        max_retries: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(2),
+    };
+    for retry in 0..10 {
+        assert!(policy.backoff(retry) <= Duration::from_millis(2));
+    }
+    assert_eq!(RetryPolicy::NEVER.backoff(3), Duration::ZERO);
+
+    let filesystem = Filesystem::new_test().with_retry_policy(policy);
+
+    // Transient errors get retried until the operation succeeds.
+    let attempts = AtomicU32::new(0);
+    let result = filesystem
+        .worker
+        .retry(|| async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(crate::Error::Interrupted),
+                1 => Err(crate::Error::StaleHandle),
+                _ => Ok(42),
+            }
+        })
+        .await;
+    assert_eq!(result.unwrap(), 42);
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+    // But only up to the limit.
+    let attempts = AtomicU32::new(0);
+    let result = filesystem
+        .worker
+        .retry(|| async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(crate::Error::WouldBlock)
+        })
+        .await;
+    assert!(matches!(result, Err(crate::Error::WouldBlock)));
+    assert_eq!(attempts.load(Ordering::SeqCst), 4);
+
+    // Other errors are returned immediately.
+    let temp = tempfile::TempDir::new().unwrap();
+    let err = filesystem
+        .stat(temp.path().join("missing"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, crate::Error::NotFound));
+    assert_eq!(filesystem.metrics().operation_latency.count, 1);
+}
+
+#[tokio::test]
+async fn smoketest_stat_cache() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let dir = temp.path().join("dir");
+    let path = dir.join("manifest.toml");
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(&path, b"a").unwrap();
+
+    let filesystem = Filesystem::new_test().with_stat_cache(16);
+    let stat = filesystem.stat(path.clone()).await.unwrap();
+    assert_eq!(stat.size, 1);
+    filesystem.stat(dir.clone()).await.unwrap();
+
+    // Changes aren't observed until the path is invalidated.
+    std::fs::write(&path, b"abc").unwrap();
+    let stat = filesystem.stat(path.clone()).await.unwrap();
+    assert_eq!(stat.size, 1);
+    let metrics = filesystem.metrics();
+    assert_eq!(metrics.stat_cache_hits, 1);
+    assert_eq!(metrics.stat_cache_misses, 2);
+
+    filesystem.invalidate_stat(&path);
+    let stat = filesystem.stat(path.clone()).await.unwrap();
+    assert_eq!(stat.size, 3);
+    // The parent directory got invalidated too.
+    filesystem.stat(dir.clone()).await.unwrap();
+    assert_eq!(filesystem.metrics().stat_cache_misses, 4);
+
+    // Invalidating a directory invalidates everything beneath it.
+    filesystem.invalidate_stat(&dir);
+    std::fs::write(&path, b"abcdef").unwrap();
+    let stat = filesystem.stat(path.clone()).await.unwrap();
+    assert_eq!(stat.size, 6);
+
+    // Removing a file through the filesystem invalidates it.
+    filesystem.remove_file(path.clone()).await.unwrap();
+    let err = filesystem.stat(path).await.unwrap_err();
+    assert!(matches!(err, crate::Error::NotFound));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn smoketest_canonicalize() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let dir = temp.path().join("dir");
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("file.txt"), b"a").unwrap();
+    std::os::unix::fs::symlink(&dir, temp.path().join("link")).unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let expected = std::fs::canonicalize(dir.join("file.txt")).unwrap();
+    let via_link = filesystem
+        .canonicalize(temp.path().join("link/./file.txt"))
+        .await
+        .unwrap();
+    assert_eq!(via_link, expected);
+    let via_parent = filesystem
+        .canonicalize(dir.join("../dir/file.txt"))
+        .await
+        .unwrap();
+    assert_eq!(via_parent, expected);
+
+    let err = filesystem
+        .canonicalize(dir.join("missing"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, crate::Error::NotFound));
+}
+
+#[test]
+fn test_normalize() {
+    let cases = [
+        ("/a/b/../c/./d", "/a/c/d"),
+        ("/a/b/", "/a/b"),
+        ("/../a", "/a"),
+        ("a/../../b", "../b"),
+        ("../a/..", ".."),
+        ("./a/..", "."),
+        ("", "."),
+    ];
+    for (path, expected) in cases {
+        assert_eq!(
+            Filesystem::normalize(&PathBuf::from(path)),
+            PathBuf::from(expected),
+            "{path}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn smoketest_tokio_pool() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("tokio.txt");
+
+    let filesystem = Filesystem::new_with_tokio(tokio::runtime::Handle::current(), 32);
+    let (mut handle, _stat) = filesystem
+        .open(path.clone())
+        .as_file()
+        .with_create()
+        .await
+        .unwrap();
+    handle.write(b"hello tokio".to_vec(), 0).await.unwrap();
+    assert_eq!(handle.read_to_string().await.unwrap(), "hello tokio");
+    drop(handle);
+
+    let tree = filesystem
+        .open(temp.path().to_path_buf())
+        .as_directory()
+        .await
+        .unwrap()
+        .tree()
+        .await
+        .unwrap();
+    assert!(tree.to_string().contains("tokio.txt"));
+
+    // Dropped handles get closed in the background.
+    let start = std::time::Instant::now();
+    while filesystem.available_permits() != 32 {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "handle never closed"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn smoketest_priority() {
+    use std::sync::{Arc, Mutex};
+
+    // One thread closes dropped handles, leaving a single thread for operations.
+    let filesystem = Filesystem::new(2, 32);
+
+    // Occupy the only thread so the following operations get queued.
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    let blocker = filesystem.worker.run(move || {
+        started_tx.send(()).unwrap();
+        release_rx.recv().unwrap();
+        Ok(())
+    });
+    started_rx.recv().unwrap();
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let operations = [
+        (Priority::Background, "background-1"),
+        (Priority::Rule, "rule"),
+        (Priority::Background, "background-2"),
+        (Priority::Interactive, "interactive"),
+    ];
+    let futures: Vec<_> = operations
+        .into_iter()
+        .map(|(priority, name)| {
+            let order = Arc::clone(&order);
+            filesystem.worker.with_priority(priority).run(move || {
+                order.lock().unwrap().push(name);
+                Ok(())
+            })
+        })
+        .collect();
+
+    release_tx.send(()).unwrap();
+    blocker.await.unwrap();
+    for future in futures {
+        future.await.unwrap();
+    }
+    assert_eq!(
+        *order.lock().unwrap(),
+        ["interactive", "rule", "background-1", "background-2"]
+    );
+}
+
+#[tokio::test]
+async fn smoketest_scratch_cleanup() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let scratch_root = temp.path().join("scratch");
+    std::fs::create_dir(&scratch_root).unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let scratch = ScratchDirectory::new(temp.path().to_path_buf(), filesystem.clone())
+        .await
+        .unwrap();
+    assert!(scratch.leaked().is_empty());
+
+    // Leak a recent, tagged, file.
+    let mut file = scratch.file().await.unwrap();
+    file.tag_ruleset("rules_rust").await.unwrap();
+    file.tag_comment("downloading").await.unwrap();
+    file.write(b"partial".to_vec(), 0).await.unwrap();
+    drop(file);
+
+    // Leak an old file and an old directory with nested contents.
+    let month_ago = std::time::SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+    std::fs::write(scratch_root.join("old-file"), b"old").unwrap();
+    std::fs::create_dir_all(scratch_root.join("old-dir/nested")).unwrap();
+    std::fs::write(scratch_root.join("old-dir/nested/file"), b"old").unwrap();
+    for name in ["old-file", "old-dir"] {
+        std::fs::File::open(scratch_root.join(name))
+            .unwrap()
+            .set_modified(month_ago)
+            .unwrap();
+    }
+
+    // Without a max age everything is kept.
+    let options = ScratchOptions {
+        max_leak_age: None,
+        ..Default::default()
+    };
+    let scratch =
+        ScratchDirectory::with_options(temp.path().to_path_buf(), filesystem.clone(), options)
+            .await
+            .unwrap();
+    let leaked = scratch.leaked();
+    assert_eq!(leaked.len(), 3);
+    let old_dir = leaked.iter().find(|entry| entry.name == "old-dir").unwrap();
+    let dir_size = |path: &str| std::fs::metadata(scratch_root.join(path)).unwrap().len();
+    assert_eq!(
+        old_dir.size,
+        dir_size("old-dir") + dir_size("old-dir/nested") + 3
+    );
+
+    let scratch = ScratchDirectory::new(temp.path().to_path_buf(), filesystem)
+        .await
+        .unwrap();
+    let leaked = scratch.leaked();
+    let [leaked] = leaked.as_slice() else {
+        panic!("expected a single leaked entry, {leaked:?}");
+    };
+    assert_eq!(leaked.kind, FileType::File);
+    assert_eq!(leaked.size, 7);
+    assert_eq!(leaked.ruleset.as_deref(), Some("rules_rust"));
+    assert_eq!(leaked.comment.as_deref(), Some("downloading"));
+
+    let remaining: Vec<_> = std::fs::read_dir(&scratch_root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(remaining, vec![leaked.name.clone()]);
+}
+
+#[tokio::test]
+async fn smoketest_scratch_budget() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let scratch_root = temp.path().join("scratch");
+    std::fs::create_dir(&scratch_root).unwrap();
+
+    // Leak two entries, the older of which should get evicted first.
+    let now = std::time::SystemTime::now();
+    for (name, days) in [("newer", 1), ("older", 2)] {
+        let path = scratch_root.join(name);
+        std::fs::write(&path, b"leak").unwrap();
+        std::fs::File::open(&path)
+            .unwrap()
+            .set_modified(now - Duration::from_secs(days * 24 * 60 * 60))
+            .unwrap();
+    }
+
+    let filesystem = Filesystem::new_test();
+    let options = ScratchOptions {
+        max_leak_age: None,
+        max_bytes: Some(10),
+    };
+    let scratch =
+        ScratchDirectory::with_options(temp.path().to_path_buf(), filesystem.clone(), options)
+            .await
+            .unwrap();
+    assert_eq!(scratch.used_bytes(), 8);
+
+    // Making room evicts the oldest leaked entry.
+    let mut file = scratch.file().await.unwrap();
+    file.write(b"data".to_vec(), 0).await.unwrap();
+    assert_eq!(scratch.used_bytes(), 8);
+    assert!(!scratch_root.join("older").exists());
+    let leaked: Vec<_> = scratch.leaked().into_iter().map(|e| e.name).collect();
+    assert_eq!(leaked, ["newer"]);
+
+    // Overwriting existing data doesn't use any more space.
+    file.write(b"DA".to_vec(), 0).await.unwrap();
+    file.write(b"ta".to_vec(), 4).await.unwrap();
+    assert_eq!(scratch.used_bytes(), 10);
+
+    // Then the last leaked entry gets evicted, and once there's nothing left we fail.
+    file.write(b"!".to_vec(), 6).await.unwrap();
+    assert!(!scratch_root.join("newer").exists());
+    assert_eq!(scratch.used_bytes(), 7);
+    let err = file.write(vec![0; 4], 7).await.unwrap_err();
+    assert!(matches!(err, crate::Error::NoSpace), "{err:?}");
+    assert_eq!(scratch.used_bytes(), 7);
+
+    // Persisting a file moves it out of the scratch space.
+    let dest = filesystem
+        .open(temp.path().to_path_buf())
+        .as_directory()
+        .await
+        .unwrap();
+    let file = file.persistat(&dest, "done".to_string()).await.unwrap();
+    file.close().await.unwrap();
+    assert_eq!(scratch.used_bytes(), 0);
+    assert_eq!(std::fs::read(temp.path().join("done")).unwrap(), b"DAtata!");
+}
+
+#[tokio::test]
+async fn smoketest_scratch_release() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let scratch_root = temp.path().join("scratch");
+    std::fs::create_dir(&scratch_root).unwrap();
+
+    let filesystem = Filesystem::new_test();
+    let scratch = ScratchDirectory::new(temp.path().to_path_buf(), filesystem)
+        .await
+        .unwrap();
+
+    // Growing or shrinking a file adjusts the budget.
+    let mut file = scratch.file().await.unwrap();
+    file.write_vectored(vec![b"ab".to_vec(), b"cd".to_vec()], 2)
+        .await
+        .unwrap();
+    assert_eq!(scratch.used_bytes(), 6);
+    file.truncate(1).await.unwrap();
+    assert_eq!(scratch.used_bytes(), 1);
+    file.allocate(3).await.unwrap();
+    assert_eq!(scratch.used_bytes(), 3);
+
+    // Dropping a file leaks it.
+    drop(file);
+    let leaked = scratch.leaked();
+    let [file] = leaked.as_slice() else {
+        panic!("expected a single leaked entry, {leaked:?}");
+    };
+    assert_eq!(file.kind, FileType::File);
+    assert_eq!(file.size, 3);
+
+    // Dropping a directory counts everything that was written into it.
+    let directory = scratch.directory().await.unwrap();
+    let (mut nested, _stat) = directory
+        .openat("nested".to_string())
+        .as_file()
+        .with_create()
+        .await
+        .unwrap();
+    nested.write(b"hello".to_vec(), 0).await.unwrap();
+    nested.close().await.unwrap();
+    drop(directory);
+
+    // The directory is sized in the background.
+    let directory = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let leaked = scratch.leaked();
+            if let Some(entry) = leaked.into_iter().find(|e| e.kind == FileType::Directory) {
+                break entry;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let dir_size = std::fs::metadata(scratch_root.join(&directory.name))
+        .unwrap()
+        .len();
+    assert_eq!(directory.size, dir_size + 5);
+    assert_eq!(scratch.used_bytes(), 3 + dir_size + 5);
+
+    // Taking the inner handle stops tracking the file, since it's still in use.
+    let mut file = scratch.file().await.unwrap();
+    file.write(b"read".to_vec(), 0).await.unwrap();
+    assert_eq!(scratch.used_bytes(), 3 + dir_size + 5 + 4);
+    let file = file.into_inner();
+    assert_eq!(scratch.used_bytes(), 3 + dir_size + 5);
+    assert_eq!(scratch.leaked().len(), 2);
+    file.close().await.unwrap();
+}
@@ -1,23 +1,26 @@
 use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
 use std::future::{Future, IntoFuture};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
-use futures::future::{LocalBoxFuture, TryFutureExt};
-use futures::FutureExt;
-use pb_trie::{TrieMap, TrieNode};
+use futures::{FutureExt, StreamExt, TryStreamExt};
+use pb_trie::{TrieChildren, TrieMap, TrieNode};
 use pb_types::InternedPath;
-use tokio::sync::Semaphore;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
+use crate::filesystem::{Filesystem, FilesystemWorker, Priority};
 use crate::handle::internal::ReadIterator;
 use crate::handle::{DirectoryHandle, DirectoryKind, FileKind, Handle};
+use crate::ignore::{IgnoreRules, IGNORE_FILES};
 use crate::platform::{FilesystemPlatform, OpenOptions, Platform, PlatformPath, PlatformPathType};
-use crate::{FileStat, FileType};
+use crate::{DirectoryEntry, FileStat, FileType};
 
 /// Tree description of an object in the filesystem.
 #[derive(Debug)]
@@ -28,6 +31,10 @@ pub struct MetadataTree<T: Clone> {
     trie: pb_trie::TrieMap<InternedPath, (), T>,
     /// The ignore set this tree was created with.
     ignore: Option<globset::GlobSet>,
+    /// Targets of symlinks in the tree, relative to `root_path`.
+    ///
+    /// Only populated when walking with [`SymlinkPolicy::RecordTarget`].
+    symlinks: BTreeMap<PathBuf, PathBuf>,
     /// Interned strings.
     strings: lasso::Rodeo,
 }
@@ -40,6 +47,555 @@ impl<T: Clone> MetadataTree<T> {
         };
         globset.is_match(path.as_ref())
     }
+
+    /// Returns the symlinks in this tree, relative to the root, and the path they point to.
+    pub fn symlinks(&self) -> &BTreeMap<PathBuf, PathBuf> {
+        &self.symlinks
+    }
+
+    /// Returns the path this tree is rooted at.
+    pub fn root_path(&self) -> &Path {
+        &self.root_path
+    }
+
+    /// Returns the metadata for the file at `path`, relative to the root.
+    ///
+    /// Returns `None` if `path` doesn't exist in the tree or is a directory.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&T> {
+        let path = self.intern(path.as_ref())?;
+        self.trie.get_leaf(path)
+    }
+
+    /// Returns an iterator over every file in the tree, with its path relative to the root.
+    ///
+    /// The order depends on the order the files were discovered in, so it isn't stable between
+    /// walks.
+    pub fn iter_files(&self) -> impl Iterator<Item = (PathBuf, &T)> + '_ {
+        self.trie
+            .iter()
+            .map(|(components, data)| (self.resolve(&components), data))
+    }
+
+    /// Returns an iterator over every file at or beneath `prefix`, relative to the root, in
+    /// the same order as [`MetadataTree::iter_files`].
+    ///
+    /// If nothing exists at `prefix` the iterator is empty.
+    pub fn files_under<P: AsRef<Path>>(
+        &self,
+        prefix: P,
+    ) -> impl Iterator<Item = (PathBuf, &T)> + '_ {
+        self.intern(prefix.as_ref())
+            .map(|prefix| self.trie.iter_prefix(prefix))
+            .into_iter()
+            .flatten()
+            .map(|(components, data)| (self.resolve(&components), data))
+    }
+
+    /// Returns the files that were added, removed, or modified in `newer` compared to this
+    /// tree, e.g. `previous.diff(&current)`.
+    ///
+    /// See [`DiffMetadata`] for how modifications are detected.
+    pub fn diff(&self, newer: &MetadataTree<T>) -> TreeDiff
+    where
+        T: DiffMetadata,
+    {
+        self.diff_by(newer, T::changed)
+    }
+
+    /// Like [`MetadataTree::diff`], but `changed` decides if a file that exists in both trees
+    /// was modified.
+    pub fn diff_by<F>(&self, newer: &MetadataTree<T>, mut changed: F) -> TreeDiff
+    where
+        F: FnMut(&T, &T) -> bool,
+    {
+        let mut diff = TreeDiff::default();
+        for (path, old) in self.iter_files() {
+            match newer.get(&path) {
+                None => diff.removed.push(path),
+                Some(new) if changed(old, new) => diff.modified.push(path),
+                Some(_) => (),
+            }
+        }
+        diff.added = newer
+            .iter_files()
+            .filter(|(path, _)| self.get(path).is_none())
+            .map(|(path, _)| path)
+            .collect();
+
+        diff.added.sort();
+        diff.removed.sort();
+        diff.modified.sort();
+        diff
+    }
+
+    /// Convert a path relative to the root into the key for our trie, `None` if any of its
+    /// components have never been seen so it can't be in the tree.
+    fn intern(&self, path: &Path) -> Option<InternedPath> {
+        path.components()
+            .filter(|component| !matches!(component, Component::CurDir))
+            .map(|component| match component {
+                Component::Normal(name) => self.strings.get(name.to_str()?),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Convert the components of a key in our trie back into a path relative to the root.
+    fn resolve(&self, components: &[lasso::Spur]) -> PathBuf {
+        components
+            .iter()
+            .map(|component| self.strings.resolve(component))
+            .collect()
+    }
+
+    /// Consume the [`MetadataTree`], returning the underlying trie and the interner used for
+    /// its path components.
+    pub fn into_parts(self) -> (TrieMap<InternedPath, (), T>, lasso::Rodeo) {
+        (self.trie, self.strings)
+    }
+}
+
+/// Kind of change to a path, see [`MetadataTree::apply_event`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TreeEventKind {
+    /// The path was created or modified, or we don't know what happened to it.
+    Changed,
+    /// The path was removed.
+    Removed,
+}
+
+impl<S: TreeFileMetadata> MetadataTree<S> {
+    /// Update the tree after a change to `path`, e.g. reported by a file watcher.
+    ///
+    /// `builder` should be configured the same as the one that created this tree, it's used
+    /// to re-stat and re-hash a changed file, or walk a new directory, creating any
+    /// intermediate directories. `path` is either absolute or relative to the root, changes
+    /// outside of the tree are ignored.
+    ///
+    /// Rules from `.gitignore` and `.pbignore` files are only applied within a new directory,
+    /// not from its parents.
+    pub async fn apply_event(
+        &mut self,
+        builder: TreeBuilder<'_, S::Value, S>,
+        path: &Path,
+        kind: TreeEventKind,
+    ) -> Result<(), crate::Error> {
+        let relative = match path.strip_prefix(&self.root_path) {
+            Ok(relative) => Filesystem::normalize(relative),
+            Err(_) if path.is_relative() => Filesystem::normalize(path),
+            Err(_) => {
+                tracing::debug!(?path, "ignoring event outside of the tree");
+                return Ok(());
+            }
+        };
+        match relative.components().next() {
+            // Changes to the root itself don't affect anything we track.
+            Some(Component::CurDir) => return Ok(()),
+            Some(Component::ParentDir) => {
+                tracing::debug!(?path, "ignoring event outside of the tree");
+                return Ok(());
+            }
+            _ => (),
+        }
+
+        let depth = relative.components().count();
+        let ignored = builder
+            .ignore
+            .as_ref()
+            .is_some_and(|ignore| ignore.is_match(self.root_path.join(&relative)));
+        let too_deep = builder.max_depth.is_some_and(|max| depth > max);
+        if kind == TreeEventKind::Removed || ignored || too_deep {
+            return self.remove(&relative);
+        }
+
+        let root = builder.root_directory;
+        let name = relative
+            .to_str()
+            .ok_or_else(|| crate::Error::InvalidData("path is not UTF-8".into()))?
+            .to_string();
+        let stat = match root.fstatat(name.clone()).await {
+            Ok(stat) => stat,
+            Err(crate::Error::NotFound) => return self.remove(&relative),
+            Err(err) => return Err(err),
+        };
+        let stat = match (stat.kind, builder.symlinks) {
+            (FileType::Symlink, SymlinkPolicy::Skip) => return self.remove(&relative),
+            (FileType::Symlink, SymlinkPolicy::RecordTarget) => {
+                let target = root.readlinkat(name).await?;
+                self.remove(&relative)?;
+                self.symlinks.insert(relative, target);
+                return Ok(());
+            }
+            (FileType::Symlink, SymlinkPolicy::Follow) => {
+                let target = PlatformPathType::try_new(self.root_path.join(&relative))?;
+                let stat = match root.worker.stat(target).await {
+                    Ok(stat) => stat,
+                    Err(crate::Error::NotFound) => return self.remove(&relative),
+                    Err(err) => return Err(err),
+                };
+                if stat.kind == FileType::Directory
+                    && self
+                        .ancestor_inodes(&builder, &relative)
+                        .await?
+                        .contains(&stat.inode)
+                {
+                    tracing::warn!(?path, "skipping symlink cycle");
+                    return self.remove(&relative);
+                }
+                stat
+            }
+            (FileType::File | FileType::Directory, _) => stat,
+        };
+        let other_filesystem = match builder.same_filesystem {
+            true => root.stat().await?.device != stat.device,
+            false => false,
+        };
+
+        match stat.kind {
+            FileType::Directory => {
+                let handle = root
+                    .openat(name)
+                    .as_directory()
+                    .diagnostics("tree-event")
+                    .await?;
+                let subtree = if other_filesystem {
+                    // Mount points are included, but empty.
+                    None
+                } else {
+                    let max_depth = builder.max_depth.map(|max| max - depth);
+                    let mut builder = builder.rooted_at(&handle);
+                    builder.max_depth = max_depth;
+                    Some(builder.await?)
+                };
+                handle.close().await?;
+                self.graft(&relative, subtree)
+            }
+            FileType::File | FileType::Symlink => {
+                if other_filesystem {
+                    return self.remove(&relative);
+                }
+                let data = match builder.file_work.as_ref() {
+                    None => S::from_parts(stat, None),
+                    Some(work) => {
+                        let (handle, stat) = root
+                            .openat(name)
+                            .as_file()
+                            .diagnostics("tree-event")
+                            .await?;
+                        let work = Arc::clone(work);
+                        let value = handle.read_with(move |reader| work(&stat, reader)).await?;
+                        handle.close().await?;
+                        S::from_parts(stat, Some(value))
+                    }
+                };
+                let key = self.intern_mut(&relative)?;
+                self.trie
+                    .insert_leaf_replacing(key, data)
+                    .map_err(trie_error)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the inodes of the root and every directory between it and `relative`, the
+    /// same as the ancestors tracked when walking the tree.
+    async fn ancestor_inodes(
+        &self,
+        builder: &TreeBuilder<'_, S::Value, S>,
+        relative: &Path,
+    ) -> Result<Vec<u64>, crate::Error> {
+        let root = builder.root_directory;
+        let mut inodes = vec![root.stat().await?.inode];
+        for parent in relative.ancestors().skip(1) {
+            if parent.as_os_str().is_empty() {
+                continue;
+            }
+            let parent = PlatformPathType::try_new(self.root_path.join(parent))?;
+            inodes.push(root.worker.stat(parent).await?.inode);
+        }
+        Ok(inodes)
+    }
+
+    /// Remove everything at or beneath `relative` from the tree.
+    fn remove(&mut self, relative: &Path) -> Result<(), crate::Error> {
+        self.symlinks.retain(|path, _| !path.starts_with(relative));
+        if let Some(key) = self.intern(relative) {
+            self.trie.remove_subtree(key).map_err(trie_error)?;
+        }
+        Ok(())
+    }
+
+    /// Replace everything at `relative` with `subtree`, or an empty directory if `None`.
+    fn graft(
+        &mut self,
+        relative: &Path,
+        subtree: Option<MetadataTree<S>>,
+    ) -> Result<(), crate::Error> {
+        self.symlinks.retain(|path, _| !path.starts_with(relative));
+        let children = match subtree {
+            None => TrieChildren::default(),
+            Some(subtree) => {
+                let root = subtree
+                    .trie
+                    .get(InternedPath::new())
+                    .expect("trie always has a root");
+                let children = match root {
+                    TrieNode::Edge { children, .. } => subtree.persist_children(children),
+                    TrieNode::Leaf { .. } => unreachable!("root of a MetadataTree is a directory"),
+                };
+                let symlinks = subtree
+                    .symlinks
+                    .iter()
+                    .map(|(path, target)| (relative.join(path), target.clone()));
+                self.symlinks.extend(symlinks);
+                // The subtree interned its path components separately.
+                Self::restore_children(&mut self.strings, children)
+            }
+        };
+
+        let key = self.intern_mut(relative)?;
+        let node = TrieNode::Edge { children, data: () };
+        self.trie
+            .graft(key, TrieMap::from_node(node))
+            .map_err(trie_error)?;
+        Ok(())
+    }
+
+    /// Like [`MetadataTree::intern`], but interns any components we haven't seen before.
+    fn intern_mut(&mut self, path: &Path) -> Result<InternedPath, crate::Error> {
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => name
+                    .to_str()
+                    .map(|name| self.strings.get_or_intern(name))
+                    .ok_or_else(|| crate::Error::InvalidData("path is not UTF-8".into())),
+                other => {
+                    let msg = format!("unexpected path component {other:?}").into();
+                    Err(crate::Error::InvalidData(msg))
+                }
+            })
+            .collect()
+    }
+}
+
+fn trie_error(err: anyhow::Error) -> crate::Error {
+    crate::Error::Unknown(format!("failed to update tree: {err}"))
+}
+
+/// Files that differ between two [`MetadataTree`]s, relative to their roots, see
+/// [`MetadataTree::diff`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TreeDiff {
+    /// Files that only exist in the newer tree.
+    pub added: Vec<PathBuf>,
+    /// Files that only exist in the older tree.
+    pub removed: Vec<PathBuf>,
+    /// Files that exist in both trees, but changed.
+    pub modified: Vec<PathBuf>,
+}
+
+impl TreeDiff {
+    /// Returns if nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Metadata stored in a [`MetadataTree`] that can tell if a file changed, see
+/// [`MetadataTree::diff`].
+pub trait DiffMetadata {
+    /// Returns if the file described by `self` changed to the one described by `other`.
+    fn changed(&self, other: &Self) -> bool;
+}
+
+impl DiffMetadata for FileStat {
+    /// Compares the size, inode, mode, and modified time, like
+    /// [`pb_types::FileMetadata::stat_changed`].
+    fn changed(&self, other: &Self) -> bool {
+        self.size != other.size
+            || self.inode != other.inode
+            || self.mode != other.mode
+            || !self.mtime.coarse_eq(&other.mtime)
+    }
+}
+
+impl<T: PartialEq> DiffMetadata for (FileStat, T) {
+    /// Compares the data computed by [`TreeBuilder::with_data`], generally a hash, and the
+    /// mode. Other changes to the stat, e.g. touching a file, are ignored.
+    fn changed(&self, other: &Self) -> bool {
+        self.0.mode != other.0.mode || self.1 != other.1
+    }
+}
+
+/// Magic bytes at the start of a persisted [`MetadataTree`].
+const PERSIST_MAGIC: &[u8; 4] = b"PBMT";
+/// Bumped whenever the format of a persisted [`MetadataTree`] changes.
+const PERSIST_VERSION: u8 = 1;
+
+/// Format a [`MetadataTree`] gets persisted in, see [`MetadataTree::to_bytes`].
+#[derive(Serialize, Deserialize)]
+struct PersistedTree<T> {
+    root_path: PathBuf,
+    children: Vec<(String, PersistedNode<T>)>,
+    symlinks: BTreeMap<PathBuf, PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum PersistedNode<T> {
+    Directory(Vec<(String, PersistedNode<T>)>),
+    File(T),
+}
+
+impl<T: Clone> MetadataTree<T> {
+    /// Encode this tree, including any data computed by [`TreeBuilder::with_data`], in a
+    /// compact binary format that can be decoded with [`MetadataTree::from_bytes`].
+    ///
+    /// The ignore set the tree was created with isn't included.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, crate::Error>
+    where
+        T: Serialize,
+    {
+        let root = self
+            .trie
+            .get(InternedPath::new())
+            .expect("trie always has a root");
+        let children = match root {
+            TrieNode::Edge { children, .. } => self.persist_children(children),
+            TrieNode::Leaf { .. } => unreachable!("root of a MetadataTree is a directory"),
+        };
+        let tree = PersistedTree {
+            root_path: self.root_path.clone(),
+            children,
+            symlinks: self.symlinks.clone(),
+        };
+
+        let mut bytes = PERSIST_MAGIC.to_vec();
+        bytes.push(PERSIST_VERSION);
+        let bytes = postcard::to_extend(&tree, bytes).map_err(|err| {
+            let msg = format!("failed to encode tree: {err}").into();
+            crate::Error::InvalidData(msg)
+        })?;
+        Ok(bytes)
+    }
+
+    /// Decode a tree that was encoded with [`MetadataTree::to_bytes`].
+    ///
+    /// Fails with [`crate::Error::InvalidData`] if `bytes` weren't created by the same version
+    /// of the format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, crate::Error>
+    where
+        T: DeserializeOwned,
+    {
+        let payload = bytes
+            .strip_prefix(PERSIST_MAGIC.as_slice())
+            .ok_or_else(|| crate::Error::InvalidData("not a persisted tree".into()))?;
+        let payload = match payload.split_first() {
+            Some((&PERSIST_VERSION, payload)) => payload,
+            Some((version, _)) => {
+                let msg = format!("unsupported persisted tree version: {version}").into();
+                return Err(crate::Error::InvalidData(msg));
+            }
+            None => return Err(crate::Error::InvalidData("truncated persisted tree".into())),
+        };
+        let tree: PersistedTree<T> = postcard::from_bytes(payload).map_err(|err| {
+            let msg = format!("failed to decode tree: {err}").into();
+            crate::Error::InvalidData(msg)
+        })?;
+
+        let mut strings = lasso::Rodeo::new();
+        let children = Self::restore_children(&mut strings, tree.children);
+        Ok(MetadataTree {
+            root_path: tree.root_path,
+            trie: TrieMap::from_node(TrieNode::Edge { children, data: () }),
+            ignore: None,
+            symlinks: tree.symlinks,
+            strings,
+        })
+    }
+
+    /// Write this tree to the file at `path`, replacing any existing file, see
+    /// [`MetadataTree::to_bytes`].
+    ///
+    /// The tree is written to a temporary file which is then renamed over `path`, so anything
+    /// reading `path` sees either the previous tree or this one, never a missing or partially
+    /// written file.
+    pub async fn save(&self, filesystem: &Filesystem, path: PathBuf) -> Result<(), crate::Error>
+    where
+        T: Serialize,
+    {
+        let bytes = self.to_bytes()?;
+        let (Some(directory), Some(filename)) = (path.parent(), path.file_name()) else {
+            let msg = format!("not a file path: {path:?}").into();
+            return Err(crate::Error::InvalidData(msg));
+        };
+        let filename = filename
+            .to_str()
+            .ok_or_else(|| crate::Error::InvalidData("filename is not UTF-8".into()))?
+            .to_string();
+
+        let mut tempfile = filesystem.tempfile_in(directory).await?;
+        tempfile.write(bytes, 0).await?;
+        // Make sure the contents are durable before they replace the previous tree.
+        tempfile.fsync().await?;
+        let handle = tempfile.persist_replacing(filename).await?;
+        handle.close().await?;
+
+        Ok(())
+    }
+
+    /// Read a tree from the file at `path`, that was written with [`MetadataTree::save`].
+    pub async fn load(filesystem: &Filesystem, path: PathBuf) -> Result<Self, crate::Error>
+    where
+        T: DeserializeOwned,
+    {
+        let (handle, _stat) = filesystem
+            .open(path)
+            .as_file()
+            .diagnostics("load tree")
+            .await?;
+        let bytes = handle.read_to_vec().await?;
+        handle.close().await?;
+        Self::from_bytes(&bytes)
+    }
+
+    fn persist_children(
+        &self,
+        children: &TrieChildren<InternedPath, (), T>,
+    ) -> Vec<(String, PersistedNode<T>)> {
+        children
+            .iter()
+            .map(|(name, node)| {
+                let name = self.strings.resolve(name).to_string();
+                let node = match node {
+                    TrieNode::Edge { children, .. } => {
+                        PersistedNode::Directory(self.persist_children(children))
+                    }
+                    TrieNode::Leaf { data } => PersistedNode::File(data.clone()),
+                };
+                (name, node)
+            })
+            .collect()
+    }
+
+    fn restore_children(
+        strings: &mut lasso::Rodeo,
+        children: Vec<(String, PersistedNode<T>)>,
+    ) -> TrieChildren<InternedPath, (), T> {
+        children
+            .into_iter()
+            .map(|(name, node)| {
+                let name = strings.get_or_intern(name);
+                let node = match node {
+                    PersistedNode::Directory(children) => TrieNode::Edge {
+                        children: Self::restore_children(strings, children),
+                        data: (),
+                    },
+                    PersistedNode::File(data) => TrieNode::Leaf { data },
+                };
+                (name, node)
+            })
+            .collect()
+    }
 }
 
 impl<T: Clone> fmt::Display for MetadataTree<T> {
@@ -80,6 +636,38 @@ impl DirectoryHandle {
     }
 }
 
+/// What a [`TreeBuilder`] does when it encounters a symlink.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Leave symlinks out of the tree.
+    #[default]
+    Skip,
+    /// Include whatever the symlink points to, as if it was in place of the symlink.
+    ///
+    /// Symlinks that point to one of their parent directories are skipped.
+    Follow,
+    /// Leave symlinks out of the tree, but record their targets in
+    /// [`MetadataTree::symlinks`].
+    RecordTarget,
+}
+
+/// Progress of a walk, reported to the callback provided to [`TreeBuilder::progress`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TreeProgress {
+    /// Number of directories visited.
+    pub directories: u64,
+    /// Number of files added to the tree.
+    pub files: u64,
+    /// Total size of the files passed to the [`TreeBuilder::with_data`] closure.
+    pub bytes_read: u64,
+}
+
+/// Default for [`TreeBuilder::concurrency`].
+pub const DEFAULT_CONCURRENCY: usize = 256;
+
+/// Callback provided to [`TreeBuilder::progress`].
+type ProgressFn = Arc<dyn Fn(TreeProgress) + Send + Sync + 'static>;
+
 pub struct TreeBuilder<'a, T, S>
 where
     T: Clone,
@@ -99,6 +687,20 @@ where
     >,
     /// Globset of files to ignore.
     ignore: Option<globset::GlobSet>,
+    /// How to handle symlinks.
+    symlinks: SymlinkPolicy,
+    /// Priority of the filesystem operations used to walk the tree.
+    priority: Priority,
+    /// Maximum number of directories to descend into below the root.
+    max_depth: Option<usize>,
+    /// Don't descend into directories on a different filesystem than the root.
+    same_filesystem: bool,
+    /// Apply the rules from `.gitignore` and `.pbignore` files found during the walk.
+    respect_gitignore: bool,
+    /// Called as the walk makes progress.
+    progress: Option<ProgressFn>,
+    /// Maximum number of directories or entries to process at once.
+    concurrency: usize,
 
     _file_stat: std::marker::PhantomData<fn() -> S>,
 }
@@ -109,6 +711,13 @@ impl<'a> TreeBuilder<'a, (), FileStat> {
             root_directory,
             file_work: None,
             ignore: None,
+            symlinks: SymlinkPolicy::default(),
+            priority: Priority::Background,
+            max_depth: None,
+            same_filesystem: false,
+            respect_gitignore: false,
+            progress: None,
+            concurrency: DEFAULT_CONCURRENCY,
             _file_stat: std::marker::PhantomData::default(),
         }
     }
@@ -123,6 +732,13 @@ impl<'a> TreeBuilder<'a, (), FileStat> {
             root_directory: self.root_directory,
             file_work: Some(Arc::new(work)),
             ignore: self.ignore,
+            symlinks: self.symlinks,
+            priority: self.priority,
+            max_depth: self.max_depth,
+            same_filesystem: self.same_filesystem,
+            respect_gitignore: self.respect_gitignore,
+            progress: self.progress,
+            concurrency: self.concurrency,
             _file_stat: std::marker::PhantomData::default(),
         }
     }
@@ -133,10 +749,94 @@ where
     T: Clone,
     S: TreeFileMetadata<Value = T>,
 {
+    /// Returns a builder with the same options, that walks `root_directory` instead.
+    fn rooted_at<'b>(self, root_directory: &'b DirectoryHandle) -> TreeBuilder<'b, T, S> {
+        TreeBuilder {
+            root_directory,
+            file_work: self.file_work,
+            ignore: self.ignore,
+            symlinks: self.symlinks,
+            priority: self.priority,
+            max_depth: self.max_depth,
+            same_filesystem: self.same_filesystem,
+            respect_gitignore: self.respect_gitignore,
+            progress: self.progress,
+            concurrency: self.concurrency,
+            _file_stat: std::marker::PhantomData,
+        }
+    }
+
     pub fn ignore(mut self, glob_set: globset::GlobSet) -> Self {
         self.ignore = Some(glob_set);
         self
     }
+
+    /// Set how symlinks are handled, defaults to [`SymlinkPolicy::Skip`].
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Set the priority of the filesystem operations used to walk the tree, defaults to
+    /// [`Priority::Background`].
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Only include entries at most `depth` directories below the root, e.g. `1` only
+    /// includes the direct children of the root.
+    ///
+    /// Directories at the limit are included in the tree, but are empty.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// If `true`, don't descend into directories that are on a different filesystem than the
+    /// root, e.g. mounted network volumes, defaults to `false`.
+    ///
+    /// Mount points are included in the tree, but are empty.
+    pub fn same_filesystem(mut self, same_filesystem: bool) -> Self {
+        self.same_filesystem = same_filesystem;
+        self
+    }
+
+    /// Skip files ignored by the `.gitignore` and `.pbignore` files found during the walk,
+    /// in addition to the [`TreeBuilder::ignore`] globset.
+    ///
+    /// Rules apply to the directory the file is in and everything beneath it, with rules in
+    /// deeper directories and `.pbignore` files taking precedence. `.git` directories are
+    /// always skipped.
+    pub fn respect_gitignore(mut self) -> Self {
+        self.respect_gitignore = true;
+        self
+    }
+
+    /// Call `callback` with the total progress of the walk every time a directory is visited
+    /// or a file is added to the tree.
+    ///
+    /// The callback runs inline with the walk so it should be cheap, e.g. forwarding to a
+    /// channel or only rendering every so often.
+    pub fn progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(TreeProgress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Set the maximum number of directories or entries processed at once, defaults to
+    /// [`DEFAULT_CONCURRENCY`].
+    ///
+    /// The walk is breadth first, so this also bounds the number of pending futures when
+    /// walking huge directories. Open handles are still limited by the [`Filesystem`].
+    ///
+    /// [`Filesystem`]: crate::filesystem::Filesystem
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
 }
 
 impl<'a, T, S> IntoFuture for TreeBuilder<'a, T, S>
@@ -148,19 +848,23 @@ where
     type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + 'a>>;
 
     fn into_future(self) -> Self::IntoFuture {
-        let handle_dir = |path: PathBuf| {
-            let worker_ = self.root_directory.worker.clone();
+        let worker = self.root_directory.worker.with_priority(self.priority);
+        let progress = self
+            .progress
+            .clone()
+            .map(ProgressTracker::new)
+            .map(Arc::new);
+
+        let dir_worker = worker.clone();
+        let handle_dir = move |path: PathBuf| {
+            let worker_ = dir_worker.clone();
             let drops_tx_ = self.root_directory.drops_tx.clone();
             let permits_ = Arc::clone(&self.root_directory.kind.permits);
 
             async move {
                 let path = PlatformPathType::try_new(path).expect("known valid");
-                let permit = Semaphore::acquire_owned(permits_.clone())
-                    .await
-                    .expect("failed to acquire permit");
-                let handle = worker_
-                    .run(|| FilesystemPlatform::open(path, OpenOptions::DIRECTORY))
-                    .await?;
+                let permit = worker_.acquire_permit(permits_.clone()).await;
+                let handle = worker_.open(path, OpenOptions::DIRECTORY).await?;
                 let handle = Handle {
                     inner: Some(handle),
                     permit: Some(permit),
@@ -175,8 +879,11 @@ where
             }
         };
 
+        let file_worker = worker.clone();
+        let file_progress = progress.clone();
         let handle_file = move |path: PathBuf| {
-            let worker_ = self.root_directory.worker.clone();
+            let worker_ = file_worker.clone();
+            let progress_ = file_progress.clone();
             let drops_tx_ = self.root_directory.drops_tx.clone();
             let permits_ = Arc::clone(&self.root_directory.kind.permits);
             let maybe_work_fn_ = match &self.file_work {
@@ -189,21 +896,13 @@ where
                 let path = PlatformPathType::try_new(path).expect("known valid");
                 let (stat, value) = match maybe_work_fn_.as_ref() {
                     None => {
-                        let stat = worker_.run(|| FilesystemPlatform::stat(path)).await?;
+                        let stat = worker_.stat(path).await?;
                         (stat, None)
                     }
                     Some(work_fn) => {
-                        let permit = Semaphore::acquire_owned(permits_.clone())
-                            .await
-                            .expect("failed to acquire permit");
-                        let (handle, stat) = worker_
-                            .run(|| {
-                                let handle =
-                                    FilesystemPlatform::open(path, OpenOptions::READ_ONLY)?;
-                                let stat = FilesystemPlatform::fstat(handle)?;
-                                Ok((handle, stat))
-                            })
-                            .await?;
+                        let permit = worker_.acquire_permit(permits_.clone()).await;
+                        let (handle, stat) =
+                            worker_.open_and_stat(path, OpenOptions::READ_ONLY).await?;
                         let handle = Handle {
                             inner: Some(handle),
                             permit: Some(permit),
@@ -223,30 +922,82 @@ where
                         (stat, Some(value))
                     }
                 };
+                if let Some(progress) = progress_ {
+                    let bytes_read = if value.is_some() { stat.size } else { 0 };
+                    progress.record_file(bytes_read);
+                }
 
                 let output = S::from_parts(stat, value);
                 Ok::<_, crate::Error>(output)
             }
         };
 
+        let read_worker = worker.clone();
+        let read_file = move |path: PathBuf| {
+            let worker_ = read_worker.clone();
+            let drops_tx_ = self.root_directory.drops_tx.clone();
+            let permits_ = Arc::clone(&self.root_directory.kind.permits);
+
+            async move {
+                let path = PlatformPathType::try_new(path).expect("known valid");
+                let permit = worker_.acquire_permit(permits_).await;
+                let (handle, stat) = worker_.open_and_stat(path, OpenOptions::READ_ONLY).await?;
+                let handle = Handle {
+                    inner: Some(handle),
+                    permit: Some(permit),
+                    worker: worker_.clone(),
+                    drops_tx: drops_tx_,
+                    diagnostics: Some(Cow::Borrowed("tree-ignore")),
+                    kind: FileKind {
+                        optimal_blocksize: stat.optimal_blocksize,
+                    },
+                };
+                let contents = handle.read_to_string().await?;
+                handle.close().await?;
+                Ok::<_, crate::Error>(contents)
+            }
+        };
+
         async move {
-            let strings = Rc::new(RefCell::new(lasso::Rodeo::new()));
             let start_path = self.root_directory.fullpath().await?;
-            let children = walk_directory(
-                start_path.clone(),
-                self.ignore.as_ref(),
-                &handle_dir,
-                &handle_file,
-                strings.clone(),
-            )
-            .await?;
+            let root_stat = self.root_directory.stat().await?;
+            let context = WalkContext {
+                ignore: self.ignore.as_ref(),
+                symlinks: self.symlinks,
+                max_depth: self.max_depth,
+                device: self.same_filesystem.then_some(root_stat.device),
+                respect_gitignore: self.respect_gitignore,
+                concurrency: self.concurrency,
+                open_dir: &handle_dir,
+                process_file: &handle_file,
+                read_file: &read_file,
+                progress: progress.as_deref(),
+                worker: &worker,
+                strings: Rc::new(RefCell::new(lasso::Rodeo::new())),
+                targets: Rc::new(RefCell::new(BTreeMap::new())),
+            };
+            let children = walk_directory(start_path.clone(), root_stat.inode, &context).await?;
+
             // All of the futures have completed by now so this is safe.
-            let strings = strings.take();
+            let strings = context.strings.take();
+            let symlinks = context
+                .targets
+                .take()
+                .into_iter()
+                .map(|(path, target)| {
+                    let path = path
+                        .strip_prefix(&start_path)
+                        .expect("walked from the start path")
+                        .to_path_buf();
+                    (path, target)
+                })
+                .collect();
 
             Ok(MetadataTree {
                 root_path: start_path,
                 trie: TrieMap::from_node(TrieNode::Edge { children, data: () }),
                 ignore: self.ignore,
+                symlinks,
                 strings,
             })
         }
@@ -254,87 +1005,340 @@ where
     }
 }
 
-/// Recursively walk a directory.
-fn walk_directory<'a, D, W, S, F1, F2>(
-    path: PathBuf,
+/// State shared by every directory visited in [`walk_directory`].
+struct WalkContext<'a, D, W, R> {
+    /// Globset of files to ignore.
     ignore: Option<&'a globset::GlobSet>,
+    /// How to handle symlinks.
+    symlinks: SymlinkPolicy,
+    /// Maximum number of directories to descend into below the root.
+    max_depth: Option<usize>,
+    /// Device of the root, if we should stay on the same filesystem.
+    device: Option<u64>,
+    /// Read ignore rules from `.gitignore` and `.pbignore` files.
+    respect_gitignore: bool,
+    /// Maximum number of directories or entries to process at once.
+    concurrency: usize,
+    /// Opens a directory at the provided path.
     open_dir: &'a D,
+    /// Processes the file at the provided path.
     process_file: &'a W,
+    /// Reads the contents of the file at the provided path.
+    read_file: &'a R,
+    /// Where we report progress, if requested.
+    progress: Option<&'a ProgressTracker>,
+    /// Worker for any other filesystem operations.
+    worker: &'a FilesystemWorker,
+    /// Interned path components.
     strings: Rc<RefCell<lasso::Rodeo>>,
-) -> LocalBoxFuture<'a, Result<BTreeMap<lasso::Spur, TrieNode<InternedPath, (), S>>, crate::Error>>
+    /// Absolute paths of symlinks and their targets, for [`SymlinkPolicy::RecordTarget`].
+    targets: Rc<RefCell<BTreeMap<PathBuf, PathBuf>>>,
+}
+
+/// Running totals for [`TreeBuilder::progress`].
+struct ProgressTracker {
+    directories: AtomicU64,
+    files: AtomicU64,
+    bytes_read: AtomicU64,
+    callback: ProgressFn,
+}
+
+impl ProgressTracker {
+    fn new(callback: ProgressFn) -> Self {
+        ProgressTracker {
+            directories: AtomicU64::new(0),
+            files: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            callback,
+        }
+    }
+
+    fn record_directory(&self) {
+        self.directories.fetch_add(1, Ordering::Relaxed);
+        self.report();
+    }
+
+    fn record_file(&self, bytes_read: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes_read, Ordering::Relaxed);
+        self.report();
+    }
+
+    fn report(&self) {
+        (self.callback)(TreeProgress {
+            directories: self.directories.load(Ordering::Relaxed),
+            files: self.files.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+        });
+    }
+}
+
+/// A directory found during the walk that still needs to be listed.
+struct PendingDirectory {
+    path: PathBuf,
+    /// Inodes of the directory and all of its parents, used to detect symlink cycles and
+    /// limit the depth of the walk.
+    ancestors: Vec<u64>,
+    /// Rules from the ignore files in the parents of the directory, if any.
+    ignores: Option<Rc<IgnoreRules>>,
+    /// Index of the [`DirectorySlot`] that the directory's children get added to.
+    slot: usize,
+}
+
+/// Children of a directory, collected while walking.
+struct DirectorySlot<S> {
+    /// Index of the parent's slot and our name within it, `None` for the root.
+    parent: Option<(usize, lasso::Spur)>,
+    children: BTreeMap<lasso::Spur, TrieNode<InternedPath, (), S>>,
+}
+
+/// Result of processing a single directory entry.
+enum EntryResult<S> {
+    File(S),
+    /// A directory, or a symlink to one, that needs to be walked, with its inode.
+    Directory(u64),
+    Skipped,
+}
+
+/// Walk everything beneath `root`, breadth first.
+///
+/// Directories are listed in batches of at most `ctx.concurrency`, and then all of their
+/// entries are processed with at most `ctx.concurrency` operations in flight, so a huge
+/// directory doesn't create a future for every one of its entries up front.
+async fn walk_directory<D, W, R, S, F1, F2, F3>(
+    root: PathBuf,
+    root_inode: u64,
+    ctx: &WalkContext<'_, D, W, R>,
+) -> Result<TrieChildren<InternedPath, (), S>, crate::Error>
 where
     S: TreeFileMetadata,
     F1: Future<Output = Result<DirectoryHandle, crate::Error>> + Send,
     F2: Future<Output = Result<S, crate::Error>> + Send,
+    F3: Future<Output = Result<String, crate::Error>> + Send,
     D: Fn(PathBuf) -> F1 + Sync,
     W: Fn(PathBuf) -> F2 + Sync,
+    R: Fn(PathBuf) -> F3 + Sync,
 {
-    enum ProcessResult<S_: TreeFileMetadata> {
-        Directory(BTreeMap<lasso::Spur, TrieNode<InternedPath, (), S_>>),
-        File(S_),
-    }
+    let mut slots = vec![DirectorySlot {
+        parent: None,
+        children: BTreeMap::default(),
+    }];
+    let mut frontier = VecDeque::from([PendingDirectory {
+        path: root,
+        ancestors: vec![root_inode],
+        ignores: None,
+        slot: 0,
+    }]);
 
-    async move {
-        tracing::trace!(?path, "processing directory");
-        let handle = open_dir(path.clone()).await?;
-        let entries = handle.list().await?;
+    while !frontier.is_empty() {
+        let batch_size = frontier.len().min(ctx.concurrency);
+        let batch: Vec<_> = frontier.drain(..batch_size).collect();
 
-        let mut children = BTreeMap::default();
-        let mut futures = Vec::new();
+        // List every directory in the batch.
+        let mut listed = futures::stream::iter(batch)
+            .map(|dir| list_directory(dir, ctx))
+            .buffer_unordered(ctx.concurrency);
+        let mut entries = Vec::new();
+        while let Some((dir, dir_entries)) = listed.try_next().await? {
+            let dir = Rc::new(dir);
+            entries.extend(
+                dir_entries
+                    .into_iter()
+                    .map(|entry| (Rc::clone(&dir), entry)),
+            );
+        }
 
-        for entry in entries {
-            let new_path = path.join(&entry.name);
-            if let Some(ignore_glob_set) = ignore.as_ref() {
-                if ignore_glob_set.is_match(&new_path) {
-                    continue;
+        // Process all of their entries, queueing up any new directories.
+        let mut results = futures::stream::iter(entries)
+            .map(|(dir, entry)| async move {
+                let result = process_entry(&dir, &entry, ctx).await?;
+                Ok::<_, crate::Error>((dir, entry.name, result))
+            })
+            .buffer_unordered(ctx.concurrency);
+        while let Some((dir, filename, result)) = results.try_next().await? {
+            let name = ctx.strings.borrow_mut().get_or_intern(&filename);
+            match result {
+                EntryResult::File(data) => {
+                    slots[dir.slot]
+                        .children
+                        .insert(name, TrieNode::Leaf { data });
                 }
+                EntryResult::Directory(inode) => {
+                    let mut ancestors = dir.ancestors.clone();
+                    ancestors.push(inode);
+                    frontier.push_back(PendingDirectory {
+                        path: dir.path.join(filename),
+                        ancestors,
+                        ignores: dir.ignores.clone(),
+                        slot: slots.len(),
+                    });
+                    slots.push(DirectorySlot {
+                        parent: Some((dir.slot, name)),
+                        children: BTreeMap::default(),
+                    });
+                }
+                EntryResult::Skipped => (),
             }
+        }
+    }
+
+    // Children always come after their parents, so we can build the tree from the bottom up.
+    while slots.len() > 1 {
+        let slot = slots.pop().expect("checked length");
+        let (parent, name) = slot.parent.expect("only the root has no parent");
+        let node = TrieNode::Edge {
+            children: slot.children.into(),
+            data: (),
+        };
+        slots[parent].children.insert(name, node);
+    }
+    let root = slots.pop().expect("always have a root");
+
+    Ok(root.children.into())
+}
 
-            match entry.kind {
-                FileType::File => {
-                    // Drive all of the file futures in parallel.
-                    let future = process_file(new_path)
-                        .map_ok(|val| (ProcessResult::File(val), entry.name))
-                        .boxed_local();
-                    futures.push(future);
+/// List a directory, returning the entries that aren't ignored.
+///
+/// The returned [`PendingDirectory`] includes the rules from any ignore files in the directory.
+async fn list_directory<D, W, R, F1, F3>(
+    mut dir: PendingDirectory,
+    ctx: &WalkContext<'_, D, W, R>,
+) -> Result<(PendingDirectory, Vec<DirectoryEntry>), crate::Error>
+where
+    F1: Future<Output = Result<DirectoryHandle, crate::Error>> + Send,
+    F3: Future<Output = Result<String, crate::Error>> + Send,
+    D: Fn(PathBuf) -> F1 + Sync,
+    R: Fn(PathBuf) -> F3 + Sync,
+{
+    let path = &dir.path;
+    if ctx.max_depth.is_some_and(|max| dir.ancestors.len() > max) {
+        tracing::trace!(?path, "skipping directory past max depth");
+        return Ok((dir, Vec::new()));
+    }
+
+    tracing::trace!(?path, "processing directory");
+    let handle = (ctx.open_dir)(path.clone()).await?;
+    if let Some(device) = ctx.device {
+        if handle.stat().await?.device != device {
+            tracing::debug!(?path, "skipping directory on another filesystem");
+            handle.close().await?;
+            return Ok((dir, Vec::new()));
+        }
+    }
+    let mut entries = handle.list().await?;
+    if let Some(progress) = ctx.progress {
+        progress.record_directory();
+    }
+    // Close our handle to make sure we free resources as quickly as possible.
+    handle.close().await?;
+
+    if ctx.respect_gitignore {
+        let mut contents = String::new();
+        for name in IGNORE_FILES {
+            let exists = entries
+                .iter()
+                .any(|entry| entry.name == name && entry.kind == FileType::File);
+            if !exists {
+                continue;
+            }
+            match (ctx.read_file)(path.join(name)).await {
+                Ok(rules) => {
+                    contents.push_str(&rules);
+                    contents.push('\n');
                 }
-                FileType::Directory => {
-                    // Drive all of the directory futures in parallel.
-                    let future = walk_directory(
-                        new_path,
-                        ignore,
-                        open_dir,
-                        process_file,
-                        Rc::clone(&strings),
-                    )
-                    .map_ok(|result| (ProcessResult::Directory(result), entry.name))
-                    .boxed_local();
-                    futures.push(future);
+                // Removed since we listed the directory.
+                Err(crate::Error::NotFound) => (),
+                Err(err) => return Err(err),
+            }
+        }
+        if !contents.is_empty() {
+            let rules = IgnoreRules::new(dir.ignores.take(), path.clone(), &contents);
+            dir.ignores = Some(Rc::new(rules));
+        }
+    }
+
+    entries.retain(|entry| {
+        let entry_path = dir.path.join(&entry.name);
+        if let Some(ignore_glob_set) = ctx.ignore.as_ref() {
+            if ignore_glob_set.is_match(&entry_path) {
+                return false;
+            }
+        }
+        if ctx.respect_gitignore {
+            let is_dir = entry.kind == FileType::Directory;
+            if is_dir && entry.name == ".git" {
+                return false;
+            }
+            if let Some(rules) = dir.ignores.as_ref() {
+                if rules.is_ignored(&entry_path, is_dir) {
+                    tracing::trace!(?entry_path, "skipping ignored entry");
+                    return false;
                 }
-                FileType::Symlink => (),
             }
         }
+        true
+    });
 
-        // Close our handle to make sure we free resources as quickly as possible.
-        handle.close().await?;
+    Ok((dir, entries))
+}
 
-        // Drive all of the child directories in parallel.
-        for result in futures::future::join_all(futures).await {
-            let (process_result, filename) = result?;
-            let name = strings.borrow_mut().get_or_intern(filename);
-            let node = match process_result {
-                ProcessResult::Directory(recursive_children) => TrieNode::Edge {
-                    children: recursive_children,
-                    data: (),
-                },
-                ProcessResult::File(data) => TrieNode::Leaf { data },
-            };
-            children.insert(name, node);
+/// Process a single entry from the directory `dir`.
+async fn process_entry<D, W, R, S, F2>(
+    dir: &PendingDirectory,
+    entry: &DirectoryEntry,
+    ctx: &WalkContext<'_, D, W, R>,
+) -> Result<EntryResult<S>, crate::Error>
+where
+    S: TreeFileMetadata,
+    F2: Future<Output = Result<S, crate::Error>> + Send,
+    W: Fn(PathBuf) -> F2 + Sync,
+{
+    let new_path = dir.path.join(&entry.name);
+    match (entry.kind, ctx.symlinks) {
+        (FileType::File, _) => {
+            let result = (ctx.process_file)(new_path).await?;
+            Ok(EntryResult::File(result))
         }
+        (FileType::Directory, _) => Ok(EntryResult::Directory(entry.inode)),
+        (FileType::Symlink, SymlinkPolicy::Skip) => Ok(EntryResult::Skipped),
+        (FileType::Symlink, SymlinkPolicy::Follow) => {
+            let stat_path = PlatformPathType::try_new(new_path.clone())?;
+            let stat = match ctx.worker.stat(stat_path).await {
+                Ok(stat) => stat,
+                Err(crate::Error::NotFound) => {
+                    tracing::warn!(?new_path, "skipping dangling symlink");
+                    return Ok(EntryResult::Skipped);
+                }
+                Err(err) => return Err(err),
+            };
+            if ctx.device.is_some_and(|device| device != stat.device) {
+                tracing::debug!(?new_path, "skipping symlink to another filesystem");
+                return Ok(EntryResult::Skipped);
+            }
 
-        Ok(children)
+            match stat.kind {
+                FileType::Directory if dir.ancestors.contains(&stat.inode) => {
+                    tracing::warn!(?new_path, "skipping symlink cycle");
+                    Ok(EntryResult::Skipped)
+                }
+                FileType::Directory => Ok(EntryResult::Directory(stat.inode)),
+                FileType::File | FileType::Symlink => {
+                    let result = (ctx.process_file)(new_path).await?;
+                    Ok(EntryResult::File(result))
+                }
+            }
+        }
+        (FileType::Symlink, SymlinkPolicy::RecordTarget) => {
+            let link_path = PlatformPathType::try_new(new_path.clone())?;
+            let target = ctx
+                .worker
+                .run(|| FilesystemPlatform::readlink(link_path))
+                .await?;
+            let target = PathBuf::from(target.into_inner());
+            ctx.targets.borrow_mut().insert(new_path, target);
+            Ok(EntryResult::Skipped)
+        }
     }
-    .boxed_local()
 }
 
 pub trait TreeFileMetadata: Clone + Send + 'static {
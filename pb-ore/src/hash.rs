@@ -1,5 +1,17 @@
 //! Hashing utilities.
 
+/// A hash function that can be fed its input incrementally, e.g. while reading a file.
+pub trait StreamingHasher: Default {
+    /// Hash produced by this hasher.
+    type Output;
+
+    /// Feed more `input` into the hasher.
+    fn update(&mut self, input: &[u8]);
+
+    /// Returns the hash of all of the input so far.
+    fn finish(&self) -> Self::Output;
+}
+
 pub struct Xxh3Hasher {
     inner: xxhash_rust::xxh3::Xxh3,
 }
@@ -28,3 +40,21 @@ impl Xxh3Hasher {
         pb_types::Xxh128Hash::new(self.inner.digest128())
     }
 }
+
+impl Default for Xxh3Hasher {
+    fn default() -> Self {
+        Xxh3Hasher::new()
+    }
+}
+
+impl StreamingHasher for Xxh3Hasher {
+    type Output = pb_types::Xxh64Hash;
+
+    fn update(&mut self, input: &[u8]) {
+        Xxh3Hasher::update(self, input);
+    }
+
+    fn finish(&self) -> Self::Output {
+        self.digest()
+    }
+}
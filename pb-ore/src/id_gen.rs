@@ -6,6 +6,12 @@ pub struct Gen<Id> {
     phantom: std::marker::PhantomData<fn() -> Id>,
 }
 
+impl<Id> Clone for Gen<Id> {
+    fn clone(&self) -> Self {
+        Gen::from_start(self.next)
+    }
+}
+
 impl<Id> Default for Gen<Id> {
     fn default() -> Self {
         Gen::from_start(0)
@@ -142,14 +142,6 @@ pub enum WriteFileHandleInner {
 }
 
 impl WriteFileHandleInner {
-    fn try_inner(&mut self) -> Result<&mut pb_filesystem::handle::FileHandle, String> {
-        match self {
-            WriteFileHandleInner::Root { file, .. } => Ok(file.inner_mut()),
-            WriteFileHandleInner::Child { file, .. } => Ok(file),
-            WriteFileHandleInner::Closed => Err("file closed".to_string()),
-        }
-    }
-
     fn try_offset(&mut self) -> Result<&mut usize, String> {
         match self {
             WriteFileHandleInner::Root { offset, .. }
@@ -216,12 +208,14 @@ impl wit::write_filesystem::HostWriteFile for HostState {
             let cur_offset = *scratch_file.try_offset()?;
             let to_write = data.len();
 
-            // Write data at our last offset.
-            scratch_file
-                .try_inner()?
-                .write(data, cur_offset)
-                .await
-                .map_err(|err| err.to_string())?;
+            // Write data at our last offset, files in the root of the scratch directory count
+            // against its size budget.
+            let result = match &mut *scratch_file {
+                WriteFileHandleInner::Root { file, .. } => file.write(data, cur_offset).await,
+                WriteFileHandleInner::Child { file, .. } => file.write(data, cur_offset).await,
+                WriteFileHandleInner::Closed => return Err("file closed".to_string()),
+            };
+            result.map_err(|err| err.to_string())?;
 
             // Update the offset for the next time that we write.
             let cur_offset = scratch_file.try_offset()?;
@@ -247,11 +241,16 @@ impl wit::write_filesystem::HostWriteFile for HostState {
         let future = async move {
             let mut scratch_file = scratch_file.state.lock().await;
 
-            scratch_file
-                .try_inner()?
-                .setxattr(name.into(), data.into())
-                .await
-                .map_err(|err| err.to_string())?;
+            let result = match &mut *scratch_file {
+                WriteFileHandleInner::Root { file, .. } => {
+                    file.setxattr(name.into(), data.into()).await
+                }
+                WriteFileHandleInner::Child { file, .. } => {
+                    file.setxattr(name.into(), data.into()).await
+                }
+                WriteFileHandleInner::Closed => return Err("file closed".to_string()),
+            };
+            result.map_err(|err| err.to_string())?;
 
             Ok::<_, String>(())
         }
@@ -271,11 +270,12 @@ impl wit::write_filesystem::HostWriteFile for HostState {
             let mut scratch_file = scratch_file.state.lock().await;
 
             let timespec = Timespec::from_epoch_millis(millis);
-            scratch_file
-                .try_inner()?
-                .setmtime(timespec)
-                .await
-                .map_err(|err| err.to_string())?;
+            let result = match &mut *scratch_file {
+                WriteFileHandleInner::Root { file, .. } => file.setmtime(timespec).await,
+                WriteFileHandleInner::Child { file, .. } => file.setmtime(timespec).await,
+                WriteFileHandleInner::Closed => return Err("file closed".to_string()),
+            };
+            result.map_err(|err| err.to_string())?;
 
             Ok::<_, String>(())
         }
@@ -425,9 +425,9 @@ pub enum WriteDirectoryInner {
 }
 
 impl WriteDirectoryInner {
-    fn try_inner(&mut self) -> Result<&mut pb_filesystem::handle::DirectoryHandle, String> {
+    fn try_inner(&self) -> Result<&pb_filesystem::handle::DirectoryHandle, String> {
         match self {
-            WriteDirectoryInner::Root { dir, .. } => Ok(dir.inner_mut()),
+            WriteDirectoryInner::Root { dir, .. } => Ok(&**dir),
             WriteDirectoryInner::Child { dir } => Ok(dir),
             WriteDirectoryInner::Closed => Err("file closed".to_string()),
         }
@@ -442,7 +442,7 @@ impl wit::write_filesystem::HostWriteDirectory for HostState {
     ) -> wasmtime::component::Resource<CreateDirectoryFuture> {
         let parent = self.resources.get(&self_).unwrap().clone();
         let future = async move {
-            let mut parent = parent.state.lock().await;
+            let parent = parent.state.lock().await;
             let child = parent
                 .try_inner()?
                 .openat(name)
@@ -465,7 +465,7 @@ impl wit::write_filesystem::HostWriteDirectory for HostState {
     ) -> wasmtime::component::Resource<CreateFileFuture> {
         let parent = self.resources.get(&self_).unwrap().clone();
         let future = async move {
-            let mut parent = parent.state.lock().await;
+            let parent = parent.state.lock().await;
             let (child, _stat) = parent
                 .try_inner()?
                 .openat(name)
@@ -491,11 +491,14 @@ impl wit::write_filesystem::HostWriteDirectory for HostState {
         let handle = self.resources.get(&self_).unwrap().clone();
         let future = async move {
             let mut handle = handle.state.lock().await;
-            handle
-                .try_inner()?
-                .setxattr(name.into(), data.into())
-                .await
-                .map_err(|err| err.to_string())?;
+            let result = match &mut *handle {
+                WriteDirectoryInner::Root { dir, .. } => {
+                    dir.setxattr(name.into(), data.into()).await
+                }
+                WriteDirectoryInner::Child { dir } => dir.setxattr(name.into(), data.into()).await,
+                WriteDirectoryInner::Closed => return Err("directory closed".to_string()),
+            };
+            result.map_err(|err| err.to_string())?;
             Ok(())
         }
         .boxed();
@@ -511,11 +514,12 @@ impl wit::write_filesystem::HostWriteDirectory for HostState {
         let future = async move {
             let mut handle = handle.state.lock().await;
             let timespec = Timespec::from_epoch_millis(millis);
-            handle
-                .try_inner()?
-                .setmtime(timespec)
-                .await
-                .map_err(|err| err.to_string())?;
+            let result = match &mut *handle {
+                WriteDirectoryInner::Root { dir, .. } => dir.setmtime(timespec).await,
+                WriteDirectoryInner::Child { dir } => dir.setmtime(timespec).await,
+                WriteDirectoryInner::Closed => return Err("directory closed".to_string()),
+            };
+            result.map_err(|err| err.to_string())?;
             Ok(())
         }
         .boxed();
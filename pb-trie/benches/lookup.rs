@@ -0,0 +1,57 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use pb_trie::{RadixTrieMap, TrieKey, TrieMap};
+
+/// A key made of pre-interned components, so we only measure the trie itself.
+#[derive(Clone, Copy)]
+struct Key<'a>(&'a [u32]);
+
+impl TrieKey for Key<'_> {
+    type Component = u32;
+
+    fn as_components(&self) -> impl Iterator<Item = Self::Component> {
+        self.0.iter().copied()
+    }
+}
+
+/// Generate `count` files, each nested `depth` directories deep.
+fn paths(depth: u32, count: u32) -> Vec<Vec<u32>> {
+    (0..count)
+        .map(|idx| (0..depth).chain(std::iter::once(depth + idx)).collect())
+        .collect()
+}
+
+fn bench_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup");
+
+    for depth in [2, 8, 32] {
+        let paths = paths(depth, 256);
+
+        let mut trie: TrieMap<Key, (), usize> = TrieMap::new();
+        let mut radix: RadixTrieMap<Key, usize> = RadixTrieMap::new();
+        for (idx, path) in paths.iter().enumerate() {
+            trie.insert_leaf(Key(path), idx).unwrap();
+            radix.insert_leaf(Key(path), idx).unwrap();
+        }
+
+        group.bench_function(BenchmarkId::new("trie", depth), |b| {
+            b.iter(|| {
+                for path in &paths {
+                    let _ = std::hint::black_box(trie.get_leaf(Key(path)));
+                }
+            })
+        });
+
+        group.bench_function(BenchmarkId::new("radix", depth), |b| {
+            b.iter(|| {
+                for path in &paths {
+                    let _ = std::hint::black_box(radix.get_leaf(Key(path)));
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_lookup);
+criterion_main!(benches);
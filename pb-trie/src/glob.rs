@@ -0,0 +1,216 @@
+//! Glob matching over the paths in a [`TrieMap`].
+//!
+//! [`TrieMap`]: crate::TrieMap
+
+use smallvec::SmallVec;
+
+use crate::{ChildrenIter, TrieKey, TrieNode};
+
+/// A set of `/` separated glob patterns that are matched one component at a time.
+///
+/// Unlike a [`globset::GlobSet`], which can only match complete paths, a [`TrieGlob`] can tell
+/// when a prefix of a path can never match, which lets [`TrieMap::match_glob`] skip entire
+/// subtrees.
+///
+/// Each component of a pattern supports the same syntax as [`globset::Glob`], with `**`
+/// matching zero or more whole components.
+///
+/// [`TrieMap::match_glob`]: crate::TrieMap::match_glob
+#[derive(Debug, Clone)]
+pub struct TrieGlob {
+    patterns: Vec<Vec<Segment>>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    /// `**`, matches zero or more components.
+    AnyPath,
+    /// Matches exactly one component.
+    Component(globset::GlobMatcher),
+}
+
+/// Position within each of the patterns of a [`TrieGlob`], `(pattern, segment)`.
+type States = SmallVec<[(usize, usize); 4]>;
+
+impl TrieGlob {
+    /// Compile a [`TrieGlob`] that matches any of the provided patterns.
+    ///
+    /// # Errors
+    ///
+    /// * If a component of any of the patterns is not a valid glob.
+    pub fn new<I, S>(patterns: I) -> Result<Self, anyhow::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let patterns = patterns
+            .into_iter()
+            .map(|pattern| {
+                pattern
+                    .as_ref()
+                    .split('/')
+                    .filter(|component| !component.is_empty())
+                    .map(|component| match component {
+                        "**" => Ok(Segment::AnyPath),
+                        other => {
+                            let glob = globset::Glob::new(other)?;
+                            Ok(Segment::Component(glob.compile_matcher()))
+                        }
+                    })
+                    .collect::<Result<Vec<_>, anyhow::Error>>()
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(TrieGlob { patterns })
+    }
+
+    /// Returns if the provided path, split into components, matches any of our patterns.
+    pub fn is_match<'a>(&self, components: impl IntoIterator<Item = &'a str>) -> bool {
+        let states = components
+            .into_iter()
+            .fold(self.start(), |states, component| {
+                self.step(&states, component)
+            });
+        self.is_accepting(&states)
+    }
+
+    /// States before any components have been matched.
+    fn start(&self) -> States {
+        let mut states = (0..self.patterns.len())
+            .map(|pattern| (pattern, 0))
+            .collect();
+        self.close(&mut states);
+        states
+    }
+
+    /// Advance `states` past `component`. An empty result means nothing can match.
+    fn step(&self, states: &States, component: &str) -> States {
+        let mut next = States::new();
+        for &(pattern, segment) in states {
+            match self.patterns[pattern].get(segment) {
+                Some(Segment::AnyPath) => next.push((pattern, segment)),
+                Some(Segment::Component(matcher)) if matcher.is_match(component) => {
+                    next.push((pattern, segment + 1));
+                }
+                Some(Segment::Component(_)) | None => (),
+            }
+        }
+        self.close(&mut next);
+        next
+    }
+
+    /// Returns if any of the patterns have been fully matched.
+    fn is_accepting(&self, states: &States) -> bool {
+        states
+            .iter()
+            .any(|(pattern, segment)| *segment == self.patterns[*pattern].len())
+    }
+
+    /// Since `**` can match zero components, also include the state after each one.
+    fn close(&self, states: &mut States) {
+        let mut idx = 0;
+        while idx < states.len() {
+            let (pattern, segment) = states[idx];
+            if let Some(Segment::AnyPath) = self.patterns[pattern].get(segment) {
+                states.push((pattern, segment + 1));
+            }
+            idx += 1;
+        }
+        states.sort_unstable();
+        states.dedup();
+    }
+}
+
+/// Iterator over the leaves in a [`TrieMap`] that match a [`TrieGlob`], in sorted order.
+///
+/// Returned from [`TrieMap::match_glob`].
+///
+/// [`TrieMap`]: crate::TrieMap
+/// [`TrieMap::match_glob`]: crate::TrieMap::match_glob
+pub struct GlobMatches<'a, K: TrieKey, E, L, F> {
+    glob: &'a TrieGlob,
+    name: F,
+    /// Iterators over the children of each edge we're descended into, along with the states
+    /// of the glob at that edge.
+    stack: Vec<(ChildrenIter<'a, K, E, L>, States)>,
+    /// Path to the edge at the top of `stack`.
+    path: Vec<K::Component>,
+}
+
+impl<'a, K: TrieKey, E, L, F> GlobMatches<'a, K, E, L, F> {
+    pub(crate) fn new(root: &'a TrieNode<K, E, L>, glob: &'a TrieGlob, name: F) -> Self {
+        let stack = match root {
+            TrieNode::Edge { children, .. } => vec![(children.iter(), glob.start())],
+            TrieNode::Leaf { .. } => Vec::new(),
+        };
+        GlobMatches {
+            glob,
+            name,
+            stack,
+            path: Vec::new(),
+        }
+    }
+}
+
+impl<'a, K, E, L, F> Iterator for GlobMatches<'a, K, E, L, F>
+where
+    K: TrieKey,
+    F: Fn(&'a K::Component) -> &'a str,
+{
+    type Item = (Vec<K::Component>, &'a L);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (children, states) = self.stack.last_mut()?;
+            let Some((component, node)) = children.next() else {
+                // Finished with this edge, move back up to its parent.
+                self.stack.pop();
+                if !self.stack.is_empty() {
+                    self.path.pop();
+                }
+                continue;
+            };
+
+            // Skip the entire subtree if nothing can match.
+            let states = self.glob.step(states, (self.name)(component));
+            if states.is_empty() {
+                continue;
+            }
+
+            match node {
+                TrieNode::Leaf { data } => {
+                    if self.glob.is_accepting(&states) {
+                        let mut path = self.path.clone();
+                        path.push(component.clone());
+                        return Some((path, data));
+                    }
+                }
+                TrieNode::Edge { children, .. } => {
+                    self.path.push(component.clone());
+                    self.stack.push((children.iter(), states));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_match() {
+        let glob = TrieGlob::new(["srcs/**/*.rs", "pb.toml"]).unwrap();
+
+        let matches = |path: &str| glob.is_match(path.split('/'));
+        assert!(matches("srcs/lib.rs"));
+        assert!(matches("srcs/a/b/c.rs"));
+        assert!(matches("pb.toml"));
+        assert!(!matches("srcs/lib.c"));
+        assert!(!matches("tests/lib.rs"));
+        assert!(!matches("srcs"));
+
+        let glob = TrieGlob::new(["**"]).unwrap();
+        assert!(glob.is_match("a/b/c".split('/')));
+    }
+}
@@ -0,0 +1,102 @@
+//! Iterators over a [`TrieMap`].
+//!
+//! [`TrieMap`]: crate::TrieMap
+
+use crate::{ChildrenIter, TrieKey, TrieNode};
+
+/// Iterator over all of the leaves in a [`TrieMap`], in sorted order.
+///
+/// Yields the full path to each leaf along with a reference to its data. The trie is walked
+/// depth-first with an explicit stack, so very deep tries don't risk overflowing the stack.
+///
+/// [`TrieMap`]: crate::TrieMap
+pub struct Iter<'a, K: TrieKey, E, L> {
+    /// Iterators over the children of each edge we're currently descended into.
+    stack: Vec<ChildrenIter<'a, K, E, L>>,
+    /// Path to the edge at the top of `stack`.
+    path: Vec<K::Component>,
+    /// Set if the iterator was created from a single leaf.
+    leaf: Option<&'a L>,
+}
+
+impl<'a, K: TrieKey, E, L> Iter<'a, K, E, L> {
+    /// Create an iterator over all of the leaves underneath `node`, with each yielded path
+    /// prefixed by `prefix`.
+    pub(crate) fn new(node: &'a TrieNode<K, E, L>, prefix: Vec<K::Component>) -> Self {
+        match node {
+            TrieNode::Edge { children, .. } => Iter {
+                stack: vec![children.iter()],
+                path: prefix,
+                leaf: None,
+            },
+            TrieNode::Leaf { data } => Iter {
+                stack: Vec::new(),
+                path: prefix,
+                leaf: Some(data),
+            },
+        }
+    }
+
+    /// Create an iterator that yields nothing.
+    pub(crate) fn empty() -> Self {
+        Iter {
+            stack: Vec::new(),
+            path: Vec::new(),
+            leaf: None,
+        }
+    }
+}
+
+impl<'a, K: TrieKey, E, L> Iterator for Iter<'a, K, E, L> {
+    type Item = (Vec<K::Component>, &'a L);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(data) = self.leaf.take() {
+            return Some((std::mem::take(&mut self.path), data));
+        }
+
+        loop {
+            let children = self.stack.last_mut()?;
+            match children.next() {
+                // Finished with this edge, move back up to its parent.
+                None => {
+                    self.stack.pop();
+                    // The root iterator doesn't have a path component.
+                    if !self.stack.is_empty() {
+                        self.path.pop();
+                    }
+                }
+                Some((component, TrieNode::Leaf { data })) => {
+                    let mut path = self.path.clone();
+                    path.push(component.clone());
+                    return Some((path, data));
+                }
+                Some((component, TrieNode::Edge { children, .. })) => {
+                    self.path.push(component.clone());
+                    self.stack.push(children.iter());
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the paths of all of the leaves in a [`TrieMap`], in sorted order.
+///
+/// [`TrieMap`]: crate::TrieMap
+pub struct Keys<'a, K: TrieKey, E, L> {
+    inner: Iter<'a, K, E, L>,
+}
+
+impl<'a, K: TrieKey, E, L> Keys<'a, K, E, L> {
+    pub(crate) fn new(inner: Iter<'a, K, E, L>) -> Self {
+        Keys { inner }
+    }
+}
+
+impl<K: TrieKey, E, L> Iterator for Keys<'_, K, E, L> {
+    type Item = Vec<K::Component>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(path, _leaf)| path)
+    }
+}
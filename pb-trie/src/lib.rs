@@ -2,37 +2,77 @@
 
 use std::{
     borrow::Cow,
-    collections::BTreeMap,
+    collections::{BTreeMap, btree_map},
     ffi::OsString,
     fmt::{self, Debug},
+    ops::{ControlFlow, RangeBounds},
     path::Path,
     rc::Rc,
+    sync::{Arc, OnceLock},
 };
 
 use derivative::Derivative;
 use smallvec::SmallVec;
 
+mod glob;
+mod iter;
+mod radix;
+mod shared;
+mod stats;
+
+pub use glob::{GlobMatches, TrieGlob};
+pub use iter::{Iter, Keys};
+pub use radix::{RadixIter, RadixTrieMap};
+pub use shared::SharedTrieMap;
+
+use crate::stats::TrieStats;
+
 /// A prefix trie data structure that supports map-like operations.
 ///
 /// You can store data along each edge, and on leaf nodes.
+///
+/// With the `serde` feature enabled a [`TrieMap`] can be serialized when its key components,
+/// edge data, and leaf data can be.
 #[derive(Debug)]
 pub struct TrieMap<K: TrieKey, E, L> {
     root: TrieNode<K, E, L>,
+    /// Size and depth of the trie, kept up to date as nodes are added and removed.
+    stats: TrieStats,
 }
 
 impl<K: TrieKey, E, L> TrieMap<K, E, L> {
     /// Create a new [`Trie`] with the provided root data.
     pub fn new_with_root(data: E) -> TrieMap<K, E, L> {
-        TrieMap {
-            root: TrieNode::Edge {
-                children: BTreeMap::default(),
-                data,
-            },
-        }
+        TrieMap::from_node(TrieNode::Edge {
+            children: TrieChildren::default(),
+            data,
+        })
     }
 
     pub fn from_node(node: TrieNode<K, E, L>) -> Self {
-        TrieMap { root: node }
+        let stats = TrieStats::from_root(&node);
+        TrieMap { root: node, stats }
+    }
+
+    /// Returns the number of leaves in the trie.
+    pub fn len(&self) -> usize {
+        self.stats.leaves()
+    }
+
+    /// Returns if the trie has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the total number of nodes in the trie, edges and leaves, including the root.
+    pub fn node_count(&self) -> usize {
+        self.stats.nodes()
+    }
+
+    /// Returns the number of components in the longest path in the trie, `0` if the root has no
+    /// children.
+    pub fn max_depth(&self) -> usize {
+        self.stats.max_depth()
     }
 
     /// Insert a piece of data at the provided `path`.
@@ -42,6 +82,7 @@ impl<K: TrieKey, E, L> TrieMap<K, E, L> {
     /// * If a component in the provided path does not exist as an edge.
     pub fn insert(&mut self, path: K, data: L) -> Result<Option<TrieNode<K, E, L>>, anyhow::Error> {
         let mut node = &mut self.root;
+        let stats = &mut self.stats;
         let mut components: SmallVec<[_; 8]> = path.as_components().collect();
         let Some(last_component) = components.pop() else {
             anyhow::bail!("inserting an empty key is not allowed");
@@ -65,7 +106,12 @@ impl<K: TrieKey, E, L> TrieMap<K, E, L> {
         match node {
             TrieNode::Leaf { .. } => Err(anyhow::anyhow!("non-edge parent {components:?}")),
             TrieNode::Edge { children, .. } => {
+                let depth = components.len() + 1;
                 let prev = children.insert(last_component.clone(), TrieNode::Leaf { data });
+                if let Some(prev) = &prev {
+                    stats.remove_subtree(prev, depth);
+                }
+                stats.add_node(depth, true);
                 Ok(prev)
             }
         }
@@ -92,6 +138,318 @@ impl<K: TrieKey, E, L> TrieMap<K, E, L> {
             TrieNode::Leaf { data } => Some(data),
         }
     }
+
+    /// Returns an iterator over the direct children of the edge at `path` whose component falls
+    /// within `range`, in sorted order.
+    ///
+    /// Returns `None` if `path` doesn't exist or points to a leaf.
+    ///
+    /// # Panics
+    ///
+    /// * Under the same conditions as [`BTreeMap::range`], e.g. if `range.start > range.end`.
+    pub fn children_range<R>(&self, path: K, range: R) -> Option<ChildrenIter<'_, K, E, L>>
+    where
+        R: RangeBounds<K::Component>,
+    {
+        match self.get(path)? {
+            TrieNode::Edge { children, .. } => Some(children.range(range)),
+            TrieNode::Leaf { .. } => None,
+        }
+    }
+
+    /// Get mutable references to the data of the node at the provided path.
+    ///
+    /// Note: Only the data is exposed, and not the node itself, so the structure of the trie
+    /// can't be changed out from under [`TrieMap::len`] and friends.
+    pub fn get_mut(&mut self, path: K) -> Option<TrieNodeMut<'_, E, L>> {
+        let mut node = &mut self.root;
+        for component in path.as_components() {
+            match node {
+                TrieNode::Leaf { .. } => return None,
+                TrieNode::Edge { children, .. } => {
+                    node = children.get_mut(&component)?;
+                }
+            }
+        }
+        let node = match node {
+            TrieNode::Edge { data, .. } => TrieNodeMut::Edge { data },
+            TrieNode::Leaf { data } => TrieNodeMut::Leaf { data },
+        };
+        Some(node)
+    }
+
+    /// Get a mutable reference to the leaf data at the provided path, if the path exists and
+    /// points to a leaf.
+    pub fn get_leaf_mut(&mut self, path: K) -> Option<&mut L> {
+        match self.get_mut(path)? {
+            TrieNodeMut::Edge { .. } => None,
+            TrieNodeMut::Leaf { data } => Some(data),
+        }
+    }
+
+    /// Get a mutable reference to the edge data at the provided path, if the path exists and
+    /// points to an edge.
+    ///
+    /// Note: An empty path returns the data for the root edge.
+    pub fn get_edge_data_mut(&mut self, path: K) -> Option<&mut E> {
+        match self.get_mut(path)? {
+            TrieNodeMut::Edge { data } => Some(data),
+            TrieNodeMut::Leaf { .. } => None,
+        }
+    }
+
+    /// Returns an iterator over every leaf in the trie, in sorted order, along with the full
+    /// path to each leaf.
+    pub fn iter(&self) -> Iter<'_, K, E, L> {
+        Iter::new(&self.root, Vec::new())
+    }
+
+    /// Returns the approximate number of bytes used by this trie.
+    ///
+    /// Includes the nodes, the maps holding the children of each edge, and the edge and leaf
+    /// data stored inline. Any heap memory owned by the edge or leaf data is not included. This
+    /// walks the entire trie so it should not be called in a hot loop.
+    pub fn heap_size_estimate(&self) -> usize {
+        std::mem::size_of::<Self>() + stats::heap_size_estimate(&self.root)
+    }
+
+    /// Returns an iterator over the full path to every leaf in the trie, in sorted order.
+    pub fn keys(&self) -> Keys<'_, K, E, L> {
+        Keys::new(self.iter())
+    }
+
+    /// Returns if there is at least one leaf at or underneath `path`.
+    ///
+    /// Stops as soon as the first leaf is found, so this is much cheaper than iterating.
+    pub fn contains_prefix(&self, path: K) -> bool {
+        match self.get(path) {
+            None => false,
+            Some(TrieNode::Leaf { .. }) => true,
+            Some(node) => Iter::new(node, Vec::new()).next().is_some(),
+        }
+    }
+
+    /// Returns an iterator over every leaf at or underneath `path`, in sorted order, along with
+    /// the full path to each leaf.
+    ///
+    /// If nothing exists at `path` the iterator is empty.
+    pub fn iter_prefix(&self, path: K) -> Iter<'_, K, E, L> {
+        let prefix: Vec<_> = path.as_components().collect();
+        match self.get(path) {
+            Some(node) => Iter::new(node, prefix),
+            None => Iter::empty(),
+        }
+    }
+
+    /// Walk every node in the trie depth-first, in sorted order, calling `f` with the full path
+    /// to each node, starting with the root at an empty path.
+    ///
+    /// `f` controls the walk by returning:
+    ///
+    /// * `ControlFlow::Continue(Walk::Descend)` to visit the children of the current node.
+    /// * `ControlFlow::Continue(Walk::Skip)` to skip the children of the current node.
+    /// * `ControlFlow::Break(val)` to stop the walk entirely, returning `val`.
+    pub fn walk<B, F>(&self, mut f: F) -> ControlFlow<B>
+    where
+        F: FnMut(&[K::Component], &TrieNode<K, E, L>) -> ControlFlow<B, Walk>,
+    {
+        let mut path = Vec::new();
+        if f(&path[..], &self.root)? == Walk::Skip {
+            return ControlFlow::Continue(());
+        }
+        let TrieNode::Edge { children, .. } = &self.root else {
+            return ControlFlow::Continue(());
+        };
+
+        let mut stack = vec![children.iter()];
+        while let Some(children) = stack.last_mut() {
+            let Some((component, node)) = children.next() else {
+                // Finished with this edge, move back up to its parent.
+                stack.pop();
+                path.pop();
+                continue;
+            };
+
+            path.push(component.clone());
+            let walk = f(&path[..], node)?;
+            match node {
+                TrieNode::Edge { children, .. } if walk == Walk::Descend => {
+                    stack.push(children.iter());
+                }
+                _ => {
+                    path.pop();
+                }
+            }
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    /// Returns an iterator over every leaf whose path matches `glob`, in sorted order, along
+    /// with the full path to each leaf.
+    ///
+    /// `name` returns the string for a component, which is what gets matched against the glob.
+    /// Subtrees that can't possibly match are skipped entirely.
+    pub fn match_glob<'a, F>(&'a self, glob: &'a TrieGlob, name: F) -> GlobMatches<'a, K, E, L, F>
+    where
+        F: Fn(&'a K::Component) -> &'a str,
+    {
+        GlobMatches::new(&self.root, glob, name)
+    }
+
+    /// Remove the leaf at the provided `path`, returning the removed node.
+    ///
+    /// Any edges that are left without children are pruned from the trie.
+    ///
+    /// # Errors
+    ///
+    /// * If a component in the provided path, other than the last, is a leaf.
+    /// * If the provided path points to an edge, see [`TrieMap::remove_subtree`].
+    pub fn remove(&mut self, path: K) -> Result<Option<TrieNode<K, E, L>>, anyhow::Error> {
+        let components: SmallVec<[_; 8]> = path.as_components().collect();
+        if components.is_empty() {
+            anyhow::bail!("removing an empty key is not allowed");
+        }
+        remove_node(&mut self.root, &mut self.stats, 0, &components[..], true)
+    }
+
+    /// Remove the node at the provided `path`, along with everything underneath it, returning
+    /// the removed node.
+    ///
+    /// Any edges that are left without children are pruned from the trie.
+    ///
+    /// # Errors
+    ///
+    /// * If a component in the provided path, other than the last, is a leaf.
+    pub fn remove_subtree(&mut self, path: K) -> Result<Option<TrieNode<K, E, L>>, anyhow::Error> {
+        let components: SmallVec<[_; 8]> = path.as_components().collect();
+        if components.is_empty() {
+            anyhow::bail!("removing an empty key is not allowed");
+        }
+        remove_node(&mut self.root, &mut self.stats, 0, &components[..], false)
+    }
+
+    /// Detach the node at the provided `path`, along with everything underneath it, as a new
+    /// [`TrieMap`] rooted at that node.
+    ///
+    /// Any edges that are left without children are pruned from the trie. The detached trie can
+    /// be re-attached elsewhere with [`TrieMap::graft`].
+    ///
+    /// # Errors
+    ///
+    /// * If a component in the provided path, other than the last, is a leaf.
+    pub fn take_subtree(&mut self, path: K) -> Result<Option<TrieMap<K, E, L>>, anyhow::Error> {
+        let node = self.remove_subtree(path)?;
+        Ok(node.map(TrieMap::from_node))
+    }
+
+    /// Retain only the leaves for which `f` returns `true`, along with their full path.
+    ///
+    /// Any edges that are left without children after removing leaves are pruned from the trie.
+    /// Edges that were already empty are left as-is.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&[K::Component], &mut L) -> bool,
+    {
+        if let TrieNode::Edge { children, .. } = &mut self.root {
+            let mut path = Vec::new();
+            retain_children(children, &mut self.stats, &mut path, &mut f);
+        }
+    }
+
+    /// Merge all of the nodes from `other` into this trie.
+    ///
+    /// When both tries have a leaf at the same path `on_conflict` is called with the full path,
+    /// our leaf, and their leaf, and is responsible for resolving the two. When both tries have
+    /// an edge at the same path, our edge data is kept and their edge data is dropped.
+    ///
+    /// # Errors
+    ///
+    /// * If one trie has a leaf where the other has an edge. Any nodes merged before the
+    ///   mismatch was found remain in this trie.
+    pub fn merge<F>(
+        &mut self,
+        other: TrieMap<K, E, L>,
+        mut on_conflict: F,
+    ) -> Result<(), anyhow::Error>
+    where
+        F: FnMut(&[K::Component], &mut L, L),
+    {
+        let mut path = Vec::new();
+        merge_node(
+            &mut self.root,
+            other.root,
+            &mut self.stats,
+            &mut path,
+            &mut on_conflict,
+        )
+    }
+
+    /// Transform every leaf in this trie with `f`, which is called with the full path of the
+    /// leaf. The shape of the trie, and all edge data, is unchanged.
+    pub fn map_leaves<L2, F>(self, mut f: F) -> TrieMap<K, E, L2>
+    where
+        F: FnMut(&[K::Component], L) -> L2,
+    {
+        /// An edge that we're in the middle of mapping.
+        struct Frame<K: TrieKey, E, L, L2> {
+            /// Remaining children to map.
+            source: ChildrenIntoIter<K, E, L>,
+            data: E,
+            children: BTreeMap<K::Component, TrieNode<K, E, L2>>,
+        }
+
+        let TrieMap { root, stats } = self;
+        let (source, data) = match root {
+            TrieNode::Leaf { data } => {
+                let root = TrieNode::Leaf { data: f(&[], data) };
+                return TrieMap { root, stats };
+            }
+            TrieNode::Edge { children, data } => (children.into_iter(), data),
+        };
+        let mut stack = vec![Frame {
+            source,
+            data,
+            children: BTreeMap::new(),
+        }];
+        // Path to the edge at the top of the stack.
+        let mut path = Vec::new();
+
+        loop {
+            let frame = stack.last_mut().expect("returns once the stack is empty");
+            match frame.source.next() {
+                Some((component, TrieNode::Leaf { data })) => {
+                    path.push(component);
+                    let data = f(&path, data);
+                    let component = path.pop().expect("just pushed");
+                    frame.children.insert(component, TrieNode::Leaf { data });
+                }
+                Some((component, TrieNode::Edge { children, data })) => {
+                    path.push(component);
+                    stack.push(Frame {
+                        source: children.into_iter(),
+                        data,
+                        children: BTreeMap::new(),
+                    });
+                }
+                // Finished mapping this edge, attach it to its parent.
+                None => {
+                    let frame = stack.pop().expect("just peeked");
+                    let edge = TrieNode::Edge {
+                        children: frame.children.into(),
+                        data: frame.data,
+                    };
+                    match stack.last_mut() {
+                        Some(parent) => {
+                            let component = path.pop().expect("only the root has no name");
+                            parent.children.insert(component, edge);
+                        }
+                        None => return TrieMap { root: edge, stats },
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl<K: TrieKey, E: Default, L> TrieMap<K, E, L> {
@@ -107,26 +465,115 @@ impl<K: TrieKey, E: Default, L> TrieMap<K, E, L> {
         &mut self,
         path: K,
         data: L,
+    ) -> Result<Option<TrieNode<K, E, L>>, anyhow::Error> {
+        self.insert_leaf_inner(path, data, false)
+    }
+
+    /// Insert a piece of data at the provided `path`, creating the necessary edges and
+    /// converting any leaf that is in the way into an edge.
+    ///
+    /// This is useful when a file is replaced by a directory of the same name. Returns the node
+    /// that was displaced, which is either the leaf that was converted into an edge, or the
+    /// previous node at `path`. At most one of these can exist.
+    pub fn insert_leaf_replacing(
+        &mut self,
+        path: K,
+        data: L,
+    ) -> Result<Option<TrieNode<K, E, L>>, anyhow::Error> {
+        self.insert_leaf_inner(path, data, true)
+    }
+
+    /// Attach all of the nodes from `subtree` at the provided `path`, creating the necessary
+    /// edges, returning the previous node at `path`.
+    ///
+    /// # Errors
+    ///
+    /// * If the provided path is empty.
+    /// * If a component in the provided path, other than the last, is a leaf.
+    pub fn graft(
+        &mut self,
+        path: K,
+        subtree: TrieMap<K, E, L>,
+    ) -> Result<Option<TrieNode<K, E, L>>, anyhow::Error> {
+        let mut node = &mut self.root;
+        let stats = &mut self.stats;
+        let mut components: SmallVec<[_; 8]> = path.as_components().collect();
+        let Some(last_component) = components.pop() else {
+            anyhow::bail!("grafting onto an empty key is not allowed");
+        };
+
+        // Walk down the trie to our final location.
+        for (idx, component) in components.iter().enumerate() {
+            match node {
+                TrieNode::Leaf { .. } => {
+                    return Err(anyhow::anyhow!("non-edge in path: {components:?}"));
+                }
+                TrieNode::Edge { children, .. } => {
+                    node = children.get_or_insert_with((*component).clone(), || {
+                        stats.add_node(idx + 1, false);
+                        TrieNode::Edge {
+                            children: TrieChildren::default(),
+                            data: E::default(),
+                        }
+                    })
+                }
+            }
+        }
+
+        // Attach the subtree.
+        match node {
+            TrieNode::Leaf { .. } => Err(anyhow::anyhow!("non-edge parent {components:?}")),
+            TrieNode::Edge { children, .. } => {
+                let depth = components.len() + 1;
+                let prev = children.insert(last_component.clone(), subtree.root);
+                if let Some(prev) = &prev {
+                    stats.remove_subtree(prev, depth);
+                }
+                stats.add_stats(&subtree.stats, depth);
+                Ok(prev)
+            }
+        }
+    }
+
+    fn insert_leaf_inner(
+        &mut self,
+        path: K,
+        data: L,
+        replace_leaves: bool,
     ) -> Result<Option<TrieNode<K, E, L>>, anyhow::Error> {
         let mut node = &mut self.root;
+        let stats = &mut self.stats;
         let mut components: SmallVec<[_; 8]> = path.as_components().collect();
         let Some(last_component) = components.pop() else {
             anyhow::bail!("inserting an empty key is not allowed");
         };
+        let mut displaced = None;
 
         // Walk down the trie to our final location.
-        for component in &components {
+        for (idx, component) in components.iter().enumerate() {
             match node {
                 TrieNode::Leaf { .. } => {
                     return Err(anyhow::anyhow!("non-edge in path: {components:?}"));
                 }
                 TrieNode::Edge { children, .. } => {
-                    node = children
-                        .entry((*component).clone())
-                        .or_insert_with(|| TrieNode::Edge {
-                            children: BTreeMap::default(),
+                    let depth = idx + 1;
+                    node = children.get_or_insert_with((*component).clone(), || {
+                        stats.add_node(depth, false);
+                        TrieNode::Edge {
+                            children: TrieChildren::default(),
+                            data: E::default(),
+                        }
+                    });
+
+                    if replace_leaves && matches!(node, TrieNode::Leaf { .. }) {
+                        let edge = TrieNode::Edge {
+                            children: TrieChildren::default(),
                             data: E::default(),
-                        })
+                        };
+                        displaced = Some(std::mem::replace(node, edge));
+                        stats.remove_node(depth, true);
+                        stats.add_node(depth, false);
+                    }
                 }
             }
         }
@@ -135,18 +582,228 @@ impl<K: TrieKey, E: Default, L> TrieMap<K, E, L> {
         match node {
             TrieNode::Leaf { .. } => Err(anyhow::anyhow!("non-edge parent {components:?}")),
             TrieNode::Edge { children, .. } => {
+                let depth = components.len() + 1;
                 let prev = children.insert(last_component.clone(), TrieNode::Leaf { data });
-                Ok(prev)
+                if let Some(prev) = &prev {
+                    stats.remove_subtree(prev, depth);
+                }
+                stats.add_node(depth, true);
+                Ok(displaced.or(prev))
+            }
+        }
+    }
+}
+
+impl<K, E, L> Clone for TrieMap<K, E, L>
+where
+    K: TrieKey,
+    E: Clone,
+    L: Clone,
+{
+    /// Clone the trie, sharing all of its nodes with the original.
+    ///
+    /// Nodes are only copied when they're modified through one of the tries, so cloning is
+    /// cheap and an update only copies the nodes along the path it modifies.
+    fn clone(&self) -> Self {
+        TrieMap {
+            root: TrieNode::clone(&self.root),
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+impl<K: TrieKey, E: Default, L> FromIterator<(K, L)> for TrieMap<K, E, L> {
+    /// Collect a [`TrieMap`] using the semantics of [`TrieMap::insert_leaf`].
+    ///
+    /// # Panics
+    ///
+    /// * If any of the keys can't be inserted, e.g. a key is empty or underneath another leaf.
+    fn from_iter<I: IntoIterator<Item = (K, L)>>(iter: I) -> Self {
+        let mut trie = TrieMap::new();
+        trie.extend(iter);
+        trie
+    }
+}
+
+impl<K: TrieKey, E: Default, L> Extend<(K, L)> for TrieMap<K, E, L> {
+    /// Insert all of the leaves using the semantics of [`TrieMap::insert_leaf`].
+    ///
+    /// # Panics
+    ///
+    /// * If any of the keys can't be inserted, e.g. a key is empty or underneath another leaf.
+    fn extend<I: IntoIterator<Item = (K, L)>>(&mut self, iter: I) {
+        for (path, data) in iter {
+            if let Err(err) = self.insert_leaf(path, data) {
+                panic!("failed to insert leaf: {err}");
+            }
+        }
+    }
+}
+
+impl<'a, K: TrieKey, E, L> IntoIterator for &'a TrieMap<K, E, L> {
+    type Item = (Vec<K::Component>, &'a L);
+    type IntoIter = Iter<'a, K, E, L>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K, E, L> serde::Serialize for TrieMap<K, E, L>
+where
+    K: TrieKey,
+    K::Component: serde::Serialize,
+    E: serde::Serialize,
+    L: serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.root.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, E, L> serde::Deserialize<'de> for TrieMap<K, E, L>
+where
+    K: TrieKey,
+    K::Component: serde::Deserialize<'de>,
+    E: serde::Deserialize<'de>,
+    L: serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Stats aren't serialized, recompute them from the nodes.
+        TrieNode::deserialize(deserializer).map(TrieMap::from_node)
+    }
+}
+
+/// Remove the node at `components` underneath `node`, which lives at `depth`, pruning any edges
+/// left without children.
+fn remove_node<K: TrieKey, E, L>(
+    node: &mut TrieNode<K, E, L>,
+    stats: &mut TrieStats,
+    depth: usize,
+    components: &[K::Component],
+    leaf_only: bool,
+) -> Result<Option<TrieNode<K, E, L>>, anyhow::Error> {
+    let TrieNode::Edge { children, .. } = node else {
+        return Err(anyhow::anyhow!("non-edge in path: {components:?}"));
+    };
+    let Some((component, rest)) = components.split_first() else {
+        return Ok(None);
+    };
+
+    // Found our final location.
+    if rest.is_empty() {
+        return match children.get(component) {
+            None => Ok(None),
+            Some(TrieNode::Edge { .. }) if leaf_only => Err(anyhow::anyhow!(
+                "cannot remove edge {component:?} as a leaf, use remove_subtree"
+            )),
+            Some(child) => {
+                stats.remove_subtree(child, depth + 1);
+                Ok(children.remove(component))
             }
+        };
+    }
+
+    let Some(child) = children.get_mut(component) else {
+        return Ok(None);
+    };
+    let removed = remove_node(child, stats, depth + 1, rest, leaf_only)?;
+
+    // Prune the child if we removed its last descendant.
+    if removed.is_some() && child.is_empty_edge() {
+        children.remove(component);
+        stats.remove_node(depth + 1, false);
+    }
+
+    Ok(removed)
+}
+
+/// Retain the leaves underneath `children` that pass `f`, where `children` lives at `path`.
+fn retain_children<K: TrieKey, E, L, F>(
+    children: &mut TrieChildren<K, E, L>,
+    stats: &mut TrieStats,
+    path: &mut Vec<K::Component>,
+    f: &mut F,
+) where
+    F: FnMut(&[K::Component], &mut L) -> bool,
+{
+    children.retain(|component, node| {
+        path.push(component.clone());
+        let keep = match node {
+            TrieNode::Leaf { data } => f(&path[..], data),
+            TrieNode::Edge { children, .. } => {
+                let was_empty = children.is_empty();
+                retain_children(children, stats, path, f);
+                was_empty || !children.is_empty()
+            }
+        };
+        if !keep {
+            stats.remove_node(path.len(), matches!(node, TrieNode::Leaf { .. }));
+        }
+        path.pop();
+        keep
+    });
+}
+
+/// Merge `theirs` into `ours`, where both nodes live at `path`.
+fn merge_node<K: TrieKey, E, L, F>(
+    ours: &mut TrieNode<K, E, L>,
+    theirs: TrieNode<K, E, L>,
+    stats: &mut TrieStats,
+    path: &mut Vec<K::Component>,
+    on_conflict: &mut F,
+) -> Result<(), anyhow::Error>
+where
+    F: FnMut(&[K::Component], &mut L, L),
+{
+    match (ours, theirs) {
+        (TrieNode::Leaf { data: ours }, TrieNode::Leaf { data: theirs }) => {
+            on_conflict(&path[..], ours, theirs);
+            Ok(())
+        }
+        (
+            TrieNode::Edge { children: ours, .. },
+            TrieNode::Edge {
+                children: theirs, ..
+            },
+        ) => {
+            for (component, theirs) in theirs {
+                match ours.get_mut(&component) {
+                    None => {
+                        stats.add_subtree(&theirs, path.len() + 1);
+                        ours.insert(component, theirs);
+                    }
+                    Some(ours) => {
+                        path.push(component);
+                        merge_node(ours, theirs, stats, path, on_conflict)?;
+                        path.pop();
+                    }
+                }
+            }
+            Ok(())
+        }
+        (TrieNode::Leaf { .. }, TrieNode::Edge { .. })
+        | (TrieNode::Edge { .. }, TrieNode::Leaf { .. }) => {
+            Err(anyhow::anyhow!("mismatched leaf and edge at {path:?}"))
         }
     }
 }
 
 /// Single node within a [`TrieMap`].
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "K::Component: serde::Serialize, E: serde::Serialize, L: serde::Serialize",
+        deserialize = "K::Component: serde::Deserialize<'de>, E: serde::Deserialize<'de>, L: serde::Deserialize<'de>"
+    ))
+)]
 pub enum TrieNode<K: TrieKey, E, L> {
     Edge {
-        children: BTreeMap<K::Component, TrieNode<K, E, L>>,
+        children: TrieChildren<K, E, L>,
         data: E,
     },
     Leaf {
@@ -154,13 +811,308 @@ pub enum TrieNode<K: TrieKey, E, L> {
     },
 }
 
-impl<K, E, L> Clone for TrieNode<K, E, L>
+impl<K: TrieKey, E, L> TrieNode<K, E, L> {
+    /// Returns if this node is an edge without any children.
+    pub fn is_empty_edge(&self) -> bool {
+        match self {
+            TrieNode::Edge { children, .. } => children.is_empty(),
+            TrieNode::Leaf { .. } => false,
+        }
+    }
+}
+
+/// What [`TrieMap::walk`] should do after visiting a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Walk {
+    /// Visit the children of the node, if it has any.
+    Descend,
+    /// Skip the children of the node.
+    Skip,
+}
+
+/// Mutable references to the data of a single node within a [`TrieMap`].
+///
+/// Returned from [`TrieMap::get_mut`].
+#[derive(Debug)]
+pub enum TrieNodeMut<'a, E, L> {
+    Edge { data: &'a mut E },
+    Leaf { data: &'a mut L },
+}
+
+/// The children of an edge in a [`TrieMap`], keyed by their component.
+///
+/// Nodes are reference counted so clones of a [`TrieMap`] can share them, a node is only copied
+/// when it's modified while shared. Descendants are dropped iteratively so very deep tries
+/// don't overflow the stack.
+#[derive(Debug)]
+pub struct TrieChildren<K: TrieKey, E, L> {
+    nodes: BTreeMap<K::Component, Arc<TrieNode<K, E, L>>>,
+    /// Copies a node that's shared with another trie before it's modified.
+    ///
+    /// Cloning is the only way nodes become shared, so this gets set on both the original and
+    /// the clone by [`TrieChildren::clone`], and modifying a trie that's never been cloned
+    /// doesn't require the edge and leaf data to be [`Clone`].
+    clone_node: OnceLock<CloneNode<K, E, L>>,
+}
+
+/// Copies a [`TrieNode`] that is shared with another trie.
+type CloneNode<K, E, L> = fn(&TrieNode<K, E, L>) -> TrieNode<K, E, L>;
+
+impl<K: TrieKey, E, L> TrieChildren<K, E, L> {
+    /// Returns the number of children.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns if there are no children.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the child with the provided component.
+    pub fn get(&self, component: &K::Component) -> Option<&TrieNode<K, E, L>> {
+        self.nodes.get(component).map(Arc::as_ref)
+    }
+
+    /// Returns an iterator over the children, in sorted order.
+    pub fn iter(&self) -> ChildrenIter<'_, K, E, L> {
+        ChildrenIter(self.nodes.range(..))
+    }
+
+    /// Returns an iterator over the child nodes, in sorted order.
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &TrieNode<K, E, L>> {
+        self.nodes.values().map(Arc::as_ref)
+    }
+
+    /// Returns an iterator over the children whose component falls within `range`, in sorted
+    /// order.
+    ///
+    /// # Panics
+    ///
+    /// * Under the same conditions as [`BTreeMap::range`], e.g. if `range.start > range.end`.
+    pub fn range<R>(&self, range: R) -> ChildrenIter<'_, K, E, L>
+    where
+        R: RangeBounds<K::Component>,
+    {
+        ChildrenIter(self.nodes.range(range))
+    }
+}
+
+impl<K: TrieKey, E, L> TrieChildren<K, E, L> {
+    pub(crate) fn get_mut(&mut self, component: &K::Component) -> Option<&mut TrieNode<K, E, L>> {
+        let node = self.nodes.get_mut(component)?;
+        Some(make_mut(&self.clone_node, node))
+    }
+
+    pub(crate) fn get_or_insert_with<F>(
+        &mut self,
+        component: K::Component,
+        f: F,
+    ) -> &mut TrieNode<K, E, L>
+    where
+        F: FnOnce() -> TrieNode<K, E, L>,
+    {
+        let node = self.nodes.entry(component).or_insert_with(|| Arc::new(f()));
+        make_mut(&self.clone_node, node)
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        component: K::Component,
+        node: TrieNode<K, E, L>,
+    ) -> Option<TrieNode<K, E, L>> {
+        let prev = self.nodes.insert(component, Arc::new(node))?;
+        Some(unwrap_or_clone(self.clone_node.get().copied(), prev))
+    }
+
+    pub(crate) fn remove(&mut self, component: &K::Component) -> Option<TrieNode<K, E, L>> {
+        let node = self.nodes.remove(component)?;
+        Some(unwrap_or_clone(self.clone_node.get().copied(), node))
+    }
+
+    pub(crate) fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K::Component, &mut TrieNode<K, E, L>) -> bool,
+    {
+        let clone_node = &self.clone_node;
+        self.nodes
+            .retain(|component, node| f(component, make_mut(clone_node, node)))
+    }
+}
+
+/// Returns a mutable reference to `node`, copying it with `clone_node` if it's shared.
+fn make_mut<'a, K: TrieKey, E, L>(
+    clone_node: &OnceLock<CloneNode<K, E, L>>,
+    node: &'a mut Arc<TrieNode<K, E, L>>,
+) -> &'a mut TrieNode<K, E, L> {
+    if Arc::get_mut(node).is_none() {
+        let clone_node = clone_node.get().expect("nodes are only shared once cloned");
+        *node = Arc::new(clone_node(node));
+    }
+    Arc::get_mut(node).expect("just made unique")
+}
+
+/// Returns the owned `node`, copying it with `clone_node` if it's shared.
+fn unwrap_or_clone<K: TrieKey, E, L>(
+    clone_node: Option<CloneNode<K, E, L>>,
+    node: Arc<TrieNode<K, E, L>>,
+) -> TrieNode<K, E, L> {
+    Arc::try_unwrap(node).unwrap_or_else(|node| {
+        let clone_node = clone_node.expect("nodes are only shared once cloned");
+        clone_node(&node)
+    })
+}
+
+impl<K: TrieKey, E, L> Default for TrieChildren<K, E, L> {
+    fn default() -> Self {
+        TrieChildren {
+            nodes: BTreeMap::new(),
+            clone_node: OnceLock::new(),
+        }
+    }
+}
+
+impl<K: TrieKey, E: Clone, L: Clone> Clone for TrieChildren<K, E, L> {
+    /// Clone the children, sharing the nodes with the original.
+    fn clone(&self) -> Self {
+        let clone_node: CloneNode<K, E, L> = TrieNode::clone;
+        // The nodes are now shared, so the original needs to copy them before modifying too.
+        let _ = self.clone_node.set(clone_node);
+        TrieChildren {
+            nodes: self.nodes.clone(),
+            clone_node: OnceLock::from(clone_node),
+        }
+    }
+}
+
+impl<K: TrieKey, E, L> From<BTreeMap<K::Component, TrieNode<K, E, L>>> for TrieChildren<K, E, L> {
+    fn from(nodes: BTreeMap<K::Component, TrieNode<K, E, L>>) -> Self {
+        nodes.into_iter().collect()
+    }
+}
+
+impl<K: TrieKey, E, L> FromIterator<(K::Component, TrieNode<K, E, L>)> for TrieChildren<K, E, L> {
+    fn from_iter<I: IntoIterator<Item = (K::Component, TrieNode<K, E, L>)>>(iter: I) -> Self {
+        let nodes = iter
+            .into_iter()
+            .map(|(component, node)| (component, Arc::new(node)))
+            .collect();
+        TrieChildren {
+            nodes,
+            clone_node: OnceLock::new(),
+        }
+    }
+}
+
+impl<K: TrieKey, E, L> IntoIterator for TrieChildren<K, E, L> {
+    type Item = (K::Component, TrieNode<K, E, L>);
+    type IntoIter = ChildrenIntoIter<K, E, L>;
+
+    /// Returns an iterator over the children, in sorted order. Nodes that are shared with
+    /// another trie are copied.
+    fn into_iter(mut self) -> Self::IntoIter {
+        // We implement `Drop` so the map can't be moved out, leave an empty one behind.
+        let nodes = std::mem::take(&mut self.nodes).into_iter();
+        ChildrenIntoIter(nodes, self.clone_node.get().copied())
+    }
+}
+
+impl<'a, K: TrieKey, E, L> IntoIterator for &'a TrieChildren<K, E, L> {
+    type Item = (&'a K::Component, &'a TrieNode<K, E, L>);
+    type IntoIter = ChildrenIter<'a, K, E, L>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: TrieKey, E, L> Drop for TrieChildren<K, E, L> {
+    /// Drop all of the descendants iteratively, so very deep tries don't overflow the stack.
+    fn drop(&mut self) {
+        let mut stack: Vec<_> = std::mem::take(&mut self.nodes).into_values().collect();
+        while let Some(node) = stack.pop() {
+            // Nodes that are still shared get dropped by whoever holds the last reference.
+            if let Some(TrieNode::Edge { mut children, .. }) = Arc::into_inner(node) {
+                stack.extend(std::mem::take(&mut children.nodes).into_values());
+            }
+            // The children were moved onto the stack, so dropping the node doesn't recurse.
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K, E, L> serde::Serialize for TrieChildren<K, E, L>
 where
-    K: TrieKey + Clone,
-    K::Component: Clone,
-    E: Clone,
-    L: Clone,
+    K: TrieKey,
+    K::Component: serde::Serialize,
+    E: serde::Serialize,
+    L: serde::Serialize,
 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, E, L> serde::Deserialize<'de> for TrieChildren<K, E, L>
+where
+    K: TrieKey,
+    K::Component: serde::Deserialize<'de>,
+    E: serde::Deserialize<'de>,
+    L: serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        BTreeMap::<K::Component, TrieNode<K, E, L>>::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// Iterator over the children of an edge, in sorted order.
+///
+/// Returned from [`TrieChildren::iter`] and [`TrieChildren::range`].
+pub struct ChildrenIter<'a, K: TrieKey, E, L>(
+    btree_map::Range<'a, K::Component, Arc<TrieNode<K, E, L>>>,
+);
+
+impl<'a, K: TrieKey, E, L> Iterator for ChildrenIter<'a, K, E, L> {
+    type Item = (&'a K::Component, &'a TrieNode<K, E, L>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0
+            .next()
+            .map(|(component, node)| (component, node.as_ref()))
+    }
+}
+
+impl<K: TrieKey, E, L> DoubleEndedIterator for ChildrenIter<'_, K, E, L> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0
+            .next_back()
+            .map(|(component, node)| (component, node.as_ref()))
+    }
+}
+
+/// Owning iterator over the children of an edge, in sorted order.
+///
+/// Returned from [`TrieChildren::into_iter`].
+pub struct ChildrenIntoIter<K: TrieKey, E, L>(
+    btree_map::IntoIter<K::Component, Arc<TrieNode<K, E, L>>>,
+    Option<CloneNode<K, E, L>>,
+);
+
+impl<K: TrieKey, E, L> Iterator for ChildrenIntoIter<K, E, L> {
+    type Item = (K::Component, TrieNode<K, E, L>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (component, node) = self.0.next()?;
+        Some((component, unwrap_or_clone(self.1, node)))
+    }
+}
+
+impl<K: TrieKey, E: Clone, L: Clone> Clone for TrieNode<K, E, L> {
+    /// Clone this node, sharing its children with the original.
+    ///
+    /// Children are only copied when they're modified through one of the nodes, so this is
+    /// cheap even for very large subtrees.
     fn clone(&self) -> Self {
         match self {
             TrieNode::Edge { children, data } => TrieNode::Edge {
@@ -186,19 +1138,29 @@ impl TrieKey for pb_types::InternedPath {
     type Component = pb_types::InternedComponent;
 
     fn as_components(&self) -> impl Iterator<Item = Self::Component> {
-        self.0.iter().copied()
+        self.components().iter().copied()
     }
 }
 
-impl<K: TrieKey + Clone, E: Clone, L: Clone> TrieMap<K, E, L> {
+/// Writes the name of a component for a [`PrettyTrieNode`].
+type FmtName<'a, C> =
+    Rc<dyn for<'w> Fn(&'w mut dyn std::io::Write, &C) -> std::io::Result<()> + 'a>;
+/// Writes the data stored on a node for a [`PrettyTrieNode`].
+type FmtData<'a, T> =
+    Rc<dyn for<'w> Fn(&'w mut dyn std::io::Write, &T) -> std::io::Result<()> + 'a>;
+
+impl<K: TrieKey, E, L> TrieMap<K, E, L> {
     /// Return a [`PrettyTrieNode`] which can be pretty printed.
+    ///
+    /// By default only the name of each node is printed, use [`PrettyTrieNode::with_edge_data`]
+    /// and [`PrettyTrieNode::with_leaf_data`] to also print the data stored on each node.
     pub fn pretty<'a, F>(&'a self, fmt_name: F) -> PrettyTrieNode<'a, K, E, L>
     where
         F: for<'w> Fn(&'w mut dyn std::io::Write, &K::Component) -> std::io::Result<()> + 'a,
     {
         PrettyTrieNode {
             name: None,
-            node: self.root.clone(),
+            node: &self.root,
             fmt_name: Rc::new(fmt_name),
             fmt_edge: None,
             fmt_leaf: None,
@@ -207,27 +1169,42 @@ impl<K: TrieKey + Clone, E: Clone, L: Clone> TrieMap<K, E, L> {
 }
 
 /// Helper struct for implementing [`ptree`]'s traits.
-#[derive(Derivative, Clone)]
-#[derivative(Debug)]
+#[derive(Derivative)]
+#[derivative(Debug, Clone(bound = ""))]
 pub struct PrettyTrieNode<'a, K: TrieKey, E, L> {
-    name: Option<K::Component>,
-    node: TrieNode<K, E, L>,
+    name: Option<&'a K::Component>,
+    #[derivative(Debug = "ignore")]
+    node: &'a TrieNode<K, E, L>,
 
     #[derivative(Debug = "ignore")]
-    fmt_name:
-        Rc<dyn for<'w> Fn(&'w mut dyn std::io::Write, &K::Component) -> std::io::Result<()> + 'a>,
+    fmt_name: FmtName<'a, K::Component>,
     #[derivative(Debug = "ignore")]
-    fmt_edge: Option<Rc<dyn for<'w> Fn(&'w mut dyn std::io::Write, &E) -> fmt::Result>>,
+    fmt_edge: Option<FmtData<'a, E>>,
     #[derivative(Debug = "ignore")]
-    fmt_leaf: Option<Rc<dyn for<'w> Fn(&'w mut dyn std::io::Write, &L) -> fmt::Result>>,
+    fmt_leaf: Option<FmtData<'a, L>>,
 }
 
-impl<'a, K, E, L> ptree::TreeItem for PrettyTrieNode<'a, K, E, L>
-where
-    K: TrieKey + Clone,
-    E: Clone,
-    L: Clone,
-{
+impl<'a, K: TrieKey, E, L> PrettyTrieNode<'a, K, E, L> {
+    /// Also print the data stored on each edge, after the name of the edge.
+    pub fn with_edge_data<F>(mut self, fmt_edge: F) -> Self
+    where
+        F: for<'w> Fn(&'w mut dyn std::io::Write, &E) -> std::io::Result<()> + 'a,
+    {
+        self.fmt_edge = Some(Rc::new(fmt_edge));
+        self
+    }
+
+    /// Also print the data stored on each leaf, after the name of the leaf.
+    pub fn with_leaf_data<F>(mut self, fmt_leaf: F) -> Self
+    where
+        F: for<'w> Fn(&'w mut dyn std::io::Write, &L) -> std::io::Result<()> + 'a,
+    {
+        self.fmt_leaf = Some(Rc::new(fmt_leaf));
+        self
+    }
+}
+
+impl<'a, K: TrieKey, E, L> ptree::TreeItem for PrettyTrieNode<'a, K, E, L> {
     type Child = PrettyTrieNode<'a, K, E, L>;
 
     fn write_self<W: std::io::Write>(
@@ -235,29 +1212,39 @@ where
         f: &mut W,
         _style: &ptree::Style,
     ) -> std::io::Result<()> {
-        // TODO: Also print the data associated with each node.
-        if let Some(name) = &self.name {
-            match self.node {
-                TrieNode::Leaf { .. } => (self.fmt_name)(f, name),
-                TrieNode::Edge { .. } => (self.fmt_name)(f, name),
+        if let Some(name) = self.name {
+            (self.fmt_name)(f, name)?;
+        }
+
+        match (self.node, &self.fmt_edge, &self.fmt_leaf) {
+            (TrieNode::Edge { data, .. }, Some(fmt_edge), _) => {
+                if self.name.is_some() {
+                    f.write_all(b" ")?;
+                }
+                fmt_edge(f, data)
             }
-        } else {
-            Ok(())
+            (TrieNode::Leaf { data }, _, Some(fmt_leaf)) => {
+                if self.name.is_some() {
+                    f.write_all(b" ")?;
+                }
+                fmt_leaf(f, data)
+            }
+            _ => Ok(()),
         }
     }
 
     fn children(&self) -> Cow<[Self::Child]> {
-        match &self.node {
+        match self.node {
             TrieNode::Leaf { .. } => Cow::Owned(vec![]),
             TrieNode::Edge { children, .. } => {
                 let children: Vec<_> = children
                     .iter()
                     .map(|(name, node)| PrettyTrieNode {
-                        name: Some(name.clone()),
-                        node: node.clone(),
+                        name: Some(name),
+                        node,
                         fmt_name: Rc::clone(&self.fmt_name),
-                        fmt_edge: self.fmt_edge.as_ref().map(|f| Rc::clone(f)),
-                        fmt_leaf: self.fmt_leaf.as_ref().map(|f| Rc::clone(f)),
+                        fmt_edge: self.fmt_edge.clone(),
+                        fmt_leaf: self.fmt_leaf.clone(),
                     })
                     .collect();
 
@@ -267,18 +1254,576 @@ where
     }
 }
 
-impl<'a, K, E, L> fmt::Display for PrettyTrieNode<'a, K, E, L>
-where
-    K: TrieKey + Clone,
-    E: Clone,
-    L: Clone,
-{
+impl<K: TrieKey, E, L> fmt::Display for PrettyTrieNode<'_, K, E, L> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // TODO: This isn't optimal at all, just threw enough code at this to make it work.
+        // ptree only supports writing to an io::Write.
         let mut buf = Vec::new();
-        ptree::write_tree(self, &mut buf).expect("TODO");
-        let buf = String::from_utf8_lossy(&buf[..]);
-        write!(f, "{buf}")?;
-        Ok(())
+        ptree::write_tree(self, &mut buf).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&buf[..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `/` separated key, used for testing.
+    #[derive(Debug, Clone)]
+    struct TestKey(&'static str);
+
+    impl TrieKey for TestKey {
+        type Component = &'static str;
+
+        fn as_components(&self) -> impl Iterator<Item = Self::Component> {
+            self.0.split('/').filter(|c| !c.is_empty())
+        }
+    }
+
+    #[test]
+    fn smoketest_copy_on_write() {
+        // Leaves that can't be cloned can still be modified, nothing is ever shared.
+        struct NotClone(u64);
+        let mut trie: TrieMap<TestKey, (), NotClone> = TrieMap::new();
+        trie.insert_leaf(TestKey("a/b"), NotClone(1)).unwrap();
+        trie.get_leaf_mut(TestKey("a/b")).unwrap().0 = 2;
+        trie.retain(|_, leaf| leaf.0 == 2);
+        assert_eq!(trie.remove(TestKey("a/b")).unwrap().map(|_| ()), Some(()));
+
+        // Modifying the original after a clone leaves the clone untouched, and vice versa.
+        let mut original: TrieMap<TestKey, (), u64> = TrieMap::new();
+        original.insert_leaf(TestKey("a/b/c"), 1).unwrap();
+        let mut clone = original.clone();
+        *original.get_leaf_mut(TestKey("a/b/c")).unwrap() = 2;
+        clone.insert_leaf(TestKey("a/b/d"), 3).unwrap();
+        assert_eq!(original.get_leaf(TestKey("a/b/c")), Some(&2));
+        assert_eq!(original.get_leaf(TestKey("a/b/d")), None);
+        assert_eq!(clone.get_leaf(TestKey("a/b/c")), Some(&1));
+        assert_eq!(clone.get_leaf(TestKey("a/b/d")), Some(&3));
+
+        // Mapping copies the nodes that are still shared.
+        let mapped = clone.map_leaves(|_, leaf| leaf * 10);
+        let leaves: Vec<_> = mapped.iter().map(|(_, leaf)| *leaf).collect();
+        assert_eq!(leaves, [10, 30]);
+        assert_eq!(original.get_leaf(TestKey("a/b/c")), Some(&2));
+    }
+
+    #[test]
+    fn smoketest_remove() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("a/b/c"), 1).unwrap();
+        trie.insert_leaf(TestKey("a/b/d"), 2).unwrap();
+        trie.insert_leaf(TestKey("a/e"), 3).unwrap();
+
+        // Removing a missing path is a no-op.
+        assert!(trie.remove(TestKey("a/b/z")).unwrap().is_none());
+        assert!(trie.remove(TestKey("x/y")).unwrap().is_none());
+
+        let removed = trie.remove(TestKey("a/b/c")).unwrap();
+        assert!(matches!(removed, Some(TrieNode::Leaf { data: 1 })));
+        assert!(trie.get_leaf(TestKey("a/b/c")).is_none());
+        assert_eq!(trie.get_leaf(TestKey("a/b/d")), Some(&2));
+
+        // Removing the last leaf under "a/b" should prune the edge.
+        trie.remove(TestKey("a/b/d")).unwrap();
+        assert!(trie.get(TestKey("a/b")).is_none());
+        assert_eq!(trie.get_leaf(TestKey("a/e")), Some(&3));
+
+        // Edges cannot be removed as leaves.
+        assert!(trie.remove(TestKey("a")).is_err());
+        // A leaf in the middle of the path is an error.
+        assert!(trie.remove(TestKey("a/e/f")).is_err());
+    }
+
+    #[test]
+    fn smoketest_remove_subtree() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("a/b/c"), 1).unwrap();
+        trie.insert_leaf(TestKey("a/b/d"), 2).unwrap();
+        trie.insert_leaf(TestKey("e"), 3).unwrap();
+
+        let removed = trie.remove_subtree(TestKey("a/b")).unwrap();
+        let Some(TrieNode::Edge { children, .. }) = &removed else {
+            panic!("expected an edge, found {removed:?}");
+        };
+        assert_eq!(children.len(), 2);
+
+        // "a" had no other children so it should have been pruned.
+        assert!(trie.get(TestKey("a")).is_none());
+        assert_eq!(trie.get_leaf(TestKey("e")), Some(&3));
+    }
+
+    #[test]
+    fn smoketest_iter() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        assert_eq!(trie.iter().count(), 0);
+
+        trie.insert_leaf(TestKey("b/a"), 3).unwrap();
+        trie.insert_leaf(TestKey("a/c/d"), 2).unwrap();
+        trie.insert_leaf(TestKey("a/b"), 1).unwrap();
+        trie.insert_leaf(TestKey("c"), 4).unwrap();
+
+        let leaves: Vec<_> = trie.iter().collect();
+        assert_eq!(
+            leaves,
+            vec![
+                (vec!["a", "b"], &1),
+                (vec!["a", "c", "d"], &2),
+                (vec!["b", "a"], &3),
+                (vec!["c"], &4),
+            ]
+        );
+    }
+
+    #[test]
+    fn smoketest_merge() {
+        let mut a: TrieMap<TestKey, (), u64> = TrieMap::new();
+        a.insert_leaf(TestKey("x/a"), 1).unwrap();
+        a.insert_leaf(TestKey("x/b"), 2).unwrap();
+
+        let mut b: TrieMap<TestKey, (), u64> = TrieMap::new();
+        b.insert_leaf(TestKey("x/b"), 20).unwrap();
+        b.insert_leaf(TestKey("x/c"), 30).unwrap();
+        b.insert_leaf(TestKey("y/d"), 40).unwrap();
+
+        let mut conflicts = Vec::new();
+        a.merge(b, |path, ours, theirs| {
+            conflicts.push(path.to_vec());
+            *ours += theirs;
+        })
+        .unwrap();
+
+        assert_eq!(conflicts, vec![vec!["x", "b"]]);
+        let leaves: Vec<_> = a.iter().collect();
+        assert_eq!(
+            leaves,
+            vec![
+                (vec!["x", "a"], &1),
+                (vec!["x", "b"], &22),
+                (vec!["x", "c"], &30),
+                (vec!["y", "d"], &40),
+            ]
+        );
+
+        // A leaf on one side and an edge on the other can't be merged.
+        let mut c: TrieMap<TestKey, (), u64> = TrieMap::new();
+        c.insert_leaf(TestKey("x/a/z"), 5).unwrap();
+        assert!(a.merge(c, |_, _, _| ()).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn smoketest_serde_roundtrip() {
+        let mut trie: TrieMap<TestKey, u32, u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("a/b"), 1).unwrap();
+        trie.insert_leaf(TestKey("a/c/d"), 2).unwrap();
+        trie.insert_leaf(TestKey("e"), 3).unwrap();
+
+        let json = serde_json::to_string(&trie).unwrap();
+        // `TestKey` components are `&'static str`, so leak the JSON to deserialize from it.
+        let json: &'static str = Box::leak(json.into_boxed_str());
+        let roundtrip: TrieMap<TestKey, u32, u64> = serde_json::from_str(json).unwrap();
+
+        let before: Vec<_> = trie.iter().collect();
+        let after: Vec<_> = roundtrip.iter().collect();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn smoketest_get_mut() {
+        let mut trie: TrieMap<TestKey, u64, u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("a/b"), 1).unwrap();
+
+        *trie.get_leaf_mut(TestKey("a/b")).unwrap() += 10;
+        assert_eq!(trie.get_leaf(TestKey("a/b")), Some(&11));
+
+        *trie.get_edge_data_mut(TestKey("a")).unwrap() = 5;
+        *trie.get_edge_data_mut(TestKey("")).unwrap() = 7;
+        assert!(matches!(
+            trie.get(TestKey("a")),
+            Some(TrieNode::Edge { data: 5, .. })
+        ));
+        assert!(matches!(
+            trie.get(TestKey("")),
+            Some(TrieNode::Edge { data: 7, .. })
+        ));
+
+        // Leaves don't have edge data, and edges don't have leaf data.
+        assert!(trie.get_edge_data_mut(TestKey("a/b")).is_none());
+        assert!(trie.get_leaf_mut(TestKey("a")).is_none());
+        assert!(trie.get_mut(TestKey("a/b/c")).is_none());
+        assert!(matches!(
+            trie.get_mut(TestKey("a")),
+            Some(TrieNodeMut::Edge { data: 5 })
+        ));
+    }
+
+    #[test]
+    fn smoketest_retain() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("srcs/lib.rs"), 1).unwrap();
+        trie.insert_leaf(TestKey("srcs/lib.rs.orig"), 2).unwrap();
+        trie.insert_leaf(TestKey("target/debug/out.orig"), 3)
+            .unwrap();
+        trie.insert_leaf(TestKey("pb.toml"), 4).unwrap();
+
+        trie.retain(|path, _| !path.last().unwrap().ends_with(".orig"));
+
+        let leaves: Vec<_> = trie.iter().collect();
+        assert_eq!(
+            leaves,
+            vec![(vec!["pb.toml"], &4), (vec!["srcs", "lib.rs"], &1)]
+        );
+        // All of the leaves under "target" were removed so it should have been pruned.
+        assert!(trie.get(TestKey("target")).is_none());
+    }
+
+    #[test]
+    fn smoketest_stats() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        assert_eq!((trie.len(), trie.node_count(), trie.max_depth()), (0, 1, 0));
+
+        trie.insert_leaf(TestKey("a/b/c"), 1).unwrap();
+        trie.insert_leaf(TestKey("a/b/d"), 2).unwrap();
+        trie.insert_leaf(TestKey("e"), 3).unwrap();
+        assert_eq!((trie.len(), trie.node_count(), trie.max_depth()), (3, 6, 3));
+
+        // Replacing a leaf doesn't change anything.
+        trie.insert_leaf(TestKey("e"), 4).unwrap();
+        assert_eq!((trie.len(), trie.node_count(), trie.max_depth()), (3, 6, 3));
+
+        trie.insert(TestKey("a/f"), 5).unwrap();
+        assert_eq!((trie.len(), trie.node_count(), trie.max_depth()), (4, 7, 3));
+
+        // Removing "a/b" removes the deepest nodes.
+        trie.remove_subtree(TestKey("a/b")).unwrap();
+        assert_eq!((trie.len(), trie.node_count(), trie.max_depth()), (2, 4, 2));
+
+        // Removing "a/f" prunes "a".
+        trie.remove(TestKey("a/f")).unwrap();
+        assert_eq!((trie.len(), trie.node_count(), trie.max_depth()), (1, 2, 1));
+
+        let mut other: TrieMap<TestKey, (), u64> = TrieMap::new();
+        other.insert_leaf(TestKey("e"), 6).unwrap();
+        other.insert_leaf(TestKey("g/h"), 7).unwrap();
+        trie.merge(other, |_, _, _| ()).unwrap();
+        assert_eq!((trie.len(), trie.node_count(), trie.max_depth()), (2, 4, 2));
+
+        trie.retain(|path, _| path != ["g", "h"]);
+        assert_eq!((trie.len(), trie.node_count(), trie.max_depth()), (1, 2, 1));
+
+        let stats = TrieStats::from_root(&trie.root);
+        assert_eq!(
+            (stats.leaves(), stats.nodes(), stats.max_depth()),
+            (trie.len(), trie.node_count(), trie.max_depth())
+        );
+    }
+
+    #[test]
+    fn smoketest_match_glob() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("library_a/srcs/lib.rs"), 1)
+            .unwrap();
+        trie.insert_leaf(TestKey("library_a/srcs/util/mod.rs"), 2)
+            .unwrap();
+        trie.insert_leaf(TestKey("library_a/srcs/README.md"), 3)
+            .unwrap();
+        trie.insert_leaf(TestKey("library_a/pb.toml"), 4).unwrap();
+        trie.insert_leaf(TestKey("library_b/srcs/lib.rs"), 5)
+            .unwrap();
+
+        let glob = TrieGlob::new(["library_a/srcs/**/*.rs", "*/pb.toml"]).unwrap();
+        let leaves: Vec<_> = trie.match_glob(&glob, |c| *c).collect();
+        assert_eq!(
+            leaves,
+            vec![
+                (vec!["library_a", "pb.toml"], &4),
+                (vec!["library_a", "srcs", "lib.rs"], &1),
+                (vec!["library_a", "srcs", "util", "mod.rs"], &2),
+            ]
+        );
+
+        // Edges are never matched, only leaves.
+        let glob = TrieGlob::new(["library_a/srcs"]).unwrap();
+        assert_eq!(trie.match_glob(&glob, |c| *c).count(), 0);
+    }
+
+    #[test]
+    fn smoketest_children_range() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("fruit/apple"), 1).unwrap();
+        trie.insert_leaf(TestKey("fruit/banana"), 2).unwrap();
+        trie.insert_leaf(TestKey("fruit/kiwi"), 3).unwrap();
+        trie.insert_leaf(TestKey("fruit/mango"), 4).unwrap();
+        trie.insert_leaf(TestKey("fruit/pear"), 5).unwrap();
+
+        let names: Vec<_> = trie
+            .children_range(TestKey("fruit"), "b".."m")
+            .unwrap()
+            .map(|(name, _)| *name)
+            .collect();
+        assert_eq!(names, vec!["banana", "kiwi"]);
+
+        let names: Vec<_> = trie
+            .children_range(TestKey("fruit"), "mango"..)
+            .unwrap()
+            .map(|(name, _)| *name)
+            .collect();
+        assert_eq!(names, vec!["mango", "pear"]);
+
+        assert!(trie.children_range(TestKey("fruit/kiwi"), ..).is_none());
+        assert!(trie.children_range(TestKey("veggies"), ..).is_none());
+    }
+
+    #[test]
+    fn smoketest_insert_leaf_replacing() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("a/b"), 1).unwrap();
+
+        // "a/b" is a file, so it can't have children.
+        assert!(trie.insert_leaf(TestKey("a/b/c"), 2).is_err());
+
+        let displaced = trie.insert_leaf_replacing(TestKey("a/b/c"), 2).unwrap();
+        assert!(matches!(displaced, Some(TrieNode::Leaf { data: 1 })));
+        assert!(matches!(
+            trie.get(TestKey("a/b")),
+            Some(TrieNode::Edge { .. })
+        ));
+        assert_eq!(trie.get_leaf(TestKey("a/b/c")), Some(&2));
+        assert_eq!((trie.len(), trie.node_count()), (1, 4));
+
+        let displaced = trie.insert_leaf_replacing(TestKey("a/b/c"), 3).unwrap();
+        assert!(matches!(displaced, Some(TrieNode::Leaf { data: 2 })));
+        assert!(
+            trie.insert_leaf_replacing(TestKey("a/d"), 4)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn smoketest_take_subtree_graft() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("old/srcs/lib.rs"), 1).unwrap();
+        trie.insert_leaf(TestKey("old/srcs/util/mod.rs"), 2)
+            .unwrap();
+        trie.insert_leaf(TestKey("other/pb.toml"), 3).unwrap();
+
+        let subtree = trie.take_subtree(TestKey("old")).unwrap().unwrap();
+        assert_eq!(subtree.len(), 2);
+        assert!(trie.get(TestKey("old")).is_none());
+        assert_eq!((trie.len(), trie.node_count(), trie.max_depth()), (1, 3, 2));
+
+        assert!(
+            trie.graft(TestKey("new/nested"), subtree)
+                .unwrap()
+                .is_none()
+        );
+        let leaves: Vec<_> = trie.iter().collect();
+        assert_eq!(
+            leaves,
+            vec![
+                (vec!["new", "nested", "srcs", "lib.rs"], &1),
+                (vec!["new", "nested", "srcs", "util", "mod.rs"], &2),
+                (vec!["other", "pb.toml"], &3),
+            ]
+        );
+        assert_eq!((trie.len(), trie.node_count(), trie.max_depth()), (3, 9, 5));
+
+        assert!(trie.take_subtree(TestKey("missing")).unwrap().is_none());
+        let leaf = TrieMap::from_node(TrieNode::Leaf { data: 4 });
+        assert!(trie.graft(TestKey("other/pb.toml/x"), leaf).is_err());
+    }
+
+    #[test]
+    fn smoketest_walk() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("a/b"), 1).unwrap();
+        trie.insert_leaf(TestKey("target/debug/out"), 2).unwrap();
+        trie.insert_leaf(TestKey("z"), 3).unwrap();
+
+        // Skip "target" and everything underneath it.
+        let mut visited = Vec::new();
+        let result = trie.walk::<(), _>(|path, _node| {
+            visited.push(path.join("/"));
+            if path == ["target"] {
+                ControlFlow::Continue(Walk::Skip)
+            } else {
+                ControlFlow::Continue(Walk::Descend)
+            }
+        });
+        assert_eq!(result, ControlFlow::Continue(()));
+        assert_eq!(visited, vec!["", "a", "a/b", "target", "z"]);
+
+        // Stop at the first leaf.
+        let result = trie.walk(|path, node| match node {
+            TrieNode::Leaf { data } => ControlFlow::Break((path.to_vec(), *data)),
+            TrieNode::Edge { .. } => ControlFlow::Continue(Walk::Descend),
+        });
+        assert_eq!(result, ControlFlow::Break((vec!["a", "b"], 1)));
+    }
+
+    #[test]
+    fn smoketest_from_iter() {
+        let mut trie: TrieMap<TestKey, (), u64> = [(TestKey("a/b"), 1), (TestKey("a/c"), 2)]
+            .into_iter()
+            .collect();
+        trie.extend([(TestKey("d"), 3), (TestKey("a/b"), 4)]);
+
+        let leaves: Vec<_> = trie.iter().collect();
+        assert_eq!(
+            leaves,
+            vec![(vec!["a", "b"], &4), (vec!["a", "c"], &2), (vec!["d"], &3)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "failed to insert leaf")]
+    fn test_extend_invalid_key() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        trie.extend([(TestKey("a"), 1), (TestKey("a/b"), 2)]);
+    }
+
+    #[test]
+    fn smoketest_pretty() {
+        let mut trie: TrieMap<TestKey, u64, u64> = TrieMap::new_with_root(0);
+        trie.insert_leaf(TestKey("a/b"), 1).unwrap();
+        *trie.get_edge_data_mut(TestKey("a")).unwrap() = 42;
+
+        let pretty = trie
+            .pretty(|f, name| f.write_all(name.as_bytes()))
+            .with_edge_data(|f, data| write!(f, "(edge {data})"))
+            .with_leaf_data(|f, data| write!(f, "(leaf {data})"))
+            .to_string();
+        let lines: Vec<_> = pretty.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("(edge 0)"));
+        assert!(lines[1].ends_with("a (edge 42)"));
+        assert!(lines[2].ends_with("b (leaf 1)"));
+
+        // Without any data formatters only the names get printed.
+        let pretty = trie
+            .pretty(|f, name| f.write_all(name.as_bytes()))
+            .to_string();
+        assert!(pretty.lines().nth(2).unwrap().ends_with(" b"));
+    }
+
+    #[test]
+    fn test_deep_trie_drop_and_clone() {
+        // Run on a thread with a small stack so recursing would overflow.
+        std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(|| {
+                let mut node: TrieNode<TestKey, (), u64> = TrieNode::Leaf { data: 1 };
+                for _ in 0..100_000 {
+                    node = TrieNode::Edge {
+                        children: BTreeMap::from([("a", node)]).into(),
+                        data: (),
+                    };
+                }
+                let trie = TrieMap::from_node(node);
+                assert_eq!(trie.max_depth(), 100_000);
+
+                let mut cloned = trie.clone();
+                assert_eq!(cloned.len(), 1);
+                assert_eq!(cloned.iter().next().unwrap().0.len(), 100_000);
+
+                // Nodes removed from a trie also drop iteratively.
+                let removed = cloned.remove_subtree(TestKey("a")).unwrap();
+                assert!(cloned.is_empty());
+                drop(removed);
+
+                drop(trie);
+                drop(cloned);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn smoketest_keys_contains_prefix() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("a/b"), 1).unwrap();
+        trie.insert_leaf(TestKey("c"), 2).unwrap();
+        trie.graft(TestKey("empty"), TrieMap::new()).unwrap();
+
+        let keys: Vec<_> = trie.keys().collect();
+        assert_eq!(keys, vec![vec!["a", "b"], vec!["c"]]);
+
+        assert!(trie.contains_prefix(TestKey("")));
+        assert!(trie.contains_prefix(TestKey("a")));
+        assert!(trie.contains_prefix(TestKey("a/b")));
+        assert!(trie.contains_prefix(TestKey("c")));
+        assert!(!trie.contains_prefix(TestKey("a/b/c")));
+        assert!(!trie.contains_prefix(TestKey("d")));
+        // Edges without any leaves don't count.
+        assert!(!trie.contains_prefix(TestKey("empty")));
+    }
+
+    #[test]
+    fn smoketest_heap_size_estimate() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        let empty = trie.heap_size_estimate();
+        assert_eq!(empty, std::mem::size_of::<TrieMap<TestKey, (), u64>>());
+
+        trie.insert_leaf(TestKey("a/b"), 1).unwrap();
+        let small = trie.heap_size_estimate();
+        assert!(small > empty);
+
+        trie.insert_leaf(TestKey("a/c"), 2).unwrap();
+        trie.insert_leaf(TestKey("d/e/f"), 3).unwrap();
+        assert!(trie.heap_size_estimate() > small);
+
+        trie.remove_subtree(TestKey("d")).unwrap();
+        trie.remove(TestKey("a/c")).unwrap();
+        assert_eq!(trie.heap_size_estimate(), small);
+    }
+
+    #[test]
+    fn smoketest_iter_prefix() {
+        let mut trie: TrieMap<TestKey, (), u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("library_a/srcs/lib.rs"), 1)
+            .unwrap();
+        trie.insert_leaf(TestKey("library_a/srcs/util/mod.rs"), 2)
+            .unwrap();
+        trie.insert_leaf(TestKey("library_a/pb.toml"), 3).unwrap();
+        trie.insert_leaf(TestKey("library_b/srcs/lib.rs"), 4)
+            .unwrap();
+
+        let leaves: Vec<_> = trie.iter_prefix(TestKey("library_a/srcs")).collect();
+        assert_eq!(
+            leaves,
+            vec![
+                (vec!["library_a", "srcs", "lib.rs"], &1),
+                (vec!["library_a", "srcs", "util", "mod.rs"], &2),
+            ]
+        );
+
+        // A prefix that points directly at a leaf yields just that leaf.
+        let leaves: Vec<_> = trie.iter_prefix(TestKey("library_a/pb.toml")).collect();
+        assert_eq!(leaves, vec![(vec!["library_a", "pb.toml"], &3)]);
+
+        assert_eq!(trie.iter_prefix(TestKey("library_c")).count(), 0);
+        assert_eq!(trie.iter_prefix(TestKey("")).count(), 4);
+    }
+
+    #[test]
+    fn smoketest_map_leaves() {
+        let mut trie: TrieMap<TestKey, u64, u64> = TrieMap::new();
+        trie.insert_leaf(TestKey("a/b"), 1).unwrap();
+        trie.insert_leaf(TestKey("a/c/d"), 2).unwrap();
+        trie.insert_leaf(TestKey("e"), 3).unwrap();
+        trie.graft(TestKey("empty"), TrieMap::new()).unwrap();
+        *trie.get_edge_data_mut(TestKey("a")).unwrap() = 10;
+
+        let mapped = trie.map_leaves(|path, data| format!("{}={data}", path.join("/")));
+        let leaves: Vec<_> = mapped.iter().map(|(_, data)| data.as_str()).collect();
+        assert_eq!(leaves, ["a/b=1", "a/c/d=2", "e=3"]);
+        assert_eq!(mapped.len(), 3);
+        assert_eq!(mapped.node_count(), 7);
+        assert!(matches!(
+            mapped.get(TestKey("a")),
+            Some(TrieNode::Edge { data: 10, .. })
+        ));
     }
 }
@@ -0,0 +1,369 @@
+//! A path-compressed variant of [`TrieMap`].
+//!
+//! [`TrieMap`]: crate::TrieMap
+
+use std::collections::{BTreeMap, btree_map};
+
+use smallvec::SmallVec;
+
+use crate::TrieKey;
+
+/// A radix (PATRICIA) trie that supports map-like operations.
+///
+/// Unlike [`TrieMap`], chains of edges that only have a single child are collapsed into one node,
+/// so deep paths like `a/b/c/d/e.rs` only require a single allocation when nothing else shares
+/// their prefix. The trade-off is that edges are implicit and cannot store any data, only leaves
+/// can.
+///
+/// Like [`TrieMap`] a path is either a leaf or an edge, a leaf cannot have children.
+///
+/// [`TrieMap`]: crate::TrieMap
+#[derive(Debug)]
+pub struct RadixTrieMap<K: TrieKey, L> {
+    root: BTreeMap<K::Component, RadixChild<K, L>>,
+    len: usize,
+}
+
+/// A compressed chain of components leading to a [`RadixNode`].
+#[derive(Debug)]
+struct RadixChild<K: TrieKey, L> {
+    /// Every component from the parent to `node`, never empty.
+    label: Box<[K::Component]>,
+    node: RadixNode<K, L>,
+}
+
+#[derive(Debug)]
+enum RadixNode<K: TrieKey, L> {
+    Edge {
+        children: BTreeMap<K::Component, RadixChild<K, L>>,
+    },
+    Leaf {
+        data: L,
+    },
+}
+
+impl<K: TrieKey, L> RadixTrieMap<K, L> {
+    /// Create a new, empty, [`RadixTrieMap`].
+    pub fn new() -> Self {
+        RadixTrieMap {
+            root: BTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of leaves in the trie.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns if the trie has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Insert a piece of data at the provided `path`, creating the necessary edges, returning the
+    /// previous data if a leaf already existed at `path`.
+    ///
+    /// # Errors
+    ///
+    /// * If the path is empty.
+    /// * If a component in the provided path, other than the last, is a leaf.
+    /// * If the provided path points to an edge.
+    pub fn insert_leaf(&mut self, path: K, data: L) -> Result<Option<L>, anyhow::Error> {
+        let components: SmallVec<[_; 8]> = path.as_components().collect();
+        if components.is_empty() {
+            anyhow::bail!("inserting an empty key is not allowed");
+        }
+
+        let mut children = &mut self.root;
+        let mut rest = &components[..];
+        loop {
+            let child = match children.entry(rest[0].clone()) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(RadixChild {
+                        label: rest.into(),
+                        node: RadixNode::Leaf { data },
+                    });
+                    self.len += 1;
+                    return Ok(None);
+                }
+                btree_map::Entry::Occupied(entry) => entry.into_mut(),
+            };
+
+            let shared = common_prefix_len(&child.label, rest);
+            if shared < child.label.len() {
+                // Our path ends in the middle of a compressed chain, which is an implicit edge.
+                if shared == rest.len() {
+                    return Err(anyhow::anyhow!("path is an edge: {components:?}"));
+                }
+                child.split(shared);
+            }
+            rest = &rest[shared..];
+
+            match &mut child.node {
+                RadixNode::Leaf { data: prev } if rest.is_empty() => {
+                    return Ok(Some(std::mem::replace(prev, data)));
+                }
+                RadixNode::Leaf { .. } => {
+                    return Err(anyhow::anyhow!("non-edge in path: {components:?}"));
+                }
+                RadixNode::Edge { .. } if rest.is_empty() => {
+                    return Err(anyhow::anyhow!("path is an edge: {components:?}"));
+                }
+                RadixNode::Edge { children: next } => children = next,
+            }
+        }
+    }
+
+    /// Get the leaf at the provided path, if the path exists and points to a leaf.
+    pub fn get_leaf(&self, path: K) -> Option<&L> {
+        let mut components = path.as_components();
+        let mut children = &self.root;
+        loop {
+            let child = children.get(&components.next()?)?;
+            // The first component of the label is the key we just matched.
+            for expected in &child.label[1..] {
+                if components.next()? != *expected {
+                    return None;
+                }
+            }
+            match &child.node {
+                RadixNode::Leaf { data } => return components.next().is_none().then_some(data),
+                RadixNode::Edge { children: next } => children = next,
+            }
+        }
+    }
+
+    /// Remove the leaf at the provided `path`, returning its data.
+    ///
+    /// Edges that are left without children are pruned, and edges left with a single child are
+    /// collapsed back into their parent.
+    pub fn remove(&mut self, path: K) -> Option<L> {
+        let components: SmallVec<[_; 8]> = path.as_components().collect();
+        let removed = remove_leaf(&mut self.root, &components[..])?;
+        self.len -= 1;
+        Some(removed)
+    }
+
+    /// Returns an iterator over every leaf in the trie, in sorted order, along with the full
+    /// path to each leaf.
+    pub fn iter(&self) -> RadixIter<'_, K, L> {
+        RadixIter {
+            stack: vec![(self.root.iter(), 0)],
+            path: Vec::new(),
+        }
+    }
+}
+
+impl<K: TrieKey, L> Default for RadixTrieMap<K, L> {
+    fn default() -> Self {
+        RadixTrieMap::new()
+    }
+}
+
+impl<'a, K: TrieKey, L> IntoIterator for &'a RadixTrieMap<K, L> {
+    type Item = (Vec<K::Component>, &'a L);
+    type IntoIter = RadixIter<'a, K, L>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: TrieKey, L> RadixChild<K, L> {
+    /// Split this chain after `at` components, turning it into an edge with a single child.
+    fn split(&mut self, at: usize) {
+        assert!(at > 0 && at < self.label.len(), "invalid split {at}");
+
+        let (head, tail) = self.label.split_at(at);
+        let (head, tail): (Box<[_]>, Box<[_]>) = (head.into(), tail.into());
+        let node = std::mem::replace(
+            &mut self.node,
+            RadixNode::Edge {
+                children: BTreeMap::new(),
+            },
+        );
+        let RadixNode::Edge { children } = &mut self.node else {
+            unreachable!("just replaced with an edge");
+        };
+        children.insert(tail[0].clone(), RadixChild { label: tail, node });
+        self.label = head;
+    }
+
+    /// If this chain points to an edge with a single child, collapse the child into this chain.
+    fn collapse(&mut self) {
+        let RadixNode::Edge { children } = &mut self.node else {
+            return;
+        };
+        if children.len() != 1 {
+            return;
+        }
+        let (_, child) = children.pop_first().expect("checked len");
+
+        let mut label = std::mem::take(&mut self.label).into_vec();
+        label.extend(child.label.into_vec());
+        self.label = label.into_boxed_slice();
+        self.node = child.node;
+    }
+}
+
+/// Remove the leaf at `components` underneath `children`, pruning and collapsing edges as we
+/// walk back up.
+fn remove_leaf<K: TrieKey, L>(
+    children: &mut BTreeMap<K::Component, RadixChild<K, L>>,
+    components: &[K::Component],
+) -> Option<L> {
+    let first = components.first()?;
+    let child = children.get_mut(first)?;
+    let rest = components.strip_prefix(&child.label[..])?;
+
+    match &mut child.node {
+        RadixNode::Leaf { .. } if rest.is_empty() => {
+            let child = children.remove(first).expect("just found");
+            let RadixNode::Leaf { data } = child.node else {
+                unreachable!("matched a leaf");
+            };
+            Some(data)
+        }
+        RadixNode::Leaf { .. } => None,
+        RadixNode::Edge { children: next } => {
+            let removed = remove_leaf(next, rest)?;
+            if next.is_empty() {
+                children.remove(first);
+            } else {
+                child.collapse();
+            }
+            Some(removed)
+        }
+    }
+}
+
+fn common_prefix_len<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+type ChildIter<'a, K, L> = btree_map::Iter<'a, <K as TrieKey>::Component, RadixChild<K, L>>;
+
+/// Iterator over all of the leaves in a [`RadixTrieMap`], in sorted order.
+pub struct RadixIter<'a, K: TrieKey, L> {
+    /// Iterators over the children of each edge we're descended into, along with the length of
+    /// `path` before we descended into it.
+    stack: Vec<(ChildIter<'a, K, L>, usize)>,
+    /// Path to the edge at the top of `stack`.
+    path: Vec<K::Component>,
+}
+
+impl<'a, K: TrieKey, L> Iterator for RadixIter<'a, K, L> {
+    type Item = (Vec<K::Component>, &'a L);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (children, _) = self.stack.last_mut()?;
+            match children.next() {
+                // Finished with this edge, move back up to its parent.
+                None => {
+                    let (_, len) = self.stack.pop().expect("just peeked");
+                    self.path.truncate(len);
+                }
+                Some((_, child)) => match &child.node {
+                    RadixNode::Leaf { data } => {
+                        let mut path = self.path.clone();
+                        path.extend_from_slice(&child.label);
+                        return Some((path, data));
+                    }
+                    RadixNode::Edge { children } => {
+                        let len = self.path.len();
+                        self.path.extend_from_slice(&child.label);
+                        self.stack.push((children.iter(), len));
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TrieMap;
+
+    /// A `/` separated key, used for testing.
+    #[derive(Debug, Clone)]
+    struct TestKey(&'static str);
+
+    impl TrieKey for TestKey {
+        type Component = &'static str;
+
+        fn as_components(&self) -> impl Iterator<Item = Self::Component> {
+            self.0.split('/').filter(|c| !c.is_empty())
+        }
+    }
+
+    #[test]
+    fn smoketest_radix() {
+        let mut trie: RadixTrieMap<TestKey, u64> = RadixTrieMap::new();
+        assert_eq!(trie.insert_leaf(TestKey("a/b/c/d"), 1).unwrap(), None);
+        assert_eq!(trie.insert_leaf(TestKey("a/b/e"), 2).unwrap(), None);
+        assert_eq!(trie.insert_leaf(TestKey("f"), 3).unwrap(), None);
+        assert_eq!(trie.insert_leaf(TestKey("a/b/e"), 4).unwrap(), Some(2));
+        assert_eq!(trie.len(), 3);
+
+        assert_eq!(trie.get_leaf(TestKey("a/b/c/d")), Some(&1));
+        assert_eq!(trie.get_leaf(TestKey("a/b/e")), Some(&4));
+        assert_eq!(trie.get_leaf(TestKey("a/b")), None);
+        assert_eq!(trie.get_leaf(TestKey("a/b/c/d/g")), None);
+
+        // Edges, implicit or not, can't be replaced by leaves, and leaves can't have children.
+        assert!(trie.insert_leaf(TestKey("a/b"), 5).is_err());
+        assert!(trie.insert_leaf(TestKey("a/b/c"), 5).is_err());
+        assert!(trie.insert_leaf(TestKey("f/g"), 5).is_err());
+
+        let leaves: Vec<_> = trie.iter().collect();
+        assert_eq!(
+            leaves,
+            vec![
+                (vec!["a", "b", "c", "d"], &1),
+                (vec!["a", "b", "e"], &4),
+                (vec!["f"], &3),
+            ]
+        );
+
+        // Removing "a/b/e" should collapse "a/b" and "c/d" back into a single chain.
+        assert_eq!(trie.remove(TestKey("a/b/e")), Some(4));
+        assert_eq!(trie.root.get("a").unwrap().label.len(), 4);
+        assert_eq!(trie.remove(TestKey("a/b/c/d")), Some(1));
+        assert_eq!(trie.remove(TestKey("a/b/c/d")), None);
+        assert!(!trie.root.contains_key("a"));
+        assert_eq!(trie.len(), 1);
+    }
+
+    #[test]
+    fn test_matches_trie_map() {
+        let paths = [
+            "library_a/srcs/lib.rs",
+            "library_a/srcs/util/mod.rs",
+            "library_a/srcs/util/fs.rs",
+            "library_a/pb.toml",
+            "library_b/srcs/deeply/nested/module/lib.rs",
+            "library_b/pb.toml",
+            "README.md",
+        ];
+
+        let mut radix: RadixTrieMap<TestKey, usize> = RadixTrieMap::new();
+        let mut trie: TrieMap<TestKey, (), usize> = TrieMap::new();
+        for (idx, path) in paths.iter().enumerate() {
+            radix.insert_leaf(TestKey(path), idx).unwrap();
+            trie.insert_leaf(TestKey(path), idx).unwrap();
+        }
+        assert!(radix.iter().eq(trie.iter()));
+
+        for path in &paths[..3] {
+            radix.remove(TestKey(path));
+            trie.remove(TestKey(path)).unwrap();
+        }
+        assert!(radix.iter().eq(trie.iter()));
+        for path in paths {
+            assert_eq!(radix.get_leaf(TestKey(path)), trie.get_leaf(TestKey(path)));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! A [`TrieMap`] that can be read from many threads while a single writer updates it.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::{TrieKey, TrieMap};
+
+/// A [`TrieMap`] that is optimized for concurrent reads.
+///
+/// Readers call [`SharedTrieMap::snapshot`] to get an immutable, [`Arc`]-shared, view of the trie
+/// which they can hold onto for as long as they like without blocking anyone else. Writers call
+/// [`SharedTrieMap::update`] which applies changes to a copy of the trie and then atomically
+/// publishes it, so readers never observe a partially applied update.
+///
+/// Versions of the trie share all of their unmodified nodes, an update only copies the nodes
+/// along the paths it modifies.
+#[derive(Debug)]
+pub struct SharedTrieMap<K: TrieKey, E, L> {
+    /// The most recently published version of the trie.
+    current: RwLock<Arc<TrieMap<K, E, L>>>,
+    /// Serializes writers so concurrent updates aren't lost.
+    writer: Mutex<()>,
+}
+
+impl<K, E, L> SharedTrieMap<K, E, L>
+where
+    K: TrieKey,
+    E: Clone,
+    L: Clone,
+{
+    /// Create a new [`SharedTrieMap`] with `trie` as the initial version.
+    pub fn new(trie: TrieMap<K, E, L>) -> Self {
+        SharedTrieMap {
+            current: RwLock::new(Arc::new(trie)),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// Returns an immutable snapshot of the current version of the trie.
+    ///
+    /// The snapshot is unaffected by any later calls to [`SharedTrieMap::update`].
+    pub fn snapshot(&self) -> Arc<TrieMap<K, E, L>> {
+        let current = self.current.read().expect("poisoned");
+        Arc::clone(&current)
+    }
+
+    /// Apply `f` to a copy of the trie and then publish the result as the current version.
+    ///
+    /// Updates are serialized with one another but never block readers, except for the brief
+    /// moment when the new version is published.
+    pub fn update<R>(&self, f: impl FnOnce(&mut TrieMap<K, E, L>) -> R) -> R {
+        let _writer = self.writer.lock().expect("poisoned");
+
+        let mut next = TrieMap::clone(&self.snapshot());
+        let result = f(&mut next);
+
+        let mut current = self.current.write().expect("poisoned");
+        *current = Arc::new(next);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `/` separated key, used for testing.
+    #[derive(Debug, Clone)]
+    struct TestKey(&'static str);
+
+    impl TrieKey for TestKey {
+        type Component = &'static str;
+
+        fn as_components(&self) -> impl Iterator<Item = Self::Component> {
+            self.0.split('/').filter(|c| !c.is_empty())
+        }
+    }
+
+    #[test]
+    fn smoketest_snapshot_isolation() {
+        let shared: SharedTrieMap<TestKey, (), u64> = SharedTrieMap::new(TrieMap::new());
+        shared.update(|trie| trie.insert_leaf(TestKey("a/b"), 1).unwrap());
+
+        let before = shared.snapshot();
+        shared.update(|trie| {
+            trie.insert_leaf(TestKey("a/c"), 2).unwrap();
+            *trie.get_leaf_mut(TestKey("a/b")).unwrap() = 10;
+        });
+        let after = shared.snapshot();
+
+        assert_eq!(before.len(), 1);
+        assert_eq!(before.get_leaf(TestKey("a/b")), Some(&1));
+        assert_eq!(after.len(), 2);
+        assert_eq!(after.get_leaf(TestKey("a/b")), Some(&10));
+    }
+
+    #[test]
+    fn smoketest_structural_sharing() {
+        let shared: SharedTrieMap<TestKey, (), u64> = SharedTrieMap::new(TrieMap::new());
+        shared.update(|trie| {
+            trie.insert_leaf(TestKey("a/b"), 1).unwrap();
+            trie.insert_leaf(TestKey("x/y"), 2).unwrap();
+        });
+
+        let before = shared.snapshot();
+        shared.update(|trie| *trie.get_leaf_mut(TestKey("a/b")).unwrap() = 10);
+        let after = shared.snapshot();
+
+        // The untouched subtree is shared, the modified path was copied.
+        let unchanged = (
+            before.get_leaf(TestKey("x/y")),
+            after.get_leaf(TestKey("x/y")),
+        );
+        assert!(std::ptr::eq(unchanged.0.unwrap(), unchanged.1.unwrap()));
+        assert_eq!(before.get_leaf(TestKey("a/b")), Some(&1));
+        assert_eq!(after.get_leaf(TestKey("a/b")), Some(&10));
+    }
+
+    #[test]
+    fn smoketest_concurrent_readers() {
+        let shared: SharedTrieMap<TestKey, (), u64> = SharedTrieMap::new(TrieMap::new());
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for idx in 0..100 {
+                    shared.update(|trie| trie.insert_leaf(TestKey("a"), idx).unwrap());
+                }
+            });
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut last = 0;
+                    for _ in 0..100 {
+                        let snapshot = shared.snapshot();
+                        let value = snapshot.get_leaf(TestKey("a")).copied().unwrap_or(0);
+                        // Updates are published in order.
+                        assert!(value >= last);
+                        last = value;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(shared.snapshot().get_leaf(TestKey("a")), Some(&99));
+    }
+}
@@ -0,0 +1,137 @@
+//! Size and depth accounting for a [`TrieMap`].
+//!
+//! [`TrieMap`]: crate::TrieMap
+
+use std::mem::size_of;
+use std::sync::Arc;
+
+use crate::{TrieKey, TrieNode};
+
+/// Maximum number of entries in a single node of a [`std::collections::BTreeMap`].
+const BTREE_CAPACITY: usize = 11;
+/// Approximate bookkeeping overhead for a single node of a [`std::collections::BTreeMap`],
+/// e.g. the parent pointer and lengths.
+const BTREE_NODE_OVERHEAD: usize = 16;
+
+/// Counts of the nodes in a [`TrieMap`], maintained as nodes are added and removed.
+///
+/// [`TrieMap`]: crate::TrieMap
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TrieStats {
+    /// Number of leaves.
+    leaves: usize,
+    /// Number of nodes, edges and leaves, including the root.
+    nodes: usize,
+    /// Number of nodes at each depth, the root is at depth 0.
+    ///
+    /// Invariant: the last entry is never zero, so the max depth is `depths.len() - 1`.
+    depths: Vec<usize>,
+}
+
+impl TrieStats {
+    /// Compute the stats for the trie rooted at `root` by walking it.
+    pub(crate) fn from_root<K: TrieKey, E, L>(root: &TrieNode<K, E, L>) -> Self {
+        let mut stats = TrieStats::default();
+        stats.add_subtree(root, 0);
+        stats
+    }
+
+    pub(crate) fn leaves(&self) -> usize {
+        self.leaves
+    }
+
+    pub(crate) fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    pub(crate) fn max_depth(&self) -> usize {
+        self.depths.len().saturating_sub(1)
+    }
+
+    /// Record a single node being added at `depth`.
+    pub(crate) fn add_node(&mut self, depth: usize, is_leaf: bool) {
+        if is_leaf {
+            self.leaves += 1;
+        }
+        self.nodes += 1;
+        if self.depths.len() <= depth {
+            self.depths.resize(depth + 1, 0);
+        }
+        self.depths[depth] += 1;
+    }
+
+    /// Record a single node being removed from `depth`.
+    pub(crate) fn remove_node(&mut self, depth: usize, is_leaf: bool) {
+        if is_leaf {
+            self.leaves -= 1;
+        }
+        self.nodes -= 1;
+        self.depths[depth] -= 1;
+        while self.depths.last() == Some(&0) {
+            self.depths.pop();
+        }
+    }
+
+    /// Record all of the nodes from another trie being added with its root at `depth`.
+    pub(crate) fn add_stats(&mut self, other: &TrieStats, depth: usize) {
+        self.leaves += other.leaves;
+        self.nodes += other.nodes;
+        if self.depths.len() < depth + other.depths.len() {
+            self.depths.resize(depth + other.depths.len(), 0);
+        }
+        for (offset, count) in other.depths.iter().enumerate() {
+            self.depths[depth + offset] += count;
+        }
+    }
+
+    /// Record `node` and all of its descendants being added at `depth`.
+    pub(crate) fn add_subtree<K: TrieKey, E, L>(&mut self, node: &TrieNode<K, E, L>, depth: usize) {
+        let mut stack = vec![(node, depth)];
+        while let Some((node, depth)) = stack.pop() {
+            self.add_node(depth, matches!(node, TrieNode::Leaf { .. }));
+            if let TrieNode::Edge { children, .. } = node {
+                stack.extend(children.values().map(|child| (child, depth + 1)));
+            }
+        }
+    }
+
+    /// Record `node` and all of its descendants being removed from `depth`.
+    pub(crate) fn remove_subtree<K: TrieKey, E, L>(
+        &mut self,
+        node: &TrieNode<K, E, L>,
+        depth: usize,
+    ) {
+        let mut stack = vec![(node, depth)];
+        while let Some((node, depth)) = stack.pop() {
+            self.remove_node(depth, matches!(node, TrieNode::Leaf { .. }));
+            if let TrieNode::Edge { children, .. } = node {
+                stack.extend(children.values().map(|child| (child, depth + 1)));
+            }
+        }
+    }
+}
+
+/// Estimate the number of bytes allocated on the heap for the descendants of `root`.
+///
+/// This includes the maps that hold the children of each edge, and the reference counted
+/// allocations for the nodes themselves, which store edge and leaf data inline. It does not
+/// include any heap memory owned by the edge or leaf data. Nodes that are shared with another
+/// trie are counted in full by both.
+pub(crate) fn heap_size_estimate<K: TrieKey, E, L>(root: &TrieNode<K, E, L>) -> usize {
+    let entry_size = size_of::<K::Component>() + size_of::<Arc<TrieNode<K, E, L>>>();
+    let btree_node_size = BTREE_CAPACITY * entry_size + BTREE_NODE_OVERHEAD;
+    // Strong and weak counts, followed by the node.
+    let arc_node_size = 2 * size_of::<usize>() + size_of::<TrieNode<K, E, L>>();
+
+    let mut total = 0;
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        if let TrieNode::Edge { children, .. } = node {
+            // Assume the B-Tree nodes are full, an empty map doesn't allocate.
+            total += children.len().div_ceil(BTREE_CAPACITY) * btree_node_size;
+            total += children.len() * arc_node_size;
+            stack.extend(children.values());
+        }
+    }
+    total
+}
@@ -0,0 +1,302 @@
+//! Cryptographic digests and a [`Digest`] type that can hold any hash we support.
+//!
+//! Internally we fingerprint files with xxhash because it's fast, but anything we download from
+//! the outside world needs to be verified with a cryptographic hash.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Xxh128Hash, Xxh64Hash};
+
+/// Hash from SHA-256.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sha256Hash([u8; 32]);
+
+impl Sha256Hash {
+    pub fn new(val: [u8; 32]) -> Self {
+        Sha256Hash(val)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// Hash from BLAKE3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Blake3Hash([u8; 32]);
+
+impl Blake3Hash {
+    pub fn new(val: [u8; 32]) -> Self {
+        Blake3Hash(val)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// A hash from any of the algorithms that we support.
+///
+/// Formats as `<algorithm>:<lowercase hex>`, e.g. `sha256:e3b0c442...`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Digest {
+    Xxh64(Xxh64Hash),
+    Xxh128(Xxh128Hash),
+    Sha256(Sha256Hash),
+    Blake3(Blake3Hash),
+}
+
+impl Digest {
+    /// Returns the algorithm used to compute this digest.
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        match self {
+            Digest::Xxh64(_) => DigestAlgorithm::Xxh64,
+            Digest::Xxh128(_) => DigestAlgorithm::Xxh128,
+            Digest::Sha256(_) => DigestAlgorithm::Sha256,
+            Digest::Blake3(_) => DigestAlgorithm::Blake3,
+        }
+    }
+
+    /// Returns if this digest was computed with a cryptographic hash function.
+    pub fn is_cryptographic(&self) -> bool {
+        self.algorithm().is_cryptographic()
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.algorithm())?;
+        match self {
+            Digest::Xxh64(hash) => write!(f, "{:016x}", hash.0),
+            Digest::Xxh128(hash) => write!(f, "{:032x}", hash.0),
+            Digest::Sha256(hash) => write_hex(f, &hash.0[..]),
+            Digest::Blake3(hash) => write_hex(f, &hash.0[..]),
+        }
+    }
+}
+
+impl FromStr for Digest {
+    type Err = DigestParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, hex) = s
+            .split_once(':')
+            .ok_or(DigestParseError::MissingAlgorithm)?;
+        let algorithm: DigestAlgorithm = algorithm.parse()?;
+
+        let expected_len = algorithm.output_len() * 2;
+        if hex.len() != expected_len {
+            return Err(DigestParseError::InvalidLength {
+                algorithm,
+                expected: expected_len,
+                found: hex.len(),
+            });
+        }
+
+        let digest = match algorithm {
+            DigestAlgorithm::Xxh64 => Digest::Xxh64(Xxh64Hash(u64::from_be_bytes(parse_hex(hex)?))),
+            DigestAlgorithm::Xxh128 => {
+                Digest::Xxh128(Xxh128Hash(u128::from_be_bytes(parse_hex(hex)?)))
+            }
+            DigestAlgorithm::Sha256 => Digest::Sha256(Sha256Hash(parse_hex(hex)?)),
+            DigestAlgorithm::Blake3 => Digest::Blake3(Blake3Hash(parse_hex(hex)?)),
+        };
+        Ok(digest)
+    }
+}
+
+impl From<Xxh64Hash> for Digest {
+    fn from(hash: Xxh64Hash) -> Self {
+        Digest::Xxh64(hash)
+    }
+}
+
+impl From<Xxh128Hash> for Digest {
+    fn from(hash: Xxh128Hash) -> Self {
+        Digest::Xxh128(hash)
+    }
+}
+
+impl From<Sha256Hash> for Digest {
+    fn from(hash: Sha256Hash) -> Self {
+        Digest::Sha256(hash)
+    }
+}
+
+impl From<Blake3Hash> for Digest {
+    fn from(hash: Blake3Hash) -> Self {
+        Digest::Blake3(hash)
+    }
+}
+
+/// Hash algorithms that can produce a [`Digest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DigestAlgorithm {
+    Xxh64,
+    Xxh128,
+    Sha256,
+    Blake3,
+}
+
+impl DigestAlgorithm {
+    /// Name of the algorithm, used as the prefix when formatting a [`Digest`].
+    pub fn name(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Xxh64 => "xxh64",
+            DigestAlgorithm::Xxh128 => "xxh128",
+            DigestAlgorithm::Sha256 => "sha256",
+            DigestAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Length of the hash output in bytes.
+    pub fn output_len(&self) -> usize {
+        match self {
+            DigestAlgorithm::Xxh64 => 8,
+            DigestAlgorithm::Xxh128 => 16,
+            DigestAlgorithm::Sha256 => 32,
+            DigestAlgorithm::Blake3 => 32,
+        }
+    }
+
+    /// Returns if the algorithm is suitable for verifying untrusted content.
+    pub fn is_cryptographic(&self) -> bool {
+        match self {
+            DigestAlgorithm::Xxh64 | DigestAlgorithm::Xxh128 => false,
+            DigestAlgorithm::Sha256 | DigestAlgorithm::Blake3 => true,
+        }
+    }
+}
+
+impl fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DigestAlgorithm {
+    type Err = DigestParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xxh64" => Ok(DigestAlgorithm::Xxh64),
+            "xxh128" => Ok(DigestAlgorithm::Xxh128),
+            "sha256" => Ok(DigestAlgorithm::Sha256),
+            "blake3" => Ok(DigestAlgorithm::Blake3),
+            other => Err(DigestParseError::UnknownAlgorithm(other.to_string())),
+        }
+    }
+}
+
+/// Errors that can occur when parsing a [`Digest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DigestParseError {
+    /// Digest was not of the form `<algorithm>:<hex>`.
+    MissingAlgorithm,
+    /// Algorithm prefix is not one we support.
+    UnknownAlgorithm(String),
+    /// Hex portion was the wrong length for the algorithm.
+    InvalidLength {
+        algorithm: DigestAlgorithm,
+        expected: usize,
+        found: usize,
+    },
+    /// Hex portion contained a non-hex character.
+    InvalidHex,
+}
+
+impl fmt::Display for DigestParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DigestParseError::MissingAlgorithm => {
+                write!(f, "expected a digest of the form '<algorithm>:<hex>'")
+            }
+            DigestParseError::UnknownAlgorithm(name) => {
+                write!(f, "unknown digest algorithm '{name}'")
+            }
+            DigestParseError::InvalidLength {
+                algorithm,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{algorithm} digest should have {expected} hex characters, found {found}"
+            ),
+            DigestParseError::InvalidHex => write!(f, "digest contains non-hex characters"),
+        }
+    }
+}
+
+impl std::error::Error for DigestParseError {}
+
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    for byte in bytes {
+        write!(f, "{byte:02x}")?;
+    }
+    Ok(())
+}
+
+fn parse_hex<const N: usize>(hex: &str) -> Result<[u8; N], DigestParseError> {
+    let mut bytes = [0u8; N];
+    if hex.len() != N * 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(DigestParseError::InvalidHex);
+    }
+    for (byte, chunk) in bytes.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        let chunk = std::str::from_utf8(chunk).map_err(|_| DigestParseError::InvalidHex)?;
+        *byte = u8::from_str_radix(chunk, 16).map_err(|_| DigestParseError::InvalidHex)?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smoketest_roundtrip() {
+        let digests = [
+            Digest::Xxh64(Xxh64Hash::new(0xdead_beef)),
+            Digest::Xxh128(Xxh128Hash::new(u128::MAX - 42)),
+            Digest::Sha256(Sha256Hash::new([0xab; 32])),
+            Digest::Blake3(Blake3Hash::new([7; 32])),
+        ];
+        for digest in digests {
+            let s = digest.to_string();
+            let rnd: Digest = s.parse().unwrap();
+            assert_eq!(digest, rnd);
+        }
+    }
+
+    #[test]
+    fn test_parse_sha256() {
+        let empty = "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let digest: Digest = empty.parse().unwrap();
+        assert_eq!(digest.algorithm(), DigestAlgorithm::Sha256);
+        assert!(digest.is_cryptographic());
+        assert_eq!(digest.to_string(), empty);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "deadbeef".parse::<Digest>(),
+            Err(DigestParseError::MissingAlgorithm)
+        );
+        assert_eq!(
+            "md5:deadbeef".parse::<Digest>(),
+            Err(DigestParseError::UnknownAlgorithm("md5".to_string()))
+        );
+        assert!(matches!(
+            "sha256:abcd".parse::<Digest>(),
+            Err(DigestParseError::InvalidLength { .. })
+        ));
+        assert_eq!(
+            "xxh64:zzzzzzzzzzzzzzzz".parse::<Digest>(),
+            Err(DigestParseError::InvalidHex)
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Paths whose components are interned strings.
+
+use std::fmt;
+
+use smallvec::SmallVec;
+
+/// A path whose components are in a [`lasso::Rodeo`].
+///
+/// Two [`InternedPath`]s are equal if they have the same components, which only makes sense when
+/// they were interned with the same [`lasso::Rodeo`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InternedPath(pub SmallVec<[InternedComponent; 8]>);
+
+/// A single component within an [`InternedPath`].
+pub type InternedComponent = lasso::Spur;
+
+impl InternedPath {
+    /// Create a new, empty, [`InternedPath`].
+    pub fn new() -> Self {
+        InternedPath(SmallVec::new())
+    }
+
+    /// Returns the components of this path.
+    pub fn components(&self) -> &[InternedComponent] {
+        &self.0[..]
+    }
+
+    /// Returns the number of components in this path.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns if this path has no components.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the final component of this path, if there is one.
+    pub fn last(&self) -> Option<InternedComponent> {
+        self.0.last().copied()
+    }
+
+    /// Returns the path without its final component, or `None` if the path is empty.
+    pub fn parent(&self) -> Option<InternedPath> {
+        let (_last, parent) = self.0.split_last()?;
+        Some(InternedPath(SmallVec::from_slice(parent)))
+    }
+
+    /// Returns a new path with `component` appended.
+    pub fn join(&self, component: InternedComponent) -> InternedPath {
+        let mut path = self.clone();
+        path.push(component);
+        path
+    }
+
+    /// Returns a new path with all of the components of `other` appended.
+    pub fn join_path(&self, other: &InternedPath) -> InternedPath {
+        let mut path = self.clone();
+        path.0.extend_from_slice(other.components());
+        path
+    }
+
+    /// Appends `component` to this path.
+    pub fn push(&mut self, component: InternedComponent) {
+        self.0.push(component);
+    }
+
+    /// Removes and returns the final component of this path.
+    pub fn pop(&mut self) -> Option<InternedComponent> {
+        self.0.pop()
+    }
+
+    /// Returns if `prefix` is a (non-strict) prefix of this path, compared component-wise.
+    pub fn starts_with(&self, prefix: &InternedPath) -> bool {
+        self.0.starts_with(prefix.components())
+    }
+
+    /// Returns a type that implements [`fmt::Display`] by resolving each component with
+    /// `resolver`, joining them with `/`.
+    pub fn display<'a, R: lasso::Resolver>(
+        &'a self,
+        resolver: &'a R,
+    ) -> InternedPathDisplay<'a, R> {
+        InternedPathDisplay {
+            path: self,
+            resolver,
+        }
+    }
+}
+
+impl FromIterator<InternedComponent> for InternedPath {
+    fn from_iter<I: IntoIterator<Item = InternedComponent>>(iter: I) -> Self {
+        InternedPath(iter.into_iter().collect())
+    }
+}
+
+impl Extend<InternedComponent> for InternedPath {
+    fn extend<I: IntoIterator<Item = InternedComponent>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+/// Adapter returned from [`InternedPath::display`].
+pub struct InternedPathDisplay<'a, R> {
+    path: &'a InternedPath,
+    resolver: &'a R,
+}
+
+impl<R: lasso::Resolver> fmt::Display for InternedPathDisplay<'_, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, component) in self.path.components().iter().enumerate() {
+            if idx > 0 {
+                f.write_str("/")?;
+            }
+            f.write_str(self.resolver.resolve(component))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smoketest_path_math() {
+        let mut strings = lasso::Rodeo::new();
+        let a = strings.get_or_intern("library_a");
+        let srcs = strings.get_or_intern("srcs");
+        let lib = strings.get_or_intern("lib.rs");
+
+        let dir: InternedPath = [a, srcs].into_iter().collect();
+        let file = dir.join(lib);
+
+        assert_eq!(file.len(), 3);
+        assert_eq!(file.last(), Some(lib));
+        assert_eq!(file.parent(), Some(dir.clone()));
+        assert!(file.starts_with(&dir));
+        assert!(file.starts_with(&InternedPath::new()));
+        assert!(!dir.starts_with(&file));
+        assert_eq!(InternedPath::new().parent(), None);
+
+        assert_eq!(file.display(&strings).to_string(), "library_a/srcs/lib.rs");
+    }
+}
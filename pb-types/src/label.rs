@@ -0,0 +1,551 @@
+//! Canonical names for targets in the build graph.
+
+use std::fmt;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use compact_str::CompactString;
+
+use crate::BuildTargetPath;
+
+/// Separator between the repository and the package in a [`Label`].
+const ROOT_PREFIX: &str = "//";
+
+/// Name of a target in the build graph, e.g. `@repo//path/to/pkg:name`.
+///
+/// A [`Label`] is made up of three parts:
+///
+/// * `repository`: the external repository the target lives in, `None` for the root workspace.
+/// * `package`: path from the root of the repository to the directory containing the manifest.
+/// * `name`: name of the target within the package.
+///
+/// When parsing, `//pkg` is shorthand for `//pkg:<last component of pkg>`. Relative forms like
+/// `:name` can be resolved with [`Label::parse_relative`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Label {
+    repository: Option<CompactString>,
+    package: CompactString,
+    name: CompactString,
+}
+
+impl Label {
+    /// Create a new [`Label`], validating each of the parts.
+    pub fn new(
+        repository: Option<&str>,
+        package: &str,
+        name: &str,
+    ) -> Result<Self, LabelParseError> {
+        if let Some(repository) = repository {
+            validate_repository(repository)?;
+        }
+        validate_package(package)?;
+        validate_name(name)?;
+
+        Ok(Label {
+            repository: repository.map(CompactString::new),
+            package: CompactString::new(package),
+            name: CompactString::new(name),
+        })
+    }
+
+    /// Parse a [`Label`] that may be relative to `base`.
+    ///
+    /// Supports all of the absolute forms of [`Label::from_str`] along with:
+    ///
+    /// * `:name`, a target in the same package as `base`.
+    /// * `name`, shorthand for `:name`.
+    /// * `//pkg:name`, a target in the same repository as `base`.
+    pub fn parse_relative(s: &str, base: &Label) -> Result<Self, LabelParseError> {
+        if s.starts_with('@') {
+            return s.parse();
+        }
+        if s.starts_with(ROOT_PREFIX) {
+            let mut label: Label = s.parse()?;
+            label.repository = base.repository.clone();
+            return Ok(label);
+        }
+
+        let name = s.strip_prefix(':').unwrap_or(s);
+        validate_name(name)?;
+        Ok(Label {
+            repository: base.repository.clone(),
+            package: base.package.clone(),
+            name: CompactString::new(name),
+        })
+    }
+
+    /// The repository this target lives in, `None` for the root workspace.
+    pub fn repository(&self) -> Option<&str> {
+        self.repository.as_deref()
+    }
+
+    /// Path of the package, relative to the root of the repository.
+    pub fn package(&self) -> &str {
+        &self.package
+    }
+
+    /// Name of the target within its package.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns a new [`Label`] in the same package with a different name.
+    pub fn with_name(&self, name: &str) -> Result<Self, LabelParseError> {
+        validate_name(name)?;
+        Ok(Label {
+            repository: self.repository.clone(),
+            package: self.package.clone(),
+            name: CompactString::new(name),
+        })
+    }
+
+    /// Iterator over the components of the package path.
+    pub fn package_components(&self) -> impl Iterator<Item = &str> {
+        self.package.split('/').filter(|c| !c.is_empty())
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(repository) = &self.repository {
+            write!(f, "@{repository}")?;
+        }
+        write!(f, "{ROOT_PREFIX}{}:{}", self.package, self.name)
+    }
+}
+
+impl FromStr for Label {
+    type Err = LabelParseError;
+
+    /// Parse an absolute [`Label`], e.g. `@repo//path/to/pkg:name` or `//path/to/pkg`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (repository, rest) = match s.strip_prefix('@') {
+            Some(rest) => {
+                let idx = rest
+                    .find(ROOT_PREFIX)
+                    .ok_or_else(|| LabelParseError::NotAbsolute(s.to_string()))?;
+                (Some(&rest[..idx]), &rest[idx..])
+            }
+            None => (None, s),
+        };
+        let rest = rest
+            .strip_prefix(ROOT_PREFIX)
+            .ok_or_else(|| LabelParseError::NotAbsolute(s.to_string()))?;
+
+        let (package, name) = match rest.split_once(':') {
+            Some((package, name)) => (package, name),
+            // `//path/to/pkg` is shorthand for `//path/to/pkg:pkg`.
+            None => {
+                let name = rest.rsplit('/').next().unwrap_or_default();
+                (rest, name)
+            }
+        };
+
+        Label::new(repository, package, name)
+    }
+}
+
+impl From<&Label> for BuildTargetPath {
+    fn from(label: &Label) -> Self {
+        BuildTargetPath {
+            repository: label.repository.clone().unwrap_or_default(),
+            parents: label.package_components().collect(),
+            name: label.name.clone(),
+        }
+    }
+}
+
+impl From<Label> for BuildTargetPath {
+    fn from(label: Label) -> Self {
+        BuildTargetPath::from(&label)
+    }
+}
+
+impl TryFrom<&BuildTargetPath> for Label {
+    type Error = LabelParseError;
+
+    fn try_from(path: &BuildTargetPath) -> Result<Self, Self::Error> {
+        let repository = (!path.repository.is_empty()).then_some(path.repository.as_str());
+        let package = package_from_path(&path.parents)?;
+        Label::new(repository, &package, &path.name)
+    }
+}
+
+impl TryFrom<BuildTargetPath> for Label {
+    type Error = LabelParseError;
+
+    fn try_from(path: BuildTargetPath) -> Result<Self, Self::Error> {
+        Label::try_from(&path)
+    }
+}
+
+impl fmt::Display for BuildTargetPath {
+    /// Formats the path in the same form as a [`Label`], e.g. `@repo//path/to/pkg:name`.
+    ///
+    /// Components of the package that aren't valid UTF-8 are formatted lossily.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.repository.is_empty() {
+            write!(f, "@{}", self.repository)?;
+        }
+        f.write_str(ROOT_PREFIX)?;
+        for (idx, component) in self.parents.components().enumerate() {
+            if idx > 0 {
+                f.write_str("/")?;
+            }
+            write!(f, "{}", component.as_os_str().to_string_lossy())?;
+        }
+        write!(f, ":{}", self.name)
+    }
+}
+
+impl FromStr for BuildTargetPath {
+    type Err = LabelParseError;
+
+    /// Parse an absolute [`Label`] into a [`BuildTargetPath`], see [`Label::from_str`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Label>().map(BuildTargetPath::from)
+    }
+}
+
+/// A pattern that matches a set of targets in the build graph.
+///
+/// Supports the following forms, each of which may be prefixed with `@repo`:
+///
+/// * `//path/to/pkg:name`, a single target, see [`Label`].
+/// * `//path/to/pkg:all`, every target in a package.
+/// * `//path/to/pkg/...`, every target in a package and all of its sub-packages.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TargetPattern {
+    /// A single target.
+    Target(Label),
+    /// Every target in a single package.
+    Package {
+        repository: Option<CompactString>,
+        package: CompactString,
+    },
+    /// Every target in a package and all of its sub-packages.
+    Recursive {
+        repository: Option<CompactString>,
+        package: CompactString,
+    },
+}
+
+impl TargetPattern {
+    /// The repository this pattern matches targets in, `None` for the root workspace.
+    pub fn repository(&self) -> Option<&str> {
+        match self {
+            TargetPattern::Target(label) => label.repository(),
+            TargetPattern::Package { repository, .. }
+            | TargetPattern::Recursive { repository, .. } => repository.as_deref(),
+        }
+    }
+
+    /// Path of the package this pattern matches targets in, relative to the root of the
+    /// repository.
+    pub fn package(&self) -> &str {
+        match self {
+            TargetPattern::Target(label) => label.package(),
+            TargetPattern::Package { package, .. } | TargetPattern::Recursive { package, .. } => {
+                package
+            }
+        }
+    }
+
+    /// Returns if the provided [`Label`] matches this pattern.
+    pub fn matches(&self, label: &Label) -> bool {
+        if self.repository() != label.repository() {
+            return false;
+        }
+        match self {
+            TargetPattern::Target(target) => target == label,
+            TargetPattern::Package { package, .. } => package == label.package(),
+            TargetPattern::Recursive { package, .. } => {
+                package.is_empty()
+                    || label
+                        .package()
+                        .strip_prefix(package.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            }
+        }
+    }
+}
+
+impl fmt::Display for TargetPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let TargetPattern::Target(label) = self {
+            return write!(f, "{label}");
+        }
+        if let Some(repository) = self.repository() {
+            write!(f, "@{repository}")?;
+        }
+        match self {
+            TargetPattern::Target(_) => unreachable!("handled above"),
+            TargetPattern::Package { package, .. } => write!(f, "{ROOT_PREFIX}{package}:all"),
+            TargetPattern::Recursive { package, .. } if package.is_empty() => {
+                write!(f, "{ROOT_PREFIX}...")
+            }
+            TargetPattern::Recursive { package, .. } => write!(f, "{ROOT_PREFIX}{package}/..."),
+        }
+    }
+}
+
+impl FromStr for TargetPattern {
+    type Err = LabelParseError;
+
+    /// Parse a [`TargetPattern`], e.g. `//path/to/pkg:all` or `@repo//path/...`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (repository, rest) = match s.strip_prefix('@') {
+            Some(rest) => {
+                let idx = rest
+                    .find(ROOT_PREFIX)
+                    .ok_or_else(|| LabelParseError::NotAbsolute(s.to_string()))?;
+                (Some(&rest[..idx]), &rest[idx..])
+            }
+            None => (None, s),
+        };
+        let rest = rest
+            .strip_prefix(ROOT_PREFIX)
+            .ok_or_else(|| LabelParseError::NotAbsolute(s.to_string()))?;
+        if let Some(repository) = repository {
+            validate_repository(repository)?;
+        }
+        let repository = repository.map(CompactString::new);
+
+        if let Some(package) = rest.strip_suffix("...") {
+            let package = match package {
+                "" => "",
+                package => package
+                    .strip_suffix('/')
+                    .ok_or_else(|| LabelParseError::InvalidPackage(rest.to_string()))?,
+            };
+            validate_package(package)?;
+            let package = CompactString::new(package);
+            return Ok(TargetPattern::Recursive {
+                repository,
+                package,
+            });
+        }
+        if let Some(package) = rest.strip_suffix(":all") {
+            validate_package(package)?;
+            let package = CompactString::new(package);
+            return Ok(TargetPattern::Package {
+                repository,
+                package,
+            });
+        }
+
+        s.parse().map(TargetPattern::Target)
+    }
+}
+
+/// Errors that can occur when parsing a [`Label`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelParseError {
+    /// Label did not start with `//` or `@repo//`.
+    NotAbsolute(String),
+    /// Repository name contained invalid characters.
+    InvalidRepository(String),
+    /// Package path was malformed.
+    InvalidPackage(String),
+    /// Target name was malformed.
+    InvalidName(String),
+}
+
+impl fmt::Display for LabelParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LabelParseError::NotAbsolute(s) => {
+                write!(f, "label '{s}' must start with '//' or '@repo//'")
+            }
+            LabelParseError::InvalidRepository(s) => write!(f, "invalid repository name '{s}'"),
+            LabelParseError::InvalidPackage(s) => write!(f, "invalid package path '{s}'"),
+            LabelParseError::InvalidName(s) => write!(f, "invalid target name '{s}'"),
+        }
+    }
+}
+
+impl std::error::Error for LabelParseError {}
+
+fn validate_repository(repository: &str) -> Result<(), LabelParseError> {
+    let valid = !repository.is_empty()
+        && repository
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(LabelParseError::InvalidRepository(repository.to_string()))
+    }
+}
+
+fn validate_package(package: &str) -> Result<(), LabelParseError> {
+    // The root package is the empty string.
+    if package.is_empty() {
+        return Ok(());
+    }
+    let valid = !package.contains(':')
+        && package
+            .split('/')
+            .all(|c| !c.is_empty() && c != "." && c != "..");
+    if valid {
+        Ok(())
+    } else {
+        Err(LabelParseError::InvalidPackage(package.to_string()))
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), LabelParseError> {
+    let valid = !name.is_empty()
+        && !name.contains(':')
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && name
+            .split('/')
+            .all(|c| !c.is_empty() && c != "." && c != "..");
+    if valid {
+        Ok(())
+    } else {
+        Err(LabelParseError::InvalidName(name.to_string()))
+    }
+}
+
+fn package_from_path(path: &Path) -> Result<String, LabelParseError> {
+    let mut package = String::new();
+    for component in path.components() {
+        let Component::Normal(component) = component else {
+            return Err(LabelParseError::InvalidPackage(path.display().to_string()));
+        };
+        let component = component
+            .to_str()
+            .ok_or_else(|| LabelParseError::InvalidPackage(path.display().to_string()))?;
+        if !package.is_empty() {
+            package.push('/');
+        }
+        package.push_str(component);
+    }
+    Ok(package)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn smoketest_parse() {
+        let label: Label = "@crates_io//serde/src:lib".parse().unwrap();
+        assert_eq!(label.repository(), Some("crates_io"));
+        assert_eq!(label.package(), "serde/src");
+        assert_eq!(label.name(), "lib");
+        assert_eq!(label.to_string(), "@crates_io//serde/src:lib");
+
+        let label: Label = "//library_a".parse().unwrap();
+        assert_eq!(label.repository(), None);
+        assert_eq!(label.package(), "library_a");
+        assert_eq!(label.name(), "library_a");
+        assert_eq!(label.to_string(), "//library_a:library_a");
+
+        let label: Label = "//:root".parse().unwrap();
+        assert_eq!(label.package(), "");
+        assert_eq!(label.to_string(), "//:root");
+    }
+
+    #[test]
+    fn test_parse_relative() {
+        let base: Label = "@repo//library_a:lib".parse().unwrap();
+
+        let label = Label::parse_relative(":tests", &base).unwrap();
+        assert_eq!(label.to_string(), "@repo//library_a:tests");
+        let label = Label::parse_relative("tests", &base).unwrap();
+        assert_eq!(label.to_string(), "@repo//library_a:tests");
+        let label = Label::parse_relative("//library_b:lib", &base).unwrap();
+        assert_eq!(label.to_string(), "@repo//library_b:lib");
+        let label = Label::parse_relative("@other//library_b:lib", &base).unwrap();
+        assert_eq!(label.to_string(), "@other//library_b:lib");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            "library_a:lib".parse::<Label>(),
+            Err(LabelParseError::NotAbsolute(_))
+        ));
+        assert!(matches!(
+            "@//library_a:lib".parse::<Label>(),
+            Err(LabelParseError::InvalidRepository(_))
+        ));
+        assert!(matches!(
+            "//library_a/../b:lib".parse::<Label>(),
+            Err(LabelParseError::InvalidPackage(_))
+        ));
+        assert!(matches!(
+            "//library_a:".parse::<Label>(),
+            Err(LabelParseError::InvalidName(_))
+        ));
+    }
+
+    #[test]
+    fn test_target_pattern() {
+        let roundtrip = |s: &str| {
+            let pattern: TargetPattern = s.parse().unwrap();
+            assert_eq!(pattern.to_string(), s);
+            pattern
+        };
+        let pattern = roundtrip("//library_a:lib");
+        assert!(matches!(pattern, TargetPattern::Target(_)));
+        let pattern = roundtrip("@repo//library_a:all");
+        assert!(matches!(pattern, TargetPattern::Package { .. }));
+        assert_eq!(pattern.repository(), Some("repo"));
+        let pattern = roundtrip("//library_a/...");
+        assert!(matches!(pattern, TargetPattern::Recursive { .. }));
+        assert_eq!(pattern.package(), "library_a");
+        let pattern = roundtrip("//...");
+        assert_eq!(pattern.package(), "");
+
+        let label = |s: &str| s.parse::<Label>().unwrap();
+        let pattern: TargetPattern = "//library_a/...".parse().unwrap();
+        assert!(pattern.matches(&label("//library_a:lib")));
+        assert!(pattern.matches(&label("//library_a/nested:lib")));
+        assert!(!pattern.matches(&label("//library_ab:lib")));
+        assert!(!pattern.matches(&label("@repo//library_a:lib")));
+        let pattern: TargetPattern = "//library_a:all".parse().unwrap();
+        assert!(pattern.matches(&label("//library_a:lib")));
+        assert!(!pattern.matches(&label("//library_a/nested:lib")));
+
+        assert!(matches!(
+            "library_a/...".parse::<TargetPattern>(),
+            Err(LabelParseError::NotAbsolute(_))
+        ));
+        assert!(matches!(
+            "//library_a...".parse::<TargetPattern>(),
+            Err(LabelParseError::InvalidPackage(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_target_path_roundtrip() {
+        let label: Label = "@repo//a/b:c".parse().unwrap();
+        let path = BuildTargetPath::from(&label);
+        assert_eq!(path.repository, "repo");
+        assert_eq!(path.parents, PathBuf::from("a/b"));
+        assert_eq!(path.name, "c");
+        assert_eq!(Label::try_from(&path).unwrap(), label);
+
+        let label: Label = "//:c".parse().unwrap();
+        let path = BuildTargetPath::from(&label);
+        assert_eq!(path.repository, "");
+        assert_eq!(Label::try_from(&path).unwrap(), label);
+
+        for s in ["@repo//a/b:c", "//:c", "//a:a"] {
+            let path: BuildTargetPath = s.parse().unwrap();
+            assert_eq!(path.to_string(), s);
+        }
+        let path: BuildTargetPath = "//a/b".parse().unwrap();
+        assert_eq!(path.to_string(), "//a/b:b");
+        assert!(matches!(
+            "a/b:c".parse::<BuildTargetPath>(),
+            Err(LabelParseError::NotAbsolute(_))
+        ));
+    }
+}
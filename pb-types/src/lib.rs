@@ -2,10 +2,19 @@
 //!
 //! The goal of this crate is to be very lightweight, so take care with adding dependencies.
 
+use std::fmt;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
 use compact_str::CompactString;
-use smallvec::SmallVec;
+
+mod digest;
+mod interned;
+mod label;
+
+pub use digest::{Blake3Hash, Digest, DigestAlgorithm, DigestParseError, Sha256Hash};
+pub use interned::{InternedComponent, InternedPath, InternedPathDisplay};
+pub use label::{Label, LabelParseError, TargetPattern};
 
 /// Metadata we track for a file to determine when it's changed.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -25,6 +34,20 @@ pub struct FileMetadata<T> {
 pub type FileMetadataXx64 = FileMetadata<Xxh64Hash>;
 pub type FileMetadataXx128 = FileMetadata<Xxh128Hash>;
 
+impl<T> FileMetadata<T> {
+    /// Returns if the `stat` portion of this metadata differs from `other`, ignoring the
+    /// fingerprint.
+    ///
+    /// Used to decide if a file needs to be re-fingerprinted. Modified times are compared with
+    /// [`Timespec::coarse_eq`].
+    pub fn stat_changed<U>(&self, other: &FileMetadata<U>) -> bool {
+        self.size != other.size
+            || self.inode != other.inode
+            || self.mode != other.mode
+            || !self.mtime.coarse_eq(&other.mtime)
+    }
+}
+
 impl FileMetadataXx64 {
     pub fn test_rand(rng: &mut impl rand::Rng) -> Self {
         FileMetadata {
@@ -39,6 +62,7 @@ impl FileMetadataXx64 {
 
 /// Hash from xxh64.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Xxh64Hash(u64);
 
 impl Xxh64Hash {
@@ -49,6 +73,7 @@ impl Xxh64Hash {
 
 /// Hash from xxh128.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Xxh128Hash(u128);
 
 impl Xxh128Hash {
@@ -58,7 +83,10 @@ impl Xxh128Hash {
 }
 
 /// Time info returned from a `stat` call.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+///
+/// Ordered chronologically, assuming `nanos` is in the range `0..1_000_000_000`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Timespec {
     /// Seconds.
     pub secs: i64,
@@ -86,10 +114,127 @@ impl Timespec {
             nanos: rng.random(),
         }
     }
+
+    /// Returns the amount of time elapsed from `earlier` to `self`, or `None` if `earlier` is
+    /// later than `self`.
+    ///
+    /// Saturates at [`Duration::MAX`] if the difference doesn't fit.
+    pub fn duration_since(&self, earlier: Timespec) -> Option<Duration> {
+        let diff = self.total_nanos() - earlier.total_nanos();
+        let diff = u128::try_from(diff).ok()?;
+        Some(nanos_to_duration(diff))
+    }
+
+    /// Returns the absolute amount of time between `self` and `other`.
+    ///
+    /// Saturates at [`Duration::MAX`] if the difference doesn't fit.
+    pub fn abs_diff(&self, other: Timespec) -> Duration {
+        let diff = self.total_nanos() - other.total_nanos();
+        nanos_to_duration(diff.unsigned_abs())
+    }
+
+    /// Nanoseconds since the epoch, which can't overflow since both fields are `i64`s.
+    fn total_nanos(&self) -> i128 {
+        i128::from(self.secs) * NANOS_PER_SEC + i128::from(self.nanos)
+    }
+
+    /// Returns if this [`Timespec`] has sub-second precision.
+    ///
+    /// Not all filesystems record nanoseconds, in which case `nanos` is always 0.
+    pub fn has_nanos(&self) -> bool {
+        self.nanos != 0
+    }
+
+    /// Compare two [`Timespec`]s at the coarsest granularity that either supports.
+    ///
+    /// If either side lacks sub-second precision, e.g. one was read from a filesystem that only
+    /// tracks seconds, then only the seconds are compared. This prevents us from treating a file
+    /// as modified just because it moved between filesystems of differing granularity.
+    pub fn coarse_eq(&self, other: &Timespec) -> bool {
+        if self.has_nanos() && other.has_nanos() {
+            self == other
+        } else {
+            self.secs == other.secs
+        }
+    }
+}
+
+/// Number of nanoseconds in a second.
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+/// Convert a number of nanoseconds into a [`Duration`], saturating at [`Duration::MAX`].
+fn nanos_to_duration(nanos: u128) -> Duration {
+    let nanos_per_sec = NANOS_PER_SEC.unsigned_abs();
+    let Ok(secs) = u64::try_from(nanos / nanos_per_sec) else {
+        return Duration::MAX;
+    };
+    let subsec_nanos = u32::try_from(nanos % nanos_per_sec).expect("less than a second");
+    Duration::new(secs, subsec_nanos)
 }
 
+impl From<SystemTime> for Timespec {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(after) => Timespec {
+                secs: i64::try_from(after.as_secs()).expect("overflowed timespec"),
+                nanos: i64::from(after.subsec_nanos()),
+            },
+            // Times before the epoch have negative seconds, but nanos always count forward.
+            Err(err) => {
+                let before = err.duration();
+                let secs = i64::try_from(before.as_secs()).expect("overflowed timespec");
+                match before.subsec_nanos() {
+                    0 => Timespec {
+                        secs: -secs,
+                        nanos: 0,
+                    },
+                    nanos => Timespec {
+                        secs: -secs - 1,
+                        nanos: 1_000_000_000 - i64::from(nanos),
+                    },
+                }
+            }
+        }
+    }
+}
+
+impl TryFrom<Timespec> for SystemTime {
+    type Error = InvalidTimespec;
+
+    fn try_from(time: Timespec) -> Result<Self, Self::Error> {
+        let nanos = u32::try_from(time.nanos)
+            .ok()
+            .filter(|nanos| *nanos < 1_000_000_000)
+            .ok_or(InvalidTimespec(time))?;
+        let secs = Duration::from_secs(time.secs.unsigned_abs());
+
+        let whole = if time.secs >= 0 {
+            SystemTime::UNIX_EPOCH.checked_add(secs)
+        } else {
+            SystemTime::UNIX_EPOCH.checked_sub(secs)
+        };
+        whole
+            .and_then(|whole| whole.checked_add(Duration::from_nanos(u64::from(nanos))))
+            .ok_or(InvalidTimespec(time))
+    }
+}
+
+/// Error returned when a [`Timespec`] can't be represented as a [`SystemTime`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTimespec(pub Timespec);
+
+impl fmt::Display for InvalidTimespec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timespec out of range: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidTimespec {}
+
 /// Location of a [`BuildTarget`].
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// See [`Label`] for the canonical string form.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BuildTargetPath {
     /// The repository we're located in. `None` indicates the root workspace.
     pub repository: CompactString,
@@ -109,6 +254,38 @@ pub struct BuildTarget {
     pub build_deps: Vec<BuildTargetPath>,
     /// Dependencies on source files.
     pub source_deps: Vec<SourceDependency>,
+    /// Which other targets are allowed to depend on this one.
+    pub visibility: Visibility,
+    /// Free-form tags used to apply policy to this target, e.g. `manual` or `no-cache`.
+    pub tags: Vec<CompactString>,
+}
+
+/// Which other targets are allowed to depend on a [`BuildTarget`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Visibility {
+    /// Any target can depend on this one.
+    #[default]
+    Public,
+    /// Only targets in the same package can depend on this one.
+    Private,
+    /// Only targets in the same package, or that match one of the patterns, can depend on this
+    /// one.
+    Allow(Vec<TargetPattern>),
+}
+
+impl Visibility {
+    /// Returns if `dependent` is allowed to depend on `target`, which has this visibility.
+    pub fn allows(&self, target: &Label, dependent: &Label) -> bool {
+        let same_package = target.repository() == dependent.repository()
+            && target.package() == dependent.package();
+        match self {
+            Visibility::Public => true,
+            Visibility::Private => same_package,
+            Visibility::Allow(patterns) => {
+                same_package || patterns.iter().any(|pattern| pattern.matches(dependent))
+            }
+        }
+    }
 }
 
 /// Types of source file dependencies that a [`BuildTarget`] can have.
@@ -122,13 +299,6 @@ pub enum SourceDependency {
     Rule(BuildTargetPath),
 }
 
-/// A path whose components are in a [`lasso::Rodeo`].
-#[derive(Clone, Debug)]
-pub struct InternedPath(pub SmallVec<[InternedComponent; 8]>);
-
-/// A single component within an [`InternedPath`].
-pub type InternedComponent = lasso::Spur;
-
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum BuildKey {
     /// User-defined target (from BUILD.pb or manifest).
@@ -153,9 +323,147 @@ pub struct CompileConfig {
     /// Optimization level we're compiling for.
     opt_level: OptimizationLevel,
     /// Compilation flags that effect the output.
+    ///
+    /// Always sorted and deduplicated, see [`CompileConfigBuilder::flag`].
+    flags: Vec<CompactString>,
+}
+
+impl CompileConfig {
+    /// Returns a [`CompileConfigBuilder`] for the specified compiler.
+    ///
+    /// Defaults to targeting the host with no optimizations and no additional flags.
+    pub fn builder(
+        compiler: impl Into<CompactString>,
+        version: semver::Version,
+    ) -> CompileConfigBuilder {
+        CompileConfigBuilder {
+            compiler: compiler.into(),
+            version,
+            target_triple: target_lexicon::Triple::host(),
+            opt_level: OptimizationLevel::None,
+            flags: Vec::new(),
+        }
+    }
+
+    /// Name of the compiler.
+    pub fn compiler(&self) -> &str {
+        &self.compiler
+    }
+
+    /// Version of the compiler.
+    pub fn version(&self) -> &semver::Version {
+        &self.version
+    }
+
+    /// Target we're building for.
+    pub fn target_triple(&self) -> &target_lexicon::Triple {
+        &self.target_triple
+    }
+
+    /// Optimization level we're compiling for.
+    pub fn opt_level(&self) -> OptimizationLevel {
+        self.opt_level
+    }
+
+    /// Compilation flags that effect the output, sorted and deduplicated.
+    pub fn flags(&self) -> &[CompactString] {
+        &self.flags[..]
+    }
+
+    /// Returns a hash of this config that is stable across processes, platforms, and versions of
+    /// `pb`, suitable for use in action cache keys.
+    ///
+    /// Unlike [`std::hash::Hash`] this does not depend on the layout of any types, every field is
+    /// hashed in a canonical string form.
+    pub fn stable_hash(&self) -> Xxh128Hash {
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        let mut write_field = |field: &str| {
+            // Length prefix every field so adjacent fields can't alias one another.
+            hasher.update(
+                &u64::try_from(field.len())
+                    .expect("usize fits in u64")
+                    .to_le_bytes(),
+            );
+            hasher.update(field.as_bytes());
+        };
+
+        write_field(&self.compiler);
+        write_field(&self.version.to_string());
+        write_field(&self.target_triple.to_string());
+        write_field(self.opt_level.name());
+        for flag in &self.flags {
+            write_field(flag);
+        }
+
+        Xxh128Hash(hasher.digest128())
+    }
+}
+
+/// Builder for a [`CompileConfig`].
+#[derive(Debug, Clone)]
+pub struct CompileConfigBuilder {
+    compiler: CompactString,
+    version: semver::Version,
+    target_triple: target_lexicon::Triple,
+    opt_level: OptimizationLevel,
     flags: Vec<CompactString>,
 }
 
+impl CompileConfigBuilder {
+    /// Set the target we're building for.
+    pub fn target_triple(mut self, triple: target_lexicon::Triple) -> Self {
+        self.target_triple = triple;
+        self
+    }
+
+    /// Set the optimization level we're compiling for.
+    pub fn opt_level(mut self, opt_level: OptimizationLevel) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    /// Add a compilation flag that effects the output.
+    ///
+    /// Flags are treated as a set, so the order they're added in does not matter.
+    pub fn flag(mut self, flag: impl Into<CompactString>) -> Self {
+        self.flags.push(flag.into());
+        self
+    }
+
+    /// Add multiple compilation flags, see [`CompileConfigBuilder::flag`].
+    pub fn flags<I, F>(mut self, flags: I) -> Self
+    where
+        I: IntoIterator<Item = F>,
+        F: Into<CompactString>,
+    {
+        self.flags.extend(flags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Consumes this [`CompileConfigBuilder`] constructing a [`CompileConfig`].
+    pub fn build(self) -> CompileConfig {
+        let CompileConfigBuilder {
+            compiler,
+            version,
+            target_triple,
+            opt_level,
+            mut flags,
+        } = self;
+
+        // Canonicalize our flags so equivalent configs are equal.
+        flags.sort();
+        flags.dedup();
+
+        CompileConfig {
+            compiler,
+            version,
+            target_triple,
+            opt_level,
+            flags,
+        }
+    }
+}
+
 /// Represents compiler optimization levels across different compilers.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum OptimizationLevel {
@@ -177,3 +485,154 @@ pub enum OptimizationLevel {
     /// Debug-friendly optimization - some performance with preserved debugging.
     Debug,
 }
+
+impl OptimizationLevel {
+    /// A stable name for this optimization level.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OptimizationLevel::None => "none",
+            OptimizationLevel::Basic => "basic",
+            OptimizationLevel::Standard => "standard",
+            OptimizationLevel::All => "all",
+            OptimizationLevel::Size => "size",
+            OptimizationLevel::MinSize => "min_size",
+            OptimizationLevel::MaxPerformence => "max_performance",
+            OptimizationLevel::Debug => "debug",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visibility() {
+        let label = |s: &str| s.parse::<Label>().unwrap();
+        let target = label("//library_a:lib");
+        let sibling = label("//library_a:tests");
+        let other = label("//library_b:lib");
+        let nested = label("//library_b/nested:lib");
+
+        assert!(Visibility::Public.allows(&target, &other));
+        assert!(Visibility::Private.allows(&target, &sibling));
+        assert!(!Visibility::Private.allows(&target, &other));
+
+        let allow = Visibility::Allow(vec!["//library_b/...".parse().unwrap()]);
+        assert!(allow.allows(&target, &sibling));
+        assert!(allow.allows(&target, &other));
+        assert!(allow.allows(&target, &nested));
+        assert!(!allow.allows(&target, &label("//library_c:lib")));
+    }
+
+    #[test]
+    fn test_timespec_system_time_roundtrip() {
+        let times = [
+            SystemTime::UNIX_EPOCH,
+            SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+            SystemTime::UNIX_EPOCH - Duration::new(10, 250_000_000),
+            SystemTime::UNIX_EPOCH - Duration::from_secs(3),
+        ];
+        for time in times {
+            let timespec = Timespec::from(time);
+            assert!((0..1_000_000_000).contains(&timespec.nanos));
+            assert_eq!(SystemTime::try_from(timespec), Ok(time));
+        }
+
+        let before = Timespec::from(SystemTime::UNIX_EPOCH - Duration::new(10, 250_000_000));
+        assert_eq!(
+            before,
+            Timespec {
+                secs: -11,
+                nanos: 750_000_000
+            }
+        );
+
+        let invalid = Timespec { secs: 0, nanos: -1 };
+        assert_eq!(SystemTime::try_from(invalid), Err(InvalidTimespec(invalid)));
+    }
+
+    #[test]
+    fn test_timespec_arithmetic() {
+        let a = Timespec {
+            secs: 10,
+            nanos: 900_000_000,
+        };
+        let b = Timespec {
+            secs: 12,
+            nanos: 100_000_000,
+        };
+
+        assert!(a < b);
+        assert_eq!(b.duration_since(a), Some(Duration::from_millis(1_200)));
+        assert_eq!(a.duration_since(b), None);
+        assert_eq!(a.abs_diff(b), Duration::from_millis(1_200));
+        assert_eq!(a.abs_diff(a), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_timespec_arithmetic_extremes() {
+        let epoch = Timespec { secs: 0, nanos: 0 };
+        let max = Timespec {
+            secs: i64::MAX,
+            nanos: 0,
+        };
+        let expected = Duration::from_secs(i64::MAX.unsigned_abs());
+        assert_eq!(max.abs_diff(epoch), expected);
+        assert_eq!(epoch.abs_diff(max), expected);
+        assert_eq!(max.duration_since(epoch), Some(expected));
+        assert_eq!(epoch.duration_since(max), None);
+
+        // Differences too large for a Duration saturate.
+        let min = Timespec {
+            secs: i64::MIN,
+            nanos: i64::MIN,
+        };
+        let max = Timespec {
+            secs: i64::MAX,
+            nanos: i64::MAX,
+        };
+        assert_eq!(max.abs_diff(min), Duration::MAX);
+        assert_eq!(min.abs_diff(max), Duration::MAX);
+        assert_eq!(max.duration_since(min), Some(Duration::MAX));
+    }
+
+    #[test]
+    fn test_timespec_coarse_eq() {
+        let precise = Timespec {
+            secs: 10,
+            nanos: 500,
+        };
+        let coarse = Timespec { secs: 10, nanos: 0 };
+
+        assert!(precise.coarse_eq(&coarse));
+        assert!(coarse.coarse_eq(&precise));
+        assert!(!precise.coarse_eq(&Timespec {
+            secs: 10,
+            nanos: 501
+        }));
+        assert!(!coarse.coarse_eq(&Timespec { secs: 11, nanos: 0 }));
+    }
+
+    #[test]
+    fn test_compile_config_stable_hash() {
+        let version = semver::Version::new(1, 87, 0);
+        let a = CompileConfig::builder("rustc", version.clone())
+            .flags(["-Cpanic=abort", "-Ccodegen-units=1"])
+            .build();
+        let b = CompileConfig::builder("rustc", version.clone())
+            .flag("-Ccodegen-units=1")
+            .flag("-Cpanic=abort")
+            .flag("-Cpanic=abort")
+            .build();
+        assert_eq!(a, b);
+        assert_eq!(a.stable_hash(), b.stable_hash());
+        assert_eq!(a.target_triple(), &target_lexicon::Triple::host());
+
+        let c = CompileConfig::builder("rustc", version)
+            .flags(["-Cpanic=abort", "-Ccodegen-units=1"])
+            .opt_level(OptimizationLevel::All)
+            .build();
+        assert_ne!(a.stable_hash(), c.stable_hash());
+    }
+}